@@ -78,6 +78,7 @@ fn setup(mut commands: Commands) {
             edl_radius: 1.4,
             edl_strength: 0.4,
             edl_neighbour_count: 4,
+            ..Default::default()
         },
         PointCloudOctreeVisibilitySettings {
             filter: Some(30.0),
@@ -98,6 +99,7 @@ fn load_pointcloud(
         point_size: 30.0,
         min_point_size: 2.0,
         max_point_size: 50.0,
+        ..default()
     });
     commands.spawn(MyMaterial(my_material.clone()));
 
@@ -99,6 +99,7 @@ fn setup(mut commands: Commands) {
             edl_radius: 2.8,
             edl_strength: 0.4,
             edl_neighbour_count: 4,
+            ..Default::default()
         },
     ));
 }
@@ -86,6 +86,7 @@ fn setup_camera(mut commands: Commands) {
             edl_radius: 1.4,
             edl_strength: 0.4,
             edl_neighbour_count: 4,
+            ..Default::default()
         },
         PointCloudOctreeVisibilitySettings {
             filter: Some(30.0),
@@ -149,6 +150,7 @@ fn spawn_pointcloud(
         point_size: 30.0,
         min_point_size: 2.0,
         max_point_size: 50.0,
+        ..default()
     });
 
     // Keep the material handle alive on a dedicated entity.
@@ -0,0 +1,476 @@
+//! Volumes isolating parts of the point clouds and octrees.
+//!
+//! The points are clipped by the [`PointCloudClipVolume`]s of the world in every pass, and the
+//! nodes of the octrees which are entirely clipped are neither drawn nor loaded.
+//! [`ClipVolumes::clips_point`] applies the same clipping on the CPU, to query the points.
+
+use bevy_app::prelude::*;
+use bevy_camera::primitives::Aabb;
+use bevy_color::Color;
+use bevy_ecs::prelude::*;
+use bevy_log::prelude::*;
+use bevy_math::{prelude::*, Affine3A, Vec3A};
+use bevy_reflect::{std_traits::ReflectDefault, Reflect};
+use bevy_render::extract_resource::{ExtractResource, ExtractResourcePlugin};
+use bevy_transform::prelude::*;
+
+use crate::{octree::visibility::OctreeVisibilitySystems, point_cloud::PointCloudData};
+
+/// Maximum number of clip volumes applied to the points, the other ones being ignored.
+pub const MAX_CLIP_VOLUMES: usize = 8;
+
+/// Maximum number of vertices of all the [`ClipShape::Polygon`] volumes applied to the points.
+pub const MAX_CLIP_POLYGON_VERTICES: usize = 512;
+
+pub struct ClipVolumePlugin;
+
+impl Plugin for ClipVolumePlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<PointCloudClipVolume>()
+            .init_resource::<ClipVolumes>()
+            .add_plugins(ExtractResourcePlugin::<ClipVolumes>::default())
+            .add_systems(
+                PostUpdate,
+                update_clip_volumes
+                    .after(TransformSystems::Propagate)
+                    .before(OctreeVisibilitySystems::CheckOctreeNodesVisibility),
+            );
+    }
+}
+
+/// A volume, placed by its transform, clipping or highlighting the points of all the point clouds
+/// and octrees.
+///
+/// The points are kept when they are inside at least one of the [`ClipMode::Inside`] volumes, if
+/// any, and outside of all the [`ClipMode::Outside`] volumes.
+#[derive(Component, Reflect, Debug, Clone)]
+#[reflect(Component, Default, Clone)]
+#[require(Transform)]
+pub struct PointCloudClipVolume {
+    pub shape: ClipShape,
+    pub mode: ClipMode,
+    /// Color blended with the points inside a [`ClipMode::Highlight`] volume, by its alpha
+    pub highlight_color: Color,
+}
+
+impl Default for PointCloudClipVolume {
+    fn default() -> Self {
+        Self {
+            shape: ClipShape::Box {
+                half_size: Vec3::splat(0.5),
+            },
+            mode: ClipMode::default(),
+            highlight_color: Color::srgba(1.0, 0.8, 0.0, 0.5),
+        }
+    }
+}
+
+#[derive(Reflect, Debug, Clone, PartialEq)]
+pub enum ClipShape {
+    /// Box centered on the origin of the volume.
+    Box { half_size: Vec3 },
+    /// Sphere centered on the origin of the volume.
+    Sphere { radius: f32 },
+    /// Everything behind the plane going through the origin of the volume, on the opposite side
+    /// of its normal.
+    HalfSpace { normal: Dir3 },
+    /// Prism extruded from a polygon of the XZ plane of the volume, between two heights along its
+    /// Y axis.
+    ///
+    /// The vertices are the X and Z coordinates of a simple polygon, in either winding order.
+    Polygon {
+        vertices: Vec<Vec2>,
+        min_height: f32,
+        max_height: f32,
+    },
+}
+
+impl ClipShape {
+    /// Polygon extruded between two heights, from a list of vertices of the XZ plane.
+    pub fn polygon(
+        vertices: impl IntoIterator<Item = Vec2>,
+        min_height: f32,
+        max_height: f32,
+    ) -> Self {
+        Self::Polygon {
+            vertices: vertices.into_iter().collect(),
+            min_height,
+            max_height,
+        }
+    }
+}
+
+#[derive(Reflect, Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum ClipMode {
+    /// Only keep the points inside the volume.
+    #[default]
+    Inside,
+    /// Discard the points inside the volume.
+    Outside,
+    /// Keep all the points, highlighting the ones inside the volume.
+    Highlight,
+}
+
+/// A [`PointCloudClipVolume`] placed in the world.
+#[derive(Debug, Clone)]
+pub struct GlobalClipVolume {
+    pub shape: ClipShape,
+    pub mode: ClipMode,
+    pub highlight_color: Color,
+    pub volume_from_world: Affine3A,
+}
+
+impl GlobalClipVolume {
+    pub fn new(clip_volume: &PointCloudClipVolume, global_transform: &GlobalTransform) -> Self {
+        Self {
+            shape: clip_volume.shape.clone(),
+            mode: clip_volume.mode,
+            highlight_color: clip_volume.highlight_color,
+            volume_from_world: global_transform.affine().inverse(),
+        }
+    }
+
+    /// Whether a position in world space is inside the volume.
+    pub fn contains_point(&self, world_position: Vec3) -> bool {
+        let position = self.volume_from_world.transform_point3(world_position);
+        match &self.shape {
+            ClipShape::Box { half_size } => position.abs().cmple(*half_size).all(),
+            ClipShape::Sphere { radius } => position.length_squared() <= radius * radius,
+            ClipShape::HalfSpace { normal } => position.dot(**normal) <= 0.0,
+            ClipShape::Polygon {
+                vertices,
+                min_height,
+                max_height,
+            } => {
+                (*min_height..=*max_height).contains(&position.y)
+                    && polygon_contains(vertices, position.xz())
+            }
+        }
+    }
+
+    /// Whether a box, in the local space of `world_from_local`, may intersect the volume.
+    pub fn intersects_obb(&self, aabb: &Aabb, world_from_local: &Affine3A) -> bool {
+        let (center, half_extents) = self.enclosing_aabb(aabb, world_from_local);
+        match &self.shape {
+            ClipShape::Box { half_size } => {
+                ((center.abs() - half_extents).cmple(Vec3A::from(*half_size))).all()
+            }
+            ClipShape::Sphere { radius } => {
+                let closest = Vec3A::ZERO.clamp(center - half_extents, center + half_extents);
+                closest.length_squared() <= radius * radius
+            }
+            ClipShape::HalfSpace { normal } => {
+                let normal = Vec3A::from(**normal);
+                center.dot(normal) - half_extents.dot(normal.abs()) <= 0.0
+            }
+            ClipShape::Polygon {
+                vertices,
+                min_height,
+                max_height,
+            } => {
+                let (min, max) = (center - half_extents, center + half_extents);
+                min.y <= *max_height
+                    && max.y >= *min_height
+                    && polygon_intersects_rect(vertices, min.xz(), max.xz())
+            }
+        }
+    }
+
+    /// Whether a box, in the local space of `world_from_local`, is entirely inside the volume.
+    pub fn contains_obb(&self, aabb: &Aabb, world_from_local: &Affine3A) -> bool {
+        let (center, half_extents) = self.enclosing_aabb(aabb, world_from_local);
+        match &self.shape {
+            ClipShape::Box { half_size } => (center.abs() + half_extents)
+                .cmple(Vec3A::from(*half_size))
+                .all(),
+            ClipShape::Sphere { radius } => {
+                (center.abs() + half_extents).length_squared() <= radius * radius
+            }
+            ClipShape::HalfSpace { normal } => {
+                let normal = Vec3A::from(**normal);
+                center.dot(normal) + half_extents.dot(normal.abs()) <= 0.0
+            }
+            ClipShape::Polygon {
+                vertices,
+                min_height,
+                max_height,
+            } => {
+                let (min, max) = (center - half_extents, center + half_extents);
+                min.y >= *min_height
+                    && max.y <= *max_height
+                    && polygon_contains_rect(vertices, min.xz(), max.xz())
+            }
+        }
+    }
+
+    /// Center and half extents of the box enclosing an oriented box, in the space of the volume.
+    fn enclosing_aabb(&self, aabb: &Aabb, world_from_local: &Affine3A) -> (Vec3A, Vec3A) {
+        let volume_from_local = self.volume_from_world * *world_from_local;
+        let matrix = volume_from_local.matrix3;
+        let center = volume_from_local.transform_point3a(aabb.center);
+        let half_extents = matrix.x_axis.abs() * aabb.half_extents.x
+            + matrix.y_axis.abs() * aabb.half_extents.y
+            + matrix.z_axis.abs() * aabb.half_extents.z;
+        (center, half_extents)
+    }
+}
+
+/// The clip volumes of the world, at most [`MAX_CLIP_VOLUMES`].
+#[derive(Resource, ExtractResource, Clone, Debug, Default)]
+pub struct ClipVolumes {
+    pub volumes: Vec<GlobalClipVolume>,
+}
+
+impl ClipVolumes {
+    /// Whether a position in world space is discarded by the volumes.
+    pub fn clips_point(&self, world_position: Vec3) -> bool {
+        let mut has_inside_volume = false;
+        let mut is_inside = false;
+        for volume in &self.volumes {
+            match volume.mode {
+                ClipMode::Inside => {
+                    has_inside_volume = true;
+                    is_inside = is_inside || volume.contains_point(world_position);
+                }
+                ClipMode::Outside if volume.contains_point(world_position) => return true,
+                ClipMode::Outside | ClipMode::Highlight => {}
+            }
+        }
+        has_inside_volume && !is_inside
+    }
+
+    /// Whether all the positions of a box, in the local space of `world_from_local`, are
+    /// discarded by the volumes.
+    pub fn clips_obb(&self, aabb: &Aabb, world_from_local: &Affine3A) -> bool {
+        let mut has_inside_volume = false;
+        let mut intersects_inside = false;
+        for volume in &self.volumes {
+            match volume.mode {
+                ClipMode::Inside => {
+                    has_inside_volume = true;
+                    intersects_inside =
+                        intersects_inside || volume.intersects_obb(aabb, world_from_local);
+                }
+                ClipMode::Outside if volume.contains_obb(aabb, world_from_local) => return true,
+                ClipMode::Outside | ClipMode::Highlight => {}
+            }
+        }
+        has_inside_volume && !intersects_inside
+    }
+
+    /// The points, in the local space of `world_from_local`, which are not discarded by the
+    /// volumes.
+    pub fn select_points<'a>(
+        &'a self,
+        points: &'a [PointCloudData],
+        world_from_local: Affine3A,
+    ) -> impl Iterator<Item = &'a PointCloudData> + 'a {
+        points.iter().filter(move |point| {
+            !self.clips_point(world_from_local.transform_point3(point.position))
+        })
+    }
+}
+
+/// Whether a point is inside a polygon, with the even-odd rule.
+pub(crate) fn polygon_contains(vertices: &[Vec2], point: Vec2) -> bool {
+    let mut inside = false;
+    let mut previous = vertices.last().copied().unwrap_or_default();
+    for &vertex in vertices {
+        if (vertex.y > point.y) != (previous.y > point.y)
+            && point.x
+                < vertex.x
+                    + (point.y - vertex.y) * (previous.x - vertex.x) / (previous.y - vertex.y)
+        {
+            inside = !inside;
+        }
+        previous = vertex;
+    }
+    inside
+}
+
+/// The edges of a polygon, as pairs of consecutive vertices.
+fn polygon_edges(vertices: &[Vec2]) -> impl Iterator<Item = (Vec2, Vec2)> + '_ {
+    vertices
+        .iter()
+        .zip(vertices.iter().cycle().skip(1))
+        .map(|(a, b)| (*a, *b))
+}
+
+/// Whether a segment crosses a rectangle, by clipping it against the rectangle.
+fn segment_intersects_rect(a: Vec2, b: Vec2, min: Vec2, max: Vec2) -> bool {
+    let direction = b - a;
+    let (mut enter, mut exit) = (0.0_f32, 1.0_f32);
+    for axis in 0..2 {
+        if direction[axis] == 0.0 {
+            if a[axis] < min[axis] || a[axis] > max[axis] {
+                return false;
+            }
+            continue;
+        }
+        let t0 = (min[axis] - a[axis]) / direction[axis];
+        let t1 = (max[axis] - a[axis]) / direction[axis];
+        enter = enter.max(t0.min(t1));
+        exit = exit.min(t0.max(t1));
+    }
+    enter <= exit
+}
+
+/// Whether a polygon and a rectangle intersect.
+fn polygon_intersects_rect(vertices: &[Vec2], min: Vec2, max: Vec2) -> bool {
+    // the rectangle is inside the polygon, or an edge of the polygon crosses the rectangle
+    polygon_contains(vertices, (min + max) / 2.0)
+        || polygon_edges(vertices).any(|(a, b)| segment_intersects_rect(a, b, min, max))
+}
+
+/// Whether a rectangle is entirely inside a polygon.
+fn polygon_contains_rect(vertices: &[Vec2], min: Vec2, max: Vec2) -> bool {
+    // the rectangle is inside the polygon, and no edge of the polygon crosses the rectangle
+    polygon_contains(vertices, (min + max) / 2.0)
+        && !polygon_edges(vertices).any(|(a, b)| segment_intersects_rect(a, b, min, max))
+}
+
+fn update_clip_volumes(
+    mut clip_volumes: ResMut<ClipVolumes>,
+    volumes: Query<(&PointCloudClipVolume, &GlobalTransform)>,
+) {
+    if volumes.iter().len() > MAX_CLIP_VOLUMES {
+        warn_once!(
+            "Only the first {} point cloud clip volumes are applied",
+            MAX_CLIP_VOLUMES
+        );
+    }
+
+    clip_volumes.volumes.clear();
+    let mut polygon_vertices = 0;
+    for (clip_volume, global_transform) in volumes.iter().take(MAX_CLIP_VOLUMES) {
+        let mut volume = GlobalClipVolume::new(clip_volume, global_transform);
+        if fit_polygon(&mut volume, &mut polygon_vertices) {
+            clip_volumes.volumes.push(volume);
+        }
+    }
+}
+
+/// Counts the vertices of a polygon volume, and rejects all its points if they exceed
+/// [`MAX_CLIP_POLYGON_VERTICES`]: the polygon is replaced by a shape containing nothing for a
+/// [`ClipMode::Inside`] volume and everything for a [`ClipMode::Outside`] one.
+///
+/// Returns `false` if the volume must be ignored, which only happens to highlights.
+fn fit_polygon(volume: &mut GlobalClipVolume, polygon_vertices: &mut usize) -> bool {
+    let ClipShape::Polygon { vertices, .. } = &volume.shape else {
+        return true;
+    };
+    if *polygon_vertices + vertices.len() <= MAX_CLIP_POLYGON_VERTICES {
+        *polygon_vertices += vertices.len();
+        return true;
+    }
+
+    warn_once!(
+        "The point cloud clip polygons have more than {} vertices, the points of the last ones are rejected",
+        MAX_CLIP_POLYGON_VERTICES
+    );
+    volume.shape = match volume.mode {
+        ClipMode::Inside => ClipShape::polygon([], 0.0, 0.0),
+        ClipMode::Outside => ClipShape::Box {
+            half_size: Vec3::splat(f32::MAX),
+        },
+        ClipMode::Highlight => return false,
+    };
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn polygon_contains_points() {
+        // concave, in clockwise order
+        let vertices = [
+            Vec2::new(0.0, 0.0),
+            Vec2::new(0.0, 4.0),
+            Vec2::new(4.0, 4.0),
+            Vec2::new(4.0, 0.0),
+            Vec2::new(2.0, 2.0),
+        ];
+        assert!(polygon_contains(&vertices, Vec2::new(1.0, 2.0)));
+        assert!(polygon_contains(&vertices, Vec2::new(3.0, 3.0)));
+        // in the notch
+        assert!(!polygon_contains(&vertices, Vec2::new(2.0, 1.0)));
+        assert!(!polygon_contains(&vertices, Vec2::new(5.0, 2.0)));
+        assert!(!polygon_contains(&vertices, Vec2::new(-1.0, 2.0)));
+    }
+
+    #[test]
+    fn polygon_contains_with_winding() {
+        let vertices = [Vec2::ZERO, Vec2::new(2.0, 0.0), Vec2::new(0.0, 2.0)];
+        let reversed: Vec<_> = vertices.iter().rev().copied().collect();
+        for point in [Vec2::new(0.5, 0.5), Vec2::new(1.5, 1.5)] {
+            assert_eq!(
+                polygon_contains(&vertices, point),
+                polygon_contains(&reversed, point)
+            );
+        }
+        assert!(polygon_contains(&vertices, Vec2::new(0.5, 0.5)));
+        assert!(!polygon_contains(&vertices, Vec2::new(1.5, 1.5)));
+    }
+
+    #[test]
+    fn overflowing_polygons_reject_their_points() {
+        let polygon = |size: usize| {
+            ClipShape::polygon(
+                (0..size).map(|index| Vec2::from_angle(index as f32 / size as f32 * 6.3)),
+                -1.0,
+                1.0,
+            )
+        };
+        let volume = |shape: ClipShape, mode: ClipMode| {
+            GlobalClipVolume::new(
+                &PointCloudClipVolume {
+                    shape,
+                    mode,
+                    ..Default::default()
+                },
+                &GlobalTransform::IDENTITY,
+            )
+        };
+
+        let mut polygon_vertices = 0;
+        let mut fitting = volume(polygon(MAX_CLIP_POLYGON_VERTICES - 4), ClipMode::Inside);
+        assert!(fit_polygon(&mut fitting, &mut polygon_vertices));
+        assert!(fitting.contains_point(Vec3::ZERO));
+        assert_eq!(polygon_vertices, MAX_CLIP_POLYGON_VERTICES - 4);
+
+        // a sole inside volume which does not fit clips everything
+        let mut inside = volume(polygon(8), ClipMode::Inside);
+        assert!(fit_polygon(&mut inside, &mut polygon_vertices));
+        let clip_volumes = ClipVolumes {
+            volumes: vec![inside],
+        };
+        assert!(clip_volumes.clips_point(Vec3::ZERO));
+        let aabb = Aabb::from_min_max(Vec3::splat(-0.1), Vec3::splat(0.1));
+        assert!(!clip_volumes.volumes[0].contains_obb(&aabb, &Affine3A::IDENTITY));
+
+        // an outside volume which does not fit clips everything too
+        let mut outside = volume(polygon(8), ClipMode::Outside);
+        assert!(fit_polygon(&mut outside, &mut polygon_vertices));
+        let clip_volumes = ClipVolumes {
+            volumes: vec![outside],
+        };
+        assert!(clip_volumes.clips_point(Vec3::new(100.0, 0.0, 100.0)));
+        assert!(clip_volumes.clips_obb(&aabb, &Affine3A::IDENTITY));
+
+        // a highlight volume which does not fit is ignored
+        let mut highlight = volume(polygon(8), ClipMode::Highlight);
+        assert!(!fit_polygon(&mut highlight, &mut polygon_vertices));
+        assert_eq!(polygon_vertices, MAX_CLIP_POLYGON_VERTICES - 4);
+    }
+
+    #[test]
+    fn degenerate_polygons_contain_nothing() {
+        assert!(!polygon_contains(&[], Vec2::ZERO));
+        assert!(!polygon_contains(
+            &[Vec2::ZERO, Vec2::ONE],
+            Vec2::splat(0.5)
+        ));
+    }
+}
@@ -0,0 +1,424 @@
+//! Digital elevation models of the point clouds and octrees.
+//!
+//! A [`PointCloudElevationModel`] bins the points inside a rectangle of its XZ plane into a
+//! [`HeightGrid`] on the [`AsyncComputeTaskPool`], and produces a raster [`Image`] of the heights
+//! and a heightfield [`Mesh`] in the space of its entity, loading the missing octree nodes of the
+//! rectangle.
+//!
+//! Terrain models select their ground points by the classes loaded from LAS and COPC files.
+
+use std::sync::Arc;
+
+use bevy_app::prelude::*;
+use bevy_asset::{Assets, Handle, RenderAssetUsages};
+use bevy_ecs::prelude::*;
+use bevy_image::Image;
+#[cfg(feature = "trace")]
+use bevy_log::prelude::*;
+use bevy_math::prelude::*;
+use bevy_mesh::{Indices, Mesh};
+use bevy_render::render_resource::{Extent3d, PrimitiveTopology, TextureDimension, TextureFormat};
+use bevy_tasks::{futures::check_ready, AsyncComputeTaskPool, Task};
+use bevy_transform::prelude::*;
+
+use crate::{
+    clip_volume::{ClipShape, GlobalClipVolume, PointCloudClipVolume},
+    octree::{server::process::process_octree_load_tasks, visibility::OctreeVisibilitySystems},
+    pointcloud_octree::asset::data::PointCloudNodeData,
+    query::{PointCloudRegionQuery, RegionQuerySettings},
+    volume::{HeightGrid, HeightStatistic},
+};
+
+/// Computes the [`PointCloudElevationModel`]s.
+pub struct PointCloudElevationPlugin;
+
+impl Plugin for PointCloudElevationPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            PostUpdate,
+            // the load requests are cleared by the visibility check
+            update_elevation_models
+                .after(TransformSystems::Propagate)
+                .after(OctreeVisibilitySystems::CheckOctreeNodesVisibility)
+                .before(process_octree_load_tasks::<PointCloudNodeData>),
+        );
+    }
+}
+
+/// How the height of a cell is computed from its points.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ElevationMethod {
+    /// Lowest point of the cell, for terrain models
+    Min,
+    /// Highest point of the cell, for surface models
+    Max,
+    Mean,
+    /// Inverse distance weighting of the mean heights of the cells within a radius, in cells,
+    /// which also fills the holes narrower than the radius
+    Idw {
+        radius: u32,
+        power: f32,
+    },
+}
+
+/// ASPRS class of the ground points.
+pub const GROUND_CLASS: u8 = 2;
+
+/// Rasterizes the heights of the points inside a rectangle, into a terrain or surface model.
+///
+/// The rectangle lies in the XZ plane of the entity, which is placed by its transform, and the
+/// heights are measured along its Y axis. The result is recomputed when the model or its
+/// transform change, and when new octree nodes of the rectangle are loaded.
+#[derive(Component, Debug, Clone)]
+#[require(Transform, PointCloudElevationResult, ElevationTask)]
+pub struct PointCloudElevationModel {
+    /// Corner of the rectangle with the lowest X and Z coordinates
+    pub min: Vec2,
+    /// Corner of the rectangle with the highest X and Z coordinates
+    pub max: Vec2,
+    /// Size of the cells, in the units of the entity
+    pub cell_size: f32,
+    pub method: ElevationMethod,
+    /// Whether to estimate the heights of the empty cells from their neighbours, see
+    /// [`HeightGrid::estimate`]
+    pub fill_holes: bool,
+    /// ASPRS classes of the binned points, all of them if empty
+    ///
+    /// The points without classification, such as the ones of PLY files, are always binned.
+    pub classes: Vec<u8>,
+    /// Deepest level of the octree nodes whose points are binned, `None` for all the levels
+    pub max_depth: Option<u32>,
+    /// Whether to load the missing octree nodes of the rectangle, up to `max_depth`
+    pub load_missing_nodes: bool,
+    /// Whether to ignore the points discarded by the clip volumes
+    pub respect_clip_volumes: bool,
+    /// The point cloud and octree entities binned, all of them if empty
+    pub entities: Vec<Entity>,
+}
+
+impl PointCloudElevationModel {
+    pub fn new(min: Vec2, max: Vec2, cell_size: f32, method: ElevationMethod) -> Self {
+        Self {
+            min,
+            max,
+            cell_size,
+            method,
+            fill_holes: true,
+            classes: Vec::new(),
+            max_depth: None,
+            load_missing_nodes: true,
+            respect_clip_volumes: true,
+            entities: Vec::new(),
+        }
+    }
+
+    /// A terrain model, from the lowest of the ground points.
+    pub fn terrain(min: Vec2, max: Vec2, cell_size: f32) -> Self {
+        Self::new(min, max, cell_size, ElevationMethod::Min).with_classes([GROUND_CLASS])
+    }
+
+    /// A surface model, from the highest points.
+    pub fn surface(min: Vec2, max: Vec2, cell_size: f32) -> Self {
+        Self::new(min, max, cell_size, ElevationMethod::Max)
+    }
+
+    pub fn with_classes(mut self, classes: impl IntoIterator<Item = u8>) -> Self {
+        self.classes = classes.into_iter().collect();
+        self
+    }
+
+    pub fn with_max_depth(mut self, max_depth: u32) -> Self {
+        self.max_depth = Some(max_depth);
+        self
+    }
+}
+
+/// The rasters of a [`PointCloudElevationModel`], in the space of its entity.
+#[derive(Component, Debug, Clone, Default)]
+pub struct PointCloudElevationResult {
+    /// The heights of the points of each cell
+    pub grid: Arc<HeightGrid>,
+    /// Height of each cell, row by row along X, NaN for the cells without height
+    pub heights: Arc<[f32]>,
+    /// Lowest and highest heights of the cells
+    pub height_range: Option<(f32, f32)>,
+    /// The heights as a [`TextureFormat::R32Float`] image, a texel per cell
+    pub image: Handle<Image>,
+    /// Heightfield with a vertex at the center of each cell, whose UVs cover the rectangle to
+    /// drape an image over it
+    pub mesh: Handle<Mesh>,
+    /// Whether all the octree nodes of the rectangle are loaded, up to the maximum depth
+    pub complete: bool,
+}
+
+/// The rasterization of a [`PointCloudElevationModel`] running in the background.
+#[derive(Component, Default)]
+struct ElevationTask {
+    task: Option<Task<ElevationRasters>>,
+    /// Whether the points changed since the running task started
+    outdated: bool,
+}
+
+struct ElevationRasters {
+    grid: HeightGrid,
+    heights: Vec<f32>,
+    image: Image,
+    mesh: Mesh,
+}
+
+/// Bins points, in the space of the model, and computes the rasters.
+fn compute_rasters(model: &PointCloudElevationModel, points: &[Vec3]) -> ElevationRasters {
+    let mut grid = HeightGrid::new(
+        model.min.min(model.max),
+        model.max.max(model.min),
+        model.cell_size,
+    );
+    for point in points {
+        grid.insert(point.xz(), point.y);
+    }
+
+    let (statistic, idw) = match model.method {
+        ElevationMethod::Min => (HeightStatistic::Min, None),
+        ElevationMethod::Max => (HeightStatistic::Max, None),
+        ElevationMethod::Mean => (HeightStatistic::Mean, None),
+        ElevationMethod::Idw { radius, power } => (HeightStatistic::Mean, Some((radius, power))),
+    };
+    let size = grid.size;
+    let heights = (0..size.y)
+        .flat_map(|y| (0..size.x).map(move |x| UVec2::new(x, y)))
+        .map(|cell| {
+            let height = match idw {
+                Some((radius, power)) => inverse_distance_weighting(&grid, cell, radius, power),
+                None => grid.get(cell).height(statistic),
+            };
+            height
+                .or_else(|| {
+                    model
+                        .fill_holes
+                        .then(|| grid.estimate(cell, statistic).map(|(height, _)| height))
+                        .flatten()
+                })
+                .unwrap_or(f32::NAN)
+        })
+        .collect::<Vec<_>>();
+
+    let image = Image::new(
+        Extent3d {
+            width: size.x,
+            height: size.y,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        heights
+            .iter()
+            .flat_map(|height| height.to_le_bytes())
+            .collect(),
+        TextureFormat::R32Float,
+        RenderAssetUsages::default(),
+    );
+    let mesh = heightfield_mesh(&grid, &heights);
+
+    ElevationRasters {
+        grid,
+        heights,
+        image,
+        mesh,
+    }
+}
+
+/// Mean height around a cell, weighted by the number of points of the cells and the inverse of
+/// their distance to the power `power`.
+fn inverse_distance_weighting(
+    grid: &HeightGrid,
+    cell: UVec2,
+    radius: u32,
+    power: f32,
+) -> Option<f32> {
+    let radius = radius as i32;
+    let (mut sum, mut weights) = (0.0, 0.0);
+    for y in -radius..=radius {
+        for x in -radius..=radius {
+            let offset = IVec2::new(x, y);
+            let neighbour = cell.as_ivec2() + offset;
+            if offset.length_squared() > radius * radius
+                || neighbour.cmplt(IVec2::ZERO).any()
+                || neighbour.cmpge(grid.size.as_ivec2()).any()
+            {
+                continue;
+            }
+            let neighbour = grid.get(neighbour.as_uvec2());
+            let Some(mean) = neighbour.mean() else {
+                continue;
+            };
+            // the points of the cell itself are half a cell away on average
+            let distance = offset.as_vec2().length().max(0.5) as f64;
+            let weight = neighbour.count as f64 / distance.powf(power as f64);
+            sum += mean as f64 * weight;
+            weights += weight;
+        }
+    }
+    (weights > 0.0).then(|| (sum / weights) as f32)
+}
+
+/// A vertex at the center of each cell, the quads of four cells with heights being triangulated.
+fn heightfield_mesh(grid: &HeightGrid, heights: &[f32]) -> Mesh {
+    let size = grid.size;
+    let height = |x: i32, y: i32| {
+        (x >= 0 && y >= 0 && x < size.x as i32 && y < size.y as i32)
+            .then(|| heights[(y as u32 * size.x + x as u32) as usize])
+            .filter(|height| height.is_finite())
+    };
+
+    let mut positions = Vec::with_capacity(heights.len());
+    let mut normals = Vec::with_capacity(heights.len());
+    let mut uvs = Vec::with_capacity(heights.len());
+    for y in 0..size.y as i32 {
+        for x in 0..size.x as i32 {
+            let cell = UVec2::new(x as u32, y as u32);
+            let center = grid.cell_center(cell);
+            let h = height(x, y);
+            positions.push([center.x, h.unwrap_or(0.0), center.y]);
+            uvs.push(((cell.as_vec2() + 0.5) / size.as_vec2()).to_array());
+
+            // central differences, falling back to one-sided ones on the borders
+            let slope = |previous: Option<f32>, next: Option<f32>| match (previous, next, h) {
+                (Some(previous), Some(next), _) => (next - previous) / (2.0 * grid.cell_size),
+                (Some(previous), None, Some(h)) => (h - previous) / grid.cell_size,
+                (None, Some(next), Some(h)) => (next - h) / grid.cell_size,
+                _ => 0.0,
+            };
+            let slope_x = slope(height(x - 1, y), height(x + 1, y));
+            let slope_z = slope(height(x, y - 1), height(x, y + 1));
+            normals.push(Vec3::new(-slope_x, 1.0, -slope_z).normalize().to_array());
+        }
+    }
+
+    let mut indices = Vec::new();
+    for y in 0..size.y.saturating_sub(1) {
+        for x in 0..size.x.saturating_sub(1) {
+            let (a, b) = (y * size.x + x, y * size.x + x + 1);
+            let (c, d) = (a + size.x, b + size.x);
+            if [a, b, c, d]
+                .iter()
+                .all(|&index| heights[index as usize].is_finite())
+            {
+                // counter clockwise seen from above
+                indices.extend_from_slice(&[a, c, b, b, c, d]);
+            }
+        }
+    }
+
+    Mesh::new(
+        PrimitiveTopology::TriangleList,
+        RenderAssetUsages::default(),
+    )
+    .with_inserted_attribute(Mesh::ATTRIBUTE_POSITION, positions)
+    .with_inserted_attribute(Mesh::ATTRIBUTE_NORMAL, normals)
+    .with_inserted_attribute(Mesh::ATTRIBUTE_UV_0, uvs)
+    .with_inserted_indices(Indices::U32(indices))
+}
+
+#[allow(clippy::type_complexity)]
+fn update_elevation_models(
+    mut models: Query<(
+        Ref<PointCloudElevationModel>,
+        Ref<GlobalTransform>,
+        &mut PointCloudElevationResult,
+        &mut ElevationTask,
+    )>,
+    mut query: PointCloudRegionQuery,
+    mut images: ResMut<Assets<Image>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+) {
+    #[cfg(feature = "trace")]
+    let _span = info_span!("update_elevation_models").entered();
+
+    // new nodes may have been loaded
+    let octrees_changed = query.octrees_changed();
+
+    for (model, global_transform, mut result, mut task) in &mut models {
+        if let Some(rasters) = task.task.as_mut().and_then(check_ready) {
+            task.task = None;
+            let height_range = rasters
+                .heights
+                .iter()
+                .filter(|height| height.is_finite())
+                .fold(None, |range, &height| {
+                    let (min, max) = range.unwrap_or((height, height));
+                    Some((min.min(height), max.max(height)))
+                });
+            // the assets are updated in place, so that the entities showing them follow
+            if images.contains(&result.image) {
+                images.insert(&result.image, rasters.image).ok();
+            } else {
+                result.image = images.add(rasters.image);
+            }
+            if meshes.contains(&result.mesh) {
+                meshes.insert(&result.mesh, rasters.mesh).ok();
+            } else {
+                result.mesh = meshes.add(rasters.mesh);
+            }
+            result.grid = Arc::new(rasters.grid);
+            result.heights = rasters.heights.into();
+            result.height_range = height_range;
+        }
+
+        // the running task is let finish, then restarted once with the latest points
+        task.outdated |= model.is_changed()
+            || global_transform.is_changed()
+            || (!result.complete && octrees_changed);
+        let rasterize = task.outdated && task.task.is_none();
+        if !rasterize && result.complete {
+            continue;
+        }
+
+        let (min, max) = (model.min.min(model.max), model.min.max(model.max));
+        let region = GlobalClipVolume::new(
+            &PointCloudClipVolume {
+                shape: ClipShape::polygon(
+                    [min, Vec2::new(max.x, min.y), max, Vec2::new(min.x, max.y)],
+                    f32::NEG_INFINITY,
+                    f32::INFINITY,
+                ),
+                ..Default::default()
+            },
+            &global_transform,
+        );
+        let settings = RegionQuerySettings {
+            max_depth: model.max_depth,
+            min_spacing: None,
+            load_missing_nodes: model.load_missing_nodes,
+            respect_clip_volumes: model.respect_clip_volumes,
+            visit_points: rasterize,
+            filter: &|entity| model.entities.is_empty() || model.entities.contains(&entity),
+            skip_points: &|_, _| false,
+        };
+        let mut points = Vec::new();
+        let status = query.query(&region, &settings, |point| {
+            if !region.contains_point(point.world_position)
+                || point.classification.is_some_and(|classification| {
+                    !model.classes.is_empty() && !model.classes.contains(&classification)
+                })
+            {
+                return;
+            }
+            points.push(
+                region
+                    .volume_from_world
+                    .transform_point3(point.world_position),
+            );
+        });
+        let complete = status.complete();
+
+        if rasterize {
+            let model = model.clone();
+            task.task = Some(
+                AsyncComputeTaskPool::get().spawn(async move { compute_rasters(&model, &points) }),
+            );
+            task.outdated = false;
+        }
+        if result.complete != complete {
+            result.complete = complete;
+        }
+    }
+}
@@ -0,0 +1,798 @@
+//! Export of the points of a region of the point clouds and octrees to LAS/LAZ or PLY files.
+//!
+//! A [`PointCloudExport`] walks the visible point clouds and octrees inside a clip volume, down
+//! to a depth or spacing, loading the missing octree nodes. The points of each node are written
+//! as soon as it is loaded, by tasks of the [`IoTaskPool`], and the progress is reported in
+//! [`PointCloudExportProgress`].
+
+use std::{
+    cell::RefCell,
+    fs::File,
+    io::{BufWriter, Cursor, Seek, SeekFrom, Write},
+    path::PathBuf,
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+};
+
+use bevy_app::prelude::*;
+use bevy_camera::primitives::Aabb;
+use bevy_ecs::prelude::*;
+#[cfg(feature = "trace")]
+use bevy_log::prelude::*;
+use bevy_math::{prelude::*, Affine3A, DVec3};
+use bevy_platform::{cell::SyncCell, collections::HashSet};
+use bevy_tasks::{futures::check_ready, IoTaskPool, Task};
+use bevy_transform::prelude::*;
+use thiserror::Error;
+
+use crate::{
+    clip_volume::{ClipMode, GlobalClipVolume, PointCloudClipVolume},
+    octree::{
+        server::process::process_octree_load_tasks, storage::NodeId,
+        visibility::OctreeVisibilitySystems,
+    },
+    pointcloud_octree::asset::data::PointCloudNodeData,
+    query::{PointCloudRegionQuery, QueryRegion, RegionQuerySettings},
+};
+
+/// Runs the [`PointCloudExport`]s.
+pub struct PointCloudExportPlugin;
+
+impl Plugin for PointCloudExportPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            PostUpdate,
+            // the load requests are cleared by the visibility check
+            (start_exports, update_exports)
+                .chain()
+                .after(TransformSystems::Propagate)
+                .after(OctreeVisibilitySystems::CheckOctreeNodesVisibility)
+                .before(process_octree_load_tasks::<PointCloudNodeData>),
+        );
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ExportFormat {
+    /// LAS 1.4 with colors and classes, in point format 7, the normals being stored as extra
+    /// bytes
+    #[cfg(feature = "las")]
+    Las,
+    /// Compressed [`ExportFormat::Las`]
+    #[cfg(feature = "las")]
+    Laz,
+    /// Binary PLY with positions, colors, normals and classes
+    ///
+    /// The positions are written as doubles, but only have the single precision of the loaded
+    /// points.
+    Ply,
+}
+
+/// Where the file of a [`PointCloudExport`] is written.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ExportTarget {
+    /// In memory, the file being available in [`ExportStatus::Finished`]
+    Memory,
+    File(PathBuf),
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum ExportCoordinates {
+    /// The positions of the points in their point cloud or octree, which are the coordinates of
+    /// the source files for the octrees.
+    ///
+    /// The loaders convert the coordinates to single precision, so the exported coordinates are
+    /// rounded to about 7 significant digits of the source ones, which is centimetres at
+    /// 100 km from the origin.
+    #[default]
+    Source,
+    /// The positions of the points in the world.
+    World,
+}
+
+/// Exports the points of a region to a file, in the background.
+///
+/// The export starts when the component is added, later changes being ignored, and is cancelled
+/// by despawning its entity.
+#[derive(Component, Debug, Clone)]
+#[require(PointCloudExportProgress)]
+pub struct PointCloudExport {
+    pub format: ExportFormat,
+    pub target: ExportTarget,
+    /// Entity of a [`PointCloudClipVolume`] whose kept points are exported, all the points if
+    /// `None`
+    pub clip_volume: Option<Entity>,
+    /// Whether to ignore the points discarded by the [`PointCloudClipVolume`]s of the world
+    pub respect_clip_volumes: bool,
+    /// Deepest level of the octree nodes exported, `None` for all the levels
+    pub max_depth: Option<u32>,
+    /// The children of the octree nodes whose spacing is at most this are not exported
+    pub min_spacing: Option<f32>,
+    pub coordinates: ExportCoordinates,
+    /// Scale of the LAS coordinates
+    pub precision: f64,
+    /// The point cloud and octree entities exported, all of them if empty
+    pub entities: Vec<Entity>,
+}
+
+impl PointCloudExport {
+    pub fn new(format: ExportFormat, target: ExportTarget) -> Self {
+        Self {
+            format,
+            target,
+            clip_volume: None,
+            respect_clip_volumes: true,
+            max_depth: None,
+            min_spacing: None,
+            coordinates: ExportCoordinates::default(),
+            precision: 0.001,
+            entities: Vec::new(),
+        }
+    }
+
+    pub fn with_clip_volume(mut self, clip_volume: Entity) -> Self {
+        self.clip_volume = Some(clip_volume);
+        self
+    }
+
+    pub fn with_max_depth(mut self, max_depth: u32) -> Self {
+        self.max_depth = Some(max_depth);
+        self
+    }
+
+    pub fn with_min_spacing(mut self, min_spacing: f32) -> Self {
+        self.min_spacing = Some(min_spacing);
+        self
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum ExportError {
+    #[cfg(feature = "las")]
+    #[error("failed to write las: {0}")]
+    Las(#[from] las::Error),
+    #[error("failed to write file: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("the clip volume of the export does not exist")]
+    MissingClipVolume,
+}
+
+#[derive(Debug, Clone, Default)]
+pub enum ExportStatus {
+    #[default]
+    Running,
+    /// The file, with [`ExportTarget::Memory`]
+    Finished(Option<Arc<Vec<u8>>>),
+    Failed(Arc<ExportError>),
+}
+
+/// The progress of a [`PointCloudExport`].
+#[derive(Component, Debug, Clone, Default)]
+pub struct PointCloudExportProgress {
+    /// Number of point clouds and octree nodes whose points have been exported
+    pub exported_nodes: usize,
+    /// Number of octree nodes of the region which remain to be loaded, as far as the hierarchy
+    /// is known
+    pub missing_nodes: usize,
+    /// Number of points written
+    pub points: u64,
+    pub status: ExportStatus,
+}
+
+impl PointCloudExportProgress {
+    /// Estimate of the exported fraction of the region, between 0 and 1.
+    pub fn fraction(&self) -> f32 {
+        match self.status {
+            ExportStatus::Running => {
+                let total = self.exported_nodes + self.missing_nodes;
+                if total == 0 {
+                    0.0
+                } else {
+                    // the hierarchy of the region is discovered along the way
+                    (self.exported_nodes as f32 / total as f32).min(0.99)
+                }
+            }
+            _ => 1.0,
+        }
+    }
+}
+
+/// A point of a region, in the coordinates of the export.
+#[derive(Debug, Clone, Copy)]
+struct ExportPoint {
+    position: DVec3,
+    color: Vec4,
+    normal: Vec3,
+    /// ASPRS class, 0 when unknown
+    classification: u8,
+}
+
+/// The file of an export, shared by its writer and the task finishing it.
+#[derive(Debug, Clone)]
+struct ExportOutput(Arc<Mutex<ExportFile>>);
+
+#[derive(Debug)]
+enum ExportFile {
+    Memory(Cursor<Vec<u8>>),
+    File(BufWriter<File>),
+}
+
+impl ExportOutput {
+    fn new(target: &ExportTarget) -> Result<Self, ExportError> {
+        let file = match target {
+            ExportTarget::Memory => ExportFile::Memory(Cursor::default()),
+            ExportTarget::File(path) => ExportFile::File(BufWriter::new(File::create(path)?)),
+        };
+        Ok(Self(Arc::new(Mutex::new(file))))
+    }
+
+    fn lock(&self) -> MutexGuard<'_, ExportFile> {
+        self.0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Flushes the file, returning it if it is in memory.
+    fn finish(&self) -> Result<Option<Vec<u8>>, ExportError> {
+        match &mut *self.lock() {
+            ExportFile::Memory(cursor) => Ok(Some(std::mem::take(cursor.get_mut()))),
+            ExportFile::File(file) => {
+                file.flush()?;
+                Ok(None)
+            }
+        }
+    }
+}
+
+impl Write for ExportOutput {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match &mut *self.lock() {
+            ExportFile::Memory(cursor) => cursor.write(buf),
+            ExportFile::File(file) => file.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match &mut *self.lock() {
+            ExportFile::Memory(cursor) => cursor.flush(),
+            ExportFile::File(file) => file.flush(),
+        }
+    }
+}
+
+impl Seek for ExportOutput {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        match &mut *self.lock() {
+            ExportFile::Memory(cursor) => cursor.seek(pos),
+            ExportFile::File(file) => file.seek(pos),
+        }
+    }
+}
+
+/// Writes the points of an export in a format.
+trait PointWriter: Send {
+    fn write(&mut self, points: &[ExportPoint]) -> Result<(), ExportError>;
+
+    /// Completes the file once all the points are written.
+    fn finish(self: Box<Self>) -> Result<(), ExportError>;
+}
+
+fn create_writer(
+    format: ExportFormat,
+    output: ExportOutput,
+    #[cfg_attr(not(feature = "las"), allow(unused))] precision: f64,
+) -> Result<Box<dyn PointWriter>, ExportError> {
+    Ok(match format {
+        #[cfg(feature = "las")]
+        ExportFormat::Las => Box::new(LasPointWriter::new(output, precision, false)),
+        #[cfg(feature = "las")]
+        ExportFormat::Laz => Box::new(LasPointWriter::new(output, precision, true)),
+        ExportFormat::Ply => Box::new(PlyPointWriter::new(output)?),
+    })
+}
+
+/// Writes a binary PLY file, the number of points being written in the header at the end.
+struct PlyPointWriter {
+    output: ExportOutput,
+    count_position: u64,
+    count: u64,
+}
+
+/// Width of the number of points in the PLY header, left padded with zeros.
+const PLY_COUNT_WIDTH: usize = 20;
+
+impl PlyPointWriter {
+    fn new(mut output: ExportOutput) -> Result<Self, ExportError> {
+        output.write_all(b"ply\nformat binary_little_endian 1.0\nelement vertex ")?;
+        let count_position = output.stream_position()?;
+        writeln!(output, "{:0PLY_COUNT_WIDTH$}", 0)?;
+        output.write_all(
+            b"property double x\nproperty double y\nproperty double z\n\
+              property uchar red\nproperty uchar green\nproperty uchar blue\n\
+              property float nx\nproperty float ny\nproperty float nz\n\
+              property uchar class\nend_header\n",
+        )?;
+        Ok(Self {
+            output,
+            count_position,
+            count: 0,
+        })
+    }
+}
+
+impl PointWriter for PlyPointWriter {
+    fn write(&mut self, points: &[ExportPoint]) -> Result<(), ExportError> {
+        let mut bytes = Vec::with_capacity(points.len() * 40);
+        for point in points {
+            for coordinate in point.position.to_array() {
+                bytes.extend_from_slice(&coordinate.to_le_bytes());
+            }
+            for channel in point.color.xyz().to_array() {
+                bytes.push((channel.clamp(0.0, 1.0) * u8::MAX as f32).round() as u8);
+            }
+            for coordinate in point.normal.to_array() {
+                bytes.extend_from_slice(&coordinate.to_le_bytes());
+            }
+            bytes.push(point.classification);
+        }
+        self.output.write_all(&bytes)?;
+        self.count += points.len() as u64;
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<(), ExportError> {
+        self.output.seek(SeekFrom::Start(self.count_position))?;
+        write!(self.output, "{:0PLY_COUNT_WIDTH$}", self.count)?;
+        self.output.seek(SeekFrom::End(0))?;
+        Ok(())
+    }
+}
+
+/// Size of the normal stored after each LAS point, as 3 floats.
+#[cfg(feature = "las")]
+const LAS_NORMAL_EXTRA_BYTES: u16 = 12;
+
+#[cfg(feature = "las")]
+const LAS_OVERLAP_CLASS: u8 = 12;
+
+/// The Extra Bytes record describing the normals of the LAS points.
+#[cfg(feature = "las")]
+fn normal_extra_bytes_vlr() -> las::Vlr {
+    // size of a descriptor, and offsets of its fields
+    const DESCRIPTOR_SIZE: usize = 192;
+    const DATA_TYPE: usize = 2;
+    const NAME: usize = 4;
+    const DESCRIPTION: usize = 160;
+    const FLOAT_TYPE: u8 = 9;
+
+    let mut data = Vec::with_capacity(3 * DESCRIPTOR_SIZE);
+    for axis in ["X", "Y", "Z"] {
+        let mut descriptor = [0; DESCRIPTOR_SIZE];
+        descriptor[DATA_TYPE] = FLOAT_TYPE;
+        let name = format!("Normal{axis}");
+        descriptor[NAME..NAME + name.len()].copy_from_slice(name.as_bytes());
+        let description = format!("{axis} of the unit normal");
+        descriptor[DESCRIPTION..DESCRIPTION + description.len()]
+            .copy_from_slice(description.as_bytes());
+        data.extend_from_slice(&descriptor);
+    }
+
+    las::Vlr {
+        user_id: "LASF_Spec".to_string(),
+        record_id: 4,
+        description: "Extra Bytes Record".to_string(),
+        data,
+    }
+}
+
+/// Writes a LAS or LAZ file, whose offset is taken from the first point.
+#[cfg(feature = "las")]
+struct LasPointWriter {
+    output: Option<ExportOutput>,
+    writer: Option<las::Writer<ExportOutput>>,
+    precision: f64,
+    compressed: bool,
+}
+
+#[cfg(feature = "las")]
+impl LasPointWriter {
+    fn new(output: ExportOutput, precision: f64, compressed: bool) -> Self {
+        Self {
+            output: Some(output),
+            writer: None,
+            precision,
+            compressed,
+        }
+    }
+
+    fn writer(&mut self, offset: DVec3) -> Result<&mut las::Writer<ExportOutput>, ExportError> {
+        if let Some(output) = self.output.take() {
+            // keeps the coordinates of the region within the range of the scaled integers
+            let offset = (offset / 1000.0).floor() * 1000.0;
+            let transform = |offset| las::Transform {
+                scale: self.precision,
+                offset,
+            };
+
+            let mut builder = las::Builder::from((1, 4));
+            // the extended format keeps all the classes, and the normals follow each point
+            builder.point_format = las::point::Format::new(7)?;
+            builder.point_format.extra_bytes = LAS_NORMAL_EXTRA_BYTES;
+            builder.point_format.is_compressed = self.compressed;
+            builder.vlrs.push(normal_extra_bytes_vlr());
+            builder.transforms = las::Vector {
+                x: transform(offset.x),
+                y: transform(offset.y),
+                z: transform(offset.z),
+            };
+            builder.generating_software = "bevy_pointcloud".to_string();
+            self.writer = Some(las::Writer::new(output, builder.into_header()?)?);
+        }
+        Ok(self.writer.as_mut().expect("The las writer is created"))
+    }
+}
+
+#[cfg(feature = "las")]
+impl PointWriter for LasPointWriter {
+    fn write(&mut self, points: &[ExportPoint]) -> Result<(), ExportError> {
+        let Some(first) = points.first() else {
+            return Ok(());
+        };
+        let writer = self.writer(first.position)?;
+        for point in points {
+            let [red, green, blue] = point
+                .color
+                .xyz()
+                .to_array()
+                .map(|channel| (channel.clamp(0.0, 1.0) * u16::MAX as f32).round() as u16);
+            writer.write_point(las::Point {
+                x: point.position.x,
+                y: point.position.y,
+                z: point.position.z,
+                // the overlap class of the legacy formats is a flag of the extended ones
+                classification: las::point::Classification::new(point.classification)
+                    .unwrap_or_default(),
+                is_overlap: point.classification == LAS_OVERLAP_CLASS,
+                gps_time: Some(0.0),
+                color: Some(las::Color::new(red, green, blue)),
+                extra_bytes: point
+                    .normal
+                    .to_array()
+                    .iter()
+                    .flat_map(|coordinate| coordinate.to_le_bytes())
+                    .collect(),
+                ..Default::default()
+            })?;
+        }
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<(), ExportError> {
+        // an empty file
+        self.writer(DVec3::ZERO)?.close()?;
+        Ok(())
+    }
+}
+
+/// The points kept by the clip volume of an export.
+struct ExportRegion(Option<GlobalClipVolume>);
+
+impl ExportRegion {
+    fn contains_point(&self, world_position: Vec3) -> bool {
+        self.0.as_ref().is_none_or(|volume| {
+            volume.contains_point(world_position) != (volume.mode == ClipMode::Outside)
+        })
+    }
+}
+
+impl QueryRegion for ExportRegion {
+    fn intersects_obb(&self, aabb: &Aabb, world_from_local: &Affine3A) -> bool {
+        self.0.as_ref().is_none_or(|volume| match volume.mode {
+            ClipMode::Outside => !volume.contains_obb(aabb, world_from_local),
+            ClipMode::Inside | ClipMode::Highlight => volume.intersects_obb(aabb, world_from_local),
+        })
+    }
+}
+
+/// The writing state of a [`PointCloudExport`].
+#[derive(Component)]
+struct ExportTask {
+    state: ExportTaskState,
+    output: ExportOutput,
+    /// The point clouds and octree nodes whose points have been exported
+    exported: HashSet<(Entity, Option<NodeId>)>,
+    /// The points waiting for the writer
+    pending: Vec<ExportPoint>,
+    /// Number of points being written
+    writing: u64,
+}
+
+enum ExportTaskState {
+    Idle(SyncCell<Box<dyn PointWriter>>),
+    Writing(Task<Result<Box<dyn PointWriter>, ExportError>>),
+    Finishing(Task<Result<Option<Vec<u8>>, ExportError>>),
+    Done,
+}
+
+fn start_exports(
+    mut commands: Commands,
+    mut exports: Query<
+        (Entity, &PointCloudExport, &mut PointCloudExportProgress),
+        Added<PointCloudExport>,
+    >,
+) {
+    for (entity, export, mut progress) in &mut exports {
+        let task = ExportOutput::new(&export.target).and_then(|output| {
+            let writer = create_writer(export.format, output.clone(), export.precision)?;
+            Ok(ExportTask {
+                state: ExportTaskState::Idle(SyncCell::new(writer)),
+                output,
+                exported: HashSet::default(),
+                pending: Vec::new(),
+                writing: 0,
+            })
+        });
+        *progress = PointCloudExportProgress::default();
+        match task {
+            Ok(task) => {
+                commands.entity(entity).insert(task);
+            }
+            Err(err) => progress.status = ExportStatus::Failed(Arc::new(err)),
+        }
+    }
+}
+
+fn update_exports(
+    mut exports: Query<(
+        &PointCloudExport,
+        &mut PointCloudExportProgress,
+        &mut ExportTask,
+    )>,
+    clip_volumes: Query<(&PointCloudClipVolume, &GlobalTransform)>,
+    mut query: PointCloudRegionQuery,
+) {
+    #[cfg(feature = "trace")]
+    let _span = info_span!("update_exports").entered();
+
+    for (export, mut progress, mut task) in &mut exports {
+        let task = &mut *task;
+
+        // collects the results of the tasks
+        match &mut task.state {
+            ExportTaskState::Writing(writing) => {
+                if let Some(result) = check_ready(writing) {
+                    match result {
+                        Ok(writer) => {
+                            progress.points += task.writing;
+                            task.writing = 0;
+                            task.state = ExportTaskState::Idle(SyncCell::new(writer));
+                        }
+                        Err(err) => {
+                            progress.status = ExportStatus::Failed(Arc::new(err));
+                            task.state = ExportTaskState::Done;
+                        }
+                    }
+                }
+            }
+            ExportTaskState::Finishing(finishing) => {
+                if let Some(result) = check_ready(finishing) {
+                    progress.status = match result {
+                        Ok(file) => ExportStatus::Finished(file.map(Arc::new)),
+                        Err(err) => ExportStatus::Failed(Arc::new(err)),
+                    };
+                    task.state = ExportTaskState::Done;
+                }
+                continue;
+            }
+            ExportTaskState::Idle(_) => {}
+            ExportTaskState::Done => continue,
+        }
+
+        let region = match export.clip_volume {
+            Some(clip_volume) => match clip_volumes.get(clip_volume) {
+                Ok((clip_volume, global_transform)) => {
+                    ExportRegion(Some(GlobalClipVolume::new(clip_volume, global_transform)))
+                }
+                Err(_) => {
+                    progress.status =
+                        ExportStatus::Failed(Arc::new(ExportError::MissingClipVolume));
+                    task.state = ExportTaskState::Done;
+                    continue;
+                }
+            },
+            None => ExportRegion(None),
+        };
+
+        let visited = RefCell::new(Vec::new());
+        let settings = RegionQuerySettings {
+            max_depth: export.max_depth,
+            min_spacing: export.min_spacing,
+            load_missing_nodes: true,
+            respect_clip_volumes: export.respect_clip_volumes,
+            visit_points: true,
+            filter: &|entity| export.entities.is_empty() || export.entities.contains(&entity),
+            skip_points: &|entity, node_id| {
+                let exported = task.exported.contains(&(entity, node_id));
+                if !exported {
+                    visited.borrow_mut().push((entity, node_id));
+                }
+                exported
+            },
+        };
+        let mut points = Vec::new();
+        let status = query.query(&region, &settings, |point| {
+            if !region.contains_point(point.world_position) {
+                return;
+            }
+            points.push(ExportPoint {
+                position: match export.coordinates {
+                    ExportCoordinates::Source => point.point.position.truncate().as_dvec3(),
+                    ExportCoordinates::World => point.world_position.as_dvec3(),
+                },
+                color: point.point.color,
+                normal: point.normal,
+                classification: point.classification.unwrap_or_default(),
+            });
+        });
+        task.exported.extend(visited.into_inner());
+        task.pending.append(&mut points);
+        progress.exported_nodes = task.exported.len();
+        progress.missing_nodes = status.missing_nodes;
+
+        // writes the pending points, then finishes the file once the region is loaded
+        if !matches!(task.state, ExportTaskState::Idle(_)) {
+            continue;
+        }
+        let finish = task.pending.is_empty() && status.complete();
+        if !finish && task.pending.is_empty() {
+            continue;
+        }
+        let ExportTaskState::Idle(writer) =
+            std::mem::replace(&mut task.state, ExportTaskState::Done)
+        else {
+            unreachable!();
+        };
+        let mut writer = SyncCell::to_inner(writer);
+        if finish {
+            let output = task.output.clone();
+            task.state = ExportTaskState::Finishing(IoTaskPool::get().spawn(async move {
+                writer.finish()?;
+                output.finish()
+            }));
+        } else {
+            let points = std::mem::take(&mut task.pending);
+            task.writing = points.len() as u64;
+            task.state = ExportTaskState::Writing(IoTaskPool::get().spawn(async move {
+                writer.write(&points)?;
+                Ok(writer)
+            }));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn points() -> Vec<ExportPoint> {
+        vec![
+            ExportPoint {
+                position: DVec3::new(1000.25, -20.5, 3.125),
+                color: Vec4::new(1.0, 0.0, 0.5, 1.0),
+                normal: Vec3::Y,
+                classification: 2,
+            },
+            ExportPoint {
+                position: DVec3::new(1001.0, -21.0, 4.0),
+                color: Vec4::new(0.0, 1.0, 0.0, 1.0),
+                normal: Vec3::ZERO,
+                classification: 0,
+            },
+        ]
+    }
+
+    /// Writes the points in two batches, returning the file.
+    fn export(writer: impl FnOnce(ExportOutput) -> Box<dyn PointWriter>) -> Vec<u8> {
+        let output = ExportOutput::new(&ExportTarget::Memory).unwrap();
+        let mut writer = writer(output.clone());
+        let points = points();
+        writer.write(&points[..1]).unwrap();
+        writer.write(&points[1..]).unwrap();
+        writer.finish().unwrap();
+        output.finish().unwrap().unwrap()
+    }
+
+    #[test]
+    fn ply_round_trip() {
+        let bytes = export(|output| Box::new(PlyPointWriter::new(output).unwrap()));
+
+        let end = b"end_header\n";
+        let header_size = bytes
+            .windows(end.len())
+            .position(|window| window == end)
+            .unwrap()
+            + end.len();
+        let header = std::str::from_utf8(&bytes[..header_size]).unwrap();
+        let count: usize = header
+            .lines()
+            .find_map(|line| line.strip_prefix("element vertex "))
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert_eq!(count, 2);
+        assert_eq!(header.matches("property ").count(), 10);
+        assert!(header.contains("property uchar class\n"));
+
+        let rows = &bytes[header_size..];
+        assert_eq!(rows.len(), count * 40);
+        for (row, point) in rows.chunks_exact(40).zip(points()) {
+            let double =
+                |offset: usize| f64::from_le_bytes(row[offset..offset + 8].try_into().unwrap());
+            let float =
+                |offset: usize| f32::from_le_bytes(row[offset..offset + 4].try_into().unwrap());
+            assert_eq!(DVec3::new(double(0), double(8), double(16)), point.position);
+            assert_eq!(
+                &row[24..27],
+                &point
+                    .color
+                    .xyz()
+                    .to_array()
+                    .map(|channel| (channel * 255.0).round() as u8)
+            );
+            assert_eq!(Vec3::new(float(27), float(31), float(35)), point.normal);
+            assert_eq!(row[39], point.classification);
+        }
+    }
+
+    #[cfg(feature = "las")]
+    #[test]
+    fn las_round_trip() {
+        for compressed in [false, true] {
+            let bytes = export(|output| Box::new(LasPointWriter::new(output, 0.001, compressed)));
+
+            let mut reader = las::Reader::new(Cursor::new(bytes)).unwrap();
+            assert_eq!(reader.header().number_of_points(), 2);
+            assert_eq!(
+                reader.header().point_format().extra_bytes,
+                LAS_NORMAL_EXTRA_BYTES
+            );
+            let read: Vec<las::Point> = reader.points().map(Result::unwrap).collect();
+            for (read, point) in read.iter().zip(points()) {
+                let position = DVec3::new(read.x, read.y, read.z);
+                assert!(position.distance(point.position) < 1e-6, "{position}");
+                let color = read.color.unwrap();
+                assert_eq!(
+                    [color.red, color.green, color.blue],
+                    point
+                        .color
+                        .xyz()
+                        .to_array()
+                        .map(|channel| (channel * u16::MAX as f32).round() as u16)
+                );
+                assert_eq!(u8::from(read.classification), point.classification);
+                let normal: Vec<f32> = read
+                    .extra_bytes
+                    .chunks_exact(4)
+                    .map(|bytes| f32::from_le_bytes(bytes.try_into().unwrap()))
+                    .collect();
+                assert_eq!(normal, point.normal.to_array());
+            }
+        }
+    }
+
+    #[cfg(feature = "las")]
+    #[test]
+    fn las_extra_bytes_record() {
+        let vlr = normal_extra_bytes_vlr();
+        assert_eq!(vlr.data.len(), 3 * 192);
+        let names: Vec<_> = vlr
+            .data
+            .chunks_exact(192)
+            .map(|descriptor| {
+                assert_eq!(descriptor[2], 9);
+                std::str::from_utf8(&descriptor[4..36])
+                    .unwrap()
+                    .trim_end_matches('\0')
+                    .to_string()
+            })
+            .collect();
+        assert_eq!(names, ["NormalX", "NormalY", "NormalZ"]);
+    }
+}
@@ -8,9 +8,15 @@ use crate::{
 };
 
 pub mod bevy;
+pub mod clip_volume;
+pub mod elevation;
+pub mod export;
 pub mod loader;
+pub mod measurement;
 pub mod octree;
 pub mod octree_loader;
+#[cfg(feature = "picking")]
+pub mod picking;
 pub mod point_cloud;
 pub mod point_cloud_material;
 pub mod pointcloud_octree;
@@ -24,7 +30,11 @@ pub mod pointcloud_octree;
 ))]
 pub mod potree;
 pub mod prelude;
+pub mod profile;
+mod query;
+pub mod raycast;
 pub mod render;
+pub mod volume;
 
 pub struct PointCloudPlugin;
 
@@ -38,7 +48,11 @@ impl Plugin for PointCloudPlugin {
             .init_asset::<PointCloudMaterial>()
             .register_asset_reflect::<PointCloud>()
             .register_asset_reflect::<PointCloudMaterial>();
-        app.add_plugins(render::RenderPipelinePlugin);
+        app.add_plugins((
+            clip_volume::ClipVolumePlugin,
+            raycast::PointCloudRayCastPlugin,
+            render::RenderPipelinePlugin,
+        ));
 
         app.world_mut()
             .register_component_hooks::<PointCloud3d>()
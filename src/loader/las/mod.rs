@@ -31,7 +31,14 @@ pub enum LasLoaderError {
 }
 
 #[derive(Default, Serialize, Deserialize)]
-pub struct LasLoaderSettings {}
+pub struct LasLoaderSettings {
+    /// Sort points spatially so that the point cloud can be partially culled when rendering.
+    /// Disabled by default, as it changes the order of the points from the file
+    pub sort_spatially: bool,
+    /// Number of neighbours used to compute the size of each point,
+    /// to be rendered with [`PointSizing::Adaptive`](crate::point_cloud_material::PointSizing::Adaptive)
+    pub adaptive_point_sizes: Option<usize>,
+}
 
 #[derive(TypePath)]
 pub struct LasLoader {}
@@ -44,7 +51,7 @@ impl AssetLoader for LasLoader {
     async fn load(
         &self,
         reader: &mut dyn Reader,
-        _settings: &Self::Settings,
+        settings: &Self::Settings,
         _load_context: &mut LoadContext<'_>,
     ) -> Result<PointCloud, Self::Error> {
         // reader.read_to_end()
@@ -54,12 +61,13 @@ impl AssetLoader for LasLoader {
         let reader = Cursor::new(las_data);
 
         let mut points = Vec::new();
+        let mut classifications = Vec::new();
         let mut las_reader = las::Reader::new(reader)?;
 
         let mut min = Vec3::new(f32::MAX, f32::MAX, f32::MAX);
         let mut max = Vec3::new(f32::MIN, f32::MIN, f32::MIN);
 
-        las_reader.points().into_iter().for_each(|point| {
+        las_reader.points().for_each(|point| {
             let point = point.unwrap();
             let vec = Vec3::new(point.x as f32, point.y as f32, point.z as f32);
 
@@ -84,12 +92,21 @@ impl AssetLoader for LasLoader {
                         1.0,
                     ],
                 });
+                classifications.push(u8::from(point.classification));
             }
         }
 
         info!("Loaded point cloud with {} points", points.len());
 
-        Ok(PointCloud { points })
+        let mut point_cloud = PointCloud::new(points).with_classifications(classifications);
+        if settings.sort_spatially {
+            point_cloud.sort_spatially();
+        }
+        if let Some(k) = settings.adaptive_point_sizes {
+            point_cloud.compute_adaptive_point_sizes(k);
+        }
+
+        Ok(point_cloud)
     }
 
     fn extensions(&self) -> &[&str] {
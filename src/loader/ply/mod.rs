@@ -23,23 +23,36 @@ impl Plugin for PlyLoaderPlugin {
     }
 }
 
-impl PropertyAccess for PointCloudData {
+/// A vertex of a PLY file, with its optional normal.
+struct PlyVertex {
+    point: PointCloudData,
+    normal: Vec3,
+}
+
+impl PropertyAccess for PlyVertex {
     fn new() -> Self {
-        PointCloudData {
-            position: Vec3::ZERO,
-            point_size: 1.0,
-            color: [1.0, 1.0, 1.0, 1.0],
+        PlyVertex {
+            point: PointCloudData {
+                position: Vec3::ZERO,
+                point_size: 1.0,
+                color: [1.0, 1.0, 1.0, 1.0],
+            },
+            normal: Vec3::ZERO,
         }
     }
 
     fn set_property(&mut self, key: String, property: Property) {
+        let point = &mut self.point;
         match (key.as_ref(), property) {
-            ("x", Property::Float(v)) => self.position[0] = v,
-            ("y", Property::Float(v)) => self.position[1] = v,
-            ("z", Property::Float(v)) => self.position[2] = v,
-            ("red", Property::UChar(v)) => self.color[0] = v as f32 / u8::MAX as f32,
-            ("green", Property::UChar(v)) => self.color[1] = v as f32 / u8::MAX as f32,
-            ("blue", Property::UChar(v)) => self.color[2] = v as f32 / u8::MAX as f32,
+            ("x", Property::Float(v)) => point.position[0] = v,
+            ("y", Property::Float(v)) => point.position[1] = v,
+            ("z", Property::Float(v)) => point.position[2] = v,
+            ("red", Property::UChar(v)) => point.color[0] = v as f32 / u8::MAX as f32,
+            ("green", Property::UChar(v)) => point.color[1] = v as f32 / u8::MAX as f32,
+            ("blue", Property::UChar(v)) => point.color[2] = v as f32 / u8::MAX as f32,
+            ("nx", Property::Float(v)) => self.normal[0] = v,
+            ("ny", Property::Float(v)) => self.normal[1] = v,
+            ("nz", Property::Float(v)) => self.normal[2] = v,
             (_, _) => {
                 // bevy_log::warn!("Unhandled PLY property: {} {:?}", key, v);
             }
@@ -59,7 +72,14 @@ pub enum PlyLoaderError {
 }
 
 #[derive(Default, Serialize, Deserialize)]
-pub struct PlyLoaderSettings {}
+pub struct PlyLoaderSettings {
+    /// Sort points spatially so that the point cloud can be partially culled when rendering.
+    /// Disabled by default, as it changes the order of the points from the file
+    pub sort_spatially: bool,
+    /// Number of neighbours used to compute the size of each point,
+    /// to be rendered with [`PointSizing::Adaptive`](crate::point_cloud_material::PointSizing::Adaptive)
+    pub adaptive_point_sizes: Option<usize>,
+}
 
 #[derive(TypePath)]
 pub struct PlyLoader {}
@@ -72,7 +92,7 @@ impl AssetLoader for PlyLoader {
     async fn load(
         &self,
         reader: &mut dyn Reader,
-        _settings: &Self::Settings,
+        settings: &Self::Settings,
         _load_context: &mut LoadContext<'_>,
     ) -> Result<PointCloud, Self::Error> {
         let mut bytes = Vec::new();
@@ -81,10 +101,11 @@ impl AssetLoader for PlyLoader {
         let cursor = Cursor::new(bytes);
         let mut f = BufReader::new(cursor);
 
-        let parser = Parser::<PointCloudData>::new();
+        let parser = Parser::<PlyVertex>::new();
         let header = parser.read_header(&mut f)?;
 
         let mut cloud = Vec::new();
+        let mut has_normals = false;
 
         let required_properties = ["x", "y", "z", "red", "green", "blue"];
         let mut required_property_count = required_properties.len();
@@ -102,12 +123,27 @@ impl AssetLoader for PlyLoader {
                     )));
                 }
 
+                has_normals = ["nx", "ny", "nz"]
+                    .iter()
+                    .any(|key| element.properties.contains_key(*key));
                 cloud = parser.read_payload_for_element(&mut f, element, &header)?;
             }
         }
         bevy_log::info!("Loaded point cloud with {} points", cloud.len());
 
-        Ok(PointCloud { points: cloud })
+        let mut point_cloud = PointCloud::new(cloud.iter().map(|vertex| vertex.point).collect());
+        if has_normals {
+            point_cloud =
+                point_cloud.with_normals(cloud.iter().map(|vertex| vertex.normal).collect());
+        }
+        if settings.sort_spatially {
+            point_cloud.sort_spatially();
+        }
+        if let Some(k) = settings.adaptive_point_sizes {
+            point_cloud.compute_adaptive_point_sizes(k);
+        }
+
+        Ok(point_cloud)
     }
 
     fn extensions(&self) -> &[&str] {
@@ -0,0 +1,505 @@
+//! Measurements on the point clouds: distances, polyline lengths, areas, height differences and
+//! angles.
+//!
+//! A [`PointCloudMeasurement`] holds the points of a measurement, usually snapped to the points
+//! picked with [`PointCloudRayCast`](crate::raycast::PointCloudRayCast) or the GPU picking pass.
+//! Its [`MeasurementResult`] is computed in double precision, in the coordinates of the point
+//! cloud data its first point is snapped to, and the measurements are drawn with the
+//! [`MeasurementGizmos`].
+//!
+//! Gizmos can't draw text: [`MeasurementResult::label_position`] and the [`Display`] of
+//! [`MeasurementValue`] are meant to be used by the UI.
+
+use std::fmt::{self, Display};
+
+use bevy_app::prelude::*;
+use bevy_color::Color;
+use bevy_ecs::prelude::*;
+use bevy_gizmos::{config::GizmoConfigGroup, prelude::*};
+use bevy_math::{prelude::*, DAffine3, DVec3};
+use bevy_reflect::{std_traits::ReflectDefault, Reflect};
+use bevy_transform::prelude::*;
+
+use crate::{raycast::PointCloudRayHit, render::picking_pass::PointCloudGpuHit};
+
+/// Computes and draws the [`PointCloudMeasurement`]s.
+///
+/// Requires the gizmos plugin.
+pub struct PointCloudMeasurementPlugin;
+
+impl Plugin for PointCloudMeasurementPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<PointCloudMeasurement>()
+            .register_type::<MeasurementResult>()
+            .init_gizmo_group::<MeasurementGizmos>()
+            .add_systems(
+                PostUpdate,
+                (update_measurements, draw_measurements)
+                    .chain()
+                    .after(TransformSystems::Propagate),
+            );
+    }
+}
+
+/// The gizmos drawing the [`PointCloudMeasurement`]s.
+#[derive(Reflect, GizmoConfigGroup)]
+#[reflect(Default)]
+pub struct MeasurementGizmos {
+    /// Radius of the spheres drawn on the measured points, in world units
+    pub point_radius: f32,
+}
+
+impl Default for MeasurementGizmos {
+    fn default() -> Self {
+        Self { point_radius: 0.05 }
+    }
+}
+
+#[derive(Reflect, Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum MeasurementKind {
+    /// Distance between the first two points.
+    #[default]
+    Distance,
+    /// Length of the line going through all the points.
+    Polyline,
+    /// Area of the polygon formed by the points.
+    Area,
+    /// Vertical difference from the first point to the second one.
+    Height,
+    /// Angle at the second point between the first and third ones.
+    Angle,
+}
+
+impl MeasurementKind {
+    /// Number of points needed to compute the measurement.
+    pub fn min_points(&self) -> usize {
+        match self {
+            MeasurementKind::Distance | MeasurementKind::Polyline | MeasurementKind::Height => 2,
+            MeasurementKind::Area | MeasurementKind::Angle => 3,
+        }
+    }
+}
+
+/// A measured point.
+#[derive(Reflect, Debug, Clone, Copy, PartialEq)]
+pub struct MeasurementPoint {
+    /// The point cloud or octree entity the point is snapped to, `None` for a point in world
+    /// space
+    pub entity: Option<Entity>,
+    /// Position in the local space of the entity, which is the space of its point data.
+    ///
+    /// The point data is stored in `f32`, so a snapped point only has its precision: the
+    /// measurements are computed in `f64` to keep it, not to improve it.
+    pub position: DVec3,
+}
+
+impl MeasurementPoint {
+    /// A point in world space, not snapped to a point cloud.
+    pub fn world(position: Vec3) -> Self {
+        Self {
+            entity: None,
+            position: position.as_dvec3(),
+        }
+    }
+}
+
+impl From<&PointCloudRayHit> for MeasurementPoint {
+    fn from(hit: &PointCloudRayHit) -> Self {
+        Self {
+            entity: Some(hit.entity),
+            position: hit.point.position.truncate().as_dvec3(),
+        }
+    }
+}
+
+impl From<&PointCloudGpuHit> for MeasurementPoint {
+    fn from(hit: &PointCloudGpuHit) -> Self {
+        Self {
+            entity: Some(hit.entity),
+            position: hit.point.position.truncate().as_dvec3(),
+        }
+    }
+}
+
+/// A measurement between points of the point clouds.
+#[derive(Component, Reflect, Debug, Clone, Default)]
+#[reflect(Component, Default, Clone)]
+#[require(MeasurementResult)]
+pub struct PointCloudMeasurement {
+    pub kind: MeasurementKind,
+    pub points: Vec<MeasurementPoint>,
+    pub color: Color,
+}
+
+impl PointCloudMeasurement {
+    pub fn new(kind: MeasurementKind) -> Self {
+        Self {
+            kind,
+            points: Vec::new(),
+            color: Color::srgb(1.0, 0.8, 0.0),
+        }
+    }
+
+    pub fn with_points(mut self, points: impl IntoIterator<Item = MeasurementPoint>) -> Self {
+        self.points.extend(points);
+        self
+    }
+
+    pub fn with_color(mut self, color: impl Into<Color>) -> Self {
+        self.color = color.into();
+        self
+    }
+
+    /// Appends a point, for instance from a [`PointCloudRayHit`] or a [`PointCloudGpuHit`].
+    pub fn push(&mut self, point: impl Into<MeasurementPoint>) {
+        self.points.push(point.into());
+    }
+}
+
+/// The value of a [`PointCloudMeasurement`], in the units of the point data.
+///
+/// The vertical direction is the world up axis, expressed in the space of the point data.
+#[derive(Reflect, Debug, Clone, PartialEq)]
+pub enum MeasurementValue {
+    Distance {
+        distance: f64,
+        horizontal: f64,
+        vertical: f64,
+    },
+    Polyline {
+        length: f64,
+        /// length of each segment
+        segments: Vec<f64>,
+    },
+    Area {
+        /// area of the polygon in its mean plane
+        area: f64,
+        /// area of the polygon projected on the horizontal plane
+        horizontal_area: f64,
+        perimeter: f64,
+    },
+    Height {
+        /// positive when the second point is above the first one
+        height: f64,
+    },
+    Angle {
+        /// in radians
+        angle: f64,
+    },
+}
+
+impl Display for MeasurementValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MeasurementValue::Distance {
+                distance,
+                horizontal,
+                vertical,
+            } => write!(f, "{distance:.3} (h {horizontal:.3}, v {vertical:.3})"),
+            MeasurementValue::Polyline { length, .. } => write!(f, "{length:.3}"),
+            MeasurementValue::Area {
+                area,
+                horizontal_area,
+                ..
+            } => write!(f, "{area:.3} (h {horizontal_area:.3})"),
+            MeasurementValue::Height { height } => write!(f, "{height:+.3}"),
+            MeasurementValue::Angle { angle } => write!(f, "{:.2}°", angle.to_degrees()),
+        }
+    }
+}
+
+/// The result of a [`PointCloudMeasurement`], updated after the transforms are propagated.
+#[derive(Component, Reflect, Debug, Clone, Default)]
+#[reflect(Component, Default, Clone)]
+pub struct MeasurementResult {
+    /// The entity whose point data the measurement is computed in, `None` for world space
+    pub frame: Option<Entity>,
+    /// Positions of the points in the space of the frame
+    pub positions: Vec<DVec3>,
+    /// Positions of the points in world space
+    pub world_positions: Vec<Vec3>,
+    /// `None` if there are not enough points, if an entity of a point has been despawned, or if
+    /// the angle is undefined because a point is on its vertex
+    pub value: Option<MeasurementValue>,
+    /// Where to display the value, in world space
+    pub label_position: Option<Vec3>,
+}
+
+/// The point positions of a measurement, in the space of the frame.
+struct MeasurementFrame<'a> {
+    positions: &'a [DVec3],
+    /// unit vertical direction
+    up: DVec3,
+}
+
+impl MeasurementFrame<'_> {
+    fn distance(&self, a: DVec3, b: DVec3) -> MeasurementValue {
+        let delta = b - a;
+        let vertical = delta.dot(self.up);
+        MeasurementValue::Distance {
+            distance: delta.length(),
+            horizontal: (delta - self.up * vertical).length(),
+            vertical: vertical.abs(),
+        }
+    }
+
+    fn polyline(&self) -> MeasurementValue {
+        let segments = self
+            .positions
+            .windows(2)
+            .map(|segment| segment[0].distance(segment[1]))
+            .collect::<Vec<_>>();
+        MeasurementValue::Polyline {
+            length: segments.iter().sum(),
+            segments,
+        }
+    }
+
+    fn area(&self) -> MeasurementValue {
+        let origin = self.positions[0];
+        // Newell's method, relative to the first point to keep the precision far from the origin
+        let normal = self
+            .positions
+            .windows(2)
+            .map(|edge| (edge[0] - origin).cross(edge[1] - origin))
+            .sum::<DVec3>();
+        let perimeter = self
+            .positions
+            .iter()
+            .zip(self.positions.iter().cycle().skip(1))
+            .map(|(a, b)| a.distance(*b))
+            .sum();
+        MeasurementValue::Area {
+            area: normal.length() / 2.0,
+            horizontal_area: normal.dot(self.up).abs() / 2.0,
+            perimeter,
+        }
+    }
+
+    fn value(&self, kind: MeasurementKind) -> Option<MeasurementValue> {
+        if self.positions.len() < kind.min_points() {
+            return None;
+        }
+
+        let p = self.positions;
+        Some(match kind {
+            MeasurementKind::Distance => self.distance(p[0], p[1]),
+            MeasurementKind::Polyline => self.polyline(),
+            MeasurementKind::Area => self.area(),
+            MeasurementKind::Height => MeasurementValue::Height {
+                height: (p[1] - p[0]).dot(self.up),
+            },
+            MeasurementKind::Angle => {
+                let (a, b) = (p[0] - p[1], p[2] - p[1]);
+                // the angle is undefined when a point is on the vertex
+                if a == DVec3::ZERO || b == DVec3::ZERO {
+                    return None;
+                }
+                MeasurementValue::Angle {
+                    angle: a.angle_between(b),
+                }
+            }
+        })
+    }
+}
+
+fn update_measurements(
+    mut measurements: Query<(&PointCloudMeasurement, &mut MeasurementResult)>,
+    transforms: Query<&GlobalTransform>,
+) {
+    for (measurement, mut result) in &mut measurements {
+        let result = result.as_mut();
+        result.positions.clear();
+        result.world_positions.clear();
+        result.value = None;
+        result.label_position = None;
+
+        let world_from_local = |entity: Option<Entity>| match entity {
+            Some(entity) => transforms
+                .get(entity)
+                .ok()
+                .map(|transform| transform.affine().as_daffine3()),
+            None => Some(DAffine3::IDENTITY),
+        };
+
+        result.frame = measurement.points.first().and_then(|point| point.entity);
+        let Some(frame_from_world) = world_from_local(result.frame).map(|world| world.inverse())
+        else {
+            continue;
+        };
+
+        let mut resolved = true;
+        for point in &measurement.points {
+            let Some(world_from_local) = world_from_local(point.entity) else {
+                resolved = false;
+                break;
+            };
+            let world_position = world_from_local.transform_point3(point.position);
+            result
+                .positions
+                .push(frame_from_world.transform_point3(world_position));
+            result.world_positions.push(world_position.as_vec3());
+        }
+        if !resolved {
+            result.positions.clear();
+            result.world_positions.clear();
+            continue;
+        }
+
+        let frame = MeasurementFrame {
+            positions: &result.positions,
+            up: frame_from_world
+                .transform_vector3(DVec3::Y)
+                .normalize_or_zero(),
+        };
+        result.value = frame.value(measurement.kind);
+        if result.value.is_none() {
+            continue;
+        }
+
+        let world_positions = &result.world_positions;
+        result.label_position = Some(match measurement.kind {
+            MeasurementKind::Distance | MeasurementKind::Height => {
+                world_positions[0].midpoint(world_positions[1])
+            }
+            MeasurementKind::Polyline => world_positions[world_positions.len() - 1],
+            MeasurementKind::Area => {
+                world_positions.iter().sum::<Vec3>() / world_positions.len() as f32
+            }
+            MeasurementKind::Angle => world_positions[1],
+        });
+    }
+}
+
+fn draw_measurements(
+    mut gizmos: Gizmos<MeasurementGizmos>,
+    measurements: Query<(&PointCloudMeasurement, &MeasurementResult)>,
+) {
+    let point_radius = gizmos.config_ext.point_radius;
+
+    for (measurement, result) in &measurements {
+        let color = measurement.color;
+        let positions = &result.world_positions;
+        for position in positions {
+            gizmos.sphere(*position, point_radius, color);
+        }
+        if positions.len() < measurement.kind.min_points() {
+            // draw the points placed so far
+            gizmos.linestrip(positions.iter().copied(), color);
+            continue;
+        }
+
+        match measurement.kind {
+            MeasurementKind::Distance => {
+                gizmos.line(positions[0], positions[1], color);
+            }
+            MeasurementKind::Polyline => {
+                gizmos.linestrip(positions.iter().copied(), color);
+            }
+            MeasurementKind::Area => {
+                gizmos.linestrip(positions.iter().chain(positions.first()).copied(), color);
+            }
+            MeasurementKind::Height => {
+                // the vertical leg from the first point, then the horizontal one
+                let corner = positions[0].with_y(positions[1].y);
+                gizmos.linestrip([positions[0], corner, positions[1]], color);
+            }
+            MeasurementKind::Angle => {
+                let (a, center, b) = (positions[0], positions[1], positions[2]);
+                gizmos.linestrip([a, center, b], color);
+                let radius = a.distance(center).min(b.distance(center)) / 4.0;
+                gizmos.short_arc_3d_between(
+                    center,
+                    center + (a - center).normalize_or_zero() * radius,
+                    center + (b - center).normalize_or_zero() * radius,
+                    color,
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn value(kind: MeasurementKind, positions: &[DVec3]) -> Option<MeasurementValue> {
+        MeasurementFrame {
+            positions,
+            up: DVec3::Y,
+        }
+        .value(kind)
+    }
+
+    #[test]
+    fn distance_splits_horizontal_and_vertical() {
+        let Some(MeasurementValue::Distance {
+            distance,
+            horizontal,
+            vertical,
+        }) = value(
+            MeasurementKind::Distance,
+            &[DVec3::new(1.0, 2.0, 3.0), DVec3::new(4.0, -2.0, 3.0)],
+        )
+        else {
+            panic!("no distance");
+        };
+        assert_eq!(distance, 5.0);
+        assert_eq!(horizontal, 3.0);
+        assert_eq!(vertical, 4.0);
+
+        assert_eq!(value(MeasurementKind::Distance, &[DVec3::ZERO]), None);
+    }
+
+    #[test]
+    fn area_of_a_tilted_square() {
+        // a unit square far from the origin, tilted by 60 degrees around the X axis
+        let origin = DVec3::new(1e6, 1e3, -1e6);
+        let (sin, cos) = 60.0_f64.to_radians().sin_cos();
+        let side = DVec3::new(0.0, sin, cos);
+        let positions = [
+            origin,
+            origin + DVec3::X,
+            origin + DVec3::X + side,
+            origin + side,
+        ];
+        let Some(MeasurementValue::Area {
+            area,
+            horizontal_area,
+            perimeter,
+        }) = value(MeasurementKind::Area, &positions)
+        else {
+            panic!("no area");
+        };
+        assert!((area - 1.0).abs() < 1e-6);
+        assert!((horizontal_area - cos).abs() < 1e-6);
+        assert!((perimeter - 4.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn angle_at_the_second_point() {
+        let Some(MeasurementValue::Angle { angle }) = value(
+            MeasurementKind::Angle,
+            &[DVec3::X, DVec3::ZERO, DVec3::new(1.0, 1.0, 0.0)],
+        ) else {
+            panic!("no angle");
+        };
+        assert!((angle - std::f64::consts::FRAC_PI_4).abs() < 1e-12);
+
+        // undefined when a point is on the vertex
+        assert_eq!(
+            value(
+                MeasurementKind::Angle,
+                &[DVec3::ZERO, DVec3::ZERO, DVec3::X]
+            ),
+            None
+        );
+        assert_eq!(
+            value(
+                MeasurementKind::Angle,
+                &[DVec3::X, DVec3::ZERO, DVec3::ZERO]
+            ),
+            None
+        );
+    }
+}
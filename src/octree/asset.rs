@@ -42,11 +42,16 @@ pub struct Octree<T: NodeData> {
     pub(crate) added_nodes: Vec<NodeId>,
     /// Contains nodes whose data has just been added
     pub(crate) added_nodes_data: Vec<NodeId>,
+    /// Contains nodes whose data has just been replaced
     pub(crate) modified_nodes: Vec<NodeId>,
+    /// Size difference of the data of the modified nodes
+    pub(crate) modified_size: isize,
     /// Contains removed nodes
     pub(crate) removed_nodes: Vec<NodeId>,
     /// Contains nodes whose data has just been removed
     pub(crate) removed_nodes_data: Vec<NodeId>,
+    /// Incremented each time the data of a node is added, replaced or removed
+    pub(crate) data_generation: u64,
 }
 
 impl<T: NodeData> Default for Octree<T> {
@@ -57,8 +62,10 @@ impl<T: NodeData> Default for Octree<T> {
             added_nodes: Default::default(),
             added_nodes_data: Default::default(),
             modified_nodes: Default::default(),
+            modified_size: Default::default(),
             removed_nodes: Default::default(),
             removed_nodes_data: Default::default(),
+            data_generation: 0,
         }
     }
 }
@@ -68,6 +75,7 @@ impl<T: NodeData> Octree<T> {
         self.added_nodes.clear();
         self.added_nodes_data.clear();
         self.modified_nodes.clear();
+        self.modified_size = 0;
         self.removed_nodes.clear();
         self.removed_nodes_data.clear();
     }
@@ -199,10 +207,38 @@ impl<T: NodeData> Octree<T> {
         node.status = NodeStatus::Loaded;
 
         self.added_nodes_data.push(node_id);
+        self.data_generation += 1;
 
         Ok(())
     }
 
+    /// Replaces the data of a node, returning the previous one.
+    /// If the node already had data, it is traced as modified so that its gpu copy is uploaded again.
+    pub fn update_node_data(
+        &mut self,
+        node_id: NodeId,
+        data: T,
+    ) -> Result<Option<T>, UpdateNodeError> {
+        let node = self
+            .hierarchy
+            .get_mut(node_id)
+            .ok_or(UpdateNodeError::NodeNotFound)?;
+
+        let size = data.size() as isize;
+        let previous_data = node.data.replace(data);
+        node.status = NodeStatus::Loaded;
+
+        if let Some(previous_data) = &previous_data {
+            self.modified_nodes.push(node_id);
+            self.modified_size += size - previous_data.size() as isize;
+        } else {
+            self.added_nodes_data.push(node_id);
+        }
+        self.data_generation += 1;
+
+        Ok(previous_data)
+    }
+
     pub fn set_node_hierarchy_loading(&mut self, node_id: NodeId) -> Result<(), UpdateNodeError> {
         let node = self
             .hierarchy
@@ -261,6 +297,9 @@ impl<T: NodeData> Octree<T> {
     fn remove_nodes_recursively(&mut self, node_id: NodeId) -> Option<OctreeNode<T>> {
         let node = self.hierarchy.remove(node_id)?;
         self.removed_nodes.push(node_id);
+        if node.data.is_some() {
+            self.data_generation += 1;
+        }
 
         for i in iter_one_bits(node.hierarchy.children_mask) {
             let child_id = node.hierarchy.children[i as usize];
@@ -282,6 +321,9 @@ impl<T: NodeData> Octree<T> {
 
         // trace the remove
         self.removed_nodes_data.push(node_id);
+        if data.is_some() {
+            self.data_generation += 1;
+        }
 
         data
     }
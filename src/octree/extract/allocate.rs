@@ -9,7 +9,7 @@ use crate::{
             resources::{ExtractOctreeNodeEvictionQueue, NodeAllocation, OctreeNodeAllocations},
             OctreeNodeExtraction,
         },
-        node::NodeData,
+        node::{NodeData, OctreeNodeKey},
         visibility::{components::ViewVisibleOctreeNodes, resources::GlobalVisibleOctreeNodes},
     },
 };
@@ -45,6 +45,28 @@ pub fn allocate_visible_octree_nodes<E: OctreeNodeExtraction>(
     octree_node_allocations.allocated_nodes_this_frame.clear();
     octree_node_allocations.freed_nodes_this_frame.clear();
 
+    // free nodes whose data has been replaced or that have been removed, so that they are uploaded again if visible
+    for (octree_id, octree) in octrees.iter() {
+        for node_id in octree.modified_nodes.iter().chain(&octree.removed_nodes) {
+            let octree_node_key = OctreeNodeKey {
+                octree_id,
+                node_id: *node_id,
+            };
+            if let Some(allocation) = octree_node_allocations.allocations.remove(&octree_node_key) {
+                // the node is not evictable anymore, its allocation is freed
+                extract_octree_node_eviction_queue
+                    .eviction_queue
+                    .remove(&octree_node_key);
+                octree_node_allocations
+                    .allocator
+                    .free(allocation.allocation);
+                octree_node_allocations
+                    .freed_nodes_this_frame
+                    .push(allocation);
+            }
+        }
+    }
+
     // iterate through nodes that needs allocations
     // note that they are ordered by priority because in insertion order
     for (octree_node_key, &weight) in &global_visible_octree_nodes.visible_octree_nodes {
@@ -76,6 +98,10 @@ pub fn allocate_visible_octree_nodes<E: OctreeNodeExtraction>(
         };
 
         let instance_count = node_data.instance_count();
+        if instance_count == 0 {
+            // nothing to upload
+            continue;
+        }
 
         // try to allocate memory for this node
         let mut allocation = None;
@@ -76,28 +76,39 @@ pub trait OctreeNodeExtraction: Send + Sync + TypePath {
 /// `prepare_assets::<AFTER>` has completed. This allows the [`RenderOctreeNode::prepare_octree_node`] function to depend on another
 /// prepared [`RenderOctreeNode`].
 #[allow(clippy::type_complexity)]
-pub struct ExtractVisibleOctreeNodesPlugin<E, A, AFTER = ()> {
+pub struct ExtractVisibleOctreeNodesPlugin<E: OctreeNodeExtraction, A, AFTER = ()> {
     max_size: usize,
+    instance_format: <E::ExtractedNodeData as RenderNodeData>::InstanceFormat,
     _phantom: PhantomData<fn() -> (E, A, AFTER)>,
 }
 
-impl<E, A, AFTER> Default for ExtractVisibleOctreeNodesPlugin<E, A, AFTER> {
+impl<E: OctreeNodeExtraction, A, AFTER> Default for ExtractVisibleOctreeNodesPlugin<E, A, AFTER> {
     fn default() -> Self {
         ExtractVisibleOctreeNodesPlugin {
             max_size: 512 * 1024 * 1024, // 512 mb
+            instance_format: Default::default(),
             _phantom: PhantomData,
         }
     }
 }
 
-impl<E, A, AFTER> ExtractVisibleOctreeNodesPlugin<E, A, AFTER> {
+impl<E: OctreeNodeExtraction, A, AFTER> ExtractVisibleOctreeNodesPlugin<E, A, AFTER> {
     /// Construct with specific max memory size for GPU
     pub fn with_max_size(max_size: usize) -> Self {
         Self {
             max_size,
-            _phantom: PhantomData,
+            ..Default::default()
         }
     }
+
+    /// Use a specific layout for the instances in the GPU buffer
+    pub fn with_instance_format(
+        mut self,
+        instance_format: <E::ExtractedNodeData as RenderNodeData>::InstanceFormat,
+    ) -> Self {
+        self.instance_format = instance_format;
+        self
+    }
 }
 
 #[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
@@ -112,28 +123,31 @@ where
     A::ExtractedOctreeNode: RenderNodeData,
 {
     fn build(&self, app: &mut App) {
-        app.insert_resource(OctreeBufferSettings::<E> {
+        let settings = OctreeBufferSettings::<E> {
             max_size: self.max_size,
+            instance_format: self.instance_format.clone(),
             _phantom: PhantomData,
-        })
-        .init_resource::<OctreeNodeAllocations<E>>()
-        .init_resource::<ExtractOctreeNodeEvictionQueue<E>>()
-        .add_plugins(ExtractComponentPlugin::<E::Component>::default())
-        .init_resource::<RenderOctreeNodesBytesPerFrame>()
-        .add_systems(
-            PostUpdate,
-            (
-                update_extract_octree_node_eviction_queue::<E>,
-                allocate_visible_octree_nodes::<E>
-                    .after(update_extract_octree_node_eviction_queue::<E>),
+        };
+
+        app.insert_resource(settings.clone())
+            .init_resource::<OctreeNodeAllocations<E>>()
+            .init_resource::<ExtractOctreeNodeEvictionQueue<E>>()
+            .add_plugins(ExtractComponentPlugin::<E::Component>::default())
+            .init_resource::<RenderOctreeNodesBytesPerFrame>()
+            .add_systems(
+                PostUpdate,
+                (
+                    update_extract_octree_node_eviction_queue::<E>,
+                    allocate_visible_octree_nodes::<E>
+                        .after(update_extract_octree_node_eviction_queue::<E>),
+                )
+                    .in_set(ExtractOctreeNode),
+            )
+            .configure_sets(
+                PostUpdate,
+                ExtractOctreeNode.after(OctreeVisibilitySystems::CheckOctreeNodesVisibility),
             )
-                .in_set(ExtractOctreeNode),
-        )
-        .configure_sets(
-            PostUpdate,
-            ExtractOctreeNode.after(OctreeVisibilitySystems::CheckOctreeNodesVisibility),
-        )
-        .add_observer(on_remove_octree::<E>);
+            .add_observer(on_remove_octree::<E>);
 
         let Some(render_app) = app.get_sub_app_mut(RenderApp) else {
             return;
@@ -144,6 +158,7 @@ where
             .register_required_components::<ExtractedView, RenderVisibleOctreeNodes::<E::NodeData, E::Component>>();
 
         render_app
+            .insert_resource(settings)
             .init_resource::<RenderOctreeNodesBytesPerFrameLimiter>()
             .add_systems(ExtractSchedule, extract_render_asset_bytes_per_frame)
             .add_systems(
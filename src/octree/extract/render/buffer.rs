@@ -1,12 +1,12 @@
-use std::marker::PhantomData;
+use std::{borrow::Cow, marker::PhantomData};
 
+use bevy_camera::primitives::Aabb;
 use bevy_ecs::prelude::*;
 use bevy_platform::collections::HashMap;
 use bevy_render::{
     render_resource::{Buffer, BufferDescriptor, BufferUsages},
     renderer::{RenderDevice, RenderQueue},
 };
-use bytemuck::Pod;
 use thiserror::Error;
 
 use super::node::RenderOctreeNode;
@@ -20,9 +20,14 @@ use crate::octree::extract::render::asset::RenderOctreeNodeAllocation;
 /// After that in the [`RenderSystems::PrepareAssets`] step the extracted octree nodes
 /// are transformed into their GPU-representation of type [`RenderOctreeNode`].
 pub trait RenderNodeData: Send + Sync {
-    type InstanceData: Pod;
+    /// Layout of the instances in the GPU buffer.
+    type InstanceFormat: Default + Clone + Send + Sync + 'static;
 
-    fn instances(&self) -> &[Self::InstanceData];
+    /// Size in bytes of one instance laid out with `format`.
+    fn instance_size(format: &Self::InstanceFormat) -> usize;
+
+    /// The instances of the node laid out with `format`, `bounding_box` being the bounds of the node.
+    fn instance_bytes(&self, format: &Self::InstanceFormat, bounding_box: &Aabb) -> Cow<'_, [u8]>;
 }
 
 /// Stores all GPU representations ([`RenderAsset`])
@@ -48,9 +53,10 @@ impl<A: RenderOctreeNode> RenderOctreesBuffers<A> {
         index: usize,
         render_device: &RenderDevice,
         max_instances: u32,
+        format: &<A::ExtractedOctreeNode as RenderNodeData>::InstanceFormat,
     ) -> &mut RenderOctreesBuffer<A::ExtractedOctreeNode> {
         self.0.entry(index).or_insert_with(|| {
-            RenderOctreesBuffer::<A::ExtractedOctreeNode>::new(render_device, max_instances)
+            RenderOctreesBuffer::<A::ExtractedOctreeNode>::new(render_device, max_instances, format)
         })
     }
 
@@ -75,6 +81,8 @@ pub enum WriteOctreeNodeError {
 #[derive(Resource)]
 pub struct RenderOctreesBuffer<A: RenderNodeData> {
     pub buffer: Buffer,
+    /// Layout of the instances in the buffer
+    pub format: A::InstanceFormat,
     // pub num_points: u64,
     // pub allocator: Allocator,
     // pub allocation_index: HashMap<NodeId, AllocationInfo>,
@@ -82,16 +90,27 @@ pub struct RenderOctreesBuffer<A: RenderNodeData> {
 }
 
 impl<A: RenderNodeData> RenderOctreesBuffer<A> {
-    pub fn new(render_device: &RenderDevice, max_instances: u32) -> Self {
+    pub fn new(
+        render_device: &RenderDevice,
+        max_instances: u32,
+        format: &A::InstanceFormat,
+    ) -> Self {
+        // the instances are also read as a storage buffer by the compute rasterization
+        #[cfg(not(feature = "webgl"))]
+        let usage = BufferUsages::VERTEX | BufferUsages::COPY_DST | BufferUsages::STORAGE;
+        #[cfg(feature = "webgl")]
+        let usage = BufferUsages::VERTEX | BufferUsages::COPY_DST;
+
         let buffer = render_device.create_buffer(&BufferDescriptor {
             label: Some("octree_data_buffer"),
-            usage: BufferUsages::VERTEX | BufferUsages::COPY_DST,
-            size: max_instances as u64 * size_of::<A::InstanceData>() as u64,
+            usage,
+            size: max_instances as u64 * A::instance_size(format) as u64,
             mapped_at_creation: false,
         });
 
         Self {
             buffer,
+            format: format.clone(),
             // num_points: 0,
             // allocator: Allocator::new(max_instances),
             // allocation_index: HashMap::new(),
@@ -104,6 +123,7 @@ impl<A: RenderNodeData> RenderOctreesBuffer<A> {
         render_queue: &RenderQueue,
         // node_id: NodeId,
         node: &A,
+        bounding_box: &Aabb,
         allocation: &RenderOctreeNodeAllocation,
     ) -> Result<(), WriteOctreeNodeError> {
         // do not reallocate the same node
@@ -113,7 +133,7 @@ impl<A: RenderNodeData> RenderOctreesBuffer<A> {
         //     return Ok(());
         // }
 
-        let instances = node.instances();
+        let data = node.instance_bytes(&self.format, bounding_box);
 
         // let num_points = instances.len() as u32;
 
@@ -131,7 +151,7 @@ impl<A: RenderNodeData> RenderOctreesBuffer<A> {
 
         // self.num_points = self.num_points.max(offset + allocation_size);
 
-        let instance_size = size_of::<A::InstanceData>() as u64;
+        let instance_size = A::instance_size(&self.format) as u64;
 
         // bevy_log::debug!(
         //     "Allocated {} at offset {} with size {} (instance size = {})",
@@ -141,12 +161,7 @@ impl<A: RenderNodeData> RenderOctreesBuffer<A> {
         //     instance_size,
         // );
 
-        let data: &[u8] = bytemuck::cast_slice(instances);
-        render_queue.write_buffer(
-            &self.buffer,
-            allocation.start as u64 * instance_size as u64,
-            data,
-        );
+        render_queue.write_buffer(&self.buffer, allocation.start as u64 * instance_size, &data);
 
         // self.allocation_index.insert(
         //     node_id,
@@ -10,7 +10,8 @@ use bevy_render::{
 
 use super::{
     super::{
-        limiter::RenderOctreeNodesBytesPerFrameLimiter, ExtractedOctreeNodes, OctreeNodeExtraction,
+        limiter::RenderOctreeNodesBytesPerFrameLimiter, resources::OctreeBufferSettings,
+        ExtractedOctreeNodes, OctreeNodeExtraction,
     },
     asset::RenderOctreeNodeData,
     node::{PrepareOctreeNodeError, RenderOctreeNode},
@@ -73,6 +74,7 @@ pub fn prepare_assets<E, A>(
     bpf: Res<RenderOctreeNodesBytesPerFrameLimiter>,
     render_device: Res<RenderDevice>,
     render_queue: Res<RenderQueue>,
+    settings: Res<OctreeBufferSettings<E>>,
 ) where
     E: OctreeNodeExtraction,
     A: RenderOctreeNode<SourceOctreeNode = E::NodeData, ExtractedOctreeNode = E::ExtractedNodeData>,
@@ -82,6 +84,7 @@ pub fn prepare_assets<E, A>(
         0,
         &render_device,
         extracted_octree_nodes.max_instances,
+        &settings.instance_format,
     );
 
     let mut wrote_asset_count = 0;
@@ -139,6 +142,7 @@ pub fn prepare_assets<E, A>(
         if let Err(error) = octrees_buffer.write(
             &render_queue,
             &extracted_octree_node.data,
+            &extracted_octree_node.bounding_box,
             &extracted_octree_node.allocation,
         ) {
             bevy_log::warn!(
@@ -239,6 +243,7 @@ pub fn prepare_assets<E, A>(
             if let Err(error) = octrees_buffer.write(
                 &render_queue,
                 &extracted_octree_node.data,
+                &extracted_octree_node.bounding_box,
                 &extracted_octree_node.allocation,
             ) {
                 bevy_log::warn!(
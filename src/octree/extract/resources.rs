@@ -15,9 +15,27 @@ use crate::octree::{
 #[derive(Resource)]
 pub struct OctreeBufferSettings<E: OctreeNodeExtraction> {
     pub(crate) max_size: usize,
+    pub(crate) instance_format: <E::ExtractedNodeData as RenderNodeData>::InstanceFormat,
     pub(crate) _phantom: PhantomData<fn() -> E>,
 }
 
+impl<E: OctreeNodeExtraction> Clone for OctreeBufferSettings<E> {
+    fn clone(&self) -> Self {
+        Self {
+            max_size: self.max_size,
+            instance_format: self.instance_format.clone(),
+            _phantom: PhantomData,
+        }
+    }
+}
+
+impl<E: OctreeNodeExtraction> OctreeBufferSettings<E> {
+    /// Layout of the instances in the GPU buffer
+    pub fn instance_format(&self) -> &<E::ExtractedNodeData as RenderNodeData>::InstanceFormat {
+        &self.instance_format
+    }
+}
+
 pub struct NodeAllocation<T: NodeData> {
     pub octree_node_key: OctreeNodeKey<T>,
     pub(crate) allocation: Allocation,
@@ -53,7 +71,7 @@ impl<E: OctreeNodeExtraction> FromWorld for OctreeNodeAllocations<E> {
 
         // compute the maximum number of instances
         let max_instances = (settings.max_size
-            / std::mem::size_of::<<E::ExtractedNodeData as RenderNodeData>::InstanceData>())
+            / E::ExtractedNodeData::instance_size(&settings.instance_format))
             as u32;
 
         Self {
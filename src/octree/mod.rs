@@ -47,6 +47,7 @@ pub fn reset_octree_nodes_tracking<T: NodeData>(
             };
             *total_size += data.size();
         }
+        *total_size = total_size.saturating_add_signed(octree.modified_size);
 
         octree.clear_tracking();
     }
@@ -4,13 +4,48 @@ use std::{
 };
 
 use bevy_asset::AssetId;
+use bevy_camera::primitives::Aabb;
 use bevy_reflect::TypePath;
 
 use crate::octree::{asset::Octree, hierarchy::HierarchyOctreeNode, storage::NodeId};
 
 pub trait NodeData: Send + Sync + TypePath {
+    /// Describes how loaded node data is kept in memory, see [`NodeData::into_storage_format`].
+    type StorageFormat: Default + Clone + Send + Sync + 'static;
+    /// Describes the background processing of loaded node data, see [`NodeData::process`].
+    type Processing: Clone + Send + Sync + 'static;
+    /// What the background processing of a node needs, see [`NodeData::prepare_processing`].
+    type ProcessingInput: Send + 'static;
+
     fn instance_count(&self) -> usize;
+    /// Size of the data in memory, counted against the octree server memory budget.
     fn size(&self) -> usize;
+    /// Convert freshly loaded data to the storage format, `bounding_box` being the one of the node.
+    ///
+    /// This is called by the octree server off the main thread, so that data can be kept in a
+    /// compact form and only be expanded when extracted to the render world.
+    fn into_storage_format(self, format: &Self::StorageFormat, bounding_box: &Aabb) -> Self
+    where
+        Self: Sized;
+    /// Gather what the processing of loaded data needs from it and from the data of the loaded
+    /// ancestors of the node, closest first, each one along with the bounding box of its node.
+    /// Returns `None` if there is nothing to process.
+    ///
+    /// This is called by the octree server on the main thread once the data has been inserted in
+    /// the octree, so the data should be shared rather than copied.
+    fn prepare_processing(
+        &self,
+        processing: &Self::Processing,
+        bounding_box: &Aabb,
+        ancestors: &[(&Self, &Aabb)],
+    ) -> Option<Self::ProcessingInput>;
+    /// Process the input gathered by [`NodeData::prepare_processing`], returning the data
+    /// replacing the one of the node, or `None` if it is left unchanged.
+    ///
+    /// This is called by the octree server on the `AsyncComputeTaskPool`.
+    fn process(input: Self::ProcessingInput) -> Option<Self>
+    where
+        Self: Sized;
 }
 
 #[derive(Debug, Clone, Copy)]
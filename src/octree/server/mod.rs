@@ -3,14 +3,14 @@ pub mod resources;
 pub mod systems;
 mod task;
 
-use std::{marker::PhantomData, sync::Arc};
+use std::sync::Arc;
 
 use bevy_app::prelude::*;
 use bevy_asset::{AssetEvent, AssetHandleProvider, AssetId, Assets, Handle};
 use bevy_ecs::prelude::*;
 use bevy_log::prelude::*;
 use bevy_platform::collections::HashMap;
-use bevy_tasks::IoTaskPool;
+use bevy_tasks::{AsyncComputeTaskPool, IoTaskPool};
 use crossbeam::channel::{Receiver, Sender};
 use process::process_octree_load_tasks;
 use resources::{OctreeLoadTasks, OctreeServerEvictionQueue, OctreeServerSettings};
@@ -29,28 +29,44 @@ use crate::octree::{
     visibility::OctreeVisibilitySystems,
 };
 
-pub struct OctreeServerPlugin<T> {
+pub struct OctreeServerPlugin<T: NodeData> {
     pub max_size: usize,
-    _phantom: PhantomData<fn() -> T>,
+    /// How loaded node data is kept in memory, see [`NodeData::into_storage_format`]
+    pub storage_format: T::StorageFormat,
+    /// Background processing of loaded node data, see [`NodeData::process`]
+    pub processing: Option<T::Processing>,
 }
 
-impl<T> Default for OctreeServerPlugin<T> {
+impl<T: NodeData> Default for OctreeServerPlugin<T> {
     fn default() -> Self {
         OctreeServerPlugin {
             max_size: 1024 * 1024 * 1024, // 1024 mb
-            _phantom: PhantomData,
+            storage_format: T::StorageFormat::default(),
+            processing: None,
         }
     }
 }
 
-impl<T> OctreeServerPlugin<T> {
+impl<T: NodeData> OctreeServerPlugin<T> {
     /// Construct with specific max memory size for CPU
     pub fn with_max_size(max_size: usize) -> Self {
         Self {
             max_size,
-            _phantom: PhantomData,
+            ..Default::default()
         }
     }
+
+    /// Keep loaded node data in a specific format, e.g. a compact one to fit more nodes in memory
+    pub fn with_storage_format(mut self, storage_format: T::StorageFormat) -> Self {
+        self.storage_format = storage_format;
+        self
+    }
+
+    /// Process loaded node data in the background, e.g. to compute additional attributes
+    pub fn with_processing(mut self, processing: T::Processing) -> Self {
+        self.processing = Some(processing);
+        self
+    }
 }
 
 impl<T> Plugin for OctreeServerPlugin<T>
@@ -60,7 +76,8 @@ where
     fn build(&self, app: &mut App) {
         app.insert_resource(OctreeServerSettings::<T> {
             max_size: self.max_size,
-            _phantom: PhantomData,
+            storage_format: self.storage_format.clone(),
+            processing: self.processing.clone(),
         })
         .init_resource::<OctreeServer<T>>()
         .init_resource::<OctreeServerEvictionQueue<T>>()
@@ -95,6 +112,8 @@ where
     pub(crate) handle_provider: AssetHandleProvider,
     pub(crate) octree_event_sender: Sender<InternalOctreeEvent<T>>,
     pub(crate) octree_event_receiver: Receiver<InternalOctreeEvent<T>>,
+    pub(crate) storage_format: T::StorageFormat,
+    pub(crate) processing: Option<T::Processing>,
 }
 
 impl<T> OctreeServerData<T>
@@ -200,14 +219,18 @@ where
         hierarchy_node: &HierarchyOctreeNode,
     ) -> Result<(), BevyError> {
         match loader.load_node_data(hierarchy_node).await {
-            Ok(node_data) => self
-                .octree_event_sender
-                .send(InternalOctreeEvent::NodeDataLoaded {
-                    id,
-                    node_id: hierarchy_node.id,
-                    node_data,
-                })
-                .expect("Failed to send internal octree event"),
+            Ok(node_data) => {
+                let node_data = node_data
+                    .into_storage_format(&self.storage_format, &hierarchy_node.bounding_box);
+
+                self.octree_event_sender
+                    .send(InternalOctreeEvent::NodeDataLoaded {
+                        id,
+                        node_id: hierarchy_node.id,
+                        node_data,
+                    })
+                    .expect("Failed to send internal octree event")
+            }
             Err(err) => {
                 error!("Error loading node data: {}", err);
                 self.octree_event_sender
@@ -222,6 +245,51 @@ where
 
         Ok(())
     }
+
+    /// Process the data of a node on the [`AsyncComputeTaskPool`], with the data of its loaded
+    /// ancestors, see [`NodeData::prepare_processing`].
+    fn process_node_data(&self, id: AssetId<Octree<T>>, octree: &Octree<T>, node_id: NodeId) {
+        let Some(processing) = &self.processing else {
+            return;
+        };
+        let Some(node) = octree.node(node_id) else {
+            return;
+        };
+        let Some(node_data) = &node.data else {
+            return;
+        };
+
+        let mut ancestors = Vec::new();
+        let mut parent_id = node.hierarchy.parent_id;
+        while let Some(parent) = parent_id.and_then(|parent_id| octree.node(parent_id)) {
+            if let Some(parent_data) = &parent.data {
+                ancestors.push((parent_data, &parent.hierarchy.bounding_box));
+            }
+            parent_id = parent.hierarchy.parent_id;
+        }
+
+        let Some(input) =
+            node_data.prepare_processing(processing, &node.hierarchy.bounding_box, &ancestors)
+        else {
+            return;
+        };
+
+        let octree_event_sender = self.octree_event_sender.clone();
+
+        AsyncComputeTaskPool::get()
+            .spawn(async move {
+                if let Some(node_data) = T::process(input) {
+                    octree_event_sender
+                        .send(InternalOctreeEvent::NodeDataProcessed {
+                            id,
+                            node_id,
+                            node_data,
+                        })
+                        .expect("Failed to send internal octree event");
+                }
+            })
+            .detach();
+    }
 }
 
 /// Internal events for asset load results
@@ -256,6 +324,11 @@ where
         #[allow(unused)]
         error: String,
     },
+    NodeDataProcessed {
+        id: AssetId<Octree<T>>,
+        node_id: NodeId,
+        node_data: T,
+    },
 }
 
 /// Build a child adjacency list and collect root indices for hierarchy vectors.
@@ -290,6 +363,10 @@ where
     fn from_world(world: &mut World) -> Self {
         let asset_server = world.resource::<Assets<Octree<T>>>();
         let handle_provider = asset_server.get_handle_provider();
+        let (storage_format, processing) = world
+            .get_resource::<OctreeServerSettings<T>>()
+            .map(|settings| (settings.storage_format.clone(), settings.processing.clone()))
+            .unwrap_or_default();
 
         let (octree_event_sender, octree_event_receiver) = crossbeam::channel::unbounded();
 
@@ -298,6 +375,8 @@ where
                 handle_provider,
                 octree_event_sender,
                 octree_event_receiver,
+                storage_format,
+                processing,
             }),
             loaders: HashMap::new(),
         }
@@ -580,6 +659,9 @@ pub fn handle_internal_octree_events<T>(
                     );
                     continue;
                 }
+
+                // the node is drawn with its loaded data until processed
+                server.data.process_node_data(id, octree, node_id);
             }
             InternalOctreeEvent::NodeDataLoadFailed {
                 id,
@@ -601,6 +683,28 @@ pub fn handle_internal_octree_events<T>(
 
                 let _ = octree.unset_node_data_loading(node_id);
             }
+            InternalOctreeEvent::NodeDataProcessed {
+                id,
+                node_id,
+                node_data,
+            } => {
+                let Some(octree) = assets.get_mut(id) else {
+                    debug!("No asset found for {:?}, unable to store node data.", id);
+                    continue;
+                };
+
+                // the data may have been evicted while being processed
+                if octree.node(node_id).is_none_or(|node| node.data.is_none()) {
+                    continue;
+                }
+
+                if let Err(error) = octree.update_node_data(node_id, node_data) {
+                    warn!(
+                        "An error occured when updating node data of node {}/{:?}: {:#}",
+                        id, node_id, error
+                    );
+                }
+            }
         }
     }
 }
@@ -18,9 +18,10 @@ use crate::octree::{
 };
 
 #[derive(Resource)]
-pub struct OctreeServerSettings<T> {
+pub struct OctreeServerSettings<T: NodeData> {
     pub(crate) max_size: usize,
-    pub(crate) _phantom: PhantomData<fn() -> T>,
+    pub(crate) storage_format: T::StorageFormat,
+    pub(crate) processing: Option<T::Processing>,
 }
 
 #[derive(Resource)]
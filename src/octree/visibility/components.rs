@@ -88,6 +88,8 @@ pub struct VisibleOctreeNode {
     pub child_index: u8,
     pub children: [usize; 8],
     pub children_mask: u8,
+    /// The node is outside of the view, or occluded, and is only kept to cast shadows
+    pub shadow_only: bool,
 }
 
 impl<T: NodeData> From<&OctreeNode<T>> for VisibleOctreeNode {
@@ -100,6 +102,7 @@ impl<T: NodeData> From<&OctreeNode<T>> for VisibleOctreeNode {
             child_index: value.hierarchy.child_index,
             children: [0_usize; 8],
             children_mask: 0b00000000,
+            shadow_only: false,
         }
     }
 }
@@ -2,7 +2,9 @@ pub mod budget;
 pub mod components;
 pub mod filter;
 mod heap_guard;
+pub mod occlusion;
 pub mod resources;
+pub mod shadow;
 pub mod stack;
 
 use std::{any::TypeId, collections::BinaryHeap, marker::PhantomData};
@@ -10,7 +12,7 @@ use std::{any::TypeId, collections::BinaryHeap, marker::PhantomData};
 use bevy_app::{App, Plugin, PostUpdate};
 use bevy_asset::{AssetEventSystems, AssetId, Assets};
 use bevy_camera::{
-    primitives::{Aabb, Frustum},
+    primitives::{Aabb, CascadesFrusta, Frustum},
     visibility::{
         add_visibility_class, NoAutoAabb, NoFrustumCulling, Visibility, VisibilityClass,
         VisibilitySystems::CheckVisibility, VisibleEntities,
@@ -21,8 +23,9 @@ use bevy_diagnostic::{
     Diagnostic, DiagnosticPath, Diagnostics, RegisterDiagnostic, DEFAULT_MAX_HISTORY_LENGTH,
 };
 use bevy_ecs::prelude::*;
+use bevy_light::{DirectionalLight, SimulationLightSystems};
 use bevy_log::prelude::*;
-use bevy_math::prelude::*;
+use bevy_math::{prelude::*, Affine3A};
 use bevy_platform::{collections::HashMap, time::Instant};
 use bevy_time::{Real, Time};
 use bevy_transform::prelude::*;
@@ -30,9 +33,13 @@ use budget::OctreeNodesBudget;
 use components::*;
 use filter::*;
 use heap_guard::HeapGuard;
+use occlusion::{OcclusionDepthPyramid, OctreeOcclusionCulling};
 use resources::GlobalVisibleOctreeNodes;
+use shadow::{OctreeShadowCaster, SHADOW_LOD_SCALE};
 use stack::*;
 
+use crate::clip_volume::ClipVolumes;
+
 use super::{
     asset::Octree,
     hierarchy::HierarchyNodeStatus,
@@ -101,7 +108,9 @@ where
             )
             .configure_sets(
                 PostUpdate,
-                OctreeVisibilitySystems::CheckOctreeNodesVisibility.after(CheckVisibility),
+                OctreeVisibilitySystems::CheckOctreeNodesVisibility
+                    .after(CheckVisibility)
+                    .after(SimulationLightSystems::UpdateLightFrusta),
             )
             .configure_sets(
                 PostUpdate,
@@ -165,9 +174,10 @@ pub fn calculate_bounds<T, C>(
 pub fn check_octree_nodes_visibility<T, C, F, B>(
     mut diagnostics: Diagnostics,
     _time: Res<Time<Real>>,
-    entities: Query<(&C, &GlobalTransform)>,
+    entities: Query<(&C, &GlobalTransform, Has<OctreeShadowCaster>)>,
     // TODO add a way to disable checking of a camera
     mut views: Query<(
+        Entity,
         &VisibleEntities,
         &Camera,
         &Frustum,
@@ -176,10 +186,15 @@ pub fn check_octree_nodes_visibility<T, C, F, B>(
         Option<&OctreeVisibilitySettings<T, F, B>>,
         &mut ViewVisibleOctreeNodes<T, C>,
         Option<&SkipOctreeVisibility>,
+        Option<&OcclusionDepthPyramid>,
+        Has<OctreeOcclusionCulling>,
     )>,
+    directional_lights: Query<(&DirectionalLight, &CascadesFrusta)>,
+    clip_volumes: Option<Res<ClipVolumes>>,
     octrees: Res<Assets<Octree<T>>>,
     mut octree_load_tasks: ResMut<OctreeLoadTasks<T>>,
     mut priority_stack: Local<BinaryHeap<StackedOctreeNode<T>>>,
+    mut shadow_frusta: Local<Vec<Frustum>>,
     mut global_visible_octree_nodes: ResMut<GlobalVisibleOctreeNodes<T>>,
 ) where
     T: NodeData,
@@ -203,6 +218,7 @@ pub fn check_octree_nodes_visibility<T, C, F, B>(
 
     // for each view
     for (
+        view_entity,
         visible_entities,
         camera,
         frustum,
@@ -211,6 +227,8 @@ pub fn check_octree_nodes_visibility<T, C, F, B>(
         visibility_settings,
         mut visible_octree_nodes,
         skip_octree_visibility,
+        occlusion_depth_pyramid,
+        occlusion_culling,
     ) in &mut views
     {
         if !camera.is_active {
@@ -228,11 +246,24 @@ pub fn check_octree_nodes_visibility<T, C, F, B>(
         // mark as changed
         visible_octree_nodes.changed_this_frame = true;
 
+        // the shadow cascades of this camera
+        shadow_frusta.clear();
+        for (directional_light, cascades_frusta) in &directional_lights {
+            if directional_light.shadows_enabled
+                && let Some(frusta) = cascades_frusta.frusta.get(&view_entity)
+            {
+                shadow_frusta.extend(frusta.iter().cloned());
+            }
+        }
+
         let camera_view = CameraView {
             global_transform: camera_global_transform,
             frustum,
             projection: camera_projection,
             physical_target_size: camera.physical_target_size(),
+            occlusion: occlusion_depth_pyramid.filter(|_| occlusion_culling),
+            shadow_frusta: &shadow_frusta,
+            clip_volumes: clip_volumes.as_deref(),
         };
 
         // get all visible octrees
@@ -245,7 +276,7 @@ pub fn check_octree_nodes_visibility<T, C, F, B>(
 
         // for each visible octree
         for entity in visible_octree_entities {
-            let (component, global_transform) = match entities.get(*entity) {
+            let (component, global_transform, casts_shadows) = match entities.get(*entity) {
                 Ok(item) => item,
                 Err(error) => {
                     warn!(
@@ -298,6 +329,8 @@ pub fn check_octree_nodes_visibility<T, C, F, B>(
                 screen_pixel_radius,
                 // TODO check for this ?
                 completely_visible: false,
+                shadow_only: false,
+                casts_shadows,
                 parent_index: None,
             });
         }
@@ -345,6 +378,22 @@ pub struct CameraView<'a> {
     pub frustum: &'a Frustum,
     pub projection: &'a Projection,
     pub physical_target_size: Option<UVec2>,
+    /// depth of a previous frame, to skip the occluded nodes
+    pub occlusion: Option<&'a OcclusionDepthPyramid>,
+    /// frusta of the shadow cascades of the directional lights for this view
+    pub shadow_frusta: &'a [Frustum],
+    /// volumes clipping the points, to skip the nodes entirely clipped
+    pub clip_volumes: Option<&'a ClipVolumes>,
+}
+
+impl CameraView<'_> {
+    /// Whether the box, in the local space of `world_from_local`, is inside a shadow cascade.
+    pub fn intersects_shadow_frusta(&self, aabb: &Aabb, world_from_local: &Affine3A) -> bool {
+        // the casters between the light and the near plane of a cascade still cast shadows
+        self.shadow_frusta
+            .iter()
+            .any(|frustum| frustum.intersects_obb(aabb, world_from_local, false, true))
+    }
 }
 
 fn compute_screen_pixel_radius(
@@ -417,6 +466,8 @@ fn compute_visible_nodes_stack<T, C, F, B>(
         screen_pixel_radius,
         weight,
         mut completely_visible,
+        mut shadow_only,
+        casts_shadows,
         parent_index,
     }) = stack.pop()
     {
@@ -442,7 +493,27 @@ fn compute_visible_nodes_stack<T, C, F, B>(
         #[cfg(feature = "trace")]
         drop(filter_span);
 
-        if !completely_visible {
+        // nodes entirely clipped are neither drawn nor loaded, and neither are their children
+        if let Some(clip_volumes) = camera_view.clip_volumes
+            && clip_volumes.clips_obb(&node.hierarchy.bounding_box, &world_from_local)
+        {
+            continue;
+        }
+
+        // nodes outside of the view, or occluded, are kept for casting shadows if they are inside
+        // a shadow cascade
+        let keep_for_shadows = || {
+            casts_shadows
+                && camera_view
+                    .intersects_shadow_frusta(&node.hierarchy.bounding_box, &world_from_local)
+        };
+
+        if shadow_only {
+            // the parent is outside of the view, or occluded, so is this node
+            if !keep_for_shadows() {
+                continue;
+            }
+        } else if !completely_visible {
             #[cfg(feature = "trace")]
             let _span = info_span!(
                 "compute_visible_nodes_stack",
@@ -457,12 +528,14 @@ fn compute_visible_nodes_stack<T, C, F, B>(
 
             // Do quick sphere-based frustum culling
             if !camera_view.frustum.intersects_sphere(&model_sphere, false) {
-                // this node is not visible, continue
-                continue;
-            }
+                // this node is not visible
+                if !keep_for_shadows() {
+                    continue;
+                }
+                shadow_only = true;
 
-            // Check if the aabb is completly inside the frustum
-            if camera_view
+                // Check if the aabb is completly inside the frustum
+            } else if camera_view
                 .frustum
                 .contains_aabb(&node.hierarchy.bounding_box, &world_from_local)
             {
@@ -477,9 +550,36 @@ fn compute_visible_nodes_stack<T, C, F, B>(
                 // we do not set a distance limit because too small might have been already filtered
                 false,
             ) {
-                // the node is completely outside the frustum, ignore it
+                // the node is completely outside the frustum
+                if !keep_for_shadows() {
+                    continue;
+                }
+                shadow_only = true;
+            }
+        }
+
+        // skip the nodes hidden behind the points of a previous frame, so that they are neither
+        // drawn nor loaded
+        if !shadow_only
+            && let Some(occlusion) = camera_view.occlusion
+            && occlusion.is_occluded(&node.hierarchy.bounding_box, &world_from_local)
+        {
+            if !keep_for_shadows() {
                 continue;
             }
+            shadow_only = true;
+        }
+
+        // the nodes only kept for shadows are refined at a coarser level of detail
+        if shadow_only
+            && !filter.filter(
+                node,
+                transform,
+                camera_view,
+                screen_pixel_radius.map(|radius| radius * SHADOW_LOD_SCALE),
+            )
+        {
+            continue;
         }
 
         match node.status {
@@ -532,7 +632,11 @@ fn compute_visible_nodes_stack<T, C, F, B>(
                             transform,
                             camera_view,
                         );
-                        let weight = child_screen_pixel_radius.unwrap_or(f32::MAX);
+                        let mut weight = child_screen_pixel_radius.unwrap_or(f32::MAX);
+                        // load the nodes only kept for shadows after the visible ones
+                        if shadow_only {
+                            weight *= SHADOW_LOD_SCALE;
+                        }
 
                         #[cfg(feature = "trace")]
                         let span_append_stack =
@@ -546,6 +650,8 @@ fn compute_visible_nodes_stack<T, C, F, B>(
                             screen_pixel_radius: child_screen_pixel_radius,
                             weight: weight.into(),
                             completely_visible,
+                            shadow_only,
+                            casts_shadows,
                             parent_index: Some(current_index),
                         });
                         #[cfg(feature = "trace")]
@@ -556,7 +662,9 @@ fn compute_visible_nodes_stack<T, C, F, B>(
 
                     // add the current node because it is visible or partially visible
                     let child_index = node.hierarchy.child_index;
-                    visible_nodes.push(node.into());
+                    let mut visible_node = VisibleOctreeNode::from(node);
+                    visible_node.shadow_only = shadow_only;
+                    visible_nodes.push(visible_node);
                     global_visible_octree_nodes.add_visible_octree_node(asset_id, node, weight);
 
                     // if there is a parent, add it to the visible children array
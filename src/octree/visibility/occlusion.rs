@@ -0,0 +1,149 @@
+use bevy_camera::primitives::Aabb;
+use bevy_ecs::prelude::*;
+use bevy_math::prelude::*;
+use bevy_math::Affine3A;
+
+/// Enables the occlusion culling of the octree nodes seen by a camera.
+///
+/// The nodes hidden behind the points rendered during the previous frames are neither drawn nor
+/// requested from the octree server. Since the depth is read back from the GPU a few frames
+/// late, the disoccluded nodes may appear with a small delay when the camera moves quickly.
+///
+/// Not available with WebGL.
+#[derive(Component, Clone, Copy, Debug, Default)]
+pub struct OctreeOcclusionCulling;
+
+/// A level of an [`OcclusionDepthPyramid`].
+#[derive(Clone, Debug)]
+struct OcclusionDepthLevel {
+    size: UVec2,
+    /// farthest depth of each texel, row by row
+    depths: Vec<f32>,
+}
+
+impl OcclusionDepthLevel {
+    fn get(&self, x: u32, y: u32) -> f32 {
+        self.depths[(y * self.size.x + x) as usize]
+    }
+
+    /// The level covering this one with half its resolution.
+    fn downsample(&self) -> Self {
+        let size = UVec2::new(self.size.x.div_ceil(2), self.size.y.div_ceil(2));
+        let mut depths = Vec::with_capacity((size.x * size.y) as usize);
+
+        for y in 0..size.y {
+            for x in 0..size.x {
+                let min = UVec2::new(x, y) * 2;
+                let max = (min + 1).min(self.size - 1);
+                depths.push(
+                    self.get(min.x, min.y)
+                        .min(self.get(max.x, min.y))
+                        .min(self.get(min.x, max.y))
+                        .min(self.get(max.x, max.y)),
+                );
+            }
+        }
+
+        Self { size, depths }
+    }
+}
+
+/// A hierarchical depth buffer of the points rendered by a camera during a previous frame.
+///
+/// Each texel stores the farthest depth of its footprint, with reverse Z and `0.0` where no
+/// point has been rendered. It is inserted on the cameras with [`OctreeOcclusionCulling`].
+#[derive(Component, Clone, Debug)]
+pub struct OcclusionDepthPyramid {
+    /// the view projection of the frame the depth has been rendered with
+    clip_from_world: Mat4,
+    /// size of the viewport in texels of the first level, which may be partially covered
+    extent: Vec2,
+    levels: Vec<OcclusionDepthLevel>,
+}
+
+impl OcclusionDepthPyramid {
+    /// Build the pyramid from the farthest depth of each tile of the viewport, row by row.
+    pub fn new(clip_from_world: Mat4, extent: Vec2, size: UVec2, depths: Vec<f32>) -> Self {
+        assert_eq!(depths.len(), (size.x * size.y) as usize);
+
+        let mut levels = vec![OcclusionDepthLevel { size, depths }];
+        while let Some(level) = levels.last()
+            && level.size.max_element() > 1
+        {
+            let next = level.downsample();
+            levels.push(next);
+        }
+
+        Self {
+            clip_from_world,
+            extent,
+            levels,
+        }
+    }
+
+    pub fn clip_from_world(&self) -> Mat4 {
+        self.clip_from_world
+    }
+
+    /// Whether the box, in the local space of `world_from_local`, is hidden behind the depth.
+    ///
+    /// Boxes crossing the camera plane or outside of the viewport are never occluded.
+    pub fn is_occluded(&self, aabb: &Aabb, world_from_local: &Affine3A) -> bool {
+        let clip_from_local = self.clip_from_world * Mat4::from(*world_from_local);
+        let center = Vec3::from(aabb.center);
+        let half_extents = Vec3::from(aabb.half_extents);
+
+        let mut min = Vec2::MAX;
+        let mut max = Vec2::MIN;
+        let mut nearest = 0.0_f32;
+        for i in 0..8 {
+            let sign = Vec3::new(
+                if i & 1 == 0 { -1.0 } else { 1.0 },
+                if i & 2 == 0 { -1.0 } else { 1.0 },
+                if i & 4 == 0 { -1.0 } else { 1.0 },
+            );
+            let clip = clip_from_local * (center + sign * half_extents).extend(1.0);
+            if clip.w <= f32::EPSILON {
+                return false;
+            }
+
+            let ndc = clip.xyz() / clip.w;
+            min = min.min(ndc.xy());
+            max = max.max(ndc.xy());
+            nearest = nearest.max(ndc.z);
+        }
+
+        if nearest >= 1.0 || min.cmpgt(Vec2::ONE).any() || max.cmplt(Vec2::NEG_ONE).any() {
+            return false;
+        }
+
+        // convert to texels of the first level, with y pointing down
+        let to_texels = |ndc: Vec2| {
+            let ndc = ndc.clamp(Vec2::NEG_ONE, Vec2::ONE);
+            Vec2::new(ndc.x * 0.5 + 0.5, 0.5 - ndc.y * 0.5) * self.extent
+        };
+        let rect_min = to_texels(Vec2::new(min.x, max.y));
+        let rect_max = to_texels(Vec2::new(max.x, min.y));
+
+        // the level where the box covers at most 3 texels per side
+        let extent = (rect_max - rect_min).max_element().max(1.0);
+        let level_index =
+            ((extent / 2.0).log2().ceil().max(0.0) as usize).min(self.levels.len() - 1);
+        let level = &self.levels[level_index];
+
+        let scale = (1_u32 << level_index) as f32;
+        let last = level.size - 1;
+        let min_texel = (rect_min / scale).floor().as_uvec2().min(last);
+        let max_texel = (rect_max / scale).floor().as_uvec2().min(last);
+
+        let mut farthest = f32::MAX;
+        for y in min_texel.y..=max_texel.y {
+            for x in min_texel.x..=max_texel.x {
+                farthest = farthest.min(level.get(x, y));
+            }
+        }
+
+        // reverse Z, the box is behind if its nearest depth is smaller than the farthest occluder
+        nearest < farthest
+    }
+}
@@ -0,0 +1,13 @@
+use bevy_ecs::prelude::*;
+
+/// Keeps the nodes of an octree which are outside of the view, or occluded, but inside the shadow
+/// cascades of a directional light, so that they can be drawn into its shadow maps.
+///
+/// These nodes are marked as [`shadow_only`](super::components::VisibleOctreeNode::shadow_only)
+/// and are refined at a coarser level of detail, see [`SHADOW_LOD_SCALE`].
+#[derive(Component, Clone, Copy, Debug, Default)]
+pub struct OctreeShadowCaster;
+
+/// Scale applied to the screen pixel radius of the nodes only kept for shadows before filtering
+/// them, so that they stop being refined earlier than the visible nodes.
+pub const SHADOW_LOD_SCALE: f32 = 0.25;
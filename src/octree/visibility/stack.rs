@@ -17,6 +17,14 @@ pub struct StackedOctreeNode<'a, T: NodeData> {
     pub screen_pixel_radius: Option<f32>,
     pub weight: OrderedFloat<f32>,
     pub completely_visible: bool,
+    /// The node is only kept to cast shadows, see [`OctreeShadowCaster`]
+    ///
+    /// [`OctreeShadowCaster`]: super::shadow::OctreeShadowCaster
+    pub shadow_only: bool,
+    /// The octree has an [`OctreeShadowCaster`]
+    ///
+    /// [`OctreeShadowCaster`]: super::shadow::OctreeShadowCaster
+    pub casts_shadows: bool,
     pub parent_index: Option<usize>,
 }
 
@@ -152,22 +152,27 @@ impl<S: ByteSource + 'static + Send + Sync> OctreeLoader<PointCloudNodeData> for
         // magic formula from Potree
         let offset = (density as f32).log2() / 2.0 - 1.5;
 
+        let classifications = points
+            .iter()
+            .map(|point| u8::from(point.classification))
+            .collect();
+
         Ok(PointCloudNodeData {
             spacing,
             level: node.data.0.key.level as u32,
             offset,
             num_points: node.data.0.point_count as usize,
-            points: Arc::new(
-                points
-                    .into_iter()
-                    .map(|point| {
-                        let position =
-                            Vec4::new(point.x as f32, point.y as f32, point.z as f32, 1.0);
-                        let color = compute_point_color(&point);
-                        PointData { position, color }
-                    })
-                    .collect(),
-            ),
+            points: points
+                .into_iter()
+                .map(|point| {
+                    let position = Vec4::new(point.x as f32, point.y as f32, point.z as f32, 1.0);
+                    let color = compute_point_color(&point);
+                    PointData { position, color }
+                })
+                .collect::<Vec<_>>()
+                .into(),
+            normals: None,
+            classifications: Some(Arc::new(classifications)),
         })
     }
 }
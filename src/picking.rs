@@ -0,0 +1,129 @@
+//! A [`bevy_picking`] backend for the [`PointCloud3d`](crate::point_cloud::PointCloud3d) and
+//! [`PointCloudOctree3d`](crate::pointcloud_octree::component::PointCloudOctree3d) entities,
+//! casting the pointer rays with [`PointCloudRayCast`].
+//!
+//! The `position` reported in the [`HitData`] is the world position of the picked point, and
+//! the `depth` its distance along the ray. The index of the picked point, and its octree node,
+//! are kept in [`PointCloudPickingHits`] for the observers of the pointer events.
+//!
+//! Picking can be disabled for individual entities by adding [`Pickable::IGNORE`], or made
+//! opt-in with [`PointCloudPickingSettings::require_markers`].
+
+use bevy_app::prelude::*;
+use bevy_camera::{visibility::RenderLayers, Camera};
+use bevy_ecs::prelude::*;
+use bevy_picking::{
+    backend::{ray::RayMap, HitData, PointerHits},
+    pointer::PointerId,
+    Pickable, PickingSystems,
+};
+use bevy_platform::collections::HashMap;
+use bevy_reflect::prelude::*;
+
+use crate::raycast::{PointCloudRayCast, PointCloudRayCastSettings, PointCloudRayHit};
+
+/// An optional component that marks the cameras used by the [`PointCloudPickingPlugin`].
+///
+/// Only needed if [`PointCloudPickingSettings::require_markers`] is set to `true`.
+#[derive(Debug, Clone, Default, Component, Reflect)]
+#[reflect(Debug, Default, Component)]
+pub struct PointCloudPickingCamera;
+
+/// Runtime settings of the [`PointCloudPickingPlugin`].
+#[derive(Resource, Reflect)]
+#[reflect(Resource, Default)]
+pub struct PointCloudPickingSettings {
+    /// Only consider the cameras marked with [`PointCloudPickingCamera`] and the entities marked
+    /// with [`Pickable`]
+    pub require_markers: bool,
+    /// Maximum distance between the pointer ray and a picked point, in world units
+    pub pick_radius: f32,
+}
+
+impl Default for PointCloudPickingSettings {
+    fn default() -> Self {
+        Self {
+            require_markers: false,
+            pick_radius: 0.1,
+        }
+    }
+}
+
+/// The point picked by each pointer during the last backend update.
+#[derive(Resource, Debug, Default)]
+pub struct PointCloudPickingHits {
+    pub hits: HashMap<PointerId, PointCloudRayHit>,
+}
+
+impl PointCloudPickingHits {
+    /// Returns the point picked by a pointer, if any.
+    pub fn get(&self, pointer_id: PointerId) -> Option<&PointCloudRayHit> {
+        self.hits.get(&pointer_id)
+    }
+}
+
+/// Adds the point cloud picking backend.
+#[derive(Clone, Default)]
+pub struct PointCloudPickingPlugin;
+
+impl Plugin for PointCloudPickingPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<PointCloudPickingCamera>()
+            .register_type::<PointCloudPickingSettings>()
+            .init_resource::<PointCloudPickingSettings>()
+            .init_resource::<PointCloudPickingHits>()
+            .add_systems(PreUpdate, update_hits.in_set(PickingSystems::Backend));
+    }
+}
+
+/// Casts the pointer rays against the point clouds and sends the [`PointerHits`].
+#[allow(clippy::too_many_arguments)]
+pub fn update_hits(
+    settings: Res<PointCloudPickingSettings>,
+    ray_map: Res<RayMap>,
+    picking_cameras: Query<(&Camera, Has<PointCloudPickingCamera>, Option<&RenderLayers>)>,
+    pickables: Query<&Pickable>,
+    layers: Query<&RenderLayers>,
+    ray_cast: PointCloudRayCast,
+    mut picking_hits: ResMut<PointCloudPickingHits>,
+    mut pointer_hits_writer: MessageWriter<PointerHits>,
+) {
+    picking_hits.hits.clear();
+
+    for (&ray_id, &ray) in ray_map.iter() {
+        let Ok((camera, camera_can_pick, camera_layers)) = picking_cameras.get(ray_id.camera)
+        else {
+            continue;
+        };
+        if settings.require_markers && !camera_can_pick {
+            continue;
+        }
+        let camera_layers = camera_layers.cloned().unwrap_or_default();
+
+        let filter = |entity| {
+            let pickable = pickables.get(entity).ok();
+            let marker_requirement = !settings.require_markers || pickable.is_some();
+            // entities without render layers are on the default layer 0
+            let entity_layers = layers.get(entity).cloned().unwrap_or_default();
+
+            marker_requirement
+                && camera_layers.intersects(&entity_layers)
+                && pickable.is_none_or(|pickable| pickable.is_hoverable)
+        };
+        let ray_cast_settings = PointCloudRayCastSettings::default()
+            .with_pick_radius(settings.pick_radius)
+            .with_camera(ray_id.camera)
+            .with_filter(&filter);
+
+        let Some(hit) = ray_cast.cast_ray(ray, &ray_cast_settings) else {
+            continue;
+        };
+        let hit_data = HitData::new(ray_id.camera, hit.distance, Some(hit.position), hit.normal);
+        pointer_hits_writer.write(PointerHits::new(
+            ray_id.pointer,
+            vec![(hit.entity, hit_data)],
+            camera.order as f32,
+        ));
+        picking_hits.hits.insert(ray_id.pointer, hit);
+    }
+}
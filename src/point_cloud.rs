@@ -1,11 +1,21 @@
+use std::ops::Range;
+
 use bevy_asset::{AsAssetId, Asset, AssetId, Handle};
+use bevy_camera::primitives::Aabb;
 use bevy_derive::{Deref, DerefMut};
 use bevy_ecs::{component::Component, reflect::ReflectComponent};
-use bevy_math::Vec3;
+use bevy_math::{UVec3, Vec3};
 use bevy_reflect::{std_traits::ReflectDefault, Reflect};
 use bevy_transform::prelude::*;
 use bytemuck::{Pod, Zeroable};
 
+use neighbours::NeighbourGrid;
+
+pub(crate) mod neighbours;
+
+/// Size of the grid used to sort points spatially, 21 bits per axis to fit a 64 bits Morton code.
+const MORTON_GRID_SIZE: f32 = (1 << 21) as f32;
+
 pub const QUAD_POSITIONS: &[[f32; 3]] = &[
     [-0.5, -0.5, 0.0],
     [0.5, -0.5, 0.0],
@@ -14,9 +24,264 @@ pub const QUAD_POSITIONS: &[[f32; 3]] = &[
 ];
 pub const QUAD_INDICES: &[u32] = &[0, 1, 2, 2, 3, 0];
 
-#[derive(Debug, Clone, Asset, Reflect)]
+/// A point cloud asset, rendered by chunks of consecutive points.
+///
+/// Each chunk is culled against the view frustum with the bounding box of its points, which only
+/// discards anything when the chunks are spatially coherent: call [`PointCloud::sort_spatially`]
+/// on large point clouds whose points are not already ordered that way.
+#[derive(Debug, Clone, Default, Asset, Reflect)]
 pub struct PointCloud {
+    /// Points of the cloud.
+    ///
+    /// Mutating this directly (instead of using [`PointCloud::push`], [`PointCloud::extend`],
+    /// [`PointCloud::overwrite`] or [`PointCloud::truncate`]) results in a full re-upload to the GPU.
     pub points: Vec<PointCloudData>,
+    /// Unit normals of the surface at the points, zero when unknown.
+    ///
+    /// When set, it has as many normals as there are points, the points added through the
+    /// [`PointCloud`] API getting unknown normals.
+    pub normals: Option<Vec<Vec3>>,
+    /// ASPRS classes of the points, as in LAS files, when they are known.
+    ///
+    /// When set, it has as many classes as there are points, the points added through the
+    /// [`PointCloud`] API being created never classified, class 0.
+    pub classifications: Option<Vec<u8>>,
+    #[reflect(ignore)]
+    changes: PointCloudChanges,
+}
+
+impl PointCloud {
+    pub fn new(points: Vec<PointCloudData>) -> Self {
+        Self {
+            points,
+            normals: None,
+            classifications: None,
+            changes: PointCloudChanges::default(),
+        }
+    }
+
+    /// Set the normals of the points, padded with unknown normals or truncated to the number of
+    /// points. This results in a full re-upload to the GPU.
+    pub fn with_normals(mut self, mut normals: Vec<Vec3>) -> Self {
+        normals.resize(self.points.len(), Vec3::ZERO);
+        self.normals = Some(normals);
+        self.changes = PointCloudChanges::default();
+        self
+    }
+
+    /// Set the classes of the points, padded with class 0 or truncated to the number of points.
+    pub fn with_classifications(mut self, mut classifications: Vec<u8>) -> Self {
+        classifications.resize(self.points.len(), 0);
+        self.classifications = Some(classifications);
+        self
+    }
+
+    /// Append a point and mark it as dirty.
+    pub fn push(&mut self, point: PointCloudData) {
+        self.extend(std::iter::once(point));
+    }
+
+    /// Append points and mark them as dirty.
+    pub fn extend(&mut self, points: impl IntoIterator<Item = PointCloudData>) {
+        let start = self.points.len();
+        self.points.extend(points);
+        self.pad_streams();
+        self.changes.mark_dirty(start..self.points.len());
+    }
+
+    /// Overwrite the points starting at `start` and mark them as dirty.
+    ///
+    /// Points past the end of the cloud are appended.
+    pub fn overwrite(&mut self, start: usize, points: &[PointCloudData]) {
+        let start = start.min(self.points.len());
+        let end = start + points.len();
+        let overlap = end.min(self.points.len());
+        self.points[start..overlap].copy_from_slice(&points[..overlap - start]);
+        self.points.extend_from_slice(&points[overlap - start..]);
+        self.pad_streams();
+        self.changes.mark_dirty(start..end);
+    }
+
+    /// Shorten the cloud to `len` points, no upload is needed for this change.
+    pub fn truncate(&mut self, len: usize) {
+        self.points.truncate(len);
+        if let Some(normals) = &mut self.normals {
+            normals.truncate(len);
+        }
+        if let Some(classifications) = &mut self.classifications {
+            classifications.truncate(len);
+        }
+        self.changes.truncate(self.points.len());
+    }
+
+    /// Remove all points, keeping the GPU buffer for later reuse.
+    pub fn clear(&mut self) {
+        self.truncate(0);
+    }
+
+    /// Sort the points along a Morton curve, so that chunks of consecutive points are spatially
+    /// coherent and can be culled efficiently. This results in a full re-upload to the GPU.
+    pub fn sort_spatially(&mut self) {
+        let Some(aabb) = Aabb::enclosing(self.points.iter().map(|point| point.position)) else {
+            return;
+        };
+
+        let min = Vec3::from(aabb.min());
+        let scale =
+            MORTON_GRID_SIZE / Vec3::from(aabb.half_extents * 2.0).max(Vec3::splat(f32::EPSILON));
+        let morton_code = |point: &PointCloudData| {
+            let cell = ((point.position - min) * scale)
+                .as_uvec3()
+                .min(UVec3::splat(MORTON_GRID_SIZE as u32 - 1));
+            morton_encode(cell)
+        };
+
+        if self.normals.is_some() || self.classifications.is_some() {
+            // sort the normals and classes along with their points
+            let mut order: Vec<usize> = (0..self.points.len()).collect();
+            order.sort_by_cached_key(|index| morton_code(&self.points[*index]));
+            self.points = order.iter().map(|index| self.points[*index]).collect();
+            if let Some(normals) = &mut self.normals {
+                *normals = order.iter().map(|index| normals[*index]).collect();
+            }
+            if let Some(classifications) = &mut self.classifications {
+                *classifications = order.iter().map(|index| classifications[*index]).collect();
+            }
+        } else {
+            self.points.sort_by_cached_key(morton_code);
+        }
+
+        self.changes = PointCloudChanges::default();
+    }
+
+    /// Set the size of each point to the mean distance to its `k` nearest neighbours, to be used
+    /// with [`PointSizing::Adaptive`]. This results in a full re-upload to the GPU.
+    ///
+    /// [`PointSizing::Adaptive`]: crate::point_cloud_material::PointSizing::Adaptive
+    pub fn compute_adaptive_point_sizes(&mut self, k: usize) {
+        let positions: Vec<Vec3> = self.points.iter().map(|point| point.position).collect();
+        let Some(grid) = NeighbourGrid::new(&positions) else {
+            return;
+        };
+
+        let mut neighbours = Vec::with_capacity(k);
+        for (index, point) in self.points.iter_mut().enumerate() {
+            grid.k_nearest(index, k, &mut neighbours);
+            if !neighbours.is_empty() {
+                point.point_size = neighbours.iter().map(|(distance, _)| distance).sum::<f32>()
+                    / neighbours.len() as f32;
+            }
+        }
+
+        self.changes = PointCloudChanges::default();
+    }
+
+    /// Bounding boxes of the consecutive chunks of `chunk_size` points, in local space.
+    pub fn chunk_bounds(&self, chunk_size: usize) -> Vec<Option<Aabb>> {
+        self.points
+            .chunks(chunk_size.max(1))
+            .map(|chunk| Aabb::enclosing(chunk.iter().map(|point| point.position)))
+            .collect()
+    }
+
+    /// The normal of the point at `index`, zero when unknown.
+    pub fn normal(&self, index: usize) -> Vec3 {
+        self.normals
+            .as_ref()
+            .and_then(|normals| normals.get(index))
+            .copied()
+            .unwrap_or(Vec3::ZERO)
+    }
+
+    /// The class of the point at `index`, if known.
+    pub fn classification(&self, index: usize) -> Option<u8> {
+        self.classifications
+            .as_ref()
+            .and_then(|classifications| classifications.get(index))
+            .copied()
+    }
+
+    /// Give unknown normals and class 0 to the points added without them.
+    fn pad_streams(&mut self) {
+        if let Some(normals) = &mut self.normals {
+            normals.resize(self.points.len(), Vec3::ZERO);
+        }
+        if let Some(classifications) = &mut self.classifications {
+            classifications.resize(self.points.len(), 0);
+        }
+    }
+
+    pub fn changes(&self) -> &PointCloudChanges {
+        &self.changes
+    }
+
+    /// Forget the recorded changes once they have been extracted to the render world.
+    pub(crate) fn clear_changes(&mut self) {
+        self.changes = PointCloudChanges::default();
+    }
+}
+
+impl From<Vec<PointCloudData>> for PointCloud {
+    fn from(points: Vec<PointCloudData>) -> Self {
+        Self::new(points)
+    }
+}
+
+/// Ranges of points changed through the [`PointCloud`] API since the last extraction.
+#[derive(Debug, Clone, Default)]
+pub struct PointCloudChanges {
+    tracked: bool,
+    dirty_ranges: Vec<Range<usize>>,
+}
+
+impl PointCloudChanges {
+    /// Returns `true` if the changes have been recorded, so that only the dirty ranges
+    /// need to be uploaded.
+    pub fn is_tracked(&self) -> bool {
+        self.tracked
+    }
+
+    /// Sorted, non overlapping ranges of points to upload.
+    pub fn dirty_ranges(&self) -> &[Range<usize>] {
+        &self.dirty_ranges
+    }
+
+    /// Number of points to upload.
+    pub fn dirty_len(&self) -> usize {
+        self.dirty_ranges.iter().map(|range| range.len()).sum()
+    }
+
+    fn mark_dirty(&mut self, range: Range<usize>) {
+        self.tracked = true;
+        if range.is_empty() {
+            return;
+        }
+
+        // keep ranges sorted and merge overlapping or adjacent ones
+        let first = self
+            .dirty_ranges
+            .partition_point(|dirty| dirty.end < range.start);
+        let last = self
+            .dirty_ranges
+            .partition_point(|dirty| dirty.start <= range.end);
+
+        let merged = if first < last {
+            self.dirty_ranges[first].start.min(range.start)
+                ..self.dirty_ranges[last - 1].end.max(range.end)
+        } else {
+            range
+        };
+        self.dirty_ranges
+            .splice(first..last, std::iter::once(merged));
+    }
+
+    fn truncate(&mut self, len: usize) {
+        self.tracked = true;
+        self.dirty_ranges.retain_mut(|range| {
+            range.end = range.end.min(len);
+            range.start < range.end
+        });
+    }
 }
 
 #[derive(Debug, Clone, Copy, Reflect, Pod, Zeroable)]
@@ -51,3 +316,171 @@ impl AsAssetId for PointCloud3d {
         self.id()
     }
 }
+
+/// Interleave the bits of the cell coordinates.
+fn morton_encode(cell: UVec3) -> u64 {
+    fn split_by_3(value: u32) -> u64 {
+        let mut x = value as u64 & 0x1f_ffff;
+        x = (x | (x << 32)) & 0x1f_0000_0000_ffff;
+        x = (x | (x << 16)) & 0x1f_0000_ff00_00ff;
+        x = (x | (x << 8)) & 0x100f_00f0_0f00_f00f;
+        x = (x | (x << 4)) & 0x10c3_0c30_c30c_30c3;
+        x = (x | (x << 2)) & 0x1249_2492_4924_9249;
+        x
+    }
+
+    split_by_3(cell.x) | (split_by_3(cell.y) << 1) | (split_by_3(cell.z) << 2)
+}
+
+#[cfg(test)]
+// the dirty ranges are compared to arrays of ranges
+#[allow(clippy::single_range_in_vec_init)]
+mod tests {
+    use super::*;
+
+    fn point(position: Vec3) -> PointCloudData {
+        PointCloudData {
+            position,
+            point_size: 1.0,
+            color: [1.0; 4],
+        }
+    }
+
+    #[test]
+    fn dirty_ranges_are_sorted_and_merged() {
+        let mut changes = PointCloudChanges::default();
+        assert!(!changes.is_tracked());
+
+        changes.mark_dirty(10..20);
+        changes.mark_dirty(0..5);
+        changes.mark_dirty(30..40);
+        assert!(changes.is_tracked());
+        assert_eq!(changes.dirty_ranges(), &[0..5, 10..20, 30..40]);
+
+        // adjacent ranges are merged
+        changes.mark_dirty(5..8);
+        assert_eq!(changes.dirty_ranges(), &[0..8, 10..20, 30..40]);
+
+        // a range overlapping several ones merges them
+        changes.mark_dirty(15..32);
+        assert_eq!(changes.dirty_ranges(), &[0..8, 10..40]);
+
+        // empty and contained ranges change nothing
+        changes.mark_dirty(50..50);
+        changes.mark_dirty(12..14);
+        assert_eq!(changes.dirty_ranges(), &[0..8, 10..40]);
+        assert_eq!(changes.dirty_len(), 38);
+    }
+
+    #[test]
+    fn truncate_clips_dirty_ranges() {
+        let mut changes = PointCloudChanges::default();
+        changes.mark_dirty(0..5);
+        changes.mark_dirty(10..20);
+        changes.mark_dirty(30..40);
+
+        changes.truncate(15);
+        assert_eq!(changes.dirty_ranges(), &[0..5, 10..15]);
+        changes.truncate(10);
+        assert_eq!(changes.dirty_ranges(), &[0..5]);
+
+        // truncating a cloud without changes still records that nothing is to upload
+        let mut changes = PointCloudChanges::default();
+        changes.truncate(0);
+        assert!(changes.is_tracked());
+        assert_eq!(changes.dirty_len(), 0);
+    }
+
+    #[test]
+    fn point_cloud_records_changes() {
+        let mut point_cloud = PointCloud::new(vec![point(Vec3::ZERO); 4]);
+        assert!(!point_cloud.changes().is_tracked());
+
+        point_cloud.overwrite(1, &[point(Vec3::X); 2]);
+        point_cloud.push(point(Vec3::Y));
+        assert_eq!(point_cloud.changes().dirty_ranges(), &[1..3, 4..5]);
+
+        // points past the end are appended
+        point_cloud.overwrite(4, &[point(Vec3::Z); 3]);
+        assert_eq!(point_cloud.points.len(), 7);
+        assert_eq!(point_cloud.changes().dirty_ranges(), &[1..3, 4..7]);
+
+        point_cloud.truncate(2);
+        assert_eq!(point_cloud.changes().dirty_ranges(), &[1..2]);
+
+        point_cloud.clear_changes();
+        assert!(!point_cloud.changes().is_tracked());
+    }
+
+    #[test]
+    fn streams_follow_the_points() {
+        let mut point_cloud = PointCloud::new(vec![point(Vec3::ZERO); 2])
+            .with_normals(vec![Vec3::X])
+            .with_classifications(vec![2, 6, 9]);
+        assert_eq!(point_cloud.normals.as_ref().unwrap().len(), 2);
+        assert_eq!(point_cloud.classifications.as_ref().unwrap(), &[2, 6]);
+
+        point_cloud.push(point(Vec3::ONE));
+        assert_eq!(point_cloud.normal(1), Vec3::ZERO);
+        assert_eq!(point_cloud.normal(2), Vec3::ZERO);
+        assert_eq!(point_cloud.classification(2), Some(0));
+
+        point_cloud.truncate(1);
+        assert_eq!(point_cloud.normals.as_ref().unwrap(), &[Vec3::X]);
+        assert_eq!(point_cloud.classification(1), None);
+    }
+
+    #[test]
+    fn morton_codes_interleave_the_axes() {
+        assert_eq!(morton_encode(UVec3::ZERO), 0);
+        assert_eq!(morton_encode(UVec3::X), 0b001);
+        assert_eq!(morton_encode(UVec3::Y), 0b010);
+        assert_eq!(morton_encode(UVec3::Z), 0b100);
+        assert_eq!(morton_encode(UVec3::new(3, 0, 1)), 0b001_101);
+        assert_eq!(
+            morton_encode(UVec3::splat(MORTON_GRID_SIZE as u32 - 1)),
+            u64::MAX >> 1
+        );
+        // the bits beyond the grid are ignored
+        assert_eq!(morton_encode(UVec3::new(1 << 21, 0, 0)), 0);
+    }
+
+    #[test]
+    fn sort_spatially_keeps_the_streams_aligned() {
+        // a 4×4×4 grid of points, in reverse order
+        let positions: Vec<Vec3> = (0..64)
+            .rev()
+            .map(|index| {
+                Vec3::new(
+                    (index % 4) as f32,
+                    (index / 4 % 4) as f32,
+                    (index / 16) as f32,
+                )
+            })
+            .collect();
+        let class = |position: Vec3| (position.x + 4.0 * position.y + 16.0 * position.z) as u8;
+        let normal = |position: Vec3| (position + 1.0).normalize();
+
+        let mut point_cloud =
+            PointCloud::new(positions.iter().map(|position| point(*position)).collect())
+                .with_normals(positions.iter().map(|position| normal(*position)).collect())
+                .with_classifications(positions.iter().map(|position| class(*position)).collect());
+        point_cloud.sort_spatially();
+
+        let codes: Vec<u64> = point_cloud
+            .points
+            .iter()
+            .map(|point| morton_encode(point.position.as_uvec3()))
+            .collect();
+        assert!(codes.is_sorted());
+        assert_eq!(point_cloud.points[0].position, Vec3::ZERO);
+        for (index, point) in point_cloud.points.iter().enumerate() {
+            assert_eq!(point_cloud.normal(index), normal(point.position));
+            assert_eq!(
+                point_cloud.classification(index),
+                Some(class(point.position))
+            );
+        }
+        assert!(!point_cloud.changes().is_tracked());
+    }
+}
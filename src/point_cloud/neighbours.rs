@@ -0,0 +1,274 @@
+use bevy_camera::primitives::Aabb;
+use bevy_math::{IVec3, Mat3, UVec3, Vec3};
+
+/// Average number of points per cell of a [`NeighbourGrid`].
+const POINTS_PER_CELL: f32 = 4.0;
+
+/// Uniform grid over a set of positions to answer nearest neighbours queries.
+pub(crate) struct NeighbourGrid<'a> {
+    positions: &'a [Vec3],
+    min: Vec3,
+    cell_size: f32,
+    dims: UVec3,
+    /// Start of each cell in `indices`, with a trailing end marker.
+    cell_starts: Vec<u32>,
+    /// Indices of the positions, sorted by cell.
+    indices: Vec<u32>,
+}
+
+impl<'a> NeighbourGrid<'a> {
+    pub fn new(positions: &'a [Vec3]) -> Option<Self> {
+        let aabb = Aabb::enclosing(positions.iter().copied())?;
+        let min = Vec3::from(aabb.min());
+        let extents = Vec3::from(aabb.half_extents * 2.0);
+
+        // pick the cell size assuming the points are spread along a line, a surface or a volume,
+        // the largest one being the one matching the actual distribution
+        let cells = (positions.len() as f32 / POINTS_PER_CELL).max(1.0);
+        let mut sorted = extents.to_array();
+        sorted.sort_by(|a, b| b.total_cmp(a));
+        let cell_size = (sorted[0] / cells)
+            .max((sorted[0] * sorted[1] / cells).sqrt())
+            .max((sorted[0] * sorted[1] * sorted[2] / cells).cbrt())
+            .max(f32::EPSILON);
+
+        let dims = (extents / cell_size).as_uvec3() + UVec3::ONE;
+
+        let mut grid = Self {
+            positions,
+            min,
+            cell_size,
+            dims,
+            cell_starts: vec![0; (dims.x * dims.y * dims.z) as usize + 1],
+            indices: vec![0; positions.len()],
+        };
+
+        // counting sort of the positions by cell
+        let cell_indices: Vec<usize> = positions
+            .iter()
+            .map(|position| grid.cell_index(grid.cell(*position).as_uvec3()))
+            .collect();
+        for &cell_index in &cell_indices {
+            grid.cell_starts[cell_index + 1] += 1;
+        }
+        for i in 1..grid.cell_starts.len() {
+            grid.cell_starts[i] += grid.cell_starts[i - 1];
+        }
+        let mut cursors = grid.cell_starts.clone();
+        for (index, &cell_index) in cell_indices.iter().enumerate() {
+            grid.indices[cursors[cell_index] as usize] = index as u32;
+            cursors[cell_index] += 1;
+        }
+
+        Some(grid)
+    }
+
+    /// Find the `k` nearest neighbours of the position at `index`, excluding itself.
+    ///
+    /// `neighbours` is filled with `(distance, index)` pairs sorted by increasing distance.
+    pub fn k_nearest(&self, index: usize, k: usize, neighbours: &mut Vec<(f32, u32)>) {
+        neighbours.clear();
+        if k == 0 {
+            return;
+        }
+
+        let position = self.positions[index];
+        let cell = self.cell(position);
+        let max_ring = self.dims.max_element() as i32;
+
+        for ring in 0..=max_ring {
+            for z in -ring..=ring {
+                for y in -ring..=ring {
+                    for x in -ring..=ring {
+                        // only visit the shell of the ring
+                        if x.abs() != ring && y.abs() != ring && z.abs() != ring {
+                            continue;
+                        }
+                        let neighbour_cell = cell + IVec3::new(x, y, z);
+                        if neighbour_cell.cmplt(IVec3::ZERO).any()
+                            || neighbour_cell.as_uvec3().cmpge(self.dims).any()
+                        {
+                            continue;
+                        }
+
+                        let cell_index = self.cell_index(neighbour_cell.as_uvec3());
+                        let start = self.cell_starts[cell_index] as usize;
+                        let end = self.cell_starts[cell_index + 1] as usize;
+                        for &neighbour in &self.indices[start..end] {
+                            if neighbour as usize == index {
+                                continue;
+                            }
+                            let distance = position.distance(self.positions[neighbour as usize]);
+                            if neighbours.len() == k && distance >= neighbours[k - 1].0 {
+                                continue;
+                            }
+                            let at = neighbours.partition_point(|(d, _)| *d <= distance);
+                            neighbours.insert(at, (distance, neighbour));
+                            neighbours.truncate(k);
+                        }
+                    }
+                }
+            }
+
+            // points outside of the visited rings are at least this far away
+            if neighbours.len() == k && neighbours[k - 1].0 <= ring as f32 * self.cell_size {
+                break;
+            }
+        }
+    }
+
+    fn cell(&self, position: Vec3) -> IVec3 {
+        ((position - self.min) / self.cell_size)
+            .as_ivec3()
+            .clamp(IVec3::ZERO, self.dims.as_ivec3() - IVec3::ONE)
+    }
+
+    fn cell_index(&self, cell: UVec3) -> usize {
+        (cell.x + self.dims.x * (cell.y + self.dims.y * cell.z)) as usize
+    }
+}
+
+/// Estimate the normal of the surface at the position at `index` from its neighbours, as found by
+/// [`NeighbourGrid::k_nearest`].
+///
+/// This is the direction of least variance of the positions, the eigenvector of the smallest
+/// eigenvalue of their covariance matrix. Its sign is arbitrary, and it is zero when the
+/// neighbours are all at the same position.
+pub(crate) fn estimate_normal(positions: &[Vec3], index: usize, neighbours: &[(f32, u32)]) -> Vec3 {
+    if neighbours.len() < 2 {
+        return Vec3::ZERO;
+    }
+
+    // relative to the position to keep the precision far from the origin
+    let origin = positions[index];
+    let offsets: Vec<Vec3> = std::iter::once(Vec3::ZERO)
+        .chain(
+            neighbours
+                .iter()
+                .map(|&(_, neighbour)| positions[neighbour as usize] - origin),
+        )
+        .collect();
+    let centroid = offsets.iter().sum::<Vec3>() / offsets.len() as f32;
+
+    let mut covariance = Mat3::ZERO;
+    for offset in &offsets {
+        let centered = *offset - centroid;
+        covariance += Mat3::from_cols(
+            centered * centered.x,
+            centered * centered.y,
+            centered * centered.z,
+        );
+    }
+
+    smallest_eigenvector(covariance / offsets.len() as f32)
+}
+
+/// Eigenvector of the smallest eigenvalue of a symmetric matrix, with the closed form of the
+/// eigenvalues of 3x3 symmetric matrices.
+fn smallest_eigenvector(matrix: Mat3) -> Vec3 {
+    let off_diagonal = matrix.y_axis.x.powi(2) + matrix.z_axis.x.powi(2) + matrix.z_axis.y.powi(2);
+    let mean = (matrix.x_axis.x + matrix.y_axis.y + matrix.z_axis.z) / 3.0;
+    let deviation = (((matrix.x_axis.x - mean).powi(2)
+        + (matrix.y_axis.y - mean).powi(2)
+        + (matrix.z_axis.z - mean).powi(2)
+        + 2.0 * off_diagonal)
+        / 6.0)
+        .sqrt();
+    if deviation <= f32::EPSILON * mean.abs() || deviation == 0.0 {
+        // all the directions have the same variance
+        return Vec3::ZERO;
+    }
+
+    let scaled = (matrix - Mat3::from_diagonal(Vec3::splat(mean))) * (1.0 / deviation);
+    let angle = (scaled.determinant() / 2.0).clamp(-1.0, 1.0).acos() / 3.0;
+    let eigenvalue = mean + 2.0 * deviation * (angle + 2.0 * std::f32::consts::FRAC_PI_3).cos();
+
+    // the eigenvector is orthogonal to the rows of the matrix minus the eigenvalue, which span a
+    // plane, so take the most reliable cross product of two of them
+    let rows = matrix - Mat3::from_diagonal(Vec3::splat(eigenvalue));
+    [
+        rows.x_axis.cross(rows.y_axis),
+        rows.x_axis.cross(rows.z_axis),
+        rows.y_axis.cross(rows.z_axis),
+    ]
+    .into_iter()
+    .max_by(|a, b| a.length_squared().total_cmp(&b.length_squared()))
+    .unwrap_or_default()
+    .normalize_or_zero()
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy_math::{Quat, Vec3};
+
+    use super::*;
+
+    fn assert_parallel(actual: Vec3, expected: Vec3) {
+        assert!(
+            actual.dot(expected).abs() > 0.999,
+            "{actual} is not parallel to {expected}"
+        );
+    }
+
+    #[test]
+    fn smallest_eigenvector_of_diagonal_matrix() {
+        let matrix = Mat3::from_diagonal(Vec3::new(3.0, 1.0, 2.0));
+        assert_parallel(smallest_eigenvector(matrix), Vec3::Y);
+    }
+
+    #[test]
+    fn smallest_eigenvector_of_rotated_matrix() {
+        let rotation = Mat3::from_quat(Quat::from_euler(bevy_math::EulerRot::XYZ, 0.3, -0.7, 1.1));
+        let matrix =
+            rotation * Mat3::from_diagonal(Vec3::new(5.0, 2.0, 0.1)) * rotation.transpose();
+        assert_parallel(smallest_eigenvector(matrix), rotation.z_axis);
+    }
+
+    #[test]
+    fn smallest_eigenvector_of_degenerate_matrices() {
+        // every direction has the same variance
+        assert_eq!(smallest_eigenvector(Mat3::IDENTITY), Vec3::ZERO);
+        assert_eq!(smallest_eigenvector(Mat3::ZERO), Vec3::ZERO);
+        // the smallest eigenvalue is double, any direction of its plane would do
+        let eigenvector = smallest_eigenvector(Mat3::from_diagonal(Vec3::new(1.0, 0.0, 0.0)));
+        assert!(eigenvector.dot(Vec3::X).abs() < 1e-3);
+    }
+
+    #[test]
+    fn estimate_normal_of_plane() {
+        let rotation = Quat::from_euler(bevy_math::EulerRot::XYZ, 0.4, 0.2, -0.5);
+        let offset = Vec3::new(1000.0, -200.0, 50.0);
+        let positions: Vec<Vec3> = (0..10)
+            .flat_map(|x| (0..10).map(move |y| Vec3::new(x as f32, y as f32, 0.0) * 0.1))
+            .map(|position| rotation * position + offset)
+            .collect();
+        let grid = NeighbourGrid::new(&positions).unwrap();
+
+        let mut neighbours = Vec::new();
+        for index in [0, 45, 99] {
+            grid.k_nearest(index, 8, &mut neighbours);
+            assert_parallel(
+                estimate_normal(&positions, index, &neighbours),
+                rotation * Vec3::Z,
+            );
+        }
+    }
+
+    #[test]
+    fn estimate_normal_of_degenerate_neighbourhoods() {
+        let positions = [Vec3::ZERO, Vec3::X, Vec3::X * 2.0, Vec3::X * 3.0];
+
+        // not enough neighbours
+        assert_eq!(estimate_normal(&positions, 0, &[]), Vec3::ZERO);
+        assert_eq!(estimate_normal(&positions, 0, &[(1.0, 1)]), Vec3::ZERO);
+        // the points are on a line, any direction orthogonal to it would do
+        let normal = estimate_normal(&positions, 0, &[(1.0, 1), (2.0, 2), (3.0, 3)]);
+        assert!(normal.dot(Vec3::X).abs() < 1e-3);
+        // the points are at the same position
+        let positions = [Vec3::ONE; 4];
+        assert_eq!(
+            estimate_normal(&positions, 0, &[(0.0, 1), (0.0, 2), (0.0, 3)]),
+            Vec3::ZERO
+        );
+    }
+}
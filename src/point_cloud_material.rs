@@ -2,19 +2,86 @@ use bevy_asset::{Asset, AssetId, Handle};
 use bevy_derive::{Deref, DerefMut};
 use bevy_ecs::{component::Component, reflect::ReflectComponent};
 use bevy_reflect::{std_traits::ReflectDefault, Reflect};
-use bevy_render::render_resource::ShaderType;
-use bytemuck::{Pod, Zeroable};
 
-// This is the component that will get passed to the shader
-#[derive(Asset, Reflect, ShaderType, Debug, Clone, Default, Copy, Pod, Zeroable)]
-#[repr(C)]
+#[derive(Asset, Reflect, Debug, Clone, Default, Copy)]
 pub struct PointCloudMaterial {
+    /// Size of the points, its unit depends on [`PointCloudMaterial::sizing`]
     pub point_size: f32,
+    /// Minimum size of the points in pixels
     pub min_point_size: f32,
+    /// Maximum size of the points in pixels, no clamping is applied if zero
     pub max_point_size: f32,
-    // WebGL2 structs must be 16 byte aligned.
-    #[cfg(all(feature = "webgl", target_arch = "wasm32", not(feature = "webgpu")))]
-    pub _webgl2_padding: f32,
+    /// How points of a [`PointCloud3d`](crate::point_cloud::PointCloud3d) are sized,
+    /// octree points are always sized from the octree spacing.
+    pub sizing: PointSizing,
+    /// Shape of the points
+    pub shape: PointShape,
+    /// How overlapping points are blended
+    pub splatting: PointSplatting,
+    /// Whether the points are drawn into the shadow maps of the directional lights
+    pub cast_shadows: bool,
+    /// Whether the points are darkened by the shadows of the directional lights
+    pub receive_shadows: bool,
+    /// How the points are shaded by the lights of the view
+    pub lighting: PointLighting,
+    /// How the points are oriented
+    pub geometry: PointGeometry,
+}
+
+#[derive(Reflect, Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum PointSizing {
+    /// Size relative to the smallest viewport dimension.
+    /// The size of each point is used if [`PointCloudMaterial::point_size`] is not positive.
+    #[default]
+    Viewport,
+    /// Constant size in pixels, whatever the distance to the camera.
+    FixedPixels,
+    /// Size in world units, scaled by the transform of the point cloud.
+    WorldSpace,
+    /// Size of each point in world units, scaled by [`PointCloudMaterial::point_size`],
+    /// typically computed from the neighbour spacing with [`PointCloud::compute_adaptive_point_sizes`].
+    ///
+    /// [`PointCloud::compute_adaptive_point_sizes`]: crate::point_cloud::PointCloud::compute_adaptive_point_sizes
+    Adaptive,
+}
+
+#[derive(Reflect, Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum PointShape {
+    Square,
+    #[default]
+    Circle,
+    /// Circle with a depth offset towards its border, giving points a rounded look.
+    Paraboloid,
+}
+
+#[derive(Reflect, Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum PointSplatting {
+    /// Blend the colors of the points close to the nearest surface, weighted by the distance to
+    /// their center. This smooths the result but is more expensive.
+    #[default]
+    Weighted,
+    /// Only draw the nearest point of each pixel.
+    Nearest,
+}
+
+#[derive(Reflect, Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum PointLighting {
+    /// Draw the color of the points as is.
+    #[default]
+    Unlit,
+    /// Diffuse shading of the points from their normal, with the ambient, directional, point and
+    /// spot lights of the view. Points without a normal are drawn unlit.
+    Lit,
+}
+
+#[derive(Reflect, Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum PointGeometry {
+    /// Points always face the camera.
+    #[default]
+    ScreenAligned,
+    /// Points are discs, or squares, lying on the surface given by their normal, which gives a
+    /// closed surface when the points are large enough. Points without a normal face the camera.
+    Surfel,
 }
 
 #[derive(Component, Clone, Debug, Default, Deref, DerefMut, Reflect, PartialEq, Eq)]
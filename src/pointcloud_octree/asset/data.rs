@@ -1,11 +1,15 @@
-use std::sync::Arc;
+use std::{borrow::Cow, sync::Arc};
 
-use bevy_math::prelude::*;
+use bevy_camera::primitives::Aabb;
+use bevy_math::{prelude::*, DVec3};
 use bevy_reflect::TypePath;
 use bevy_render::render_resource::AsBindGroup;
 use bytemuck::{Pod, Zeroable};
 
-use crate::octree::node::NodeData;
+use crate::{
+    octree::node::NodeData,
+    pointcloud_octree::asset::normals::{NormalEstimation, NormalEstimationInput},
+};
 
 #[derive(Default, Debug, Clone, TypePath, AsBindGroup)]
 pub struct PointCloudNodeData {
@@ -17,7 +21,29 @@ pub struct PointCloudNodeData {
     #[uniform(2)]
     pub offset: f32,
     pub num_points: usize,
-    pub points: Arc<Vec<PointData>>,
+    pub points: NodePoints,
+    /// Octahedral normals of the points when they are known, see [`encode_normal`]
+    pub normals: Option<Arc<Vec<[u8; 2]>>>,
+    /// ASPRS classes of the points when they are known, as in LAS files
+    pub classifications: Option<Arc<Vec<u8>>>,
+}
+
+impl PointCloudNodeData {
+    /// The normal of the point at `index`, zero when unknown
+    pub fn normal(&self, index: usize) -> Vec3 {
+        self.normals
+            .as_ref()
+            .and_then(|normals| normals.get(index))
+            .map_or(Vec3::ZERO, |normal| decode_normal(*normal))
+    }
+
+    /// The class of the point at `index`, if known
+    pub fn classification(&self, index: usize) -> Option<u8> {
+        self.classifications
+            .as_ref()
+            .and_then(|classifications| classifications.get(index))
+            .copied()
+    }
 }
 
 #[derive(Default, Debug, Clone, Copy, Pod, Zeroable, TypePath)]
@@ -28,12 +54,319 @@ pub struct PointData {
     pub color: Vec4,
 }
 
+/// Layout of the points of an octree node, in memory or in the GPU buffer.
+///
+/// In the GPU buffer, the octahedral normal of each point is stored along with it.
+///
+/// Quantized formats need the bounds of their node to be rendered, so they are only supported
+/// as GPU formats without WebGL, which falls back to [`PointFormat::Full`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum PointFormat {
+    /// Full precision positions and colors, 32 bytes per point and 36 bytes on the GPU.
+    #[default]
+    Full,
+    /// Positions quantized to 16 bits relative to the node bounding box and 8 bits colors,
+    /// 12 bytes per point.
+    Quantized16,
+    /// Positions quantized to 32 bits relative to the node bounding box and 8 bits colors,
+    /// 16 bytes per point and 20 bytes on the GPU.
+    Quantized32,
+}
+
+impl PointFormat {
+    /// Size of a point in memory, in bytes.
+    pub fn point_size(&self) -> usize {
+        match self {
+            PointFormat::Full => size_of::<PointData>(),
+            PointFormat::Quantized16 => size_of::<QuantizedPointData16>(),
+            PointFormat::Quantized32 => size_of::<QuantizedPointData32>(),
+        }
+    }
+
+    /// Size of a point in the GPU buffer, with its normal, in bytes.
+    pub fn instance_size(&self) -> usize {
+        match self {
+            // the normal replaces the padding of the position
+            PointFormat::Quantized16 => self.point_size(),
+            PointFormat::Full | PointFormat::Quantized32 => self.point_size() + 4,
+        }
+    }
+
+    /// Offset of the normal in a point of the GPU buffer, in bytes.
+    pub fn normal_offset(&self) -> usize {
+        match self {
+            PointFormat::Quantized16 => 6,
+            PointFormat::Full | PointFormat::Quantized32 => self.point_size(),
+        }
+    }
+}
+
+/// A point with [`PointFormat::Quantized16`]
+#[derive(Default, Debug, Clone, Copy, Pod, Zeroable)]
+#[repr(C)]
+pub struct QuantizedPointData16 {
+    // position + padding
+    pub position: [u16; 4],
+    pub color: [u8; 4],
+}
+
+/// A point with [`PointFormat::Quantized32`]
+#[derive(Default, Debug, Clone, Copy, Pod, Zeroable)]
+#[repr(C)]
+pub struct QuantizedPointData32 {
+    pub position: [u32; 3],
+    pub color: [u8; 4],
+}
+
+/// The points of an octree node, either decoded or kept in a compact form.
+///
+/// Quantized positions are relative to the bounding box of the node.
+#[derive(Debug, Clone)]
+pub enum NodePoints {
+    Full(Arc<Vec<PointData>>),
+    Quantized16(Arc<Vec<QuantizedPointData16>>),
+    Quantized32(Arc<Vec<QuantizedPointData32>>),
+}
+
+impl Default for NodePoints {
+    fn default() -> Self {
+        NodePoints::Full(Arc::default())
+    }
+}
+
+impl From<Vec<PointData>> for NodePoints {
+    fn from(points: Vec<PointData>) -> Self {
+        NodePoints::Full(Arc::new(points))
+    }
+}
+
+impl NodePoints {
+    pub fn format(&self) -> PointFormat {
+        match self {
+            NodePoints::Full(_) => PointFormat::Full,
+            NodePoints::Quantized16(_) => PointFormat::Quantized16,
+            NodePoints::Quantized32(_) => PointFormat::Quantized32,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            NodePoints::Full(points) => points.len(),
+            NodePoints::Quantized16(points) => points.len(),
+            NodePoints::Quantized32(points) => points.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Size of the points in memory, in bytes.
+    pub fn size(&self) -> usize {
+        self.len() * self.format().point_size()
+    }
+
+    /// The raw bytes of the points, laid out as described by [`NodePoints::format`].
+    pub fn bytes(&self) -> &[u8] {
+        match self {
+            NodePoints::Full(points) => bytemuck::cast_slice(points),
+            NodePoints::Quantized16(points) => bytemuck::cast_slice(points),
+            NodePoints::Quantized32(points) => bytemuck::cast_slice(points),
+        }
+    }
+
+    /// Decode the points to full precision, `bounding_box` being the one of the node.
+    pub fn decode(&self, bounding_box: &Aabb) -> Cow<'_, [PointData]> {
+        let (min, size) = quantization_bounds(bounding_box);
+
+        match self {
+            NodePoints::Full(points) => Cow::Borrowed(points.as_slice()),
+            NodePoints::Quantized16(points) => Cow::Owned(
+                points
+                    .iter()
+                    .map(|point| PointData {
+                        position: dequantize_position(
+                            UVec3::new(
+                                point.position[0] as u32,
+                                point.position[1] as u32,
+                                point.position[2] as u32,
+                            ),
+                            min,
+                            size,
+                            u16::MAX as f64,
+                        ),
+                        color: dequantize_color(point.color),
+                    })
+                    .collect(),
+            ),
+            NodePoints::Quantized32(points) => Cow::Owned(
+                points
+                    .iter()
+                    .map(|point| PointData {
+                        position: dequantize_position(
+                            UVec3::from_array(point.position),
+                            min,
+                            size,
+                            u32::MAX as f64,
+                        ),
+                        color: dequantize_color(point.color),
+                    })
+                    .collect(),
+            ),
+        }
+    }
+
+    /// Convert the points to another format, `bounding_box` being the one of the node.
+    pub fn encode(&self, format: PointFormat, bounding_box: &Aabb) -> NodePoints {
+        if self.format() == format {
+            return self.clone();
+        }
+
+        let points = self.decode(bounding_box);
+        let (min, size) = quantization_bounds(bounding_box);
+
+        match format {
+            PointFormat::Full => points.into_owned().into(),
+            PointFormat::Quantized16 => NodePoints::Quantized16(Arc::new(
+                points
+                    .iter()
+                    .map(|point| {
+                        let position =
+                            quantize_position(point.position, min, size, u16::MAX as f64);
+                        QuantizedPointData16 {
+                            position: [position.x as u16, position.y as u16, position.z as u16, 0],
+                            color: quantize_color(point.color),
+                        }
+                    })
+                    .collect(),
+            )),
+            PointFormat::Quantized32 => NodePoints::Quantized32(Arc::new(
+                points
+                    .iter()
+                    .map(|point| {
+                        let position =
+                            quantize_position(point.position, min, size, u32::MAX as f64);
+                        QuantizedPointData32 {
+                            position: [position.x as u32, position.y as u32, position.z as u32],
+                            color: quantize_color(point.color),
+                        }
+                    })
+                    .collect(),
+            )),
+        }
+    }
+}
+
+/// Returns the origin and size used to quantize positions in the bounding box.
+fn quantization_bounds(bounding_box: &Aabb) -> (DVec3, DVec3) {
+    let min = Vec3::from(bounding_box.min()).as_dvec3();
+    let size = Vec3::from(bounding_box.half_extents * 2.0)
+        .as_dvec3()
+        .max(DVec3::splat(f64::EPSILON));
+    (min, size)
+}
+
+/// Quantize a position relative to a bounding box, `max` being the largest quantized value.
+fn quantize_position(position: Vec4, min: DVec3, size: DVec3, max: f64) -> DVec3 {
+    let normalized = (position.truncate().as_dvec3() - min) / size;
+    (normalized.clamp(DVec3::ZERO, DVec3::ONE) * max).round()
+}
+
+fn dequantize_position(position: UVec3, min: DVec3, size: DVec3, max: f64) -> Vec4 {
+    (min + position.as_dvec3() / max * size)
+        .as_vec3()
+        .extend(1.0)
+}
+
+fn quantize_color(color: Vec4) -> [u8; 4] {
+    (color.clamp(Vec4::ZERO, Vec4::ONE) * 255.0)
+        .round()
+        .to_array()
+        .map(|component| component as u8)
+}
+
+fn dequantize_color(color: [u8; 4]) -> Vec4 {
+    Vec4::from_array(color.map(|component| component as f32 / 255.0))
+}
+
+/// Octahedral encoding of a normal on 8 bits per component, `[0, 0]` being kept for unknown
+/// normals.
+pub fn encode_normal(normal: Vec3) -> [u8; 2] {
+    let l1_norm = normal.x.abs() + normal.y.abs() + normal.z.abs();
+    if l1_norm <= f32::EPSILON {
+        return [0, 0];
+    }
+
+    let mut octahedral = normal.xy() / l1_norm;
+    if normal.z < 0.0 {
+        // fold the lower hemisphere onto the corners
+        let sign = Vec2::select(octahedral.cmpge(Vec2::ZERO), Vec2::ONE, Vec2::NEG_ONE);
+        octahedral = (Vec2::ONE - octahedral.yx().abs()) * sign;
+    }
+
+    match ((octahedral * 0.5 + 0.5).clamp(Vec2::ZERO, Vec2::ONE) * 255.0)
+        .round()
+        .to_array()
+        .map(|component| component as u8)
+    {
+        // the closest direction which is not the unknown normal
+        [0, 0] => [1, 0],
+        encoded => encoded,
+    }
+}
+
+/// Decodes a normal encoded with [`encode_normal`], zero if it is unknown.
+pub fn decode_normal(normal: [u8; 2]) -> Vec3 {
+    if normal == [0, 0] {
+        return Vec3::ZERO;
+    }
+
+    let octahedral = Vec2::from_array(normal.map(|component| component as f32 / 255.0)) * 2.0 - 1.0;
+    let mut decoded = octahedral.extend(1.0 - octahedral.x.abs() - octahedral.y.abs());
+    let fold = (-decoded.z).max(0.0);
+    decoded.x += if decoded.x >= 0.0 { -fold } else { fold };
+    decoded.y += if decoded.y >= 0.0 { -fold } else { fold };
+    decoded.normalize_or_zero()
+}
+
 impl NodeData for PointCloudNodeData {
+    type StorageFormat = PointFormat;
+    type Processing = NormalEstimation;
+    type ProcessingInput = NormalEstimationInput;
+
     fn size(&self) -> usize {
-        self.num_points * size_of::<PointData>()
+        self.points.size()
+            + self
+                .normals
+                .as_ref()
+                .map_or(0, |normals| normals.len() * size_of::<[u8; 2]>())
+            + self
+                .classifications
+                .as_ref()
+                .map_or(0, |classifications| classifications.len())
     }
 
     fn instance_count(&self) -> usize {
         self.num_points
     }
+
+    fn into_storage_format(self, format: &Self::StorageFormat, bounding_box: &Aabb) -> Self {
+        Self {
+            points: self.points.encode(*format, bounding_box),
+            ..self
+        }
+    }
+
+    fn prepare_processing(
+        &self,
+        processing: &Self::Processing,
+        bounding_box: &Aabb,
+        ancestors: &[(&Self, &Aabb)],
+    ) -> Option<Self::ProcessingInput> {
+        processing.prepare(self, bounding_box, ancestors)
+    }
+
+    fn process(input: Self::ProcessingInput) -> Option<Self> {
+        input.estimate()
+    }
 }
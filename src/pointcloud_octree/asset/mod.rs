@@ -4,5 +4,6 @@ use crate::octree::asset::Octree;
 
 pub mod data;
 pub mod extract;
+pub mod normals;
 
 pub type PointCloudOctree = Octree<PointCloudNodeData>;
@@ -0,0 +1,114 @@
+use std::sync::Arc;
+
+use bevy_camera::primitives::Aabb;
+use bevy_math::prelude::*;
+
+use crate::{
+    point_cloud::neighbours::{estimate_normal, NeighbourGrid},
+    pointcloud_octree::asset::data::{encode_normal, NodePoints, PointCloudNodeData},
+};
+
+/// Fraction of the node size around it in which the points of the ancestors are used as
+/// neighbours, so that the normals of the points close to its border are not biased.
+const NEIGHBOURHOOD_MARGIN: f32 = 0.1;
+
+/// Estimates the normals of the points of the loaded octree nodes which have none, in order to
+/// be rendered with [`PointLighting::Lit`](crate::point_cloud_material::PointLighting::Lit).
+///
+/// Enable it with
+/// [`PointCloudOctreeServerPlugin::with_processing`](crate::octree::server::OctreeServerPlugin::with_processing).
+/// The normal of each point is fitted to its nearest neighbours among the points of its node
+/// and of its loaded ancestors, the sign of the normals being arbitrary.
+#[derive(Debug, Clone, Copy)]
+pub struct NormalEstimation {
+    /// Number of nearest neighbours used to estimate the normal of each point
+    pub neighbours: usize,
+}
+
+impl Default for NormalEstimation {
+    fn default() -> Self {
+        Self { neighbours: 12 }
+    }
+}
+
+impl NormalEstimation {
+    /// Shares the points of the node and of its ancestors with the estimation, or returns `None`
+    /// if the node already has normals.
+    pub(crate) fn prepare(
+        &self,
+        node_data: &PointCloudNodeData,
+        bounding_box: &Aabb,
+        ancestors: &[(&PointCloudNodeData, &Aabb)],
+    ) -> Option<NormalEstimationInput> {
+        if node_data.normals.is_some() || node_data.points.is_empty() {
+            return None;
+        }
+
+        Some(NormalEstimationInput {
+            estimation: *self,
+            node_data: node_data.clone(),
+            bounding_box: *bounding_box,
+            ancestors: ancestors
+                .iter()
+                .map(|(ancestor, bounding_box)| (ancestor.points.clone(), **bounding_box))
+                .collect(),
+        })
+    }
+}
+
+/// The points used to estimate the normals of a node, shared with the octree.
+pub struct NormalEstimationInput {
+    estimation: NormalEstimation,
+    node_data: PointCloudNodeData,
+    bounding_box: Aabb,
+    ancestors: Vec<(NodePoints, Aabb)>,
+}
+
+impl NormalEstimationInput {
+    /// Returns the node data with estimated normals.
+    pub(crate) fn estimate(self) -> Option<PointCloudNodeData> {
+        let Self {
+            estimation,
+            node_data,
+            bounding_box,
+            ancestors,
+        } = self;
+        let points = node_data.points.decode(&bounding_box);
+
+        let margin = Vec3::from(bounding_box.half_extents) * 2.0 * NEIGHBOURHOOD_MARGIN;
+        let min = Vec3::from(bounding_box.min()) - margin;
+        let max = Vec3::from(bounding_box.max()) + margin;
+
+        // the points of the node come first, followed by the close points of its ancestors
+        let mut positions: Vec<Vec3> = points
+            .iter()
+            .map(|point| point.position.truncate())
+            .collect();
+        for (ancestor, ancestor_bounding_box) in &ancestors {
+            positions.extend(
+                ancestor
+                    .decode(ancestor_bounding_box)
+                    .iter()
+                    .map(|point| point.position.truncate())
+                    .filter(|position| position.cmpge(min).all() && position.cmple(max).all()),
+            );
+        }
+
+        let grid = NeighbourGrid::new(&positions)?;
+
+        let mut neighbours = Vec::with_capacity(estimation.neighbours);
+        let normals = (0..points.len())
+            .map(|index| {
+                grid.k_nearest(index, estimation.neighbours, &mut neighbours);
+                encode_normal(estimate_normal(&positions, index, &neighbours))
+            })
+            .collect();
+        drop(points);
+
+        // the points are shared with the node data
+        Some(PointCloudNodeData {
+            normals: Some(Arc::new(normals)),
+            ..node_data
+        })
+    }
+}
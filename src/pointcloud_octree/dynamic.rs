@@ -0,0 +1,720 @@
+use std::{cmp::Reverse, collections::BinaryHeap, sync::Arc};
+
+use bevy_app::prelude::*;
+use bevy_asset::Assets;
+use bevy_camera::primitives::Aabb;
+use bevy_ecs::prelude::*;
+use bevy_log::prelude::*;
+use bevy_math::prelude::*;
+use bevy_platform::collections::HashSet;
+
+use crate::{
+    octree::{
+        hierarchy::{HierarchyNode, HierarchyNodeStatus},
+        storage::NodeId,
+        visibility::OctreeVisibilitySystems,
+    },
+    pointcloud_octree::{
+        asset::{
+            data::{NodePoints, PointCloudNodeData, PointData},
+            PointCloudOctree,
+        },
+        component::PointCloudOctree3d,
+    },
+};
+
+/// Size of the grid used to estimate the density of a node
+const DENSITY_GRID_SIZE: u32 = 32;
+
+/// This plugin ingests points pushed into [`DynamicPointCloudOctree`] components
+/// into their [`PointCloudOctree`] asset.
+#[derive(Default)]
+pub struct DynamicPointCloudOctreePlugin;
+
+impl Plugin for DynamicPointCloudOctreePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            PostUpdate,
+            update_dynamic_point_cloud_octrees
+                .before(OctreeVisibilitySystems::CheckOctreeNodesVisibility),
+        );
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct DynamicPointCloudOctreeSettings {
+    /// Bounds of the octree in local space, points outside are dropped
+    pub bounds: Aabb,
+    /// Maximum number of points in a leaf before it is split
+    pub max_points_per_node: usize,
+    /// Size of the subsampling grid of inner nodes
+    pub grid_size: u32,
+    /// Maximum depth of the octree, leaves at this depth are never split
+    pub max_depth: u32,
+    /// If set, oldest points are aged out to keep at most this number of points
+    pub max_points: Option<usize>,
+}
+
+impl Default for DynamicPointCloudOctreeSettings {
+    fn default() -> Self {
+        Self {
+            bounds: Aabb::from_min_max(Vec3::splat(-100.0), Vec3::splat(100.0)),
+            max_points_per_node: 20_000,
+            grid_size: 128,
+            max_depth: 12,
+            max_points: None,
+        }
+    }
+}
+
+/// A point cloud octree built incrementally from point batches, e.g. from a live sensor.
+///
+/// Points are inserted Potree-style: inner nodes keep one point per cell of their subsampling grid,
+/// and the remaining points go down to the children. Modified nodes are updated in the
+/// [`PointCloudOctree`] asset referenced by the [`PointCloudOctree3d`] of the same entity.
+#[derive(Component)]
+pub struct DynamicPointCloudOctree {
+    settings: DynamicPointCloudOctreeSettings,
+    /// Cubic root bounds
+    bounds: Aabb,
+    nodes: Vec<DynamicNode>,
+    /// Oldest sequence of the nodes with points, and their index, oldest first. Entries whose
+    /// sequence is no longer the oldest of their node are skipped.
+    ages: BinaryHeap<Reverse<(u64, usize)>>,
+    pending: Vec<PointData>,
+    next_sequence: u64,
+    len: usize,
+    dropped_points: usize,
+}
+
+struct DynamicNode {
+    id: NodeId,
+    bounding_box: Aabb,
+    depth: u32,
+    children: [Option<usize>; 8],
+    /// The points of the node, empty while they are moved into the asset
+    points: Vec<PointData>,
+    /// Insertion sequence of each point
+    sequences: Vec<u64>,
+    /// Occupied cells of the subsampling grid, `None` for leaves
+    grid: Option<HashSet<u32>>,
+    oldest_sequence: u64,
+    /// Sequence of the last entry of the node in the age queue
+    queued_sequence: u64,
+    dirty: bool,
+    /// Whether the points have been moved into the asset by the last sync
+    synced: bool,
+}
+
+#[derive(Clone, Copy)]
+struct DynamicPoint {
+    sequence: u64,
+    data: PointData,
+}
+
+impl DynamicPointCloudOctree {
+    pub fn new(settings: DynamicPointCloudOctreeSettings) -> Self {
+        // the octree has to be cubic for the shaders
+        let bounds = Aabb {
+            center: settings.bounds.center,
+            half_extents: Vec3A::splat(settings.bounds.half_extents.max_element()),
+        };
+
+        Self {
+            settings,
+            bounds,
+            nodes: Vec::new(),
+            ages: BinaryHeap::new(),
+            pending: Vec::new(),
+            next_sequence: 0,
+            len: 0,
+            dropped_points: 0,
+        }
+    }
+
+    pub fn settings(&self) -> &DynamicPointCloudOctreeSettings {
+        &self.settings
+    }
+
+    /// Queue a batch of points, they are inserted in the octree during [`PostUpdate`]
+    pub fn push_points(&mut self, points: impl IntoIterator<Item = PointData>) {
+        self.pending.extend(points);
+    }
+
+    /// Number of points in the octree, excluding queued points
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Number of points dropped because they were outside of the bounds
+    pub fn dropped_points(&self) -> usize {
+        self.dropped_points
+    }
+
+    /// Insert queued points in the octree, age out old points and update modified nodes in the asset
+    pub fn ingest(&mut self, octree: &mut PointCloudOctree) {
+        if self.pending.is_empty() {
+            return;
+        }
+
+        if self.nodes.is_empty() {
+            let Ok(root_id) = octree.insert_hierarchy_node(
+                None,
+                HierarchyNode {
+                    status: HierarchyNodeStatus::Loaded,
+                    child_index: 0,
+                    parent_id: None,
+                    bounding_box: self.bounds,
+                    data: Arc::new(()),
+                },
+            ) else {
+                warn!("Dynamic octree asset already has a root node, points are ignored");
+                self.pending.clear();
+                return;
+            };
+
+            self.nodes.push(DynamicNode::new(root_id, self.bounds, 0));
+        }
+
+        let pending = std::mem::take(&mut self.pending);
+        for data in pending {
+            if !contains(&self.bounds, data.position.xyz()) {
+                self.dropped_points += 1;
+                continue;
+            }
+
+            let point = DynamicPoint {
+                sequence: self.next_sequence,
+                data,
+            };
+            self.next_sequence += 1;
+            self.len += 1;
+
+            self.insert(octree, 0, point);
+        }
+
+        if let Some(max_points) = self.settings.max_points
+            && self.len > max_points
+        {
+            self.age_out(octree, self.next_sequence - max_points as u64);
+        }
+
+        self.sync(octree);
+    }
+
+    fn insert(&mut self, octree: &mut PointCloudOctree, mut index: usize, point: DynamicPoint) {
+        loop {
+            let grid_size = self.settings.grid_size;
+            let node = &mut self.nodes[index];
+
+            // inner nodes keep the point if its grid cell is still free
+            let keep = node.grid.as_mut().is_none_or(|grid| {
+                grid.insert(cell_index(
+                    &node.bounding_box,
+                    grid_size,
+                    point.data.position,
+                ))
+            });
+            if !keep {
+                index = self.child(octree, index, point.data.position);
+                continue;
+            }
+
+            self.reclaim(octree, index);
+            self.nodes[index].push(point);
+            self.track_age(index);
+
+            let node = &self.nodes[index];
+            if node.grid.is_none()
+                && node.points.len() > self.settings.max_points_per_node
+                && node.depth < self.settings.max_depth
+            {
+                self.split(octree, index);
+            }
+            return;
+        }
+    }
+
+    /// Turn a leaf into an inner node, subsampling its points and moving the others to its children
+    fn split(&mut self, octree: &mut PointCloudOctree, index: usize) {
+        let node = &mut self.nodes[index];
+        node.grid = Some(HashSet::new());
+        node.dirty = true;
+        let points = std::mem::take(&mut node.points);
+        let sequences = std::mem::take(&mut node.sequences);
+
+        for (data, sequence) in points.into_iter().zip(sequences) {
+            self.insert(octree, index, DynamicPoint { sequence, data });
+        }
+    }
+
+    /// Takes back the points of a node moved into the asset by the last sync, before modifying
+    /// them. They are only copied if the asset data is still shared.
+    fn reclaim(&mut self, octree: &mut PointCloudOctree, index: usize) {
+        let node = &mut self.nodes[index];
+        node.dirty = true;
+        if !std::mem::take(&mut node.synced) {
+            return;
+        }
+
+        let points = octree
+            .hierarchy
+            .get_mut(node.id)
+            .and_then(|octree_node| octree_node.data.as_mut())
+            .map(|data| std::mem::take(&mut data.points));
+        if let Some(points) = points {
+            // the size of the data is tracked again when it is synced
+            octree.modified_size -= points.size() as isize;
+            if let NodePoints::Full(points) = points {
+                node.points = Arc::try_unwrap(points).unwrap_or_else(|points| (*points).clone());
+            }
+        }
+    }
+
+    /// The points of a node, in the asset if they have been moved by the last sync.
+    fn points<'a>(&'a self, octree: &'a PointCloudOctree, index: usize) -> &'a [PointData] {
+        let node = &self.nodes[index];
+        if !node.synced {
+            return &node.points;
+        }
+        match octree.node(node.id).and_then(|node| node.data.as_ref()) {
+            Some(PointCloudNodeData {
+                points: NodePoints::Full(points),
+                ..
+            }) => points,
+            _ => &[],
+        }
+    }
+
+    /// Returns the child containing the position, creating it if needed
+    fn child(&mut self, octree: &mut PointCloudOctree, index: usize, position: Vec4) -> usize {
+        let node = &self.nodes[index];
+        let relative_position = Vec3A::from(position.xyz()) - node.bounding_box.center;
+        let child_index = (4 * (relative_position.x >= 0.0) as u8)
+            + (2 * (relative_position.y >= 0.0) as u8)
+            + (relative_position.z >= 0.0) as u8;
+
+        if let Some(child) = node.children[child_index as usize] {
+            return child;
+        }
+
+        let half_extents = node.bounding_box.half_extents / 2.0;
+        let direction = Vec3A::new(
+            if child_index & 4 > 0 { 1.0 } else { -1.0 },
+            if child_index & 2 > 0 { 1.0 } else { -1.0 },
+            if child_index & 1 > 0 { 1.0 } else { -1.0 },
+        );
+        let bounding_box = Aabb {
+            center: node.bounding_box.center + direction * half_extents,
+            half_extents,
+        };
+        let depth = node.depth + 1;
+
+        // infallible because the parent exists and the child index is free
+        let id = octree
+            .insert_hierarchy_node(
+                Some(node.id),
+                HierarchyNode {
+                    status: HierarchyNodeStatus::Loaded,
+                    child_index,
+                    parent_id: None,
+                    bounding_box,
+                    data: Arc::new(()),
+                },
+            )
+            .expect("failed to insert dynamic octree child node");
+
+        let child = self.nodes.len();
+        self.nodes.push(DynamicNode::new(id, bounding_box, depth));
+        self.nodes[index].children[child_index as usize] = Some(child);
+
+        child
+    }
+
+    /// Queues the node by its oldest point if it changed since it was last queued.
+    fn track_age(&mut self, index: usize) {
+        let node = &mut self.nodes[index];
+        if node.oldest_sequence != u64::MAX && node.oldest_sequence != node.queued_sequence {
+            node.queued_sequence = node.oldest_sequence;
+            self.ages.push(Reverse((node.oldest_sequence, index)));
+        }
+    }
+
+    /// Remove points older than `min_sequence`, and refill freed grid cells of inner nodes
+    /// with points of their children.
+    fn age_out(&mut self, octree: &mut PointCloudOctree, min_sequence: u64) {
+        let grid_size = self.settings.grid_size;
+        let mut refill = Vec::new();
+
+        // only the nodes holding old points are visited
+        while let Some(&Reverse((sequence, index))) = self.ages.peek()
+            && sequence < min_sequence
+        {
+            self.ages.pop();
+            if self.nodes[index].oldest_sequence != sequence {
+                continue;
+            }
+            self.reclaim(octree, index);
+
+            let node = &mut self.nodes[index];
+            let len = node.points.len();
+            let mut kept = 0;
+            for i in 0..len {
+                if node.sequences[i] >= min_sequence {
+                    node.points[kept] = node.points[i];
+                    node.sequences[kept] = node.sequences[i];
+                    kept += 1;
+                } else if let Some(grid) = node.grid.as_mut() {
+                    grid.remove(&cell_index(
+                        &node.bounding_box,
+                        grid_size,
+                        node.points[i].position,
+                    ));
+                }
+            }
+            node.points.truncate(kept);
+            node.sequences.truncate(kept);
+            node.oldest_sequence = node.sequences.iter().copied().min().unwrap_or(u64::MAX);
+            self.len -= len - kept;
+
+            if node.grid.is_some() && !refill.contains(&index) {
+                refill.push(index);
+            }
+            self.track_age(index);
+        }
+
+        // pull points up from the children, which frees cells of inner children in turn
+        let mut i = 0;
+        while i < refill.len() {
+            let index = refill[i];
+            i += 1;
+
+            for child_index in 0..8 {
+                let Some(child) = self.nodes[index].children[child_index] else {
+                    continue;
+                };
+
+                // the child is only modified if some of its points are promoted
+                let node = &self.nodes[index];
+                let Some(grid) = &node.grid else {
+                    continue;
+                };
+                let mut free_cells = HashSet::new();
+                let promoted = self
+                    .points(octree, child)
+                    .iter()
+                    .map(|point| {
+                        let cell = cell_index(&node.bounding_box, grid_size, point.position);
+                        !grid.contains(&cell) && free_cells.insert(cell)
+                    })
+                    .collect::<Vec<_>>();
+                if !promoted.contains(&true) {
+                    continue;
+                }
+
+                self.reclaim(octree, child);
+                let child_node = &mut self.nodes[child];
+                let mut promoted_points = Vec::new();
+                let mut kept = 0;
+                for (i, promoted) in promoted.into_iter().enumerate() {
+                    let point = DynamicPoint {
+                        sequence: child_node.sequences[i],
+                        data: child_node.points[i],
+                    };
+                    if promoted {
+                        promoted_points.push(point);
+                        if let Some(child_grid) = &mut child_node.grid {
+                            child_grid.remove(&cell_index(
+                                &child_node.bounding_box,
+                                grid_size,
+                                point.data.position,
+                            ));
+                        }
+                    } else {
+                        child_node.points[kept] = point.data;
+                        child_node.sequences[kept] = point.sequence;
+                        kept += 1;
+                    }
+                }
+                child_node.points.truncate(kept);
+                child_node.sequences.truncate(kept);
+                child_node.oldest_sequence = child_node
+                    .sequences
+                    .iter()
+                    .copied()
+                    .min()
+                    .unwrap_or(u64::MAX);
+                if child_node.grid.is_some() && !refill.contains(&child) {
+                    refill.push(child);
+                }
+                self.track_age(child);
+
+                let node = &mut self.nodes[index];
+                if let Some(grid) = &mut node.grid {
+                    grid.extend(free_cells);
+                }
+                for point in promoted_points {
+                    node.push(point);
+                }
+                self.track_age(index);
+            }
+        }
+    }
+
+    /// Move the points of the dirty nodes into the asset
+    fn sync(&mut self, octree: &mut PointCloudOctree) {
+        let root_spacing = 2.0 * self.bounds.half_extents.x / self.settings.grid_size as f32;
+
+        for node in self.nodes.iter_mut().filter(|node| node.dirty) {
+            node.dirty = false;
+            node.synced = true;
+
+            let points = std::mem::take(&mut node.points);
+            let density = compute_density(&points, &node.bounding_box);
+            let data = PointCloudNodeData {
+                spacing: root_spacing / (node.depth as f32).exp2(),
+                level: node.depth,
+                // magic formula from Potree
+                offset: (density as f32).log2() / 2.0 - 1.5,
+                num_points: points.len(),
+                points: points.into(),
+                normals: None,
+                classifications: None,
+            };
+
+            if let Err(error) = octree.update_node_data(node.id, data) {
+                warn!("Unable to update dynamic octree node data: {:#}", error);
+            }
+        }
+    }
+}
+
+impl DynamicNode {
+    fn new(id: NodeId, bounding_box: Aabb, depth: u32) -> Self {
+        Self {
+            id,
+            bounding_box,
+            depth,
+            children: [None; 8],
+            points: Vec::new(),
+            sequences: Vec::new(),
+            grid: None,
+            oldest_sequence: u64::MAX,
+            queued_sequence: u64::MAX,
+            dirty: true,
+            synced: false,
+        }
+    }
+
+    fn push(&mut self, point: DynamicPoint) {
+        self.oldest_sequence = self.oldest_sequence.min(point.sequence);
+        self.points.push(point.data);
+        self.sequences.push(point.sequence);
+        self.dirty = true;
+    }
+}
+
+fn contains(aabb: &Aabb, position: Vec3) -> bool {
+    let position = Vec3A::from(position);
+    position.cmpge(aabb.min()).all() && position.cmple(aabb.max()).all()
+}
+
+fn cell_index(aabb: &Aabb, grid_size: u32, position: Vec4) -> u32 {
+    let relative_position = (Vec3A::from(position.xyz()) - aabb.min()) / (2.0 * aabb.half_extents);
+    let cell = (relative_position * grid_size as f32)
+        .as_uvec3()
+        .min(UVec3::splat(grid_size - 1));
+
+    cell.x + grid_size * (cell.y + grid_size * cell.z)
+}
+
+fn compute_density(points: &[PointData], aabb: &Aabb) -> u32 {
+    let mut occupied_cells = HashSet::new();
+    for point in points {
+        occupied_cells.insert(cell_index(aabb, DENSITY_GRID_SIZE, point.position));
+    }
+
+    points
+        .len()
+        .checked_div(occupied_cells.len())
+        .unwrap_or_default() as u32
+}
+
+/// Ingest queued points of dynamic octrees into their asset
+pub fn update_dynamic_point_cloud_octrees(
+    mut dynamic_octrees: Query<(&PointCloudOctree3d, &mut DynamicPointCloudOctree)>,
+    mut octrees: ResMut<Assets<PointCloudOctree>>,
+) {
+    #[cfg(feature = "trace")]
+    let _span = info_span!("update_dynamic_point_cloud_octrees").entered();
+
+    for (point_cloud_octree_3d, mut dynamic_octree) in &mut dynamic_octrees {
+        if dynamic_octree.pending.is_empty() {
+            continue;
+        }
+
+        let Some(octree) = octrees.get_mut(&point_cloud_octree_3d.0) else {
+            continue;
+        };
+
+        dynamic_octree.ingest(octree);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::octree::node::NodeStatus;
+
+    /// Points spread over the bounds, their red channel being their rank.
+    fn points(range: std::ops::Range<usize>) -> Vec<PointData> {
+        range
+            .map(|rank| {
+                // a pseudo-random position, so that the points spread over the cells
+                let hash = (rank as u32).wrapping_mul(2_654_435_761);
+                let position = Vec3::new(
+                    (hash & 0x3ff) as f32,
+                    (hash >> 10 & 0x3ff) as f32,
+                    (hash >> 20 & 0x3ff) as f32,
+                ) / 1024.0
+                    * 19.0
+                    - 9.5;
+                PointData {
+                    position: position.extend(1.0),
+                    color: Vec4::new(rank as f32, 0.0, 0.0, 1.0),
+                }
+            })
+            .collect()
+    }
+
+    fn settings() -> DynamicPointCloudOctreeSettings {
+        DynamicPointCloudOctreeSettings {
+            bounds: Aabb::from_min_max(Vec3::splat(-10.0), Vec3::splat(10.0)),
+            max_points_per_node: 100,
+            grid_size: 4,
+            max_depth: 6,
+            max_points: None,
+        }
+    }
+
+    /// The ranks of the points in the asset.
+    fn ranks(dynamic: &DynamicPointCloudOctree, octree: &PointCloudOctree) -> Vec<usize> {
+        let mut ranks: Vec<usize> = (0..dynamic.nodes.len())
+            .flat_map(|index| dynamic.points(octree, index))
+            .map(|point| point.color.x as usize)
+            .collect();
+        ranks.sort_unstable();
+        ranks
+    }
+
+    #[test]
+    fn ingest_inserts_the_points_in_the_asset() {
+        let mut octree = PointCloudOctree::default();
+        let mut dynamic = DynamicPointCloudOctree::new(settings());
+
+        dynamic.push_points(points(0..50));
+        dynamic.push_points([PointData {
+            position: Vec4::new(20.0, 0.0, 0.0, 1.0),
+            color: Vec4::ONE,
+        }]);
+        assert!(dynamic.is_empty());
+        dynamic.ingest(&mut octree);
+
+        assert_eq!(dynamic.len(), 50);
+        assert_eq!(dynamic.dropped_points(), 1);
+        assert_eq!(dynamic.nodes.len(), 1);
+        let root = octree.node_root().unwrap();
+        assert!(matches!(root.status, NodeStatus::Loaded));
+        assert_eq!(root.data.as_ref().unwrap().num_points, 50);
+        assert_eq!(ranks(&dynamic, &octree), (0..50).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn full_leaves_are_split() {
+        let mut octree = PointCloudOctree::default();
+        let mut dynamic = DynamicPointCloudOctree::new(settings());
+
+        dynamic.push_points(points(0..1000));
+        dynamic.ingest(&mut octree);
+
+        assert!(dynamic.nodes.len() > 1);
+        let root = &dynamic.nodes[0];
+        assert!(root.grid.is_some());
+        // the root keeps at most a point per cell of its grid
+        assert!(dynamic.points(&octree, 0).len() <= 4 * 4 * 4);
+        for (index, node) in dynamic.nodes.iter().enumerate() {
+            let points = dynamic.points(&octree, index);
+            if node.grid.is_none() && node.depth < 6 {
+                assert!(points.len() <= 100);
+            }
+            for point in points {
+                assert!(contains(&node.bounding_box, point.position.xyz()));
+            }
+        }
+        assert_eq!(ranks(&dynamic, &octree), (0..1000).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn synced_points_are_reclaimed() {
+        let mut octree = PointCloudOctree::default();
+        let mut dynamic = DynamicPointCloudOctree::new(settings());
+
+        dynamic.push_points(points(0..30));
+        dynamic.ingest(&mut octree);
+        assert!(dynamic.nodes[0].synced);
+        assert!(dynamic.nodes[0].points.is_empty());
+
+        // the points of the asset are taken back before adding the new ones
+        dynamic.push_points(points(30..60));
+        dynamic.ingest(&mut octree);
+        assert_eq!(dynamic.len(), 60);
+        assert_eq!(
+            octree
+                .node_root()
+                .unwrap()
+                .data
+                .as_ref()
+                .unwrap()
+                .num_points,
+            60
+        );
+        assert_eq!(ranks(&dynamic, &octree), (0..60).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn oldest_points_are_aged_out() {
+        let mut octree = PointCloudOctree::default();
+        let mut dynamic = DynamicPointCloudOctree::new(DynamicPointCloudOctreeSettings {
+            max_points: Some(500),
+            ..settings()
+        });
+
+        for batch in 0..10 {
+            dynamic.push_points(points(batch * 200..(batch + 1) * 200));
+            dynamic.ingest(&mut octree);
+
+            let len = ((batch + 1) * 200).min(500);
+            assert_eq!(dynamic.len(), len);
+            assert_eq!(
+                ranks(&dynamic, &octree),
+                ((batch + 1) * 200 - len..(batch + 1) * 200).collect::<Vec<_>>()
+            );
+        }
+
+        // the freed cells of the root are refilled with the points of its children
+        assert!(!dynamic.points(&octree, 0).is_empty());
+        // every node with points is queued by its oldest point
+        for (index, node) in dynamic.nodes.iter().enumerate() {
+            if node.oldest_sequence != u64::MAX {
+                assert!(dynamic
+                    .ages
+                    .iter()
+                    .any(|Reverse(entry)| *entry == (node.oldest_sequence, index)));
+            }
+        }
+    }
+}
@@ -1,5 +1,6 @@
 pub mod asset;
 pub mod component;
+pub mod dynamic;
 pub mod extract;
 pub mod render;
 pub mod visibility;
@@ -47,6 +48,7 @@ plugin_group! {
     pub struct PointCloudOctreePlugin {
             self:::PointCloudOctreeAssetPlugin,
             self:::PointCloudOctreeVisibilityPlugin,
+            dynamic:::DynamicPointCloudOctreePlugin,
             self:::ExtractVisiblePointCloudOctreeNodesPlugin,
             render:::RenderPointCloudOctreePlugin,
     }
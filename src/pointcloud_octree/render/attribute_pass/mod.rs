@@ -4,12 +4,14 @@ use bevy_app::prelude::*;
 use bevy_camera::{Camera, Camera3d};
 use bevy_core_pipeline::core_3d::graph::Core3d;
 use bevy_ecs::{change_detection::Tick, prelude::*};
+use bevy_light::ShadowFilteringMethod;
 use bevy_log::prelude::*;
 use bevy_pbr::{MeshPipelineKey, SetMeshViewBindGroup};
 use bevy_platform::collections::HashSet;
 use bevy_render::{
     batching::gpu_preprocessing::GpuPreprocessingSupport,
     prelude::*,
+    render_asset::RenderAssets,
     render_graph::{RenderGraphExt, ViewNodeRunner},
     render_phase::{AddRenderCommand, DrawFunctions, SetItemPipeline},
     render_resource::{PipelineCache, SpecializedRenderPipelines},
@@ -27,15 +29,19 @@ use crate::pointcloud_octree::render::draw::DrawPointCloudOctree;
 #[cfg(not(feature = "webgl"))]
 use crate::pointcloud_octree::render::draw::DrawPointCloudOctreeIndirect;
 use crate::{
-    octree::extract::render::components::RenderVisibleOctreeNodes,
+    octree::extract::{
+        render::components::RenderVisibleOctreeNodes, resources::OctreeBufferSettings,
+    },
+    point_cloud_material::PointCloudMaterial3d,
     pointcloud_octree::{
-        asset::data::PointCloudNodeData,
+        asset::{data::PointCloudNodeData, extract::PointCloudOctreeExtraction},
         component::PointCloudOctree3d,
         render::{
+            compute_rasterize::ComputeRasterizedView,
             data::SetPointCloudOctree3dUniformGroup,
             draw::{SetPointCloudOctreeNodeUniformGroup, SetRenderOctreeUniformGroup},
             phase::{PointCloudOctree3dNodePhase, ViewOctreeNodesRenderAttributePhases},
-            prepare::SetVisibleNodesTexture,
+            prepare::SetVisibleNodes,
         },
     },
     render::{
@@ -43,9 +49,10 @@ use crate::{
             pipeline::{AttributePassPipeline, AttributePipelineKey},
             texture::prepare_attribute_pass_bind_groups,
         },
-        material::SetPointCloudMaterialGroup,
+        material::{RenderPointCloudMaterial, SetPointCloudMaterialGroup},
         normalize_pass::node::NormalizePassLabel,
         phase::PointCloud3dBatchSetKey,
+        shadow_pass::shadow_filter_method_key,
     },
 };
 
@@ -87,7 +94,7 @@ type DrawAttributePass = (
     SetMeshViewBindGroup<0>,
     SetPointCloudOctree3dUniformGroup<1>,
     SetPointCloudMaterialGroup<2>,
-    SetVisibleNodesTexture<3>,
+    SetVisibleNodes<3>,
     SetPointCloudOctreeNodeUniformGroup<4>,
     SetRenderOctreeUniformGroup<5>,
     DrawPointCloudOctreeIndirect,
@@ -99,7 +106,7 @@ type DrawAttributePass = (
     SetMeshViewBindGroup<0>,
     SetPointCloudOctree3dUniformGroup<1>,
     SetPointCloudMaterialGroup<2>,
-    SetVisibleNodesTexture<3>,
+    SetVisibleNodes<3>,
     SetPointCloudOctreeNodeUniformGroup<4>,
     SetRenderOctreeUniformGroup<5>,
     DrawPointCloudOctree,
@@ -134,12 +141,15 @@ fn extract_camera_phases(
 }
 
 #[allow(clippy::too_many_arguments)]
+#[allow(clippy::type_complexity)]
 fn queue_attribute_pass(
     custom_draw_functions: Res<DrawFunctions<PointCloudOctree3dNodePhase>>,
     mut pipelines: ResMut<SpecializedRenderPipelines<AttributePassPipeline>>,
     pipeline_cache: Res<PipelineCache>,
     custom_draw_pipeline: Res<AttributePassPipeline>,
-    point_cloud_octrees_3d: Query<&PointCloudOctree3d>,
+    point_cloud_octrees_3d: Query<(&PointCloudOctree3d, &PointCloudMaterial3d)>,
+    render_materials: Res<RenderAssets<RenderPointCloudMaterial>>,
+    octree_buffer_settings: Res<OctreeBufferSettings<PointCloudOctreeExtraction>>,
     mut custom_render_phases: ResMut<
         ViewOctreeNodesRenderAttributePhases<PointCloudOctree3dNodePhase>,
     >,
@@ -147,28 +157,27 @@ fn queue_attribute_pass(
         &ExtractedView,
         &RenderVisibleOctreeNodes<PointCloudNodeData, PointCloudOctree3d>,
         &Msaa,
+        Option<&ShadowFilteringMethod>,
+        Has<ComputeRasterizedView>,
     )>,
     main_entities: Query<&MainEntity>,
     mut next_tick: Local<Tick>,
 ) {
-    for (view, visible_entities, msaa) in &mut views {
+    for (view, visible_entities, msaa, shadow_filter_method, compute_rasterized) in &mut views {
         let Some(custom_phase) = custom_render_phases.get_mut(&view.retained_view_entity) else {
             continue;
         };
+        // the octrees of this view are drawn by the compute rasterization pass
+        if compute_rasterized {
+            continue;
+        }
         let draw_custom = custom_draw_functions.read().id::<DrawAttributePass>();
 
         // Create the key based on the view.
-        // In this case we only care about MSAA and HDR
+        // In this case we care about MSAA, HDR and the shadow filtering method
         let view_key = MeshPipelineKey::from_msaa_samples(msaa.samples())
-            | MeshPipelineKey::from_hdr(view.hdr);
-
-        let attribute_key = AttributePipelineKey {
-            mesh_key: view_key,
-            is_octree: true,
-        };
-
-        let pipeline_id =
-            pipelines.specialize(&pipeline_cache, &custom_draw_pipeline, attribute_key);
+            | MeshPipelineKey::from_hdr(view.hdr)
+            | shadow_filter_method_key(shadow_filter_method);
 
         // Since our phase can work on any 3d mesh we can reuse the default mesh 3d filter
         for (render_entity, _) in &visible_entities.octrees {
@@ -176,10 +185,29 @@ fn queue_attribute_pass(
                 warn!("Render entity not found, skipping.");
                 continue;
             };
-            let Ok(point_cloud_octree_3d) = point_cloud_octrees_3d.get(*render_entity) else {
+            let Ok((point_cloud_octree_3d, point_cloud_material_3d)) =
+                point_cloud_octrees_3d.get(*render_entity)
+            else {
                 warn!("point_cloud_octree_3d missing");
                 continue;
             };
+            let Some(material) = render_materials.get(point_cloud_material_3d) else {
+                continue;
+            };
+
+            let attribute_key = AttributePipelineKey {
+                mesh_key: view_key,
+                is_octree: true,
+                shape: material.shape,
+                splatting: material.splatting,
+                geometry: material.geometry,
+                point_format: *octree_buffer_settings.instance_format(),
+                receive_shadows: material.receive_shadows,
+                lighting: material.lighting,
+            };
+
+            let pipeline_id =
+                pipelines.specialize(&pipeline_cache, &custom_draw_pipeline, attribute_key);
 
             // Bump the change tick in order to force Bevy to rebuild the bin.
             let this_tick = next_tick.get() + 1;
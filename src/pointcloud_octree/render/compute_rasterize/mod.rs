@@ -0,0 +1,484 @@
+//! Rasterization of the point cloud octrees with a compute shader, for massive clouds.
+//!
+//! Each point is projected to a single pixel and merged in a per view framebuffer with a 64 bits
+//! `atomicMax` of its depth and color, as described by Schütz et al. in "Rendering Point Clouds
+//! with Compute Shaders and Vertex Order Optimization". The framebuffer is then resolved into the
+//! depth and attribute pass textures, so that eye dome lighting and the normalize pass still apply.
+pub mod node;
+pub mod pipeline;
+
+use bevy_app::prelude::*;
+use bevy_asset::{load_internal_asset, uuid_handle, Handle};
+use bevy_camera::{primitives::Aabb, Camera3d};
+use bevy_core_pipeline::core_3d::graph::Core3d;
+use bevy_ecs::prelude::*;
+use bevy_log::prelude::*;
+use bevy_math::prelude::*;
+use bevy_render::{
+    camera::{extract_cameras, ExtractedCamera},
+    render_graph::{RenderGraphExt, ViewNodeRunner},
+    render_resource::{
+        BindGroup, BindGroupEntries, Buffer, BufferDescriptor, BufferUsages,
+        CachedComputePipelineId, CachedRenderPipelineId, PipelineCache, RawBufferVec, ShaderType,
+        SpecializedComputePipelines, SpecializedRenderPipelines, UniformBuffer,
+    },
+    renderer::{RenderDevice, RenderQueue},
+    settings::WgpuFeatures,
+    sync_world::RenderEntity,
+    view::{ExtractedView, Msaa},
+    Extract, ExtractSchedule, Render, RenderApp, RenderSystems,
+};
+use bevy_shader::Shader;
+use bytemuck::{Pod, Zeroable};
+use node::{ComputeRasterizeLabel, ComputeRasterizeNode};
+use pipeline::{ComputeRasterizePipeline, ComputeResolvePipeline, ComputeResolvePipelineKey};
+
+use crate::{
+    octree::extract::{
+        render::{
+            buffer::RenderOctreesBuffers, components::RenderVisibleOctreeNodes,
+            resources::RenderOctrees,
+        },
+        resources::OctreeBufferSettings,
+    },
+    pointcloud_octree::{
+        asset::{data::PointCloudNodeData, extract::PointCloudOctreeExtraction},
+        component::PointCloudOctree3d,
+        extract::RenderPointCloudNodeData,
+        render::{attribute_pass::node::AttributePassOctreeLabel, data::PointCloudOctree3dUniform},
+    },
+    render::{
+        attribute_pass::node::AttributePassLabel, clip_volume::RenderClipVolumes,
+        normalize_pass::node::NormalizePassLabel, PointCloudRasterization, PointCloudRenderMode,
+        PointCloudRenderModeOpt,
+    },
+};
+
+const COMPUTE_RASTERIZE_SHADER_HANDLE: Handle<Shader> =
+    uuid_handle!("5b8e2f0c-3d1a-4c6e-9f27-8a41d6b3c2e5");
+
+const COMPUTE_RESOLVE_SHADER_HANDLE: Handle<Shader> =
+    uuid_handle!("c1f4a7e2-6b39-4d85-a0e3-27d9f5b8146c");
+
+/// Number of invocations of a rasterization workgroup, must match the shader.
+pub const WORKGROUP_SIZE: u32 = 128;
+/// Number of points rasterized by each invocation, must match the shader.
+pub const POINTS_PER_THREAD: u32 = 32;
+/// Number of points rasterized by a workgroup, the visible nodes are split in batches of this size.
+pub const POINTS_PER_BATCH: u32 = WORKGROUP_SIZE * POINTS_PER_THREAD;
+/// Maximum number of workgroups per dispatch dimension.
+pub const MAX_DISPATCH_SIZE: u32 = 65535;
+
+/// Renders the point cloud octrees of the cameras using [`PointCloudRasterization::Compute`].
+///
+/// Not available with WebGL.
+pub struct ComputeRasterizePlugin;
+
+impl Plugin for ComputeRasterizePlugin {
+    fn build(&self, app: &mut App) {
+        load_internal_asset!(
+            app,
+            COMPUTE_RASTERIZE_SHADER_HANDLE,
+            "rasterize.wgsl",
+            Shader::from_wgsl
+        );
+
+        load_internal_asset!(
+            app,
+            COMPUTE_RESOLVE_SHADER_HANDLE,
+            "resolve.wgsl",
+            Shader::from_wgsl
+        );
+
+        let Some(render_app) = app.get_sub_app_mut(RenderApp) else {
+            return;
+        };
+
+        render_app
+            .world_mut()
+            .register_required_components::<Camera3d, ComputeRasterizeBuffers>();
+
+        render_app
+            .init_resource::<SpecializedComputePipelines<ComputeRasterizePipeline>>()
+            .init_resource::<SpecializedRenderPipelines<ComputeResolvePipeline>>()
+            .add_systems(
+                ExtractSchedule,
+                extract_compute_rasterized_views.after(extract_cameras),
+            )
+            .add_systems(
+                Render,
+                (
+                    prepare_compute_rasterize_pipelines.in_set(RenderSystems::Prepare),
+                    prepare_compute_rasterize_buffers.in_set(RenderSystems::PrepareResources),
+                    prepare_compute_rasterize_bind_groups.in_set(RenderSystems::PrepareBindGroups),
+                ),
+            )
+            .add_render_graph_node::<ViewNodeRunner<ComputeRasterizeNode>>(
+                Core3d,
+                ComputeRasterizeLabel,
+            )
+            .add_render_graph_edges(
+                Core3d,
+                (
+                    AttributePassOctreeLabel,
+                    ComputeRasterizeLabel,
+                    NormalizePassLabel,
+                ),
+            )
+            .add_render_graph_edges(Core3d, (AttributePassLabel, ComputeRasterizeLabel));
+    }
+
+    fn finish(&self, app: &mut App) {
+        let Some(render_app) = app.get_sub_app_mut(RenderApp) else {
+            return;
+        };
+
+        render_app
+            .init_resource::<ComputeRasterizationSupport>()
+            .init_resource::<ComputeRasterizePipeline>()
+            .init_resource::<ComputeResolvePipeline>();
+    }
+}
+
+/// Whether the render device can rasterize the octrees with a compute shader.
+#[derive(Resource, Clone, Copy, Debug)]
+pub struct ComputeRasterizationSupport {
+    pub supported: bool,
+}
+
+impl FromWorld for ComputeRasterizationSupport {
+    fn from_world(world: &mut World) -> Self {
+        let render_device = world.resource::<RenderDevice>();
+        let settings = world.resource::<OctreeBufferSettings<PointCloudOctreeExtraction>>();
+
+        // the whole octree buffer is bound as a single storage buffer
+        let supported = render_device
+            .features()
+            .contains(WgpuFeatures::SHADER_INT64 | WgpuFeatures::SHADER_INT64_ATOMIC_MIN_MAX)
+            && render_device.limits().max_storage_buffer_binding_size as usize >= settings.max_size;
+
+        Self { supported }
+    }
+}
+
+/// Marks the views whose octrees are rasterized by the compute shader instead of the
+/// depth and attribute passes.
+#[derive(Component, Clone, Copy, Debug, Default)]
+pub struct ComputeRasterizedView;
+
+#[allow(clippy::type_complexity)]
+pub fn extract_compute_rasterized_views(
+    mut commands: Commands,
+    support: Res<ComputeRasterizationSupport>,
+    cameras: Extract<Query<(&RenderEntity, Option<&PointCloudRenderMode>), With<Camera3d>>>,
+    mut warned: Local<bool>,
+) {
+    for (render_entity, render_mode) in &cameras {
+        let requested = render_mode.is_some_and(|render_mode| {
+            render_mode.rasterization == PointCloudRasterization::Compute
+        });
+
+        if requested && !support.supported && !*warned {
+            warn!(
+                "Compute rasterization of point clouds is not supported by this device, falling back to hardware rasterization"
+            );
+            *warned = true;
+        }
+
+        let mut entity = commands.entity(render_entity.id());
+        if requested && support.supported {
+            entity.insert(ComputeRasterizedView);
+        } else {
+            entity.remove::<ComputeRasterizedView>();
+        }
+    }
+}
+
+/// Transform of an octree rasterized by a view.
+#[derive(Clone, Copy, Debug, Pod, Zeroable)]
+#[repr(C)]
+pub struct ComputeRasterizeOctree {
+    pub clip_from_local: Mat4,
+    /// to place the points in the clip volumes
+    pub world_from_local: Mat4,
+}
+
+/// A range of at most [`POINTS_PER_BATCH`] points of a visible node, rasterized by one workgroup.
+#[derive(Default, Clone, Copy, Debug, Pod, Zeroable)]
+#[repr(C)]
+pub struct ComputeRasterizeBatch {
+    /// center of the node bounding box, to decode quantized positions
+    pub center: [f32; 3],
+    pub first_instance: u32,
+    /// half extents of the node bounding box, to decode quantized positions
+    pub half_extents: [f32; 3],
+    pub instance_count: u32,
+    /// index of the octree in [`ComputeRasterizeBuffers::octrees`]
+    pub octree_index: u32,
+    pub _padding: [u32; 3],
+}
+
+#[derive(Clone, Copy, Debug, Default, ShaderType)]
+pub struct ComputeRasterizeViewUniform {
+    pub view_from_clip: Mat4,
+    /// x, y, width and height of the viewport, in physical pixels
+    pub viewport: Vec4,
+    /// width of the render target, which is the row length of the framebuffer
+    pub target_width: u32,
+    pub batch_count: u32,
+}
+
+/// Buffers used to rasterize the octrees of a view with a compute shader.
+#[derive(Component)]
+pub struct ComputeRasterizeBuffers {
+    /// packed depth and color of each pixel of the render target
+    pub framebuffer: Option<Buffer>,
+    /// size of the render target covered by the framebuffer
+    pub size: UVec2,
+    pub octrees: RawBufferVec<ComputeRasterizeOctree>,
+    pub batches: RawBufferVec<ComputeRasterizeBatch>,
+    pub view: UniformBuffer<ComputeRasterizeViewUniform>,
+    pub batch_count: u32,
+}
+
+impl Default for ComputeRasterizeBuffers {
+    fn default() -> Self {
+        Self {
+            framebuffer: None,
+            size: UVec2::ZERO,
+            octrees: RawBufferVec::new(BufferUsages::STORAGE),
+            batches: RawBufferVec::new(BufferUsages::STORAGE),
+            view: UniformBuffer::default(),
+            batch_count: 0,
+        }
+    }
+}
+
+impl ComputeRasterizeBuffers {
+    /// Number of workgroups to dispatch to rasterize all the batches.
+    pub fn workgroups(&self) -> UVec2 {
+        UVec2::new(
+            self.batch_count.min(MAX_DISPATCH_SIZE),
+            self.batch_count.div_ceil(MAX_DISPATCH_SIZE),
+        )
+    }
+}
+
+#[derive(Component)]
+pub struct ComputeRasterizePipelineIds {
+    pub rasterize: CachedComputePipelineId,
+    pub resolve: CachedRenderPipelineId,
+}
+
+#[derive(Component)]
+pub struct ComputeRasterizeBindGroups {
+    pub rasterize: BindGroup,
+    pub resolve: BindGroup,
+}
+
+#[allow(clippy::too_many_arguments)]
+fn prepare_compute_rasterize_pipelines(
+    mut commands: Commands,
+    pipeline_cache: Res<PipelineCache>,
+    mut rasterize_pipelines: ResMut<SpecializedComputePipelines<ComputeRasterizePipeline>>,
+    mut resolve_pipelines: ResMut<SpecializedRenderPipelines<ComputeResolvePipeline>>,
+    rasterize_pipeline: Res<ComputeRasterizePipeline>,
+    resolve_pipeline: Res<ComputeResolvePipeline>,
+    octree_buffer_settings: Res<OctreeBufferSettings<PointCloudOctreeExtraction>>,
+    views: Query<(Entity, &Msaa, Option<&PointCloudRenderMode>), With<ComputeRasterizedView>>,
+) {
+    for (entity, msaa, point_cloud_render_mode) in &views {
+        let rasterize = rasterize_pipelines.specialize(
+            &pipeline_cache,
+            &rasterize_pipeline,
+            *octree_buffer_settings.instance_format(),
+        );
+        let resolve = resolve_pipelines.specialize(
+            &pipeline_cache,
+            &resolve_pipeline,
+            ComputeResolvePipelineKey {
+                samples: msaa.samples(),
+                use_edl: point_cloud_render_mode.use_edl(),
+            },
+        );
+
+        commands
+            .entity(entity)
+            .insert(ComputeRasterizePipelineIds { rasterize, resolve });
+    }
+}
+
+#[allow(clippy::type_complexity)]
+fn prepare_compute_rasterize_buffers(
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+    mut views: Query<
+        (
+            &ExtractedView,
+            &ExtractedCamera,
+            &RenderVisibleOctreeNodes<PointCloudNodeData, PointCloudOctree3d>,
+            &mut ComputeRasterizeBuffers,
+        ),
+        With<ComputeRasterizedView>,
+    >,
+    octree_uniforms: Query<&PointCloudOctree3dUniform>,
+    render_octrees: Res<RenderOctrees<RenderPointCloudNodeData>>,
+) {
+    for (view, camera, visible_nodes, mut buffers) in &mut views {
+        let Some(target_size) = camera.physical_target_size else {
+            continue;
+        };
+
+        let ComputeRasterizeBuffers {
+            framebuffer,
+            size,
+            octrees,
+            batches,
+            view: view_uniform,
+            batch_count,
+        } = buffers.as_mut();
+
+        if framebuffer.is_none() || *size != target_size {
+            *framebuffer = Some(render_device.create_buffer(&BufferDescriptor {
+                label: Some("pcl_compute_rasterize_framebuffer"),
+                size: target_size.x as u64 * target_size.y as u64 * size_of::<u64>() as u64,
+                usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            }));
+            *size = target_size;
+        }
+
+        octrees.clear();
+        batches.clear();
+
+        let clip_from_world = view
+            .clip_from_world
+            .unwrap_or_else(|| view.clip_from_view * view.world_from_view.to_matrix().inverse());
+
+        for (entity, (asset_id, octree_nodes)) in &visible_nodes.octrees {
+            let Ok(octree_uniform) = octree_uniforms.get(*entity) else {
+                continue;
+            };
+            let Some(render_octree) = render_octrees.get(*asset_id) else {
+                continue;
+            };
+
+            let octree_index = octrees.len() as u32;
+            octrees.push(ComputeRasterizeOctree {
+                clip_from_local: clip_from_world * octree_uniform.world_from_local,
+                world_from_local: octree_uniform.world_from_local,
+            });
+
+            for visible_node in octree_nodes.iter().filter(|node| !node.shadow_only) {
+                let Some(node) = render_octree.nodes.get(&visible_node.id) else {
+                    continue;
+                };
+                push_node_batches(
+                    batches,
+                    octree_index,
+                    &node.bounding_box,
+                    node.allocation.start,
+                    node.allocation.count,
+                );
+            }
+        }
+
+        *batch_count = batches.len() as u32;
+
+        // empty storage buffers can't be bound
+        if octrees.is_empty() {
+            octrees.push(ComputeRasterizeOctree {
+                clip_from_local: Mat4::IDENTITY,
+                world_from_local: Mat4::IDENTITY,
+            });
+        }
+        if batches.is_empty() {
+            batches.push(ComputeRasterizeBatch::default());
+        }
+
+        view_uniform.set(ComputeRasterizeViewUniform {
+            view_from_clip: view.clip_from_view.inverse(),
+            viewport: view.viewport.as_vec4(),
+            target_width: target_size.x,
+            batch_count: *batch_count,
+        });
+
+        octrees.write_buffer(&render_device, &render_queue);
+        batches.write_buffer(&render_device, &render_queue);
+        view_uniform.write_buffer(&render_device, &render_queue);
+    }
+}
+
+/// Split the instances of a node in batches of at most [`POINTS_PER_BATCH`] points.
+fn push_node_batches(
+    batches: &mut RawBufferVec<ComputeRasterizeBatch>,
+    octree_index: u32,
+    bounding_box: &Aabb,
+    start: u32,
+    count: u32,
+) {
+    for offset in (0..count).step_by(POINTS_PER_BATCH as usize) {
+        batches.push(ComputeRasterizeBatch {
+            center: bounding_box.center.into(),
+            first_instance: start + offset,
+            half_extents: bounding_box.half_extents.into(),
+            instance_count: (count - offset).min(POINTS_PER_BATCH),
+            octree_index,
+            _padding: [0; 3],
+        });
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn prepare_compute_rasterize_bind_groups(
+    mut commands: Commands,
+    render_device: Res<RenderDevice>,
+    pipeline_cache: Res<PipelineCache>,
+    rasterize_pipeline: Res<ComputeRasterizePipeline>,
+    resolve_pipeline: Res<ComputeResolvePipeline>,
+    render_octrees_buffers: Res<RenderOctreesBuffers<RenderPointCloudNodeData>>,
+    render_clip_volumes: Res<RenderClipVolumes>,
+    views: Query<(Entity, &ComputeRasterizeBuffers), With<ComputeRasterizedView>>,
+) {
+    // all the octrees share the same instance buffer
+    let Some(octrees_buffer) = render_octrees_buffers.get(0) else {
+        return;
+    };
+    let Some(clip_volumes) = render_clip_volumes.buffer.binding() else {
+        return;
+    };
+
+    for (entity, buffers) in &views {
+        let (Some(framebuffer), Some(view), Some(octrees), Some(batches)) = (
+            buffers.framebuffer.as_ref(),
+            buffers.view.binding(),
+            buffers.octrees.binding(),
+            buffers.batches.binding(),
+        ) else {
+            continue;
+        };
+
+        let rasterize = render_device.create_bind_group(
+            "pcl_compute_rasterize_bind_group",
+            &pipeline_cache.get_bind_group_layout(&rasterize_pipeline.layout),
+            &BindGroupEntries::sequential((
+                view.clone(),
+                octrees,
+                batches,
+                octrees_buffer.buffer.as_entire_binding(),
+                framebuffer.as_entire_binding(),
+                clip_volumes.clone(),
+            )),
+        );
+
+        let resolve = render_device.create_bind_group(
+            "pcl_compute_resolve_bind_group",
+            &pipeline_cache.get_bind_group_layout(&resolve_pipeline.layout),
+            &BindGroupEntries::sequential((view, framebuffer.as_entire_binding())),
+        );
+
+        commands
+            .entity(entity)
+            .insert(ComputeRasterizeBindGroups { rasterize, resolve });
+    }
+}
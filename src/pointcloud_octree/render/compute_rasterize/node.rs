@@ -0,0 +1,104 @@
+use bevy_ecs::{prelude::*, query::QueryItem};
+use bevy_render::{
+    render_graph::{NodeRunError, RenderGraphContext, RenderLabel, ViewNode},
+    render_resource::*,
+    renderer::RenderContext,
+    view::ViewDepthTexture,
+};
+
+use super::{
+    ComputeRasterizeBindGroups, ComputeRasterizeBuffers, ComputeRasterizePipelineIds,
+    ComputeRasterizedView,
+};
+use crate::render::{
+    attribute_pass::texture::ViewAttributePrepassTextures,
+    depth_pass::texture::ViewDepthPrepassTextures,
+};
+
+#[derive(RenderLabel, Debug, Clone, Hash, PartialEq, Eq)]
+pub struct ComputeRasterizeLabel;
+
+#[derive(Default)]
+pub struct ComputeRasterizeNode;
+
+impl ViewNode for ComputeRasterizeNode {
+    type ViewQuery = (
+        Has<ComputeRasterizedView>,
+        &'static ViewDepthTexture,
+        &'static ViewDepthPrepassTextures,
+        &'static ViewAttributePrepassTextures,
+        &'static ComputeRasterizeBuffers,
+        &'static ComputeRasterizeBindGroups,
+        &'static ComputeRasterizePipelineIds,
+    );
+
+    fn run(
+        &self,
+        _graph: &mut RenderGraphContext,
+        render_context: &mut RenderContext,
+        (
+            compute_rasterized,
+            view_depth_texture,
+            depth_textures,
+            attribute_textures,
+            buffers,
+            bind_groups,
+            pipeline_ids,
+        ): QueryItem<Self::ViewQuery>,
+        world: &World,
+    ) -> Result<(), NodeRunError> {
+        // the components are kept when the view switches back to hardware rasterization
+        if !compute_rasterized || buffers.batch_count == 0 {
+            return Ok(());
+        }
+
+        let pipeline_cache = world.resource::<PipelineCache>();
+
+        let (Some(rasterize_pipeline), Some(resolve_pipeline), Some(framebuffer)) = (
+            pipeline_cache.get_compute_pipeline(pipeline_ids.rasterize),
+            pipeline_cache.get_render_pipeline(pipeline_ids.resolve),
+            buffers.framebuffer.as_ref(),
+        ) else {
+            return Ok(());
+        };
+
+        let command_encoder = render_context.command_encoder();
+        command_encoder.clear_buffer(framebuffer, 0, None);
+        {
+            let mut compute_pass = command_encoder.begin_compute_pass(&ComputePassDescriptor {
+                label: Some("pcl_compute_rasterize_pass"),
+                timestamp_writes: None,
+            });
+            compute_pass.set_pipeline(rasterize_pipeline);
+            compute_pass.set_bind_group(0, &bind_groups.rasterize, &[]);
+
+            let workgroups = buffers.workgroups();
+            compute_pass.dispatch_workgroups(workgroups.x, workgroups.y, 1);
+        }
+
+        let color_attachments = [
+            depth_textures
+                .depth
+                .as_ref()
+                .map(|depth_texture| depth_texture.get_attachment()),
+            attribute_textures
+                .attribute
+                .as_ref()
+                .map(|attribute_texture| attribute_texture.get_attachment()),
+        ];
+
+        let mut render_pass = render_context.begin_tracked_render_pass(RenderPassDescriptor {
+            label: Some("pcl_compute_resolve_pass"),
+            color_attachments: &color_attachments,
+            depth_stencil_attachment: Some(view_depth_texture.get_attachment(StoreOp::Store)),
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+
+        render_pass.set_render_pipeline(resolve_pipeline);
+        render_pass.set_bind_group(0, &bind_groups.resolve, &[]);
+        render_pass.draw(0..3, 0..1);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,160 @@
+use bevy_core_pipeline::FullscreenShader;
+use bevy_ecs::prelude::*;
+use bevy_render::render_resource::{
+    binding_types::{storage_buffer_read_only_sized, storage_buffer_sized, uniform_buffer},
+    *,
+};
+
+use super::{
+    ComputeRasterizeViewUniform, COMPUTE_RASTERIZE_SHADER_HANDLE, COMPUTE_RESOLVE_SHADER_HANDLE,
+};
+use crate::{
+    pointcloud_octree::asset::data::PointFormat,
+    render::clip_volume::{clip_volumes_shader_defs, ClipVolumesUniform},
+};
+
+/// The compute pipeline writing the points to the framebuffer, specialized by instance format.
+#[derive(Resource)]
+pub struct ComputeRasterizePipeline {
+    pub layout: BindGroupLayoutDescriptor,
+}
+
+impl FromWorld for ComputeRasterizePipeline {
+    fn from_world(_world: &mut World) -> Self {
+        let layout = BindGroupLayoutDescriptor {
+            label: "pcl_compute_rasterize_bind_group_layout".into(),
+            entries: BindGroupLayoutEntries::sequential(
+                ShaderStages::COMPUTE,
+                (
+                    // view
+                    uniform_buffer::<ComputeRasterizeViewUniform>(false),
+                    // octrees transforms
+                    storage_buffer_read_only_sized(false, None),
+                    // batches of points
+                    storage_buffer_read_only_sized(false, None),
+                    // octrees instances
+                    storage_buffer_read_only_sized(false, None),
+                    // framebuffer (depth << 32 | color)
+                    storage_buffer_sized(false, None),
+                    // clip volumes
+                    uniform_buffer::<ClipVolumesUniform>(false),
+                ),
+            )
+            .to_vec(),
+        };
+
+        Self { layout }
+    }
+}
+
+impl SpecializedComputePipeline for ComputeRasterizePipeline {
+    type Key = PointFormat;
+
+    fn specialize(&self, key: Self::Key) -> ComputePipelineDescriptor {
+        let mut shader_defs = clip_volumes_shader_defs(0, 5).to_vec();
+        if let Some(shader_def) = key.shader_def() {
+            shader_defs.push(shader_def.into());
+        }
+
+        ComputePipelineDescriptor {
+            label: Some("pcl_compute_rasterize_pipeline".into()),
+            layout: vec![self.layout.clone()],
+            push_constant_ranges: vec![],
+            shader: COMPUTE_RASTERIZE_SHADER_HANDLE,
+            shader_defs,
+            entry_point: Some("rasterize".into()),
+            zero_initialize_workgroup_memory: false,
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Hash, Clone)]
+pub struct ComputeResolvePipelineKey {
+    pub samples: u32,
+    pub use_edl: bool,
+}
+
+/// The fullscreen pipeline copying the framebuffer into the depth and attribute pass textures.
+#[derive(Resource)]
+pub struct ComputeResolvePipeline {
+    pub layout: BindGroupLayoutDescriptor,
+    fullscreen_shader: FullscreenShader,
+}
+
+impl FromWorld for ComputeResolvePipeline {
+    fn from_world(world: &mut World) -> Self {
+        let layout = BindGroupLayoutDescriptor {
+            label: "pcl_compute_resolve_bind_group_layout".into(),
+            entries: BindGroupLayoutEntries::sequential(
+                ShaderStages::FRAGMENT,
+                (
+                    // view
+                    uniform_buffer::<ComputeRasterizeViewUniform>(false),
+                    // framebuffer
+                    storage_buffer_read_only_sized(false, None),
+                ),
+            )
+            .to_vec(),
+        };
+
+        Self {
+            layout,
+            fullscreen_shader: world.resource::<FullscreenShader>().clone(),
+        }
+    }
+}
+
+impl SpecializedRenderPipeline for ComputeResolvePipeline {
+    type Key = ComputeResolvePipelineKey;
+
+    fn specialize(&self, key: Self::Key) -> RenderPipelineDescriptor {
+        let mut shader_defs = Vec::new();
+        if key.use_edl {
+            shader_defs.push("USE_EDL".into());
+        }
+
+        RenderPipelineDescriptor {
+            label: Some("pcl_compute_resolve_pipeline".into()),
+            layout: vec![self.layout.clone()],
+            vertex: self.fullscreen_shader.to_vertex_state(),
+            fragment: Some(FragmentState {
+                shader: COMPUTE_RESOLVE_SHADER_HANDLE,
+                shader_defs,
+                entry_point: Some("fragment".into()),
+                targets: vec![
+                    // same targets as the depth pass, followed by the attribute pass one
+                    Some(ColorTargetState {
+                        format: if key.use_edl {
+                            TextureFormat::Rg32Float
+                        } else {
+                            TextureFormat::R32Float
+                        },
+                        blend: None,
+                        write_mask: ColorWrites::ALL,
+                    }),
+                    // the points replace the splats they hide
+                    Some(ColorTargetState {
+                        format: TextureFormat::Rgba32Float,
+                        blend: None,
+                        write_mask: ColorWrites::ALL,
+                    }),
+                ],
+            }),
+            primitive: PrimitiveState::default(),
+            depth_stencil: Some(DepthStencilState {
+                format: TextureFormat::Depth32Float,
+                depth_write_enabled: true,
+                depth_compare: CompareFunction::GreaterEqual,
+                stencil: StencilState::default(),
+                bias: DepthBiasState::default(),
+            }),
+            multisample: MultisampleState {
+                count: key.samples,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            push_constant_ranges: vec![],
+            zero_initialize_workgroup_memory: false,
+        }
+    }
+}
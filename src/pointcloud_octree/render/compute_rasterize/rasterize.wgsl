@@ -0,0 +1,145 @@
+// Rasterizes the points of the visible octree nodes with 64 bits atomics, one pixel per point.
+// Based on "Rendering Point Clouds with Compute Shaders and Vertex Order Optimization"
+// (Schütz, Kerbl, Wimmer, 2021).
+
+#import bevy_pointcloud::clip_volume::{
+    clip_volumes, clip_point, clip_state_is_clipped, clip_state_highlight,
+}
+
+struct ComputeRasterizeView {
+    view_from_clip: mat4x4<f32>,
+    viewport: vec4<f32>,
+    target_width: u32,
+    batch_count: u32,
+};
+
+struct Octree {
+    clip_from_local: mat4x4<f32>,
+    world_from_local: mat4x4<f32>,
+};
+
+struct Batch {
+    center: vec3<f32>,
+    first_instance: u32,
+    half_extents: vec3<f32>,
+    instance_count: u32,
+    octree_index: u32,
+};
+
+struct Point {
+    position: vec3<f32>,
+    // rgba8 color
+    color: u32,
+};
+
+// Must match the constants of the compute_rasterize module
+const WORKGROUP_SIZE: u32 = 128u;
+const POINTS_PER_THREAD: u32 = 32u;
+const MAX_DISPATCH_SIZE: u32 = 65535u;
+
+@group(0) @binding(0)
+var<uniform> view: ComputeRasterizeView;
+
+@group(0) @binding(1)
+var<storage, read> octrees: array<Octree>;
+
+@group(0) @binding(2)
+var<storage, read> batches: array<Batch>;
+
+// The instances of all the octrees, read as words
+@group(0) @binding(3)
+var<storage, read> points: array<u32>;
+
+// Depth in the high 32 bits and color in the low 32 bits, so that the nearest point wins
+// with reverse Z. Zero means that no point has been written.
+@group(0) @binding(4)
+var<storage, read_write> framebuffer: array<atomic<u64>>;
+
+fn dequantize_position(batch: Batch, position: vec3<u32>, max_value: f32) -> vec3<f32> {
+    return batch.center + batch.half_extents * (vec3<f32>(position) / max_value * 2.0 - 1.0);
+}
+
+fn load_point(batch: Batch, instance_index: u32) -> Point {
+    var point: Point;
+#ifdef QUANTIZED_POSITIONS_16
+    // x and y, z and normal, color
+    let base = instance_index * 3u;
+    let xy = points[base];
+    let position = vec3<u32>(xy & 0xffffu, xy >> 16u, points[base + 1u] & 0xffffu);
+    point.position = dequantize_position(batch, position, 65535.0);
+    point.color = points[base + 2u];
+#else ifdef QUANTIZED_POSITIONS_32
+    // x, y, z, color, normal and padding
+    let base = instance_index * 5u;
+    let position = vec3<u32>(points[base], points[base + 1u], points[base + 2u]);
+    point.position = dequantize_position(batch, position, 4294967295.0);
+    point.color = points[base + 3u];
+#else
+    // position + padding, color, normal
+    let base = instance_index * 9u;
+    point.position = vec3<f32>(
+        bitcast<f32>(points[base]),
+        bitcast<f32>(points[base + 1u]),
+        bitcast<f32>(points[base + 2u]),
+    );
+    point.color = pack4x8unorm(vec4<f32>(
+        bitcast<f32>(points[base + 4u]),
+        bitcast<f32>(points[base + 5u]),
+        bitcast<f32>(points[base + 6u]),
+        bitcast<f32>(points[base + 7u]),
+    ));
+#endif
+    return point;
+}
+
+@compute @workgroup_size(128, 1, 1)
+fn rasterize(
+    @builtin(workgroup_id) workgroup_id: vec3<u32>,
+    @builtin(local_invocation_index) local_index: u32,
+) {
+    let batch_index = workgroup_id.y * MAX_DISPATCH_SIZE + workgroup_id.x;
+    if batch_index >= view.batch_count {
+        return;
+    }
+
+    let batch = batches[batch_index];
+    let clip_from_local = octrees[batch.octree_index].clip_from_local;
+    let world_from_local = octrees[batch.octree_index].world_from_local;
+    let viewport_max = vec2<u32>(view.viewport.xy + view.viewport.zw) - 1u;
+
+    for (var i = 0u; i < POINTS_PER_THREAD; i++) {
+        // consecutive invocations read consecutive points
+        let index = i * WORKGROUP_SIZE + local_index;
+        if index >= batch.instance_count {
+            break;
+        }
+
+        let point = load_point(batch, batch.first_instance + index);
+        let clip_position = clip_from_local * vec4<f32>(point.position, 1.0);
+        if clip_position.w <= 0.0 {
+            continue;
+        }
+
+        let ndc = clip_position.xyz / clip_position.w;
+        if any(abs(ndc.xy) > vec2<f32>(1.0)) || ndc.z < 0.0 || ndc.z > 1.0 {
+            continue;
+        }
+
+        var color = point.color;
+        if clip_volumes.count > 0u {
+            let world_position = (world_from_local * vec4<f32>(point.position, 1.0)).xyz;
+            let clip_state = clip_point(world_position);
+            if clip_state_is_clipped(clip_state) {
+                continue;
+            }
+            let unpacked = unpack4x8unorm(color);
+            color = pack4x8unorm(vec4<f32>(clip_state_highlight(clip_state, unpacked.rgb), unpacked.a));
+        }
+
+        let pixel = view.viewport.xy + (ndc.xy * vec2<f32>(0.5, -0.5) + 0.5) * view.viewport.zw;
+        let coords = min(vec2<u32>(pixel), viewport_max);
+
+        let packed = (u64(bitcast<u32>(ndc.z)) << 32u) | u64(color);
+        atomicMax(&framebuffer[coords.y * view.target_width + coords.x], packed);
+    }
+}
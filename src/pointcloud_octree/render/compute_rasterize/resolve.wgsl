@@ -0,0 +1,56 @@
+// Copies the framebuffer written by the compute rasterization into the depth and attribute
+// pass textures, so that the normalize pass shades these points like the other ones.
+
+#import bevy_core_pipeline::fullscreen_vertex_shader::FullscreenVertexOutput
+
+struct ComputeRasterizeView {
+    view_from_clip: mat4x4<f32>,
+    viewport: vec4<f32>,
+    target_width: u32,
+    batch_count: u32,
+};
+
+@group(0) @binding(0)
+var<uniform> view: ComputeRasterizeView;
+
+// Color in x and depth in y
+@group(0) @binding(1)
+var<storage, read> framebuffer: array<vec2<u32>>;
+
+struct FragmentOutput {
+#ifdef USE_EDL
+    @location(0) depth_texture: vec2<f32>,
+#else // USE_EDL
+    @location(0) depth_texture: f32,
+#endif // USE_EDL
+    @location(1) color: vec4<f32>,
+    @builtin(frag_depth) depth: f32,
+}
+
+@fragment
+fn fragment(in: FullscreenVertexOutput) -> FragmentOutput {
+    let coords = vec2<u32>(in.position.xy);
+    let pixel = framebuffer[coords.y * view.target_width + coords.x];
+
+    if pixel.y == 0u {
+        discard;
+    }
+
+    let depth = bitcast<f32>(pixel.y);
+
+    var output: FragmentOutput;
+    output.depth = depth;
+
+#ifdef USE_EDL
+    let view_position = view.view_from_clip * vec4<f32>(0.0, 0.0, depth, 1.0);
+    output.depth_texture = vec2<f32>(depth, log2(-view_position.z / view_position.w));
+#else // USE_EDL
+    output.depth_texture = depth;
+#endif // USE_EDL
+
+    // convert the color to linear RGB, with a weight of 1
+    let color = unpack4x8unorm(pixel.x);
+    output.color = vec4<f32>(pow(color.rgb, vec3<f32>(2.2)), 1.0);
+
+    return output;
+}
@@ -10,6 +10,7 @@ use bevy_platform::collections::HashSet;
 use bevy_render::{
     batching::gpu_preprocessing::GpuPreprocessingSupport,
     prelude::*,
+    render_asset::RenderAssets,
     render_graph::{RenderGraphExt, ViewNodeRunner},
     render_phase::{AddRenderCommand, DrawFunctions, SetItemPipeline},
     render_resource::{PipelineCache, SpecializedRenderPipelines},
@@ -25,15 +26,19 @@ use crate::pointcloud_octree::render::draw::DrawPointCloudOctree;
 #[cfg(not(feature = "webgl"))]
 use crate::pointcloud_octree::render::draw::DrawPointCloudOctreeIndirect;
 use crate::{
-    octree::extract::render::components::RenderVisibleOctreeNodes,
+    octree::extract::{
+        render::components::RenderVisibleOctreeNodes, resources::OctreeBufferSettings,
+    },
+    point_cloud_material::PointCloudMaterial3d,
     pointcloud_octree::{
-        asset::data::PointCloudNodeData,
+        asset::{data::PointCloudNodeData, extract::PointCloudOctreeExtraction},
         component::PointCloudOctree3d,
         render::{
+            compute_rasterize::ComputeRasterizedView,
             data::SetPointCloudOctree3dUniformGroup,
             draw::{SetPointCloudOctreeNodeUniformGroup, SetRenderOctreeUniformGroup},
             phase::{PointCloudOctree3dNodePhase, ViewOctreeNodesRenderDepthPhases},
-            prepare::SetVisibleNodesTexture,
+            prepare::SetVisibleNodes,
         },
     },
     render::{
@@ -41,7 +46,7 @@ use crate::{
             pipeline::{DepthPipeline, DepthPipelineKey},
             texture::prepare_depth_pass_textures,
         },
-        material::SetPointCloudMaterialGroup,
+        material::{RenderPointCloudMaterial, SetPointCloudMaterialGroup},
         phase::PointCloud3dBatchSetKey,
         PointCloudRenderMode, PointCloudRenderModeOpt,
     },
@@ -73,8 +78,8 @@ impl Plugin for DepthPassPlugin {
                 Core3d,
                 DepthPassOctreeLabel,
             )
-            // Tell the node to run before the main transparent pass
-            .add_render_graph_edges(Core3d, (DepthPassOctreeLabel, Node3d::MainTransparentPass));
+            // Run after the opaque meshes so that the points are tested against their depth
+            .add_render_graph_edges(Core3d, (Node3d::MainOpaquePass, DepthPassOctreeLabel));
     }
 }
 
@@ -85,7 +90,7 @@ type DrawDepthPass = (
     SetMeshViewBindGroup<0>,
     SetPointCloudOctree3dUniformGroup<1>,
     SetPointCloudMaterialGroup<2>,
-    SetVisibleNodesTexture<3>,
+    SetVisibleNodes<3>,
     SetPointCloudOctreeNodeUniformGroup<4>,
     SetRenderOctreeUniformGroup<5>,
     DrawPointCloudOctreeIndirect,
@@ -97,7 +102,7 @@ type DrawDepthPass = (
     SetMeshViewBindGroup<0>,
     SetPointCloudOctree3dUniformGroup<1>,
     SetPointCloudMaterialGroup<2>,
-    SetVisibleNodesTexture<3>,
+    SetVisibleNodes<3>,
     SetPointCloudOctreeNodeUniformGroup<4>,
     SetRenderOctreeUniformGroup<5>,
     DrawPointCloudOctree,
@@ -136,21 +141,28 @@ fn queue_depth_pass(
     mut pipelines: ResMut<SpecializedRenderPipelines<DepthPipeline>>,
     pipeline_cache: Res<PipelineCache>,
     custom_draw_pipeline: Res<DepthPipeline>,
-    point_cloud_octrees_3d: Query<&PointCloudOctree3d>,
+    point_cloud_octrees_3d: Query<(&PointCloudOctree3d, &PointCloudMaterial3d)>,
+    render_materials: Res<RenderAssets<RenderPointCloudMaterial>>,
+    octree_buffer_settings: Res<OctreeBufferSettings<PointCloudOctreeExtraction>>,
     mut custom_render_phases: ResMut<ViewOctreeNodesRenderDepthPhases<PointCloudOctree3dNodePhase>>,
     mut views: Query<(
         &ExtractedView,
         &RenderVisibleOctreeNodes<PointCloudNodeData, PointCloudOctree3d>,
         &Msaa,
         Option<&PointCloudRenderMode>,
+        Has<ComputeRasterizedView>,
     )>,
     main_entities: Query<&MainEntity>,
     mut next_tick: Local<Tick>,
 ) {
-    for (view, visible_entities, msaa, point_cloud_render_mode) in &mut views {
+    for (view, visible_entities, msaa, point_cloud_render_mode, compute_rasterized) in &mut views {
         let Some(custom_phase) = custom_render_phases.get_mut(&view.retained_view_entity) else {
             continue;
         };
+        // the octrees of this view are drawn by the compute rasterization pass
+        if compute_rasterized {
+            continue;
+        }
         let draw_custom = custom_draw_functions.read().id::<DrawDepthPass>();
 
         // Create the key based on the view.
@@ -158,24 +170,35 @@ fn queue_depth_pass(
         let view_key = MeshPipelineKey::from_msaa_samples(msaa.samples())
             | MeshPipelineKey::from_hdr(view.hdr);
 
-        let depth_key = DepthPipelineKey {
-            mesh_key: view_key,
-            use_edl: point_cloud_render_mode.use_edl(),
-            is_octree: true,
-        };
-
-        let pipeline_id = pipelines.specialize(&pipeline_cache, &custom_draw_pipeline, depth_key);
-
         // Since our phase can work on any 3d mesh we can reuse the default mesh 3d filter
         for (render_entity, _) in &visible_entities.octrees {
             let Ok(main_entity) = main_entities.get(*render_entity) else {
                 warn!("Render entity not found, skipping.");
                 continue;
             };
-            let Ok(point_cloud_octree_3d) = point_cloud_octrees_3d.get(*render_entity) else {
+            let Ok((point_cloud_octree_3d, point_cloud_material_3d)) =
+                point_cloud_octrees_3d.get(*render_entity)
+            else {
                 warn!("point_cloud_octree_3d missing");
                 continue;
             };
+            let Some(material) = render_materials.get(point_cloud_material_3d) else {
+                continue;
+            };
+
+            let depth_key = DepthPipelineKey {
+                mesh_key: view_key,
+                use_edl: point_cloud_render_mode.use_edl(),
+                is_octree: true,
+                shape: material.shape,
+                splatting: material.splatting,
+                geometry: material.geometry,
+                point_format: *octree_buffer_settings.instance_format(),
+                picking: false,
+            };
+
+            let pipeline_id =
+                pipelines.specialize(&pipeline_cache, &custom_draw_pipeline, depth_key);
 
             // Bump the change tick in order to force Bevy to rebuild the bin.
             let this_tick = next_tick.get() + 1;
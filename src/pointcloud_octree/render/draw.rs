@@ -1,8 +1,11 @@
 use bevy_ecs::{
-    query::ROQueryItem,
+    query::{Has, ROQueryItem},
     system::{lifetimeless::*, SystemParamItem},
 };
 use bevy_log::prelude::*;
+use bevy_pbr::LightEntity;
+#[cfg(not(feature = "webgl"))]
+use bevy_render::batching::gpu_preprocessing::IndirectParametersNonIndexed;
 use bevy_render::{
     render_phase::{BinnedPhaseItem, RenderCommand, RenderCommandResult, TrackedRenderPass},
     renderer::RenderQueue,
@@ -10,6 +13,7 @@ use bevy_render::{
 
 #[cfg(not(feature = "webgl"))]
 use crate::pointcloud_octree::render::indirect::RenderVisibleNodesIndirectBuffers;
+use crate::pointcloud_octree::render::prepare::VisibleNodesBindGroup;
 use crate::{
     octree::{
         extract::render::{
@@ -108,13 +112,21 @@ impl<P: BinnedPhaseItem> RenderCommand<P> for DrawPointCloudOctree {
         SRes<RenderOctrees<RenderPointCloudNodeData>>,
         SRes<RenderOctreesBuffers<RenderPointCloudNodeData>>,
     );
-    type ViewQuery = Read<RenderVisibleOctreeNodes<PointCloudNodeData, PointCloudOctree3d>>;
+    type ViewQuery = (
+        Read<RenderVisibleOctreeNodes<PointCloudNodeData, PointCloudOctree3d>>,
+        Has<LightEntity>,
+        Option<Read<VisibleNodesBindGroup>>,
+    );
     type ItemQuery = Read<PointCloudOctree3d>;
 
     #[inline]
     fn render<'w>(
         item: &P,
-        visible_octree_nodes: &RenderVisibleOctreeNodes<PointCloudNodeData, PointCloudOctree3d>,
+        (visible_octree_nodes, is_light_view, visible_nodes_bind_group): (
+            &RenderVisibleOctreeNodes<PointCloudNodeData, PointCloudOctree3d>,
+            bool,
+            Option<&'w VisibleNodesBindGroup>,
+        ),
         point_cloud_octree_3d: Option<&PointCloudOctree3d>,
         (point_cloud_mesh, render_octrees, render_octrees_buffers): SystemParamItem<
             'w,
@@ -166,8 +178,20 @@ impl<P: BinnedPhaseItem> RenderCommand<P> for DrawPointCloudOctree {
         //     0..node.data.num_points as u32,
         // );
 
-        for VisibleOctreeNode { id: node_id, .. } in visible_octree_nodes {
+        for VisibleOctreeNode {
+            id: node_id,
+            shadow_only,
+            ..
+        } in visible_octree_nodes
+        {
+            // the nodes kept for the shadows are only drawn into the shadow maps
+            if *shadow_only && !is_light_view {
+                continue;
+            }
             if let Some(render_octree_node_data) = render_octree.nodes.get(node_id) {
+                if let Some(visible_nodes_bind_group) = visible_nodes_bind_group {
+                    visible_nodes_bind_group.set_node_bounds(pass, &item.entity(), node_id);
+                }
                 pass.draw(
                     0..point_cloud_mesh.index_count,
                     render_octree_node_data.allocation.start
@@ -190,13 +214,20 @@ impl<P: BinnedPhaseItem> RenderCommand<P> for DrawPointCloudOctreeIndirect {
         SRes<PointCloudMesh>,
         SRes<RenderOctreesBuffers<RenderPointCloudNodeData>>,
     );
-    type ViewQuery = Read<RenderVisibleNodesIndirectBuffers>;
+    type ViewQuery = (
+        Read<RenderVisibleNodesIndirectBuffers>,
+        Option<Read<VisibleNodesBindGroup>>,
+    );
     type ItemQuery = ();
 
     #[inline]
     fn render<'w>(
         item: &P,
-        render_visible_nodes_indirect_buffers: ROQueryItem<'w, '_, Self::ViewQuery>,
+        (render_visible_nodes_indirect_buffers, visible_nodes_bind_group): ROQueryItem<
+            'w,
+            '_,
+            Self::ViewQuery,
+        >,
         _: Option<ROQueryItem<'w, '_, Self::ItemQuery>>,
         (point_cloud_mesh, render_octrees_buffers): SystemParamItem<'w, '_, Self::Param>,
         pass: &mut TrackedRenderPass<'w>,
@@ -210,17 +241,20 @@ impl<P: BinnedPhaseItem> RenderCommand<P> for DrawPointCloudOctreeIndirect {
             return RenderCommandResult::Skip;
         };
 
-        let Some(indirect_buffer) = render_visible_nodes_indirect_buffers.get(&item.entity())
-        else {
+        let Some(range) = render_visible_nodes_indirect_buffers.get(&item.entity()) else {
             warn!("Missing visible octree data");
             return RenderCommandResult::Skip;
         };
 
-        let Some(buffer) = indirect_buffer.buffer() else {
+        let Some(buffer) = render_visible_nodes_indirect_buffers.buffer() else {
             warn!("Missing indirect buffer");
             return RenderCommandResult::Skip;
         };
 
+        if range.is_empty() {
+            return RenderCommandResult::Skip;
+        }
+
         pass.set_vertex_buffer(0, point_cloud_mesh.vertex_buffer.slice(..));
         // not needed is using a single triangle
         // pass.set_index_buffer(
@@ -238,7 +272,21 @@ impl<P: BinnedPhaseItem> RenderCommand<P> for DrawPointCloudOctreeIndirect {
         //     0..node.data.num_points as u32,
         // );
 
-        pass.multi_draw_indirect(buffer, 0, indirect_buffer.len() as u32);
+        // the draws are written by the culling pass
+        let indirect_offset =
+            |index: u32| index as u64 * size_of::<IndirectParametersNonIndexed>() as u64;
+        match visible_nodes_bind_group.and_then(|bind_group| bind_group.node_bounds.as_ref()) {
+            // the quantized points need the bounds of their node, one draw per node
+            Some(node_bounds) => {
+                for index in range {
+                    node_bounds.set(pass, index);
+                    pass.draw_indirect(buffer, indirect_offset(index));
+                }
+            }
+            None => {
+                pass.multi_draw_indirect(buffer, indirect_offset(range.start), range.len() as u32);
+            }
+        }
 
         RenderCommandResult::Success
     }
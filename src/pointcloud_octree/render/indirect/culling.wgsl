@@ -0,0 +1,82 @@
+// Culls the visible nodes of the octrees of a view against its frustum and writes one
+// indirect draw per node, with no instances when the node is outside of the frustum or only
+// kept for the shadows of a camera view.
+
+struct CullingOctree {
+    clip_from_local: mat4x4<f32>,
+    first_node: u32,
+    node_count: u32,
+    vertex_count: u32,
+    // the light views draw the nodes only kept for the shadows, and clamp the depth of the
+    // casters in front of their near plane
+    is_light_view: u32,
+};
+
+struct VisibleNodeBounds {
+    center: vec3<f32>,
+    first_instance: u32,
+    half_extents: vec3<f32>,
+    instance_count: u32,
+    shadow_only: u32,
+};
+
+struct IndirectParametersNonIndexed {
+    vertex_count: u32,
+    instance_count: u32,
+    base_vertex: u32,
+    first_instance: u32,
+};
+
+@group(0) @binding(0)
+var<storage, read> octrees: array<CullingOctree>;
+
+// Bounds of the visible nodes, in the local space of their octree
+@group(0) @binding(1)
+var<storage, read> visible_nodes_bounds: array<VisibleNodeBounds>;
+
+@group(0) @binding(2)
+var<storage, read_write> indirect: array<IndirectParametersNonIndexed>;
+
+// Whether the box is at least partially on the positive side of the plane
+fn intersects_plane(plane: vec4<f32>, center: vec3<f32>, half_extents: vec3<f32>) -> bool {
+    let distance = dot(plane.xyz, center) + plane.w;
+    let radius = dot(abs(plane.xyz), half_extents);
+    return distance + radius >= 0.0;
+}
+
+// Test the box against the frustum planes extracted from the clip matrix, with reverse Z
+fn intersects_frustum(octree: CullingOctree, center: vec3<f32>, half_extents: vec3<f32>) -> bool {
+    let rows = transpose(octree.clip_from_local);
+    return intersects_plane(rows[3] + rows[0], center, half_extents)
+        && intersects_plane(rows[3] - rows[0], center, half_extents)
+        && intersects_plane(rows[3] + rows[1], center, half_extents)
+        && intersects_plane(rows[3] - rows[1], center, half_extents)
+        // near
+        && (octree.is_light_view != 0u || intersects_plane(rows[3] - rows[2], center, half_extents))
+        // far
+        && intersects_plane(rows[2], center, half_extents);
+}
+
+@compute @workgroup_size(64, 1, 1)
+fn cull(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let octree = octrees[global_id.y];
+    if global_id.x >= octree.node_count {
+        return;
+    }
+
+    let node_index = octree.first_node + global_id.x;
+    let node = visible_nodes_bounds[node_index];
+
+    var instance_count = 0u;
+    let drawn = node.shadow_only == 0u || octree.is_light_view != 0u;
+    if drawn && intersects_frustum(octree, node.center, node.half_extents) {
+        instance_count = node.instance_count;
+    }
+
+    indirect[node_index] = IndirectParametersNonIndexed(
+        octree.vertex_count,
+        instance_count,
+        0u,
+        node.first_instance,
+    );
+}
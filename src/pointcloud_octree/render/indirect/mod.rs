@@ -0,0 +1,299 @@
+pub mod node;
+
+use std::ops::Range;
+
+use bevy_asset::{load_internal_asset, uuid_handle, Handle};
+use bevy_math::Mat4;
+use bevy_pbr::{LightEntity, ViewLightEntities};
+use bevy_platform::collections::HashMap;
+use bevy_render::{
+    batching::gpu_preprocessing::IndirectParametersNonIndexed,
+    render_resource::{
+        binding_types::{storage_buffer_read_only_sized, storage_buffer_sized},
+        BindGroup, BindGroupEntries, BindGroupLayoutDescriptor, BindGroupLayoutEntries, Buffer,
+        BufferDescriptor, BufferUsages, CachedComputePipelineId, ComputePipelineDescriptor,
+        PipelineCache, RawBufferVec, ShaderStages,
+    },
+    renderer::{RenderDevice, RenderQueue},
+    view::ExtractedView,
+};
+use bevy_shader::Shader;
+use bytemuck::{Pod, Zeroable};
+
+use crate::{
+    bevy::prelude::*,
+    octree::extract::render::{components::RenderVisibleOctreeNodes, resources::RenderOctreeIndex},
+    pointcloud_octree::{
+        asset::data::PointCloudNodeData,
+        component::PointCloudOctree3d,
+        render::{data::PointCloudOctree3dUniform, prepare::VisibleNodesBuffers},
+    },
+    render::{mesh::PointCloudMesh, shadow_pass::directional_light_views},
+};
+
+const OCTREE_CULLING_SHADER_HANDLE: Handle<Shader> =
+    uuid_handle!("8d2c6e41-7a0f-4b93-b5d8-1e6f3a9c07b2");
+
+/// Number of nodes culled by a workgroup, must match the shader.
+pub const CULLING_WORKGROUP_SIZE: u32 = 64;
+
+/// Load the culling shader, called when building the octree render plugin.
+pub fn load_octree_culling_shader(app: &mut App) {
+    load_internal_asset!(
+        app,
+        OCTREE_CULLING_SHADER_HANDLE,
+        "culling.wgsl",
+        Shader::from_wgsl
+    );
+}
+
+/// An octree culled by a view, and the range of its nodes in the visible nodes bounds.
+#[derive(Clone, Copy, Debug, Pod, Zeroable)]
+#[repr(C)]
+pub struct CullingOctree {
+    pub clip_from_local: Mat4,
+    pub first_node: u32,
+    pub node_count: u32,
+    /// vertex count of the point mesh
+    pub vertex_count: u32,
+    /// whether the view is a light view, which draws the nodes only kept for the shadows and
+    /// clamps the depth of the casters in front of its near plane
+    pub is_light_view: u32,
+}
+
+/// Stores the multi draw indirect buffer of each view, cameras and the cascades of their
+/// directional lights.
+///
+/// The draws are written by the culling pass, one per visible node in the order of
+/// [`VisibleNodesBuffers::visible_nodes_bounds`], with no instances if the node is culled.
+#[derive(Component)]
+pub struct RenderVisibleNodesIndirectBuffers {
+    indirect: Option<Buffer>,
+    /// the visible nodes bounds of the camera whose nodes are drawn
+    visible_nodes_bounds: Option<Buffer>,
+    /// number of draws the indirect buffer can hold
+    capacity: usize,
+    octrees: RawBufferVec<CullingOctree>,
+    /// range of the draws of each octree entity
+    ranges: HashMap<Entity, Range<u32>>,
+    /// largest number of nodes of the culled octrees
+    max_node_count: u32,
+}
+
+impl Default for RenderVisibleNodesIndirectBuffers {
+    fn default() -> Self {
+        Self {
+            indirect: None,
+            visible_nodes_bounds: None,
+            capacity: 0,
+            octrees: RawBufferVec::new(BufferUsages::STORAGE),
+            ranges: HashMap::new(),
+            max_node_count: 0,
+        }
+    }
+}
+
+impl RenderVisibleNodesIndirectBuffers {
+    pub fn buffer(&self) -> Option<&Buffer> {
+        self.indirect.as_ref()
+    }
+
+    /// The range of the draws of an octree entity in the indirect buffer.
+    pub fn get(&self, entity: &Entity) -> Option<Range<u32>> {
+        self.ranges.get(entity).cloned()
+    }
+
+    /// Number of workgroups to dispatch to cull all the nodes.
+    pub fn workgroups(&self) -> (u32, u32) {
+        (
+            self.max_node_count.div_ceil(CULLING_WORKGROUP_SIZE),
+            self.octrees.len() as u32,
+        )
+    }
+}
+
+/// Prepare the culling inputs of each view drawing octrees and of the cascades of its
+/// directional lights: the transform of each octree and the range of its nodes in the visible
+/// nodes bounds written by [`prepare_visible_nodes_buffers`].
+///
+/// [`prepare_visible_nodes_buffers`]: crate::pointcloud_octree::render::prepare::prepare_visible_nodes_buffers
+#[allow(clippy::type_complexity)]
+#[allow(clippy::too_many_arguments)]
+pub fn prepare_indirect_buffer(
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+    views: Query<(
+        Entity,
+        &ExtractedView,
+        &RenderVisibleOctreeNodes<PointCloudNodeData, PointCloudOctree3d>,
+        &VisibleNodesBuffers,
+        Option<&ViewLightEntities>,
+    )>,
+    light_views: Query<(&LightEntity, &ExtractedView)>,
+    mut views_indirect_buffers: Query<&mut RenderVisibleNodesIndirectBuffers>,
+    render_octree_index: Res<RenderOctreeIndex<PointCloudOctree3d>>,
+    octree_uniforms: Query<&PointCloudOctree3dUniform>,
+    point_cloud_mesh: Res<PointCloudMesh>,
+) {
+    // for each view
+    for (entity, view, visible_octree_nodes, visible_nodes_buffers, view_light_entities) in &views {
+        // the cascades of the directional lights draw the visible nodes of the view
+        let cascades = view_light_entities
+            .into_iter()
+            .flat_map(|view_light_entities| {
+                directional_light_views(view_light_entities, &light_views)
+            })
+            .map(|(light_view_entity, light_view)| (light_view_entity, light_view, true));
+
+        for (view_entity, view, is_light_view) in
+            std::iter::once((entity, view, false)).chain(cascades)
+        {
+            let Ok(mut indirect_buffers) = views_indirect_buffers.get_mut(view_entity) else {
+                continue;
+            };
+
+            let RenderVisibleNodesIndirectBuffers {
+                indirect,
+                visible_nodes_bounds,
+                capacity,
+                octrees,
+                ranges,
+                max_node_count,
+            } = indirect_buffers.as_mut();
+
+            octrees.clear();
+            ranges.clear();
+            *max_node_count = 0;
+
+            if visible_octree_nodes.octrees.is_empty() {
+                continue;
+            }
+
+            let clip_from_world = view.clip_from_world.unwrap_or_else(|| {
+                view.clip_from_view * view.world_from_view.to_matrix().inverse()
+            });
+
+            let octree_offsets = visible_nodes_buffers.octree_offsets.values();
+            for entity in visible_octree_nodes.octrees.keys() {
+                let Ok(octree_uniform) = octree_uniforms.get(*entity) else {
+                    continue;
+                };
+                let Some(&[first_node, node_count]) = render_octree_index
+                    .get_octree_index(*entity)
+                    .and_then(|octree_index| octree_offsets.get(octree_index))
+                else {
+                    continue;
+                };
+
+                octrees.push(CullingOctree {
+                    clip_from_local: clip_from_world * octree_uniform.world_from_local,
+                    first_node,
+                    node_count,
+                    vertex_count: point_cloud_mesh.index_count,
+                    is_light_view: is_light_view as u32,
+                });
+                ranges.insert(*entity, first_node..first_node + node_count);
+                *max_node_count = (*max_node_count).max(node_count);
+            }
+
+            if octrees.is_empty() {
+                continue;
+            }
+
+            // one draw per visible node, grow geometrically
+            let draw_count = visible_nodes_buffers.visible_nodes_bounds.len();
+            if indirect.is_none() || *capacity < draw_count {
+                *capacity = draw_count.next_power_of_two();
+                *indirect = Some(render_device.create_buffer(&BufferDescriptor {
+                    label: Some("pcl_octree_indirect_buffer"),
+                    size: (*capacity * size_of::<IndirectParametersNonIndexed>()) as u64,
+                    usage: BufferUsages::STORAGE | BufferUsages::INDIRECT,
+                    mapped_at_creation: false,
+                }));
+            }
+            *visible_nodes_bounds = visible_nodes_buffers.visible_nodes_bounds.buffer().cloned();
+
+            octrees.write_buffer(&render_device, &render_queue);
+        }
+    }
+}
+
+/// The compute pipeline culling the visible nodes against the view frustum.
+#[derive(Resource)]
+pub struct OctreeCullingPipeline {
+    pub layout: BindGroupLayoutDescriptor,
+    pub pipeline_id: CachedComputePipelineId,
+}
+
+impl FromWorld for OctreeCullingPipeline {
+    fn from_world(world: &mut World) -> Self {
+        let layout = BindGroupLayoutDescriptor {
+            label: "pcl_octree_culling_bind_group_layout".into(),
+            entries: BindGroupLayoutEntries::sequential(
+                ShaderStages::COMPUTE,
+                (
+                    // octrees
+                    storage_buffer_read_only_sized(false, None),
+                    // visible nodes bounds
+                    storage_buffer_read_only_sized(false, None),
+                    // indirect draws
+                    storage_buffer_sized(false, None),
+                ),
+            )
+            .to_vec(),
+        };
+
+        let pipeline_id =
+            world
+                .resource::<PipelineCache>()
+                .queue_compute_pipeline(ComputePipelineDescriptor {
+                    label: Some("pcl_octree_culling_pipeline".into()),
+                    layout: vec![layout.clone()],
+                    push_constant_ranges: vec![],
+                    shader: OCTREE_CULLING_SHADER_HANDLE,
+                    shader_defs: vec![],
+                    entry_point: Some("cull".into()),
+                    zero_initialize_workgroup_memory: false,
+                });
+
+        Self {
+            layout,
+            pipeline_id,
+        }
+    }
+}
+
+#[derive(Component)]
+pub struct OctreeCullingBindGroup {
+    pub bind_group: BindGroup,
+}
+
+pub fn prepare_octree_culling_bind_group(
+    mut commands: Commands,
+    render_device: Res<RenderDevice>,
+    pipeline_cache: Res<PipelineCache>,
+    culling_pipeline: Res<OctreeCullingPipeline>,
+    views: Query<(Entity, &RenderVisibleNodesIndirectBuffers)>,
+) {
+    for (entity, indirect_buffers) in &views {
+        let (Some(octrees), Some(visible_nodes_bounds), Some(indirect)) = (
+            indirect_buffers.octrees.binding(),
+            indirect_buffers.visible_nodes_bounds.as_ref(),
+            indirect_buffers.buffer(),
+        ) else {
+            continue;
+        };
+
+        commands.entity(entity).insert(OctreeCullingBindGroup {
+            bind_group: render_device.create_bind_group(
+                "pcl_octree_culling_bind_group",
+                &pipeline_cache.get_bind_group_layout(&culling_pipeline.layout),
+                &BindGroupEntries::sequential((
+                    octrees,
+                    visible_nodes_bounds.as_entire_binding(),
+                    indirect.as_entire_binding(),
+                )),
+            ),
+        });
+    }
+}
@@ -0,0 +1,75 @@
+use bevy_ecs::{prelude::*, query::QueryItem};
+use bevy_pbr::ViewLightEntities;
+use bevy_render::{
+    render_graph::{NodeRunError, RenderGraphContext, RenderLabel, ViewNode},
+    render_resource::{ComputePassDescriptor, PipelineCache},
+    renderer::RenderContext,
+};
+
+use super::{OctreeCullingBindGroup, OctreeCullingPipeline, RenderVisibleNodesIndirectBuffers};
+
+#[derive(RenderLabel, Debug, Clone, Hash, PartialEq, Eq)]
+pub struct OctreeCullingLabel;
+
+/// Culls the visible nodes of the view and writes the indirect draws of the octree passes.
+#[derive(Default)]
+pub struct OctreeCullingNode;
+
+impl ViewNode for OctreeCullingNode {
+    type ViewQuery = (
+        &'static RenderVisibleNodesIndirectBuffers,
+        &'static OctreeCullingBindGroup,
+        Option<&'static ViewLightEntities>,
+    );
+
+    fn run(
+        &self,
+        _graph: &mut RenderGraphContext,
+        render_context: &mut RenderContext,
+        (indirect_buffers, culling_bind_group, view_light_entities): QueryItem<Self::ViewQuery>,
+        world: &World,
+    ) -> Result<(), NodeRunError> {
+        let pipeline_cache = world.resource::<PipelineCache>();
+        let culling_pipeline = world.resource::<OctreeCullingPipeline>();
+
+        let Some(pipeline) = pipeline_cache.get_compute_pipeline(culling_pipeline.pipeline_id)
+        else {
+            return Ok(());
+        };
+
+        // the light views are culled with the view, before the shadow passes
+        let light_views = view_light_entities
+            .into_iter()
+            .flat_map(|view_light_entities| &view_light_entities.lights)
+            .filter_map(|light_view_entity| {
+                let light_view = world.get_entity(*light_view_entity).ok()?;
+                Some((
+                    light_view.get::<RenderVisibleNodesIndirectBuffers>()?,
+                    light_view.get::<OctreeCullingBindGroup>()?,
+                ))
+            });
+
+        let mut compute_pass =
+            render_context
+                .command_encoder()
+                .begin_compute_pass(&ComputePassDescriptor {
+                    label: Some("pcl_octree_culling_pass"),
+                    timestamp_writes: None,
+                });
+        compute_pass.set_pipeline(pipeline);
+
+        for (indirect_buffers, culling_bind_group) in
+            std::iter::once((indirect_buffers, culling_bind_group)).chain(light_views)
+        {
+            let (workgroups_x, workgroups_y) = indirect_buffers.workgroups();
+            if workgroups_x == 0 || workgroups_y == 0 {
+                continue;
+            }
+
+            compute_pass.set_bind_group(0, &culling_bind_group.bind_group, &[]);
+            compute_pass.dispatch_workgroups(workgroups_x, workgroups_y, 1);
+        }
+
+        Ok(())
+    }
+}
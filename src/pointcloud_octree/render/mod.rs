@@ -6,30 +6,66 @@ pub mod prepare;
 pub mod render_node;
 
 pub mod attribute_pass;
+pub mod compute_rasterize;
 pub mod depth_pass;
+pub mod shadow_pass;
 
 #[cfg(not(feature = "webgl"))]
 pub mod indirect;
+#[cfg(not(feature = "webgl"))]
+pub mod occlusion;
 
 use bevy_app::prelude::*;
+#[cfg(not(feature = "webgl"))]
 use bevy_camera::Camera3d;
+#[cfg(not(feature = "webgl"))]
+use bevy_core_pipeline::core_3d::graph::Core3d;
 use bevy_ecs::prelude::*;
+#[cfg(feature = "webgl")]
+use bevy_log::warn;
+#[cfg(not(feature = "webgl"))]
+use bevy_pbr::{graph::NodePbr, LightEntity};
+#[cfg(not(feature = "webgl"))]
+use bevy_render::render_graph::{RenderGraphExt, ViewNodeRunner};
 use bevy_render::{Render, RenderApp, RenderSystems};
+#[cfg(not(feature = "webgl"))]
+use compute_rasterize::ComputeRasterizePlugin;
 use data::{prepare_point_cloud_octree_3d_uniform, PointCloudOctree3dUniformLayout};
 #[cfg(not(feature = "webgl"))]
-use indirect::{prepare_indirect_buffer, RenderVisibleNodesIndirectBuffers};
-use prepare::{
-    prepare_visible_nodes_texture, prepare_visible_nodes_texture_bind_group,
-    VisibleNodesTextureLayout,
+use depth_pass::node::DepthPassOctreeLabel;
+#[cfg(not(feature = "webgl"))]
+use indirect::{
+    load_octree_culling_shader,
+    node::{OctreeCullingLabel, OctreeCullingNode},
+    prepare_indirect_buffer, prepare_octree_culling_bind_group, OctreeCullingPipeline,
+    RenderVisibleNodesIndirectBuffers,
 };
+#[cfg(not(feature = "webgl"))]
+use occlusion::OcclusionCullingPlugin;
+#[cfg(feature = "webgl")]
+use prepare::prepare_visible_nodes_texture;
+use prepare::{prepare_visible_nodes_bind_group, VisibleNodesLayout};
+#[cfg(not(feature = "webgl"))]
+use prepare::{prepare_visible_nodes_buffers, VisibleNodesBuffers};
 
 use super::asset::extract::PointCloudOctreeNodeUniformLayout;
+#[cfg(feature = "webgl")]
+use crate::{
+    octree::extract::resources::OctreeBufferSettings,
+    pointcloud_octree::asset::{data::PointFormat, extract::PointCloudOctreeExtraction},
+};
 
 #[derive(Default)]
 pub struct RenderPointCloudOctreePlugin;
 
 impl Plugin for RenderPointCloudOctreePlugin {
     fn build(&self, app: &mut App) {
+        #[cfg(feature = "webgl")]
+        fall_back_to_full_point_format(app);
+
+        #[cfg(not(feature = "webgl"))]
+        load_octree_culling_shader(app);
+
         let Some(render_app) = app.get_sub_app_mut(RenderApp) else {
             return;
         };
@@ -38,15 +74,31 @@ impl Plugin for RenderPointCloudOctreePlugin {
         render_app
             .world_mut()
             .register_required_components::<Camera3d, RenderVisibleNodesIndirectBuffers>();
+        // the cascades of the directional lights are culled with the visible nodes of the camera
+        #[cfg(not(feature = "webgl"))]
+        render_app
+            .world_mut()
+            .register_required_components::<LightEntity, RenderVisibleNodesIndirectBuffers>();
+        #[cfg(not(feature = "webgl"))]
+        render_app
+            .world_mut()
+            .register_required_components::<Camera3d, VisibleNodesBuffers>();
 
         render_app.add_systems(
             Render,
             (
                 #[cfg(not(feature = "webgl"))]
-                prepare_indirect_buffer.in_set(RenderSystems::PrepareResources),
+                prepare_indirect_buffer
+                    .in_set(RenderSystems::PrepareResources)
+                    .after(prepare_visible_nodes_buffers),
+                #[cfg(not(feature = "webgl"))]
+                prepare_octree_culling_bind_group.in_set(RenderSystems::PrepareBindGroups),
+                #[cfg(feature = "webgl")]
                 prepare_visible_nodes_texture.in_set(RenderSystems::PrepareResources),
+                #[cfg(not(feature = "webgl"))]
+                prepare_visible_nodes_buffers.in_set(RenderSystems::PrepareResources),
                 // nodes_mapping::prepare_octree_nodes_mapping_buffers.in_set(RenderSystems::PrepareBindGroups),
-                prepare_visible_nodes_texture_bind_group.in_set(RenderSystems::PrepareBindGroups),
+                prepare_visible_nodes_bind_group.in_set(RenderSystems::PrepareBindGroups),
                 prepare_point_cloud_octree_3d_uniform.in_set(RenderSystems::PrepareResources),
             ),
         );
@@ -54,7 +106,23 @@ impl Plugin for RenderPointCloudOctreePlugin {
         app.add_plugins((
             depth_pass::DepthPassPlugin,
             attribute_pass::AttributePassPlugin,
+            shadow_pass::ShadowPassPlugin,
         ));
+
+        // cull the visible nodes on the GPU before drawing them indirectly
+        #[cfg(not(feature = "webgl"))]
+        if let Some(render_app) = app.get_sub_app_mut(RenderApp) {
+            render_app
+                .add_render_graph_node::<ViewNodeRunner<OctreeCullingNode>>(
+                    Core3d,
+                    OctreeCullingLabel,
+                )
+                .add_render_graph_edges(Core3d, (OctreeCullingLabel, DepthPassOctreeLabel))
+                .add_render_graph_edges(Core3d, (OctreeCullingLabel, NodePbr::EarlyShadowPass));
+        }
+
+        #[cfg(not(feature = "webgl"))]
+        app.add_plugins((ComputeRasterizePlugin, OcclusionCullingPlugin));
     }
 
     fn finish(&self, app: &mut App) {
@@ -63,7 +131,35 @@ impl Plugin for RenderPointCloudOctreePlugin {
         };
         render_app.init_resource::<PointCloudOctreeNodeUniformLayout>();
         render_app.init_resource::<PointCloudOctree3dUniformLayout>();
-        render_app.init_resource::<VisibleNodesTextureLayout>();
+        render_app.init_resource::<VisibleNodesLayout>();
+        #[cfg(not(feature = "webgl"))]
+        render_app.init_resource::<OctreeCullingPipeline>();
         // render_app.init_resource::<nodes_mapping::OctreeNodesMappingBindGroups>();
     }
 }
+
+/// Use [`PointFormat::Full`] for the octree instances, the quantized formats need the bounds of
+/// the drawn nodes which are not available with WebGL.
+#[cfg(feature = "webgl")]
+fn fall_back_to_full_point_format(app: &mut App) {
+    let Some(mut settings) = app
+        .world_mut()
+        .get_resource_mut::<OctreeBufferSettings<PointCloudOctreeExtraction>>()
+    else {
+        return;
+    };
+    if settings.instance_format == PointFormat::Full {
+        return;
+    }
+
+    warn!("Quantized point formats are not supported with WebGL, using the full format instead");
+    settings.instance_format = PointFormat::Full;
+
+    if let Some(render_app) = app.get_sub_app_mut(RenderApp)
+        && let Some(mut settings) = render_app
+            .world_mut()
+            .get_resource_mut::<OctreeBufferSettings<PointCloudOctreeExtraction>>()
+    {
+        settings.instance_format = PointFormat::Full;
+    }
+}
@@ -0,0 +1,48 @@
+// Reduces the depth pass texture of a view to the farthest depth of each tile of its viewport,
+// which is read back to build the occlusion depth pyramid on the CPU.
+
+struct OcclusionDownsample {
+    viewport_origin: vec2<u32>,
+    viewport_size: vec2<u32>,
+    size: vec2<u32>,
+    tile_size: u32,
+};
+
+@group(0) @binding(0)
+var<uniform> params: OcclusionDownsample;
+
+#ifdef MULTISAMPLED
+@group(0) @binding(1)
+var depth_texture: texture_multisampled_2d<f32>;
+#else // MULTISAMPLED
+@group(0) @binding(1)
+var depth_texture: texture_2d<f32>;
+#endif // MULTISAMPLED
+
+@group(0) @binding(2)
+var<storage, read_write> depths: array<f32>;
+
+@compute @workgroup_size(8, 8, 1)
+fn downsample(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    if any(global_id.xy >= params.size) {
+        return;
+    }
+
+    let start = params.viewport_origin + global_id.xy * params.tile_size;
+    let end = min(start + params.tile_size, params.viewport_origin + params.viewport_size);
+
+    // reverse Z, the farthest depth is the smallest one
+    var farthest = 1.0;
+    for (var y = start.y; y < end.y; y++) {
+        for (var x = start.x; x < end.x; x++) {
+            var depth = textureLoad(depth_texture, vec2<u32>(x, y), 0).r;
+            // the texture is cleared to 1.0 where no point has been rendered
+            if depth >= 1.0 {
+                depth = 0.0;
+            }
+            farthest = min(farthest, depth);
+        }
+    }
+
+    depths[global_id.y * params.size.x + global_id.x] = farthest;
+}
@@ -0,0 +1,492 @@
+//! Occlusion culling of the octree nodes, from the depth of the points rendered by a view.
+//!
+//! The depth pass texture is reduced to the farthest depth of each tile of the viewport, copied
+//! to a staging buffer and read back asynchronously. The main world then builds an
+//! [`OcclusionDepthPyramid`] used by the visibility check of the next frames.
+pub mod node;
+
+use std::sync::{
+    atomic::{AtomicU8, Ordering},
+    Arc,
+};
+
+use bevy_app::prelude::*;
+use bevy_asset::{load_internal_asset, uuid_handle, Handle};
+use bevy_camera::Camera3d;
+use bevy_core_pipeline::core_3d::graph::Core3d;
+use bevy_ecs::prelude::*;
+use bevy_log::prelude::*;
+use bevy_math::prelude::*;
+use bevy_render::{
+    camera::extract_cameras,
+    render_graph::{RenderGraphExt, ViewNodeRunner},
+    render_resource::{
+        binding_types::{
+            storage_buffer_sized, texture_2d, texture_2d_multisampled, uniform_buffer,
+        },
+        BindGroup, BindGroupEntries, BindGroupLayoutDescriptor, BindGroupLayoutEntries, Buffer,
+        BufferDescriptor, BufferUsages, CachedComputePipelineId, ComputePipelineDescriptor,
+        MapMode, PipelineCache, ShaderStages, ShaderType, SpecializedComputePipeline,
+        SpecializedComputePipelines, TextureSampleType, UniformBuffer,
+    },
+    renderer::{render_system, RenderDevice, RenderQueue},
+    sync_world::{MainEntity, RenderEntity},
+    view::{ExtractedView, Msaa},
+    Extract, ExtractSchedule, Render, RenderApp, RenderSystems,
+};
+use bevy_shader::Shader;
+use crossbeam::channel::{Receiver, Sender};
+use node::{OcclusionDownsampleLabel, OcclusionDownsampleNode};
+
+use crate::{
+    octree::visibility::{
+        occlusion::{OcclusionDepthPyramid, OctreeOcclusionCulling},
+        OctreeVisibilitySystems,
+    },
+    pointcloud_octree::render::compute_rasterize::node::ComputeRasterizeLabel,
+    render::{
+        depth_pass::texture::ViewDepthPrepassTextures, normalize_pass::node::NormalizePassLabel,
+    },
+};
+
+const OCCLUSION_DOWNSAMPLE_SHADER_HANDLE: Handle<Shader> =
+    uuid_handle!("2f7a9c3e-84d1-4b6a-9e05-c3b8d17f4a26");
+
+/// Size in pixels of the square tiles reduced to a single depth.
+pub const OCCLUSION_TILE_SIZE: u32 = 8;
+/// Number of depth readbacks a view can have in flight.
+const MAX_OCCLUSION_READBACKS: usize = 3;
+
+const READBACK_FREE: u8 = 0;
+/// waiting for the node to copy the depths
+const READBACK_PREPARED: u8 = 1;
+/// copied, waiting to be mapped after the submission
+const READBACK_COPIED: u8 = 2;
+const READBACK_MAPPING: u8 = 3;
+
+/// Reads back the depth of the cameras with [`OctreeOcclusionCulling`] and inserts their
+/// [`OcclusionDepthPyramid`].
+///
+/// Not available with WebGL.
+pub struct OcclusionCullingPlugin;
+
+impl Plugin for OcclusionCullingPlugin {
+    fn build(&self, app: &mut App) {
+        load_internal_asset!(
+            app,
+            OCCLUSION_DOWNSAMPLE_SHADER_HANDLE,
+            "downsample.wgsl",
+            Shader::from_wgsl
+        );
+
+        let (sender, receiver) = crossbeam::channel::unbounded();
+
+        app.insert_resource(OcclusionDepthReceiver(receiver))
+            .add_systems(
+                PostUpdate,
+                receive_occlusion_depth_pyramids
+                    .before(OctreeVisibilitySystems::CheckOctreeNodesVisibility),
+            );
+
+        let Some(render_app) = app.get_sub_app_mut(RenderApp) else {
+            return;
+        };
+
+        render_app
+            .world_mut()
+            .register_required_components::<Camera3d, OcclusionDepthBuffers>();
+
+        render_app
+            .insert_resource(OcclusionDepthSender(sender))
+            .init_resource::<SpecializedComputePipelines<OcclusionDownsamplePipeline>>()
+            .add_systems(
+                ExtractSchedule,
+                extract_occlusion_culling_views.after(extract_cameras),
+            )
+            .add_systems(
+                Render,
+                (
+                    prepare_occlusion_downsample_pipelines.in_set(RenderSystems::Prepare),
+                    prepare_occlusion_depth_buffers.in_set(RenderSystems::PrepareResources),
+                    prepare_occlusion_downsample_bind_groups
+                        .in_set(RenderSystems::PrepareBindGroups),
+                    map_occlusion_readbacks
+                        .after(render_system)
+                        .in_set(RenderSystems::Render),
+                ),
+            )
+            .add_render_graph_node::<ViewNodeRunner<OcclusionDownsampleNode>>(
+                Core3d,
+                OcclusionDownsampleLabel,
+            )
+            .add_render_graph_edges(
+                Core3d,
+                (
+                    ComputeRasterizeLabel,
+                    OcclusionDownsampleLabel,
+                    NormalizePassLabel,
+                ),
+            );
+    }
+
+    fn finish(&self, app: &mut App) {
+        let Some(render_app) = app.get_sub_app_mut(RenderApp) else {
+            return;
+        };
+
+        render_app.init_resource::<OcclusionDownsamplePipeline>();
+    }
+}
+
+/// Receives the depth pyramids read back by the render world, in the main world.
+#[derive(Resource)]
+pub struct OcclusionDepthReceiver(Receiver<(Entity, OcclusionDepthPyramid)>);
+
+/// Sends the depth pyramids read back to the main world.
+#[derive(Resource)]
+pub struct OcclusionDepthSender(Sender<(Entity, OcclusionDepthPyramid)>);
+
+/// Insert the received depth pyramids on their camera, and remove them from the cameras which
+/// disabled occlusion culling.
+fn receive_occlusion_depth_pyramids(
+    mut commands: Commands,
+    receiver: Res<OcclusionDepthReceiver>,
+    occlusion_culled: Query<(), With<OctreeOcclusionCulling>>,
+    disabled: Query<Entity, (With<OcclusionDepthPyramid>, Without<OctreeOcclusionCulling>)>,
+) {
+    for (entity, pyramid) in receiver.0.try_iter() {
+        if occlusion_culled.contains(entity) {
+            commands.entity(entity).try_insert(pyramid);
+        }
+    }
+
+    for entity in &disabled {
+        commands.entity(entity).remove::<OcclusionDepthPyramid>();
+    }
+}
+
+#[allow(clippy::type_complexity)]
+pub fn extract_occlusion_culling_views(
+    mut commands: Commands,
+    cameras: Extract<Query<(&RenderEntity, Has<OctreeOcclusionCulling>), With<Camera3d>>>,
+) {
+    for (render_entity, occlusion_culling) in &cameras {
+        let mut entity = commands.entity(render_entity.id());
+        if occlusion_culling {
+            entity.insert(OctreeOcclusionCulling);
+        } else {
+            entity.remove::<OctreeOcclusionCulling>();
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, ShaderType)]
+pub struct OcclusionDownsampleUniform {
+    pub viewport_origin: UVec2,
+    pub viewport_size: UVec2,
+    /// number of tiles of the viewport
+    pub size: UVec2,
+    pub tile_size: u32,
+}
+
+/// A staging buffer the depths of a frame are copied to, and mapped once the frame is submitted.
+struct OcclusionReadback {
+    buffer: Buffer,
+    size: UVec2,
+    extent: Vec2,
+    clip_from_world: Mat4,
+    state: Arc<AtomicU8>,
+}
+
+/// Buffers used to read back the depth of a view.
+#[derive(Component, Default)]
+pub struct OcclusionDepthBuffers {
+    /// farthest depth of each tile
+    depths: Option<Buffer>,
+    /// number of tiles of the viewport
+    size: UVec2,
+    uniform: UniformBuffer<OcclusionDownsampleUniform>,
+    readbacks: Vec<OcclusionReadback>,
+    /// index of the readback written this frame
+    current: Option<usize>,
+}
+
+impl OcclusionDepthBuffers {
+    /// Number of workgroups to dispatch to reduce all the tiles.
+    pub fn workgroups(&self) -> UVec2 {
+        UVec2::new(self.size.x.div_ceil(8), self.size.y.div_ceil(8))
+    }
+
+    /// The depths buffer and the staging buffer it must be copied to this frame.
+    fn current_copy(&self) -> Option<(&Buffer, &OcclusionReadback)> {
+        Some((self.depths.as_ref()?, self.readbacks.get(self.current?)?))
+    }
+}
+
+/// The compute pipeline reducing the depth pass texture, specialized by sample count.
+#[derive(Resource)]
+pub struct OcclusionDownsamplePipeline {
+    pub layout: BindGroupLayoutDescriptor,
+    pub layout_msaa: BindGroupLayoutDescriptor,
+}
+
+impl FromWorld for OcclusionDownsamplePipeline {
+    fn from_world(_world: &mut World) -> Self {
+        let layout = BindGroupLayoutDescriptor {
+            label: "pcl_occlusion_downsample_bind_group_layout".into(),
+            entries: BindGroupLayoutEntries::sequential(
+                ShaderStages::COMPUTE,
+                (
+                    uniform_buffer::<OcclusionDownsampleUniform>(false),
+                    // the depth pass texture
+                    texture_2d(TextureSampleType::Float { filterable: false }),
+                    // farthest depth of each tile
+                    storage_buffer_sized(false, None),
+                ),
+            )
+            .to_vec(),
+        };
+        let layout_msaa = BindGroupLayoutDescriptor {
+            label: "pcl_occlusion_downsample_bind_group_layout_msaa".into(),
+            entries: BindGroupLayoutEntries::sequential(
+                ShaderStages::COMPUTE,
+                (
+                    uniform_buffer::<OcclusionDownsampleUniform>(false),
+                    // the depth pass texture
+                    texture_2d_multisampled(TextureSampleType::Float { filterable: false }),
+                    // farthest depth of each tile
+                    storage_buffer_sized(false, None),
+                ),
+            )
+            .to_vec(),
+        };
+
+        Self {
+            layout,
+            layout_msaa,
+        }
+    }
+}
+
+impl OcclusionDownsamplePipeline {
+    fn layout(&self, samples: u32) -> &BindGroupLayoutDescriptor {
+        match samples {
+            1 => &self.layout,
+            _ => &self.layout_msaa,
+        }
+    }
+}
+
+impl SpecializedComputePipeline for OcclusionDownsamplePipeline {
+    /// number of samples of the depth pass texture
+    type Key = u32;
+
+    fn specialize(&self, samples: Self::Key) -> ComputePipelineDescriptor {
+        let mut shader_defs = Vec::new();
+        if samples > 1 {
+            shader_defs.push("MULTISAMPLED".into());
+        }
+
+        ComputePipelineDescriptor {
+            label: Some("pcl_occlusion_downsample_pipeline".into()),
+            layout: vec![self.layout(samples).clone()],
+            push_constant_ranges: vec![],
+            shader: OCCLUSION_DOWNSAMPLE_SHADER_HANDLE,
+            shader_defs,
+            entry_point: Some("downsample".into()),
+            zero_initialize_workgroup_memory: false,
+        }
+    }
+}
+
+#[derive(Component)]
+pub struct OcclusionDownsamplePipelineId(pub CachedComputePipelineId);
+
+#[derive(Component)]
+pub struct OcclusionDownsampleBindGroup(pub BindGroup);
+
+fn prepare_occlusion_downsample_pipelines(
+    mut commands: Commands,
+    pipeline_cache: Res<PipelineCache>,
+    mut pipelines: ResMut<SpecializedComputePipelines<OcclusionDownsamplePipeline>>,
+    pipeline: Res<OcclusionDownsamplePipeline>,
+    views: Query<(Entity, &Msaa), With<OctreeOcclusionCulling>>,
+) {
+    for (entity, msaa) in &views {
+        let pipeline_id = pipelines.specialize(&pipeline_cache, &pipeline, msaa.samples());
+        commands
+            .entity(entity)
+            .insert(OcclusionDownsamplePipelineId(pipeline_id));
+    }
+}
+
+fn prepare_occlusion_depth_buffers(
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+    mut views: Query<(&ExtractedView, &mut OcclusionDepthBuffers), With<OctreeOcclusionCulling>>,
+) {
+    for (view, mut buffers) in &mut views {
+        let OcclusionDepthBuffers {
+            depths,
+            size,
+            uniform,
+            readbacks,
+            current,
+        } = buffers.as_mut();
+
+        *current = None;
+
+        let viewport_origin = view.viewport.xy();
+        let viewport_size = view.viewport.zw();
+        let tiles = UVec2::new(
+            viewport_size.x.div_ceil(OCCLUSION_TILE_SIZE),
+            viewport_size.y.div_ceil(OCCLUSION_TILE_SIZE),
+        );
+        if tiles.min_element() == 0 {
+            continue;
+        }
+
+        let byte_size = tiles.x as u64 * tiles.y as u64 * size_of::<f32>() as u64;
+        if depths.is_none() || *size != tiles {
+            *depths = Some(render_device.create_buffer(&BufferDescriptor {
+                label: Some("pcl_occlusion_depths_buffer"),
+                size: byte_size,
+                usage: BufferUsages::STORAGE | BufferUsages::COPY_SRC,
+                mapped_at_creation: false,
+            }));
+            *size = tiles;
+
+            // drop the idle readbacks of the previous size
+            readbacks.retain(|readback| {
+                readback.size == tiles || readback.state.load(Ordering::Acquire) != READBACK_FREE
+            });
+        }
+
+        let free = readbacks.iter().position(|readback| {
+            readback.size == tiles && readback.state.load(Ordering::Acquire) == READBACK_FREE
+        });
+        let index = match free {
+            Some(index) => index,
+            None if readbacks.len() < MAX_OCCLUSION_READBACKS => {
+                readbacks.push(OcclusionReadback {
+                    buffer: render_device.create_buffer(&BufferDescriptor {
+                        label: Some("pcl_occlusion_readback_buffer"),
+                        size: byte_size,
+                        usage: BufferUsages::MAP_READ | BufferUsages::COPY_DST,
+                        mapped_at_creation: false,
+                    }),
+                    size: tiles,
+                    extent: Vec2::ZERO,
+                    clip_from_world: Mat4::IDENTITY,
+                    state: Arc::new(AtomicU8::new(READBACK_FREE)),
+                });
+                readbacks.len() - 1
+            }
+            // all the readbacks are in flight, skip this frame
+            None => continue,
+        };
+
+        let readback = &mut readbacks[index];
+        readback.extent = viewport_size.as_vec2() / OCCLUSION_TILE_SIZE as f32;
+        readback.clip_from_world = view
+            .clip_from_world
+            .unwrap_or_else(|| view.clip_from_view * view.world_from_view.to_matrix().inverse());
+        readback.state.store(READBACK_PREPARED, Ordering::Release);
+        *current = Some(index);
+
+        uniform.set(OcclusionDownsampleUniform {
+            viewport_origin,
+            viewport_size,
+            size: tiles,
+            tile_size: OCCLUSION_TILE_SIZE,
+        });
+        uniform.write_buffer(&render_device, &render_queue);
+    }
+}
+
+fn prepare_occlusion_downsample_bind_groups(
+    mut commands: Commands,
+    render_device: Res<RenderDevice>,
+    pipeline_cache: Res<PipelineCache>,
+    pipeline: Res<OcclusionDownsamplePipeline>,
+    views: Query<
+        (
+            Entity,
+            &Msaa,
+            &ViewDepthPrepassTextures,
+            &OcclusionDepthBuffers,
+        ),
+        With<OctreeOcclusionCulling>,
+    >,
+) {
+    for (entity, msaa, depth_textures, buffers) in &views {
+        let (Some(uniform), Some(depth_view), Some(depths)) = (
+            buffers.uniform.binding(),
+            depth_textures.depth_view(),
+            buffers.depths.as_ref(),
+        ) else {
+            continue;
+        };
+
+        commands.entity(entity).insert(OcclusionDownsampleBindGroup(
+            render_device.create_bind_group(
+                "pcl_occlusion_downsample_bind_group",
+                &pipeline_cache.get_bind_group_layout(pipeline.layout(msaa.samples())),
+                &BindGroupEntries::sequential((uniform, depth_view, depths.as_entire_binding())),
+            ),
+        ));
+    }
+}
+
+/// Map the readbacks copied this frame once the commands are submitted, and send their depths
+/// to the main world.
+fn map_occlusion_readbacks(
+    sender: Res<OcclusionDepthSender>,
+    mut views: Query<(&MainEntity, &mut OcclusionDepthBuffers)>,
+) {
+    for (main_entity, mut buffers) in &mut views {
+        let Some(readback) = buffers
+            .current
+            .take()
+            .and_then(|index| buffers.readbacks.get(index))
+        else {
+            continue;
+        };
+
+        // the node didn't run this frame
+        if readback.state.load(Ordering::Acquire) != READBACK_COPIED {
+            readback.state.store(READBACK_FREE, Ordering::Release);
+            continue;
+        }
+        readback.state.store(READBACK_MAPPING, Ordering::Release);
+
+        let entity = main_entity.id();
+        let buffer = readback.buffer.clone();
+        let state = readback.state.clone();
+        let (clip_from_world, extent, size) =
+            (readback.clip_from_world, readback.extent, readback.size);
+        let sender = sender.0.clone();
+
+        readback
+            .buffer
+            .slice(..)
+            .map_async(MapMode::Read, move |result| {
+                if let Err(error) = result {
+                    warn!("Unable to read back the occlusion depth: {}", error);
+                    state.store(READBACK_FREE, Ordering::Release);
+                    return;
+                }
+
+                let depths = {
+                    let data = buffer.slice(..).get_mapped_range();
+                    bytemuck::pod_collect_to_vec::<u8, f32>(&data)
+                };
+                buffer.unmap();
+                state.store(READBACK_FREE, Ordering::Release);
+
+                let pyramid = OcclusionDepthPyramid::new(clip_from_world, extent, size, depths);
+                // the main world may have been dropped on exit
+                let _ = sender.send((entity, pyramid));
+            });
+    }
+}
@@ -0,0 +1,70 @@
+use std::sync::atomic::Ordering;
+
+use bevy_ecs::{prelude::*, query::QueryItem};
+use bevy_render::{
+    render_graph::{NodeRunError, RenderGraphContext, RenderLabel, ViewNode},
+    render_resource::{ComputePassDescriptor, PipelineCache},
+    renderer::RenderContext,
+};
+
+use super::{
+    OcclusionDepthBuffers, OcclusionDownsampleBindGroup, OcclusionDownsamplePipelineId,
+    READBACK_COPIED,
+};
+use crate::octree::visibility::occlusion::OctreeOcclusionCulling;
+
+#[derive(RenderLabel, Debug, Clone, Hash, PartialEq, Eq)]
+pub struct OcclusionDownsampleLabel;
+
+/// Reduces the depth pass texture of the view and copies it to a readback buffer.
+#[derive(Default)]
+pub struct OcclusionDownsampleNode;
+
+impl ViewNode for OcclusionDownsampleNode {
+    type ViewQuery = (
+        Has<OctreeOcclusionCulling>,
+        &'static OcclusionDepthBuffers,
+        &'static OcclusionDownsampleBindGroup,
+        &'static OcclusionDownsamplePipelineId,
+    );
+
+    fn run(
+        &self,
+        _graph: &mut RenderGraphContext,
+        render_context: &mut RenderContext,
+        (occlusion_culling, buffers, bind_group, pipeline_id): QueryItem<Self::ViewQuery>,
+        world: &World,
+    ) -> Result<(), NodeRunError> {
+        // the components are kept when the view disables occlusion culling
+        if !occlusion_culling {
+            return Ok(());
+        }
+
+        let Some((depths, readback)) = buffers.current_copy() else {
+            return Ok(());
+        };
+
+        let pipeline_cache = world.resource::<PipelineCache>();
+        let Some(pipeline) = pipeline_cache.get_compute_pipeline(pipeline_id.0) else {
+            return Ok(());
+        };
+
+        let command_encoder = render_context.command_encoder();
+        {
+            let mut compute_pass = command_encoder.begin_compute_pass(&ComputePassDescriptor {
+                label: Some("pcl_occlusion_downsample_pass"),
+                timestamp_writes: None,
+            });
+            compute_pass.set_pipeline(pipeline);
+            compute_pass.set_bind_group(0, &bind_group.0, &[]);
+
+            let workgroups = buffers.workgroups();
+            compute_pass.dispatch_workgroups(workgroups.x, workgroups.y, 1);
+        }
+
+        command_encoder.copy_buffer_to_buffer(depths, 0, &readback.buffer, 0, depths.size());
+        readback.state.store(READBACK_COPIED, Ordering::Release);
+
+        Ok(())
+    }
+}
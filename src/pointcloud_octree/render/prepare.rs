@@ -1,31 +1,50 @@
 use std::cmp::Ordering;
+#[cfg(not(feature = "webgl"))]
+use std::sync::Arc;
 
+use bevy_camera::primitives::Aabb;
+#[cfg(feature = "webgl")]
 use bevy_color::LinearRgba;
 use bevy_ecs::{prelude::*, query::ROQueryItem, system::SystemParamItem};
 use bevy_log::prelude::*;
 use bevy_platform::collections::HashMap;
+#[cfg(not(feature = "webgl"))]
+use bevy_render::render_resource::{
+    binding_types::storage_buffer_read_only_sized, Buffer, BufferUsages, RawBufferVec,
+};
+#[cfg(feature = "webgl")]
 use bevy_render::{
     prelude::*,
+    render_resource::{
+        binding_types::texture_2d, Extent3d, TexelCopyBufferLayout, TextureDescriptor,
+        TextureDimension, TextureFormat::Rgba8Uint, TextureSampleType, TextureUsages,
+    },
+    texture::{ColorAttachment, TextureCache},
+};
+use bevy_render::{
     render_phase::{PhaseItem, RenderCommand, RenderCommandResult, TrackedRenderPass},
     render_resource::{
-        binding_types::texture_2d, BindGroup, BindGroupEntries, BindGroupLayout, Extent3d,
-        ShaderStages, TexelCopyBufferLayout, TextureDescriptor, TextureDimension,
-        TextureFormat::Rgba8Uint, TextureSampleType, TextureUsages,
+        BindGroup, BindGroupEntries, BindGroupLayout, BindGroupLayoutEntries, BindGroupLayoutEntry,
+        ShaderStages,
     },
     renderer::{RenderDevice, RenderQueue},
-    texture::{ColorAttachment, TextureCache},
     view::ExtractedView,
 };
 use bytemuck::{Pod, Zeroable};
 
+#[cfg(not(feature = "webgl"))]
+use crate::{
+    octree::extract::resources::OctreeBufferSettings, pointcloud_octree::asset::data::PointFormat,
+};
 use crate::{
     octree::{
         extract::render::{
+            asset::{RenderOctree, RenderOctreeNodeAllocation},
             components::RenderVisibleOctreeNodes,
             resources::{AllocatedOctreeNodes, RenderOctreeIndex, RenderOctrees},
         },
         storage::NodeId,
-        visibility::iter_one_bits,
+        visibility::{components::VisibleOctreeNode, iter_one_bits},
     },
     pointcloud_octree::{
         asset::{data::PointCloudNodeData, extract::PointCloudOctreeExtraction},
@@ -38,7 +57,15 @@ use crate::{
     },
 };
 
+/// Maximum number of visible nodes per octree, limited by the width of the visible nodes texture.
+#[cfg(feature = "webgl")]
+pub const MAX_NODES: usize = 2048;
+/// Maximum number of visible nodes per octree.
+#[cfg(not(feature = "webgl"))]
+pub const MAX_NODES: usize = 1 << 20;
+
 /// Stores visible nodes and mapping textures for each view
+#[cfg(feature = "webgl")]
 #[derive(Component)]
 pub struct VisibleNodesTexture {
     pub visible_nodes: Option<ColorAttachment>,
@@ -46,7 +73,39 @@ pub struct VisibleNodesTexture {
     pub node_index: Vec<HashMap<NodeId, u32>>,
 }
 
+/// Stores visible nodes buffers for each view
+#[cfg(not(feature = "webgl"))]
+#[derive(Component)]
+pub struct VisibleNodesBuffers {
+    /// visible nodes of all the octrees
+    pub visible_nodes: RawBufferVec<VisibleOctreeNodeStorage>,
+    /// bounds and instances of the visible nodes, in the order of `visible_nodes`
+    pub visible_nodes_bounds: RawBufferVec<VisibleOctreeNodeBounds>,
+    /// the bounds uploaded during the previous frame, they are only uploaded when they change
+    previous_visible_nodes_bounds: Vec<VisibleOctreeNodeBounds>,
+    /// index of the first node and number of nodes of each octree in `visible_nodes`,
+    /// per octree index
+    pub octree_offsets: RawBufferVec<[u32; 2]>,
+    /// contains the index of each node in `visible_nodes` per octree entity
+    pub node_index: Arc<HashMap<Entity, HashMap<NodeId, u32>>>,
+}
+
+#[cfg(not(feature = "webgl"))]
+impl Default for VisibleNodesBuffers {
+    fn default() -> Self {
+        Self {
+            visible_nodes: RawBufferVec::new(BufferUsages::STORAGE),
+            // also bound per draw to decode the quantized positions
+            visible_nodes_bounds: RawBufferVec::new(BufferUsages::STORAGE | BufferUsages::VERTEX),
+            previous_visible_nodes_bounds: Vec::new(),
+            octree_offsets: RawBufferVec::new(BufferUsages::STORAGE),
+            node_index: Arc::default(),
+        }
+    }
+}
+
 /// The data layout for the texture containing visible nodes data
+#[cfg(feature = "webgl")]
 #[derive(Default, Clone, Copy, Pod, Zeroable)]
 #[repr(C)]
 pub struct VisibleOctreeNodeUniform {
@@ -57,6 +116,7 @@ pub struct VisibleOctreeNodeUniform {
     pub first_child_index: u16,
 }
 
+#[cfg(feature = "webgl")]
 impl ::core::fmt::Debug for VisibleOctreeNodeUniform {
     #[inline]
     fn fmt(&self, f: &mut ::core::fmt::Formatter) -> ::core::fmt::Result {
@@ -69,13 +129,120 @@ impl ::core::fmt::Debug for VisibleOctreeNodeUniform {
     }
 }
 
+/// The data layout for the storage buffer containing visible nodes data
+#[derive(Default, Clone, Copy, Debug, Pod, Zeroable)]
+#[repr(C)]
+pub struct VisibleOctreeNodeStorage {
+    pub children_mask: u32,
+    pub offset: u32,
+    // index of the first child, relative to the first node of the octree
+    pub first_child_index: u32,
+}
+
+/// The data layout of the bounds of a visible node, used to decode quantized positions
+#[cfg(not(feature = "webgl"))]
+#[derive(Default, Clone, Copy, Debug, Pod, Zeroable)]
+#[repr(C)]
+pub struct VisibleOctreeNodeBounds {
+    pub center: [f32; 3],
+    pub first_instance: u32,
+    pub half_extents: [f32; 3],
+    pub instance_count: u32,
+    /// whether the node is only drawn by the light views
+    pub shadow_only: u32,
+    pub _padding: [u32; 3],
+}
+
 #[derive(Clone, Debug)]
 pub struct PreparedVisibleOctreeNode {
     pub id: NodeId,
+    pub bounding_box: Aabb,
+    pub allocation: RenderOctreeNodeAllocation,
     pub children_mask: u8,
-    pub first_child_index: u16,
+    /// LOD offset of the node, encoded on 8 bits
+    pub offset: u8,
+    pub first_child_index: u32,
+    /// the node is only kept for the shadows
+    pub shadow_only: bool,
+}
+
+/// Sort the visible nodes of an octree in order of depth, so that the children of a node are
+/// contiguous, and link each node to its first child.
+///
+/// Nodes which are missing from the render world are skipped, and at most [`MAX_NODES`] are kept.
+fn prepare_visible_octree_nodes(
+    octree_nodes: &[VisibleOctreeNode],
+    render_octree: &RenderOctree<RenderPointCloudNodeData>,
+    octree_allocations: &HashMap<NodeId, RenderOctreeNodeAllocation>,
+) -> Vec<PreparedVisibleOctreeNode> {
+    let mut sorted_octree_nodes = octree_nodes.to_vec();
+
+    // remove the missing nodes or unallocated nodes
+    sorted_octree_nodes.retain(|node| {
+        render_octree.nodes.contains_key(&node.id) && octree_allocations.contains_key(&node.id)
+    });
+
+    // sort nodes in order of depth, then child index ordering
+    sorted_octree_nodes.sort_by(|a, b| {
+        if a.depth < b.depth {
+            return Ordering::Less;
+        }
+        if a.depth > b.depth {
+            return Ordering::Greater;
+        }
+
+        a.name.cmp(&b.name)
+    });
+
+    sorted_octree_nodes.truncate(MAX_NODES);
+
+    // create an index of child indexes for each parent
+    let index = sorted_octree_nodes
+        .iter()
+        .enumerate()
+        .map(|(index, node)| ((node.parent_id, node.child_index), index))
+        .collect::<HashMap<_, _>>();
+
+    sorted_octree_nodes
+        .iter()
+        .filter_map(|node| {
+            let render_node = render_octree.nodes.get(&node.id)?;
+            let allocation = octree_allocations.get(&node.id)?;
+
+            // recompute children mask
+            let mut children_mask = node.children_mask;
+            for child_index in iter_one_bits(node.children_mask) {
+                // if the child is missing in the render octree, in the allocations,
+                // or has been truncated, patch the children mask
+                if !index.contains_key(&(Some(node.id), child_index)) {
+                    children_mask &= !(1u8 << child_index);
+                }
+            }
+
+            let mut first_child_index = 0;
+            // get the first child index
+            if let Some(child_index) = iter_one_bits(children_mask).next()
+                && let Some(index) = index.get(&(Some(node.id), child_index))
+            {
+                first_child_index = *index as u32;
+            }
+
+            let offset = ((render_node.data.offset + 10.0) * 10.0).clamp(0.0, 255.0) as u8;
+
+            Some(PreparedVisibleOctreeNode {
+                id: node.id,
+                bounding_box: render_node.bounding_box,
+                allocation: allocation.clone(),
+                children_mask,
+                offset,
+                first_child_index,
+                shadow_only: node.shadow_only,
+            })
+        })
+        .collect()
 }
 
+#[cfg(feature = "webgl")]
 #[allow(clippy::too_many_arguments)]
 pub fn prepare_visible_nodes_texture(
     mut commands: Commands,
@@ -172,90 +339,14 @@ pub fn prepare_visible_nodes_texture(
                 continue;
             };
 
-            let mut sorted_octree_nodes = octree_nodes.clone();
-
-            // remove the missing nodes or unallocated nodes
-            sorted_octree_nodes.retain(|node| {
-                render_octree.nodes.contains_key(&node.id)
-                    && octree_allocations.contains_key(&node.id)
-            });
-
-            // sort nodes in order of depth, then child index ordering
-            sorted_octree_nodes.sort_by(|a, b| {
-                if a.depth < b.depth {
-                    return Ordering::Less;
-                }
-                if a.depth > b.depth {
-                    return Ordering::Greater;
-                }
-
-                a.name.cmp(&b.name)
-            });
-
-            // recompute children mask
-            for node in &mut sorted_octree_nodes {
-                for child_index in iter_one_bits(node.children_mask) {
-                    let index = node.children[child_index as usize];
-                    let child_node_id = octree_nodes[index].id;
-
-                    // if the node is missing in the render octree, or in the allocations, patch the children mask
-                    if !render_octree.nodes.contains_key(&child_node_id)
-                        || !octree_allocations.contains_key(&node.id)
-                    {
-                        node.children_mask &= !(1u8 << child_index);
-                    }
-                }
-            }
-
-            // create an index of child indexes for each parent
-            let index = sorted_octree_nodes
-                .iter()
-                .enumerate()
-                .map(|(index, node)| ((node.parent_id, node.child_index), index))
-                .collect::<HashMap<_, _>>();
-
-            // prepare octree nodes
-            let prepared_octree_nodes = sorted_octree_nodes
-                .clone()
-                .into_iter()
-                .map(|node| {
-                    let mut first_child_index = 0;
-                    // get the first child index
-                    if let Some(child_index) = iter_one_bits(node.children_mask).next() {
-                        if let Some(index) = index.get(&(Some(node.id), child_index)) {
-                            first_child_index = *index as u16;
-                        } else {
-                            warn!("No first child index found in index");
-                        }
-                    }
-                    PreparedVisibleOctreeNode {
-                        id: node.id,
-                        children_mask: node.children_mask,
-                        first_child_index,
-                    }
-                })
-                .collect::<Vec<_>>();
+            let prepared_octree_nodes =
+                prepare_visible_octree_nodes(octree_nodes, render_octree, octree_allocations);
 
             for (i, visible_node) in prepared_octree_nodes.iter().enumerate() {
-                if i >= MAX_NODES {
-                    // warn!("Too many nodes in octree, some will be ignored.");
-                    break;
-                }
-
-                let Some(node) = render_octree.nodes.get(&visible_node.id) else {
-                    debug!(
-                        "Render Point Cloud Octree node {:?} not found in RenderOctrees, skip.",
-                        visible_node.id
-                    );
-                    continue;
-                };
-
-                let offset = ((node.data.offset + 10.0) * 10.0).min(255.0) as u8;
-
                 visible_nodes_buffer[base_offset + i] = VisibleOctreeNodeUniform {
                     children_mask: visible_node.children_mask,
-                    offset,
-                    first_child_index: visible_node.first_child_index,
+                    offset: visible_node.offset,
+                    first_child_index: visible_node.first_child_index as u16,
                 };
 
                 node_mapping.insert(visible_node.id, i as u32);
@@ -291,40 +382,237 @@ pub fn prepare_visible_nodes_texture(
     }
 }
 
-#[derive(Component)]
-pub struct VisibleNodesTextureBindGroup {
-    pub texture: BindGroup,
+#[cfg(not(feature = "webgl"))]
+#[allow(clippy::too_many_arguments)]
+#[allow(clippy::type_complexity)]
+pub fn prepare_visible_nodes_buffers(
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+    render_octree_index: Res<RenderOctreeIndex<PointCloudOctree3d>>,
+    point_cloud_octree_3d_node_depth_phases: Res<
+        ViewOctreeNodesRenderAttributePhases<PointCloudOctree3dNodePhase>,
+    >,
+    point_cloud_octree_3d_node_attribute_phases: Res<
+        ViewOctreeNodesRenderDepthPhases<PointCloudOctree3dNodePhase>,
+    >,
+    mut views_3d: Query<(
+        &ExtractedView,
+        &RenderVisibleOctreeNodes<PointCloudNodeData, PointCloudOctree3d>,
+        &mut VisibleNodesBuffers,
+    )>,
+    render_octrees: Res<RenderOctrees<RenderPointCloudNodeData>>,
+    allocated_octree_nodes: Res<AllocatedOctreeNodes<PointCloudOctreeExtraction>>,
+) {
+    // for each camera
+    for (extracted_view, visible_nodes, mut visible_nodes_buffers) in &mut views_3d {
+        // skip if no phases
+        if !point_cloud_octree_3d_node_depth_phases
+            .contains_key(&extracted_view.retained_view_entity)
+            || !point_cloud_octree_3d_node_attribute_phases
+                .contains_key(&extracted_view.retained_view_entity)
+        {
+            continue;
+        };
+
+        if visible_nodes.octrees.is_empty() {
+            continue;
+        }
+
+        let VisibleNodesBuffers {
+            visible_nodes: visible_nodes_buffer,
+            visible_nodes_bounds,
+            previous_visible_nodes_bounds,
+            octree_offsets,
+            node_index,
+        } = visible_nodes_buffers.as_mut();
+
+        visible_nodes_buffer.clear();
+        std::mem::swap(
+            visible_nodes_bounds.values_mut(),
+            previous_visible_nodes_bounds,
+        );
+        visible_nodes_bounds.clear();
+        octree_offsets.clear();
+
+        let mut octrees_node_index = HashMap::default();
+        let octrees_count = render_octree_index.octrees_slab.len();
+        for _ in 0..octrees_count {
+            octree_offsets.push([0, 0]);
+        }
+
+        for (entity, (asset_id, octree_nodes)) in &visible_nodes.octrees {
+            let octree_index = render_octree_index
+                .get_octree_index(*entity)
+                .expect("octree index out of bounds");
+
+            let Some(render_octree) = render_octrees.get(*asset_id) else {
+                debug!(
+                    "Render Point Cloud octree {} not found in RenderOctrees, skip",
+                    entity
+                );
+                continue;
+            };
+
+            let Some(octree_allocations) = allocated_octree_nodes.allocations.get(asset_id) else {
+                debug!("Missing asset allocation {}", asset_id,);
+                // no allocations means no visible nodes
+                continue;
+            };
+
+            let prepared_octree_nodes =
+                prepare_visible_octree_nodes(octree_nodes, render_octree, octree_allocations);
+
+            octree_offsets.values_mut()[octree_index] = [
+                visible_nodes_buffer.len() as u32,
+                prepared_octree_nodes.len() as u32,
+            ];
+
+            let node_mapping: &mut HashMap<_, _> = octrees_node_index.entry(*entity).or_default();
+            for visible_node in &prepared_octree_nodes {
+                node_mapping.insert(visible_node.id, visible_nodes_buffer.len() as u32);
+
+                visible_nodes_buffer.push(VisibleOctreeNodeStorage {
+                    children_mask: visible_node.children_mask as u32,
+                    offset: visible_node.offset as u32,
+                    first_child_index: visible_node.first_child_index,
+                });
+                visible_nodes_bounds.push(VisibleOctreeNodeBounds {
+                    center: visible_node.bounding_box.center.into(),
+                    first_instance: visible_node.allocation.start,
+                    half_extents: visible_node.bounding_box.half_extents.into(),
+                    instance_count: visible_node.allocation.count,
+                    shadow_only: visible_node.shadow_only as u32,
+                    _padding: [0; 3],
+                });
+            }
+        }
+
+        // empty storage buffers can't be bound
+        if visible_nodes_buffer.is_empty() {
+            visible_nodes_buffer.push(VisibleOctreeNodeStorage::default());
+            visible_nodes_bounds.push(VisibleOctreeNodeBounds::default());
+        }
+
+        *node_index = Arc::new(octrees_node_index);
+
+        visible_nodes_buffer.write_buffer(&render_device, &render_queue);
+        let bounds_changed = bytemuck::cast_slice::<_, u8>(visible_nodes_bounds.values())
+            != bytemuck::cast_slice::<_, u8>(previous_visible_nodes_bounds);
+        if bounds_changed || visible_nodes_bounds.buffer().is_none() {
+            visible_nodes_bounds.write_buffer(&render_device, &render_queue);
+        }
+        octree_offsets.write_buffer(&render_device, &render_queue);
+    }
+}
+
+#[derive(Component, Clone)]
+pub struct VisibleNodesBindGroup {
+    pub bind_group: BindGroup,
+    /// The bounds of the visible nodes when the points are quantized
+    #[cfg(not(feature = "webgl"))]
+    pub node_bounds: Option<VisibleNodesBounds>,
+}
+
+impl VisibleNodesBindGroup {
+    /// Bind the bounds of a visible node as vertex buffer, if they are needed to decode the
+    /// quantized positions of its points.
+    #[cfg(not(feature = "webgl"))]
+    pub fn set_node_bounds<'w>(
+        &'w self,
+        pass: &mut TrackedRenderPass<'w>,
+        entity: &Entity,
+        node_id: &NodeId,
+    ) {
+        if let Some(node_bounds) = &self.node_bounds
+            && let Some(index) = node_bounds
+                .node_index
+                .get(entity)
+                .and_then(|node_index| node_index.get(node_id))
+        {
+            node_bounds.set(pass, *index);
+        }
+    }
+
+    /// The points are never quantized with WebGL.
+    #[cfg(feature = "webgl")]
+    pub fn set_node_bounds<'w>(
+        &'w self,
+        _pass: &mut TrackedRenderPass<'w>,
+        _entity: &Entity,
+        _node_id: &NodeId,
+    ) {
+    }
+}
+
+/// The bounds of the visible nodes, bound per draw with a zero stride so that the instances can
+/// decode their quantized positions, see [`PointFormat::node_bounds_buffer_layout`].
+#[cfg(not(feature = "webgl"))]
+#[derive(Clone)]
+pub struct VisibleNodesBounds {
+    /// The buffer of [`VisibleNodesBuffers::visible_nodes_bounds`]
+    pub buffer: Buffer,
+    /// The index of the bounds of each visible node, per octree entity
+    pub node_index: Arc<HashMap<Entity, HashMap<NodeId, u32>>>,
+}
+
+#[cfg(not(feature = "webgl"))]
+impl VisibleNodesBounds {
+    /// The vertex buffer slot of the node bounds
+    pub const SLOT: u32 = 2;
+
+    /// Bind the bounds at an index of the visible nodes
+    pub fn set<'w>(&'w self, pass: &mut TrackedRenderPass<'w>, index: u32) {
+        let offset = index as u64 * size_of::<VisibleOctreeNodeBounds>() as u64;
+        pass.set_vertex_buffer(Self::SLOT as usize, self.buffer.slice(offset..));
+    }
 }
 
 #[derive(Resource)]
-pub struct VisibleNodesTextureLayout {
+pub struct VisibleNodesLayout {
     pub layout: BindGroupLayout,
     // pub uniform_layout: BindGroupLayout,
 }
 
-pub const MAX_NODES: usize = 2048;
+/// The layout of the visible nodes hierarchy: a texture on WebGL, storage buffers otherwise.
+pub fn visible_nodes_layout_entries() -> Vec<BindGroupLayoutEntry> {
+    #[cfg(feature = "webgl")]
+    let entries =
+        BindGroupLayoutEntries::single(ShaderStages::VERTEX, texture_2d(TextureSampleType::Uint));
+    #[cfg(not(feature = "webgl"))]
+    let entries = BindGroupLayoutEntries::sequential(
+        ShaderStages::VERTEX,
+        (
+            // visible nodes
+            storage_buffer_read_only_sized(false, None),
+            // octree offsets
+            storage_buffer_read_only_sized(false, None),
+        ),
+    );
+
+    entries.to_vec()
+}
 
-impl FromWorld for VisibleNodesTextureLayout {
+impl FromWorld for VisibleNodesLayout {
     fn from_world(world: &mut World) -> Self {
         let render_device = world.resource::<RenderDevice>();
 
-        VisibleNodesTextureLayout {
+        VisibleNodesLayout {
             layout: render_device.create_bind_group_layout(
-                "pcl_attribute_layout",
-                &[texture_2d(TextureSampleType::Uint).build(0, ShaderStages::VERTEX)],
+                "pcl_octree_visible_nodes_layout",
+                &visible_nodes_layout_entries(),
             ),
         }
     }
 }
 
-pub fn prepare_visible_nodes_texture_bind_group(
+#[cfg(feature = "webgl")]
+pub fn prepare_visible_nodes_bind_group(
     mut commands: Commands,
-    visible_nodes_texture_layout: Res<VisibleNodesTextureLayout>,
+    visible_nodes_layout: Res<VisibleNodesLayout>,
     render_device: Res<RenderDevice>,
-    // render_queue: Res<RenderQueue>,
-    views: Query<(Entity, &VisibleNodesTexture, &Msaa)>,
+    views: Query<(Entity, &VisibleNodesTexture)>,
 ) {
-    for (entity, prepass_textures, _msaa) in &views {
+    for (entity, prepass_textures) in &views {
         let Some(texture) = &prepass_textures.visible_nodes else {
             warn!("No visible nodes pass texture for {}", entity);
             continue;
@@ -332,32 +620,63 @@ pub fn prepare_visible_nodes_texture_bind_group(
 
         let texture_view = texture.texture.default_view.clone();
 
-        commands
-            .entity(entity)
-            .insert(VisibleNodesTextureBindGroup {
-                texture: render_device.create_bind_group(
-                    "pcl_octree_visible_nodes__bind_group",
-                    &visible_nodes_texture_layout.layout,
-                    &BindGroupEntries::single(&texture_view),
-                ),
-            });
+        commands.entity(entity).insert(VisibleNodesBindGroup {
+            bind_group: render_device.create_bind_group(
+                "pcl_octree_visible_nodes__bind_group",
+                &visible_nodes_layout.layout,
+                &BindGroupEntries::single(&texture_view),
+            ),
+        });
+    }
+}
+
+#[cfg(not(feature = "webgl"))]
+pub fn prepare_visible_nodes_bind_group(
+    mut commands: Commands,
+    visible_nodes_layout: Res<VisibleNodesLayout>,
+    render_device: Res<RenderDevice>,
+    octree_buffer_settings: Res<OctreeBufferSettings<PointCloudOctreeExtraction>>,
+    views: Query<(Entity, &VisibleNodesBuffers)>,
+) {
+    for (entity, visible_nodes_buffers) in &views {
+        let (Some(visible_nodes), Some(octree_offsets), Some(visible_nodes_bounds)) = (
+            visible_nodes_buffers.visible_nodes.binding(),
+            visible_nodes_buffers.octree_offsets.binding(),
+            visible_nodes_buffers.visible_nodes_bounds.buffer(),
+        ) else {
+            continue;
+        };
+
+        commands.entity(entity).insert(VisibleNodesBindGroup {
+            bind_group: render_device.create_bind_group(
+                "pcl_octree_visible_nodes__bind_group",
+                &visible_nodes_layout.layout,
+                &BindGroupEntries::sequential((visible_nodes, octree_offsets)),
+            ),
+            node_bounds: (*octree_buffer_settings.instance_format() != PointFormat::Full).then(
+                || VisibleNodesBounds {
+                    buffer: visible_nodes_bounds.clone(),
+                    node_index: visible_nodes_buffers.node_index.clone(),
+                },
+            ),
+        });
     }
 }
 
-pub struct SetVisibleNodesTexture<const I: usize>;
-impl<P: PhaseItem, const I: usize> RenderCommand<P> for SetVisibleNodesTexture<I> {
+pub struct SetVisibleNodes<const I: usize>;
+impl<P: PhaseItem, const I: usize> RenderCommand<P> for SetVisibleNodes<I> {
     type Param = ();
-    type ViewQuery = &'static VisibleNodesTextureBindGroup;
+    type ViewQuery = &'static VisibleNodesBindGroup;
     type ItemQuery = ();
 
     fn render<'w>(
         _item: &P,
-        attribute_pass_view_bind_group: ROQueryItem<'w, '_, Self::ViewQuery>,
+        visible_nodes_bind_group: ROQueryItem<'w, '_, Self::ViewQuery>,
         _entity: Option<ROQueryItem<'w, '_, Self::ItemQuery>>,
         _param: SystemParamItem<'w, '_, Self::Param>,
         pass: &mut TrackedRenderPass<'w>,
     ) -> RenderCommandResult {
-        pass.set_bind_group(I, &attribute_pass_view_bind_group.texture, &[]);
+        pass.set_bind_group(I, &visible_nodes_bind_group.bind_group, &[]);
 
         RenderCommandResult::Success
     }
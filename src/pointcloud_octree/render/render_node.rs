@@ -1,12 +1,130 @@
+use std::borrow::Cow;
+
+use bevy_camera::primitives::Aabb;
+use bevy_mesh::{VertexBufferLayout, VertexFormat};
+use bevy_render::render_resource::{VertexAttribute, VertexStepMode};
+
 use crate::{
     octree::extract::render::buffer::RenderNodeData,
-    pointcloud_octree::asset::data::{PointCloudNodeData, PointData},
+    pointcloud_octree::asset::data::{PointCloudNodeData, PointFormat},
 };
 
+impl PointFormat {
+    /// The shader def used to decode the points, if any
+    pub fn shader_def(&self) -> Option<&'static str> {
+        match self {
+            PointFormat::Full => None,
+            PointFormat::Quantized16 => Some("QUANTIZED_POSITIONS_16"),
+            PointFormat::Quantized32 => Some("QUANTIZED_POSITIONS_32"),
+        }
+    }
+
+    pub fn instance_buffer_layout(&self) -> VertexBufferLayout {
+        let (position_format, color_format, color_offset) = match self {
+            PointFormat::Full => (
+                VertexFormat::Float32x4,
+                VertexFormat::Float32x4,
+                VertexFormat::Float32x4.size(),
+            ),
+            PointFormat::Quantized16 => (
+                VertexFormat::Uint16x4,
+                VertexFormat::Unorm8x4,
+                VertexFormat::Uint16x4.size(),
+            ),
+            PointFormat::Quantized32 => (
+                VertexFormat::Uint32x3,
+                VertexFormat::Unorm8x4,
+                VertexFormat::Uint32x3.size(),
+            ),
+        };
+
+        let mut layout = VertexBufferLayout {
+            array_stride: self.instance_size() as u64,
+            step_mode: VertexStepMode::Instance,
+            attributes: vec![
+                // Point position
+                VertexAttribute {
+                    format: position_format,
+                    offset: 0,
+                    shader_location: 1,
+                },
+                // Point color
+                VertexAttribute {
+                    format: color_format,
+                    offset: color_offset,
+                    shader_location: 2,
+                },
+            ],
+        };
+
+        // Point normal, the normal of the 16 bits format is read as the 4th position component
+        if *self != PointFormat::Quantized16 {
+            layout.attributes.push(VertexAttribute {
+                format: VertexFormat::Uint8x2,
+                offset: self.normal_offset() as u64,
+                shader_location: 3,
+            });
+        }
+
+        layout
+    }
+
+    /// The layout of the bounds of the drawn node, used to decode the quantized positions.
+    /// The stride is zero so that all the instances of a draw read the same bounds.
+    pub fn node_bounds_buffer_layout(&self) -> Option<VertexBufferLayout> {
+        if *self == PointFormat::Full {
+            return None;
+        }
+
+        Some(VertexBufferLayout {
+            array_stride: 0,
+            step_mode: VertexStepMode::Instance,
+            attributes: vec![
+                // Node center
+                VertexAttribute {
+                    format: VertexFormat::Float32x3,
+                    offset: 0,
+                    shader_location: 4,
+                },
+                // Node half extents
+                VertexAttribute {
+                    format: VertexFormat::Float32x3,
+                    offset: VertexFormat::Float32x4.size(),
+                    shader_location: 5,
+                },
+            ],
+        })
+    }
+}
+
 impl RenderNodeData for PointCloudNodeData {
-    type InstanceData = PointData;
+    type InstanceFormat = PointFormat;
+
+    fn instance_size(format: &Self::InstanceFormat) -> usize {
+        format.instance_size()
+    }
+
+    fn instance_bytes(&self, format: &Self::InstanceFormat, bounding_box: &Aabb) -> Cow<'_, [u8]> {
+        // compact points are expanded here, when uploaded to the GPU
+        let encoded;
+        let points = if self.points.format() == *format {
+            &self.points
+        } else {
+            encoded = self.points.encode(*format, bounding_box);
+            &encoded
+        };
 
-    fn instances(&self) -> &[Self::InstanceData] {
-        &self.points
+        // interleave the normals with the points, unknown normals being zero
+        let (point_size, instance_size) = (format.point_size(), format.instance_size());
+        let normal_offset = format.normal_offset();
+        let mut bytes = vec![0; points.len() * instance_size];
+        for (index, point) in points.bytes().chunks_exact(point_size).enumerate() {
+            let instance = &mut bytes[index * instance_size..(index + 1) * instance_size];
+            instance[..point_size].copy_from_slice(point);
+            if let Some(normal) = self.normals.as_ref().and_then(|normals| normals.get(index)) {
+                instance[normal_offset..normal_offset + 2].copy_from_slice(normal);
+            }
+        }
+        Cow::Owned(bytes)
     }
 }
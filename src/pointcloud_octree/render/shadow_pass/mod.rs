@@ -0,0 +1,222 @@
+//! Draws the point cloud octrees into the shadow maps of the directional lights.
+//!
+//! The visible nodes of a camera, including the nodes only kept for the shadows, are shared with
+//! the cascades of its directional lights.
+
+use bevy_app::prelude::*;
+use bevy_asset::prelude::*;
+use bevy_ecs::{change_detection::Tick, prelude::*};
+use bevy_log::prelude::*;
+use bevy_pbr::{LightEntity, Shadow, ShadowBatchSetKey, ShadowBinKey, ViewLightEntities};
+use bevy_render::{
+    mesh::allocator::SlabId,
+    render_asset::RenderAssets,
+    render_phase::{
+        AddRenderCommand, BinnedRenderPhaseType, DrawFunctions, InputUniformIndex, SetItemPipeline,
+        ViewBinnedRenderPhases,
+    },
+    render_resource::{PipelineCache, SpecializedRenderPipelines},
+    sync_world::MainEntity,
+    view::ExtractedView,
+    Render, RenderApp, RenderSystems,
+};
+
+#[cfg(feature = "webgl")]
+use crate::pointcloud_octree::render::draw::DrawPointCloudOctree;
+#[cfg(not(feature = "webgl"))]
+use crate::pointcloud_octree::render::draw::DrawPointCloudOctreeIndirect;
+use crate::{
+    octree::{
+        extract::{render::components::RenderVisibleOctreeNodes, resources::OctreeBufferSettings},
+        visibility::{shadow::OctreeShadowCaster, OctreeVisibilitySystems},
+    },
+    point_cloud_material::{PointCloudMaterial, PointCloudMaterial3d},
+    pointcloud_octree::{
+        asset::{data::PointCloudNodeData, extract::PointCloudOctreeExtraction},
+        component::PointCloudOctree3d,
+        render::{
+            data::SetPointCloudOctree3dUniformGroup,
+            draw::{SetPointCloudOctreeNodeUniformGroup, SetRenderOctreeUniformGroup},
+            prepare::{prepare_visible_nodes_bind_group, SetVisibleNodes, VisibleNodesBindGroup},
+        },
+    },
+    render::{
+        material::{RenderPointCloudMaterial, SetPointCloudMaterialGroup},
+        shadow_pass::{
+            directional_light_views,
+            pipeline::{ShadowPipeline, ShadowPipelineKey},
+            SetShadowViewBindGroup,
+        },
+    },
+};
+
+pub struct ShadowPassPlugin;
+impl Plugin for ShadowPassPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            PostUpdate,
+            update_octree_shadow_casters
+                .before(OctreeVisibilitySystems::CheckOctreeNodesVisibility),
+        );
+
+        let Some(render_app) = app.get_sub_app_mut(RenderApp) else {
+            return;
+        };
+        render_app
+            .add_render_command::<Shadow, DrawShadowPass>()
+            .init_resource::<SpecializedRenderPipelines<ShadowPipeline>>()
+            .add_systems(
+                Render,
+                (
+                    queue_shadow_pass.in_set(RenderSystems::QueueMeshes),
+                    prepare_octree_shadow_views
+                        .in_set(RenderSystems::PrepareBindGroups)
+                        .after(prepare_visible_nodes_bind_group),
+                ),
+            );
+    }
+}
+
+// The visible nodes of the camera are culled against each cascade
+#[cfg(not(feature = "webgl"))]
+type DrawShadowPass = (
+    SetItemPipeline,
+    SetShadowViewBindGroup<0>,
+    SetPointCloudOctree3dUniformGroup<1>,
+    SetPointCloudMaterialGroup<2>,
+    SetVisibleNodes<3>,
+    SetPointCloudOctreeNodeUniformGroup<4>,
+    SetRenderOctreeUniformGroup<5>,
+    DrawPointCloudOctreeIndirect,
+);
+
+#[cfg(feature = "webgl")]
+type DrawShadowPass = (
+    SetItemPipeline,
+    SetShadowViewBindGroup<0>,
+    SetPointCloudOctree3dUniformGroup<1>,
+    SetPointCloudMaterialGroup<2>,
+    SetVisibleNodes<3>,
+    SetPointCloudOctreeNodeUniformGroup<4>,
+    SetRenderOctreeUniformGroup<5>,
+    DrawPointCloudOctree,
+);
+
+/// Mark the octrees whose material casts shadows, so that their nodes inside the shadow cascades
+/// are kept by the visibility check.
+#[allow(clippy::type_complexity)]
+fn update_octree_shadow_casters(
+    mut commands: Commands,
+    octrees: Query<
+        (Entity, &PointCloudMaterial3d, Has<OctreeShadowCaster>),
+        With<PointCloudOctree3d>,
+    >,
+    materials: Res<Assets<PointCloudMaterial>>,
+) {
+    for (entity, material, is_shadow_caster) in &octrees {
+        let cast_shadows = materials
+            .get(material)
+            .is_some_and(|material| material.cast_shadows);
+
+        if cast_shadows && !is_shadow_caster {
+            commands.entity(entity).insert(OctreeShadowCaster);
+        } else if !cast_shadows && is_shadow_caster {
+            commands.entity(entity).remove::<OctreeShadowCaster>();
+        }
+    }
+}
+
+/// Share the visible nodes of each camera with the cascades of its directional lights.
+fn prepare_octree_shadow_views(
+    mut commands: Commands,
+    cameras: Query<(
+        &ViewLightEntities,
+        &RenderVisibleOctreeNodes<PointCloudNodeData, PointCloudOctree3d>,
+        &VisibleNodesBindGroup,
+    )>,
+    light_views: Query<(&LightEntity, &ExtractedView)>,
+) {
+    for (view_light_entities, visible_nodes, visible_nodes_bind_group) in &cameras {
+        for (light_view_entity, _) in directional_light_views(view_light_entities, &light_views) {
+            commands
+                .entity(light_view_entity)
+                .insert((visible_nodes.clone(), visible_nodes_bind_group.clone()));
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn queue_shadow_pass(
+    shadow_draw_functions: Res<DrawFunctions<Shadow>>,
+    mut pipelines: ResMut<SpecializedRenderPipelines<ShadowPipeline>>,
+    pipeline_cache: Res<PipelineCache>,
+    shadow_pipeline: Res<ShadowPipeline>,
+    point_cloud_octrees_3d: Query<(&MainEntity, &PointCloudOctree3d, &PointCloudMaterial3d)>,
+    render_materials: Res<RenderAssets<RenderPointCloudMaterial>>,
+    octree_buffer_settings: Res<OctreeBufferSettings<PointCloudOctreeExtraction>>,
+    mut shadow_render_phases: ResMut<ViewBinnedRenderPhases<Shadow>>,
+    cameras: Query<(
+        &ViewLightEntities,
+        &RenderVisibleOctreeNodes<PointCloudNodeData, PointCloudOctree3d>,
+    )>,
+    light_views: Query<(&LightEntity, &ExtractedView)>,
+    mut next_tick: Local<Tick>,
+) {
+    let draw_shadow = shadow_draw_functions.read().id::<DrawShadowPass>();
+
+    for (view_light_entities, visible_nodes) in &cameras {
+        for (_, light_view) in directional_light_views(view_light_entities, &light_views) {
+            let Some(shadow_phase) = shadow_render_phases.get_mut(&light_view.retained_view_entity)
+            else {
+                continue;
+            };
+
+            for render_entity in visible_nodes.octrees.keys() {
+                let Ok((main_entity, point_cloud_octree_3d, point_cloud_material_3d)) =
+                    point_cloud_octrees_3d.get(*render_entity)
+                else {
+                    warn!("point_cloud_octree_3d missing");
+                    continue;
+                };
+                let Some(material) = render_materials.get(point_cloud_material_3d) else {
+                    continue;
+                };
+                if !material.cast_shadows {
+                    continue;
+                }
+
+                let shadow_key = ShadowPipelineKey {
+                    is_octree: true,
+                    shape: material.shape,
+                    geometry: material.geometry,
+                    point_format: *octree_buffer_settings.instance_format(),
+                    depth_clamp_ortho: true,
+                };
+
+                let pipeline_id =
+                    pipelines.specialize(&pipeline_cache, &shadow_pipeline, shadow_key);
+
+                // Bump the change tick in order to force Bevy to rebuild the bin.
+                let this_tick = next_tick.get() + 1;
+                next_tick.set(this_tick);
+
+                shadow_phase.add(
+                    ShadowBatchSetKey {
+                        pipeline: pipeline_id,
+                        draw_function: draw_shadow,
+                        material_bind_group_index: None,
+                        vertex_slab: SlabId::default(),
+                        index_slab: None,
+                    },
+                    ShadowBinKey {
+                        asset_id: point_cloud_octree_3d.0.id().untyped(),
+                    },
+                    (*render_entity, *main_entity),
+                    InputUniformIndex::default(),
+                    BinnedRenderPhaseType::NonMesh,
+                    *next_tick,
+                );
+            }
+        }
+    }
+}
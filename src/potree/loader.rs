@@ -1,5 +1,3 @@
-use std::sync::Arc;
-
 use async_trait::async_trait;
 use bevy_camera::primitives::Aabb;
 use bevy_ecs::error::BevyError;
@@ -17,7 +15,7 @@ use crate::{
         hierarchy::HierarchyNodeStatus,
         loader::{LoadedHierarchyNode, OctreeLoader},
     },
-    pointcloud_octree::asset::data::PointCloudNodeData,
+    pointcloud_octree::asset::data::{PointCloudNodeData, PointData},
 };
 
 pub struct PotreeLoader<T> {
@@ -78,7 +76,13 @@ impl<T: PotreeAsset + 'static> OctreeLoader<PointCloudNodeData> for PotreeLoader
             level: node.data.0.level,
             offset,
             num_points: node.data.0.num_points as usize,
-            points: Arc::new(points.into_iter().map(Into::into).collect()),
+            points: points
+                .into_iter()
+                .map(Into::into)
+                .collect::<Vec<PointData>>()
+                .into(),
+            normals: None,
+            classifications: None,
         })
     }
 }
@@ -0,0 +1,411 @@
+//! Cross-sections of the point clouds along a polyline.
+//!
+//! A [`PointCloudProfile`] selects the points of a vertical corridor around a polyline drawn on
+//! the ground, and projects them into its [`PointCloudProfileResult`] as (distance along the
+//! polyline, height) coordinates. The octree nodes of the corridor are read up to a level of
+//! detail, the missing ones being loaded by the [`OctreeServer`](crate::octree::server::OctreeServer)
+//! whatever the cameras see.
+//!
+//! A [`PointCloudProfileView`] shows a profile in a secondary orthographic camera.
+
+use bevy_app::prelude::*;
+use bevy_asset::prelude::*;
+use bevy_camera::{primitives::Aabb, visibility::RenderLayers, Projection, ScalingMode};
+use bevy_ecs::prelude::*;
+use bevy_math::{prelude::*, Affine3A};
+use bevy_reflect::{std_traits::ReflectDefault, Reflect};
+use bevy_transform::prelude::*;
+
+use crate::{
+    octree::{
+        server::process::process_octree_load_tasks, storage::NodeId,
+        visibility::OctreeVisibilitySystems,
+    },
+    point_cloud::{PointCloud, PointCloud3d, PointCloudData},
+    point_cloud_material::{PointCloudMaterial, PointCloudMaterial3d},
+    pointcloud_octree::asset::data::{PointCloudNodeData, PointData},
+    query::{PointCloudRegionQuery, QueryRegion, RegionQuerySettings},
+};
+
+/// Extracts the [`PointCloudProfile`]s and updates the [`PointCloudProfileView`]s.
+pub struct PointCloudProfilePlugin;
+
+impl Plugin for PointCloudProfilePlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<PointCloudProfile>()
+            .add_systems(
+                PostUpdate,
+                (
+                    // the load requests are cleared by the visibility check
+                    update_profiles
+                        .after(TransformSystems::Propagate)
+                        .after(OctreeVisibilitySystems::CheckOctreeNodesVisibility)
+                        .before(process_octree_load_tasks::<PointCloudNodeData>),
+                    update_profile_views.after(update_profiles),
+                ),
+            )
+            .add_observer(on_remove_profile_view);
+    }
+}
+
+/// A vertical corridor around a polyline, whose points are projected into a
+/// [`PointCloudProfileResult`].
+#[derive(Component, Reflect, Debug, Clone)]
+#[reflect(Component, Default, Clone)]
+#[require(PointCloudProfileResult)]
+pub struct PointCloudProfile {
+    /// Vertices of the polyline in world space, only their horizontal position is used
+    pub polyline: Vec<Vec3>,
+    /// Width of the corridor, in world units
+    pub width: f32,
+    /// Deepest level of the octree nodes whose points are extracted, `None` for all the levels
+    pub max_depth: Option<u32>,
+    /// Whether to load the missing octree nodes of the corridor, up to `max_depth`
+    pub load_missing_nodes: bool,
+    /// Whether to ignore the points discarded by the
+    /// [`PointCloudClipVolume`](crate::clip_volume::PointCloudClipVolume)s
+    pub respect_clip_volumes: bool,
+}
+
+impl Default for PointCloudProfile {
+    fn default() -> Self {
+        Self {
+            polyline: Vec::new(),
+            width: 1.0,
+            max_depth: None,
+            load_missing_nodes: true,
+            respect_clip_volumes: true,
+        }
+    }
+}
+
+impl PointCloudProfile {
+    pub fn new(polyline: impl IntoIterator<Item = Vec3>, width: f32) -> Self {
+        Self {
+            polyline: polyline.into_iter().collect(),
+            width,
+            ..Default::default()
+        }
+    }
+
+    pub fn with_max_depth(mut self, max_depth: u32) -> Self {
+        self.max_depth = Some(max_depth);
+        self
+    }
+}
+
+/// A point of a [`PointCloudProfile`].
+#[derive(Debug, Clone)]
+pub struct ProfilePoint {
+    /// Distance along the polyline and height, in world units
+    pub position: Vec2,
+    /// Position in world space
+    pub world_position: Vec3,
+    /// The [`PointCloud3d`] or
+    /// [`PointCloudOctree3d`](crate::pointcloud_octree::component::PointCloudOctree3d) entity
+    pub entity: Entity,
+    /// The octree node of the point, `None` for a [`PointCloud3d`]
+    pub node_id: Option<NodeId>,
+    /// Index of the point in its point cloud or octree node
+    pub point_index: usize,
+    /// Attributes of the point, in the local space of the entity
+    pub point: PointData,
+}
+
+/// The points of a [`PointCloudProfile`], updated when the profile changes or while nodes of
+/// its corridor are being loaded.
+#[derive(Component, Debug, Clone, Default)]
+pub struct PointCloudProfileResult {
+    pub points: Vec<ProfilePoint>,
+    /// Length of the polyline, in world units
+    pub length: f32,
+    /// Whether all the octree nodes of the corridor are loaded, up to the maximum depth
+    pub complete: bool,
+}
+
+impl PointCloudProfileResult {
+    /// Bounds of the projected points, as (distance along the polyline, height).
+    pub fn bounds(&self) -> Option<Rect> {
+        let mut points = self.points.iter().map(|point| point.position);
+        let first = points.next()?;
+        Some(
+            points.fold(Rect::from_corners(first, first), |rect, position| {
+                rect.union_point(position)
+            }),
+        )
+    }
+}
+
+/// A segment of the polyline, in the horizontal plane.
+struct Segment {
+    start: Vec2,
+    /// unit direction
+    direction: Vec2,
+    length: f32,
+    /// distance along the polyline at the start of the segment
+    offset: f32,
+}
+
+/// The corridor of a profile, in the horizontal XZ plane.
+struct Corridor {
+    segments: Vec<Segment>,
+    half_width: f32,
+}
+
+impl Corridor {
+    fn new(profile: &PointCloudProfile) -> Self {
+        let mut offset = 0.0;
+        let segments = profile
+            .polyline
+            .windows(2)
+            .filter_map(|segment| {
+                let (start, end) = (segment[0].xz(), segment[1].xz());
+                let length = start.distance(end);
+                let direction = (end - start).try_normalize()?;
+                let segment = Segment {
+                    start,
+                    direction,
+                    length,
+                    offset,
+                };
+                offset += length;
+                Some(segment)
+            })
+            .collect();
+
+        Self {
+            segments,
+            half_width: profile.width / 2.0,
+        }
+    }
+
+    fn length(&self) -> f32 {
+        self.segments
+            .last()
+            .map_or(0.0, |segment| segment.offset + segment.length)
+    }
+
+    /// Distance along the polyline of a position of the corridor, in the first segment
+    /// containing it.
+    fn project(&self, position: Vec2) -> Option<f32> {
+        self.segments.iter().find_map(|segment| {
+            let to_position = position - segment.start;
+            let along = to_position.dot(segment.direction);
+            let across = to_position.perp_dot(segment.direction);
+            ((0.0..=segment.length).contains(&along) && across.abs() <= self.half_width)
+                .then_some(segment.offset + along)
+        })
+    }
+
+    /// Whether a rectangle of the horizontal plane intersects the corridor, with the separating
+    /// axis theorem.
+    fn intersects_rect(&self, min: Vec2, max: Vec2) -> bool {
+        let center = (min + max) / 2.0;
+        let half_size = (max - min) / 2.0;
+        self.segments.iter().any(|segment| {
+            let normal = segment.direction.perp();
+            let segment_center = segment.start + segment.direction * (segment.length / 2.0);
+            let half_length = segment.length / 2.0;
+
+            // the axes of the rectangle
+            let extent = (segment.direction * half_length).abs() + normal.abs() * self.half_width;
+            if ((segment_center - center).abs() - extent)
+                .cmpgt(half_size)
+                .any()
+            {
+                return false;
+            }
+            // the axes of the segment
+            let to_center = center - segment_center;
+            let along = half_size.dot(segment.direction.abs());
+            let across = half_size.dot(normal.abs());
+            to_center.dot(segment.direction).abs() <= half_length + along
+                && to_center.dot(normal).abs() <= self.half_width + across
+        })
+    }
+}
+
+impl QueryRegion for Corridor {
+    fn intersects_obb(&self, aabb: &Aabb, world_from_local: &Affine3A) -> bool {
+        let matrix = world_from_local.matrix3;
+        let center = world_from_local.transform_point3a(aabb.center);
+        let half_extents = matrix.x_axis.abs() * aabb.half_extents.x
+            + matrix.y_axis.abs() * aabb.half_extents.y
+            + matrix.z_axis.abs() * aabb.half_extents.z;
+        self.intersects_rect((center - half_extents).xz(), (center + half_extents).xz())
+    }
+}
+
+fn update_profiles(
+    mut profiles: Query<(Ref<PointCloudProfile>, &mut PointCloudProfileResult)>,
+    mut query: PointCloudRegionQuery,
+    profile_point_clouds: Query<(), With<ProfilePointCloud>>,
+) {
+    #[cfg(feature = "trace")]
+    let _span = bevy_log::info_span!("update_profiles").entered();
+
+    // new nodes may have been loaded
+    let octrees_changed = query.octrees_changed();
+
+    for (profile, mut result) in &mut profiles {
+        let extract_points = profile.is_changed() || (!result.complete && octrees_changed);
+        if !extract_points && result.complete {
+            continue;
+        }
+
+        let corridor = Corridor::new(&profile);
+        let settings = RegionQuerySettings {
+            max_depth: profile.max_depth,
+            min_spacing: None,
+            load_missing_nodes: profile.load_missing_nodes,
+            respect_clip_volumes: profile.respect_clip_volumes,
+            visit_points: extract_points,
+            // the points shown by the profile views are not part of the scene
+            filter: &|entity| !profile_point_clouds.contains(entity),
+            skip_points: &|_, _| false,
+        };
+        let mut points = Vec::new();
+        let status = query.query(&corridor, &settings, |point| {
+            let Some(distance) = corridor.project(point.world_position.xz()) else {
+                return;
+            };
+            points.push(ProfilePoint {
+                position: Vec2::new(distance, point.world_position.y),
+                world_position: point.world_position,
+                entity: point.entity,
+                node_id: point.node_id,
+                point_index: point.point_index,
+                point: point.point,
+            });
+        });
+        let complete = status.complete();
+
+        if extract_points {
+            *result = PointCloudProfileResult {
+                points,
+                length: corridor.length(),
+                complete,
+            };
+        } else if result.complete != complete {
+            result.complete = complete;
+        }
+    }
+}
+
+/// Shows the points of a [`PointCloudProfile`] in an orthographic camera.
+///
+/// Add it to a camera with [`Projection::Orthographic`] and [`RenderLayers`] isolating it from
+/// the scene. The points are copied into a [`PointCloud3d`] spawned on the same layers, laid out
+/// in its XY plane as (distance along the polyline, height), and the camera is framed on them.
+#[derive(Component, Debug, Clone)]
+#[require(RenderLayers)]
+pub struct PointCloudProfileView {
+    /// The [`PointCloudProfile`] entity
+    pub profile: Entity,
+    pub material: Handle<PointCloudMaterial>,
+    /// Margin around the points, relative to their bounds
+    pub margin: f32,
+    point_cloud: Option<Entity>,
+}
+
+impl PointCloudProfileView {
+    pub fn new(profile: Entity, material: Handle<PointCloudMaterial>) -> Self {
+        Self {
+            profile,
+            material,
+            margin: 0.05,
+            point_cloud: None,
+        }
+    }
+
+    /// The [`PointCloud3d`] entity showing the profile.
+    pub fn point_cloud(&self) -> Option<Entity> {
+        self.point_cloud
+    }
+}
+
+fn update_profile_views(
+    mut commands: Commands,
+    mut views: Query<(
+        &mut PointCloudProfileView,
+        &RenderLayers,
+        &mut Projection,
+        &mut Transform,
+    )>,
+    results: Query<Ref<PointCloudProfileResult>>,
+    mut point_clouds: ResMut<Assets<PointCloud>>,
+    point_cloud_entities: Query<&PointCloud3d>,
+) {
+    for (mut view, render_layers, mut projection, mut transform) in &mut views {
+        let Ok(result) = results.get(view.profile) else {
+            continue;
+        };
+
+        let handle = view
+            .point_cloud
+            .and_then(|entity| point_cloud_entities.get(entity).ok())
+            .map(|point_cloud_3d| point_cloud_3d.0.clone())
+            .filter(|handle| point_clouds.contains(handle));
+        // only mark the point cloud as modified when the profile changed
+        if handle.is_some() && !result.is_changed() {
+            continue;
+        }
+
+        let points = result.points.iter().map(|point| PointCloudData {
+            position: point.position.extend(0.0),
+            point_size: point.point.position.w,
+            color: point.point.color.to_array(),
+        });
+        if let Some(point_cloud) = handle.and_then(|handle| point_clouds.get_mut(&handle)) {
+            point_cloud.clear();
+            point_cloud.extend(points);
+        } else {
+            // the point cloud of a previous profile is replaced
+            if let Some(entity) = view.point_cloud.take() {
+                commands.entity(entity).try_despawn();
+            }
+            let handle = point_clouds.add(PointCloud::new(points.collect()));
+            view.point_cloud = Some(
+                commands
+                    .spawn((
+                        PointCloud3d(handle),
+                        PointCloudMaterial3d(view.material.clone()),
+                        render_layers.clone(),
+                        ProfilePointCloud,
+                    ))
+                    .id(),
+            );
+        }
+
+        let Some(bounds) = result.bounds() else {
+            continue;
+        };
+        let size = bounds.size().max(Vec2::splat(f32::EPSILON)) * (1.0 + view.margin * 2.0);
+        // looking at the XY plane along -Z
+        transform.translation = bounds.center().extend(1.0);
+        transform.rotation = Quat::IDENTITY;
+        if let Projection::Orthographic(orthographic) = projection.as_mut() {
+            orthographic.scaling_mode = ScalingMode::AutoMin {
+                min_width: size.x,
+                min_height: size.y,
+            };
+        }
+    }
+}
+
+/// Marks the point clouds spawned by the [`PointCloudProfileView`]s.
+#[derive(Component)]
+struct ProfilePointCloud;
+
+/// Despawns the point cloud showing the profile along with its view.
+fn on_remove_profile_view(
+    trigger: On<Remove, PointCloudProfileView>,
+    views: Query<&PointCloudProfileView>,
+    mut commands: Commands,
+) {
+    if let Ok(view) = views.get(trigger.entity)
+        && let Some(entity) = view.point_cloud
+    {
+        commands.entity(entity).try_despawn();
+    }
+}
@@ -0,0 +1,315 @@
+//! Queries of the points of the point clouds and of the octree nodes inside a region of the
+//! world, loading the missing nodes of the region.
+
+use bevy_asset::{AssetId, Assets};
+use bevy_camera::{primitives::Aabb, visibility::InheritedVisibility};
+use bevy_ecs::{prelude::*, system::SystemParam};
+use bevy_math::{prelude::*, Affine3A};
+use bevy_platform::collections::HashMap;
+use bevy_transform::prelude::*;
+use ordered_float::OrderedFloat;
+
+use crate::{
+    clip_volume::{ClipVolumes, GlobalClipVolume},
+    octree::{
+        hierarchy::HierarchyNodeStatus,
+        node::NodeStatus,
+        server::resources::{LoadRequestType, OctreeLoadTasks},
+        storage::NodeId,
+        visibility::iter_one_bits,
+    },
+    point_cloud::{PointCloud, PointCloud3d},
+    pointcloud_octree::{
+        asset::{
+            data::{PointCloudNodeData, PointData},
+            PointCloudOctree,
+        },
+        component::PointCloudOctree3d,
+    },
+};
+
+/// A region of the world queried by a [`PointCloudRegionQuery`].
+pub(crate) trait QueryRegion {
+    /// Whether a box, in the local space of `world_from_local`, may intersect the region.
+    fn intersects_obb(&self, aabb: &Aabb, world_from_local: &Affine3A) -> bool;
+}
+
+impl QueryRegion for GlobalClipVolume {
+    fn intersects_obb(&self, aabb: &Aabb, world_from_local: &Affine3A) -> bool {
+        GlobalClipVolume::intersects_obb(self, aabb, world_from_local)
+    }
+}
+
+/// Settings of a [`PointCloudRegionQuery`].
+pub(crate) struct RegionQuerySettings<'a> {
+    /// Deepest level of the octree nodes whose points are visited, `None` for all the levels
+    pub max_depth: Option<u32>,
+    /// The children of the octree nodes whose spacing is at most this are not visited
+    pub min_spacing: Option<f32>,
+    /// Whether to queue the loads of the missing octree nodes of the region
+    pub load_missing_nodes: bool,
+    /// Whether to skip the points discarded by the clip volumes
+    pub respect_clip_volumes: bool,
+    /// Whether to visit the points, or only to load the missing nodes
+    pub visit_points: bool,
+    /// Only the entities for which this returns `true` are queried
+    pub filter: &'a dyn Fn(Entity) -> bool,
+    /// The points of the point clouds and octree nodes for which this returns `true` are not
+    /// visited, for instance because they were visited by a previous query
+    pub skip_points: &'a dyn Fn(Entity, Option<NodeId>) -> bool,
+}
+
+/// The octree nodes of the region found by a [`PointCloudRegionQuery`].
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct RegionQueryStatus {
+    /// Number of loaded nodes
+    pub loaded_nodes: usize,
+    /// Number of nodes whose hierarchy or data are not loaded
+    pub missing_nodes: usize,
+}
+
+impl RegionQueryStatus {
+    /// Whether all the octree nodes of the region are loaded, as far as the hierarchy is known.
+    pub fn complete(&self) -> bool {
+        self.missing_nodes == 0
+    }
+}
+
+/// A point visited by a [`PointCloudRegionQuery`].
+pub(crate) struct RegionPoint {
+    pub entity: Entity,
+    pub node_id: Option<NodeId>,
+    pub point_index: usize,
+    pub world_position: Vec3,
+    pub point: PointData,
+    /// Unit normal of the point in local space, zero when unknown
+    pub normal: Vec3,
+    /// ASPRS class of the point, if known
+    pub classification: Option<u8>,
+}
+
+/// Visits the points of the visible point clouds and of the loaded octree nodes which may be in
+/// a region, and queues the loads of the missing nodes.
+///
+/// Must run after the octree visibility check, which clears the load requests, and before the
+/// loads are processed.
+#[derive(SystemParam)]
+pub(crate) struct PointCloudRegionQuery<'w, 's> {
+    point_clouds: Res<'w, Assets<PointCloud>>,
+    octrees: Option<Res<'w, Assets<PointCloudOctree>>>,
+    /// Data generation of each octree at the last [`PointCloudRegionQuery::octrees_changed`]
+    octree_generations: Local<'s, HashMap<AssetId<PointCloudOctree>, u64>>,
+    load_tasks: Option<ResMut<'w, OctreeLoadTasks<PointCloudNodeData>>>,
+    clip_volumes: Option<Res<'w, ClipVolumes>>,
+    point_cloud_entities: Query<
+        'w,
+        's,
+        (
+            Entity,
+            &'static PointCloud3d,
+            &'static GlobalTransform,
+            &'static InheritedVisibility,
+        ),
+    >,
+    octree_entities: Query<
+        'w,
+        's,
+        (
+            Entity,
+            &'static PointCloudOctree3d,
+            &'static GlobalTransform,
+            &'static InheritedVisibility,
+        ),
+    >,
+}
+
+impl PointCloudRegionQuery<'_, '_> {
+    /// Whether the data of the octree nodes has changed since the last call, new nodes being
+    /// possibly loaded.
+    pub fn octrees_changed(&mut self) -> bool {
+        let Some(octrees) = &self.octrees else {
+            return false;
+        };
+
+        let generations = &mut *self.octree_generations;
+        let count = generations.len();
+        generations.retain(|id, _| octrees.contains(*id));
+        let mut changed = generations.len() != count;
+        for (id, octree) in octrees.iter() {
+            let generation = generations.entry(id).or_insert(u64::MAX);
+            changed |= *generation != octree.data_generation;
+            *generation = octree.data_generation;
+        }
+        changed
+    }
+
+    /// Visits the points of the region, returning how many of its octree nodes are loaded.
+    pub fn query(
+        &mut self,
+        region: &impl QueryRegion,
+        settings: &RegionQuerySettings,
+        mut visit: impl FnMut(RegionPoint),
+    ) -> RegionQueryStatus {
+        let clip_volumes = self
+            .clip_volumes
+            .as_deref()
+            .filter(|_| settings.respect_clip_volumes);
+        if settings.visit_points {
+            for (entity, point_cloud_3d, global_transform, inherited_visibility) in
+                &self.point_cloud_entities
+            {
+                if !inherited_visibility.get()
+                    || !(settings.filter)(entity)
+                    || (settings.skip_points)(entity, None)
+                {
+                    continue;
+                }
+                let Some(point_cloud) = self.point_clouds.get(&point_cloud_3d.0) else {
+                    continue;
+                };
+                let points = point_cloud.points.iter().enumerate().map(|(index, point)| {
+                    (
+                        point.into(),
+                        point_cloud.normal(index),
+                        point_cloud.classification(index),
+                    )
+                });
+                visit_points(
+                    (entity, None),
+                    &global_transform.affine(),
+                    points,
+                    clip_volumes,
+                    &mut visit,
+                );
+            }
+        }
+
+        let mut status = RegionQueryStatus::default();
+        for (entity, octree_3d, global_transform, inherited_visibility) in &self.octree_entities {
+            if !inherited_visibility.get() || !(settings.filter)(entity) {
+                continue;
+            }
+            let Some(octree) = self
+                .octrees
+                .as_ref()
+                .and_then(|octrees| octrees.get(&octree_3d.0))
+            else {
+                status.missing_nodes += 1;
+                continue;
+            };
+            let Some(root) = octree.node_root() else {
+                status.missing_nodes += 1;
+                continue;
+            };
+
+            let world_from_local = global_transform.affine();
+            let mut stack = vec![root.hierarchy.id];
+            while let Some(node_id) = stack.pop() {
+                let Some(node) = octree.node(node_id) else {
+                    continue;
+                };
+                let bounding_box = &node.hierarchy.bounding_box;
+                if settings
+                    .max_depth
+                    .is_some_and(|max_depth| node.hierarchy.depth > max_depth)
+                    || !region.intersects_obb(bounding_box, &world_from_local)
+                {
+                    continue;
+                }
+                if let Some(clip_volumes) = clip_volumes
+                    && clip_volumes.clips_obb(bounding_box, &world_from_local)
+                {
+                    continue;
+                }
+
+                match node.status {
+                    NodeStatus::HierarchyOnly => {
+                        status.missing_nodes += 1;
+                        let request_type = match node.hierarchy.status {
+                            HierarchyNodeStatus::Proxy => Some(LoadRequestType::Hierarchy),
+                            HierarchyNodeStatus::Loading => None,
+                            HierarchyNodeStatus::Loaded => Some(LoadRequestType::NodeData),
+                        };
+                        if settings.load_missing_nodes
+                            && let Some(load_tasks) = self.load_tasks.as_mut()
+                            && let Some(request_type) = request_type
+                        {
+                            // after the nodes seen by the cameras, the coarsest nodes first
+                            let weight = 1.0 / (node.hierarchy.depth + 1) as f32;
+                            load_tasks.queue_load_request(
+                                octree_3d.0.id(),
+                                node_id,
+                                OrderedFloat(weight),
+                                request_type,
+                            );
+                        }
+                        continue;
+                    }
+                    NodeStatus::Loading => {
+                        status.missing_nodes += 1;
+                        continue;
+                    }
+                    NodeStatus::Loaded => status.loaded_nodes += 1,
+                }
+
+                if settings.visit_points
+                    && let Some(data) = &node.data
+                    && !(settings.skip_points)(entity, Some(node_id))
+                {
+                    visit_points(
+                        (entity, Some(node_id)),
+                        &world_from_local,
+                        data.points.decode(bounding_box).iter().enumerate().map(
+                            |(index, point)| {
+                                (*point, data.normal(index), data.classification(index))
+                            },
+                        ),
+                        clip_volumes,
+                        &mut visit,
+                    );
+                }
+
+                if let Some(min_spacing) = settings.min_spacing
+                    && node
+                        .data
+                        .as_ref()
+                        .is_some_and(|data| data.spacing <= min_spacing)
+                {
+                    continue;
+                }
+                for child_index in iter_one_bits(node.hierarchy.children_mask) {
+                    stack.push(node.hierarchy.children[child_index as usize]);
+                }
+            }
+        }
+
+        status
+    }
+}
+
+/// Visits the points of an entity or octree node which are not clipped.
+fn visit_points(
+    (entity, node_id): (Entity, Option<NodeId>),
+    world_from_local: &Affine3A,
+    points: impl Iterator<Item = (PointData, Vec3, Option<u8>)>,
+    clip_volumes: Option<&ClipVolumes>,
+    visit: &mut impl FnMut(RegionPoint),
+) {
+    for (point_index, (point, normal, classification)) in points.enumerate() {
+        let world_position = world_from_local.transform_point3(point.position.truncate());
+        if let Some(clip_volumes) = clip_volumes
+            && clip_volumes.clips_point(world_position)
+        {
+            continue;
+        }
+        visit(RegionPoint {
+            entity,
+            node_id,
+            point_index,
+            world_position,
+            point,
+            normal,
+            classification,
+        });
+    }
+}
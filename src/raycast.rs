@@ -0,0 +1,561 @@
+//! Ray casting against the points of the point clouds and of the loaded octree nodes, to find the
+//! point under the cursor.
+
+use std::{cmp::Ordering, collections::BinaryHeap};
+
+use bevy_app::prelude::*;
+use bevy_asset::{AssetEvent, AssetId, Assets};
+use bevy_camera::{primitives::Aabb, visibility::InheritedVisibility};
+use bevy_ecs::{entity::EntityHashMap, prelude::*, system::SystemParam};
+use bevy_math::{prelude::*, Affine3A, Mat3A, Vec3A, Vec4};
+use bevy_platform::collections::{HashMap, HashSet};
+use bevy_transform::prelude::*;
+
+use crate::{
+    clip_volume::ClipVolumes,
+    octree::{
+        storage::NodeId,
+        visibility::{components::ViewVisibleOctreeNodes, iter_one_bits, OctreeVisibilitySystems},
+    },
+    point_cloud::{PointCloud, PointCloud3d, PointCloudData},
+    pointcloud_octree::{
+        asset::{
+            data::{PointCloudNodeData, PointData},
+            PointCloudOctree,
+        },
+        component::PointCloudOctree3d,
+    },
+};
+
+/// Number of consecutive points of a [`PointCloud`] whose bounding box is tested before their
+/// points, which is effective once they are sorted with [`PointCloud::sort_spatially`].
+pub const RAY_CAST_CHUNK_SIZE: usize = 4096;
+
+/// Keeps the [`PointCloudRayCastCache`] used by [`PointCloudRayCast`] up to date.
+pub struct PointCloudRayCastPlugin;
+
+impl Plugin for PointCloudRayCastPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<PointCloudRayCastCache>().add_systems(
+            PostUpdate,
+            update_ray_cast_cache.after(OctreeVisibilitySystems::CheckOctreeNodesVisibility),
+        );
+    }
+}
+
+/// What the ray casts reuse from one frame to the next.
+#[derive(Resource, Default)]
+pub struct PointCloudRayCastCache {
+    /// Bounding boxes of the chunks of [`RAY_CAST_CHUNK_SIZE`] points of each point cloud, in
+    /// local space
+    chunks: HashMap<AssetId<PointCloud>, Vec<Option<Aabb>>>,
+    /// Octree nodes drawn by each camera, for each octree entity
+    visible_nodes: EntityHashMap<EntityHashMap<HashSet<NodeId>>>,
+}
+
+/// Updates the chunks of the changed point clouds and the nodes visible by the cameras when they
+/// change.
+pub fn update_ray_cast_cache(
+    mut cache: ResMut<PointCloudRayCastCache>,
+    mut point_cloud_events: MessageReader<AssetEvent<PointCloud>>,
+    point_clouds: Res<Assets<PointCloud>>,
+    views: Query<(
+        Entity,
+        &ViewVisibleOctreeNodes<PointCloudNodeData, PointCloudOctree3d>,
+    )>,
+) {
+    for event in point_cloud_events.read() {
+        match event {
+            AssetEvent::Added { id } | AssetEvent::Modified { id } => {
+                if let Some(point_cloud) = point_clouds.get(*id) {
+                    let chunks = point_cloud.chunk_bounds(RAY_CAST_CHUNK_SIZE);
+                    cache.chunks.insert(*id, chunks);
+                }
+            }
+            AssetEvent::Removed { id } | AssetEvent::Unused { id } => {
+                cache.chunks.remove(id);
+            }
+            AssetEvent::LoadedWithDependencies { .. } => {}
+        }
+    }
+
+    cache
+        .visible_nodes
+        .retain(|camera, _| views.contains(*camera));
+    for (camera, view) in &views {
+        if !view.changed_this_frame && cache.visible_nodes.contains_key(&camera) {
+            continue;
+        }
+        let octrees = view
+            .octrees
+            .iter()
+            .map(|(entity, (_, nodes))| {
+                let nodes = nodes
+                    .iter()
+                    .filter(|node| !node.shadow_only)
+                    .map(|node| node.id)
+                    .collect();
+                (*entity, nodes)
+            })
+            .collect();
+        cache.visible_nodes.insert(camera, octrees);
+    }
+}
+
+/// Settings of a [`PointCloudRayCast`].
+#[derive(Clone, Copy)]
+pub struct PointCloudRayCastSettings<'a> {
+    /// Maximum distance between the ray and a hit point, in world units
+    pub pick_radius: f32,
+    /// Only test the octree nodes visible by this camera, at its current level of detail, instead
+    /// of all the loaded nodes
+    pub camera: Option<Entity>,
+    /// Whether to ignore the points discarded by the
+    /// [`PointCloudClipVolume`](crate::clip_volume::PointCloudClipVolume)s
+    pub respect_clip_volumes: bool,
+    /// Only the entities for which this returns `true` are tested
+    pub filter: &'a dyn Fn(Entity) -> bool,
+}
+
+impl Default for PointCloudRayCastSettings<'_> {
+    fn default() -> Self {
+        Self {
+            pick_radius: 0.1,
+            camera: None,
+            respect_clip_volumes: true,
+            filter: &|_| true,
+        }
+    }
+}
+
+impl<'a> PointCloudRayCastSettings<'a> {
+    pub fn with_pick_radius(mut self, pick_radius: f32) -> Self {
+        self.pick_radius = pick_radius;
+        self
+    }
+
+    pub fn with_camera(mut self, camera: Entity) -> Self {
+        self.camera = Some(camera);
+        self
+    }
+
+    pub fn with_filter(mut self, filter: &'a dyn Fn(Entity) -> bool) -> Self {
+        self.filter = filter;
+        self
+    }
+}
+
+/// The closest point hit by a ray.
+#[derive(Debug, Clone)]
+pub struct PointCloudRayHit {
+    /// The [`PointCloud3d`] or [`PointCloudOctree3d`] entity
+    pub entity: Entity,
+    /// The octree node of the point, `None` for a [`PointCloud3d`]
+    pub node_id: Option<NodeId>,
+    /// Index of the point in its point cloud or octree node
+    pub point_index: usize,
+    /// Position of the point in world space
+    pub position: Vec3,
+    /// Unit normal of the point in world space, if known
+    pub normal: Option<Vec3>,
+    /// Attributes of the point, in the local space of the entity
+    pub point: PointData,
+    /// Distance from the ray origin to the projection of the point on the ray
+    pub distance: f32,
+    /// Distance between the point and the ray
+    pub ray_distance: f32,
+}
+
+/// Casts rays against the points of the point clouds and of the loaded nodes of the point cloud
+/// octrees.
+///
+/// The octree hierarchies are walked nearest node first using their bounding boxes, so that only
+/// the nodes that may contain a closer hit are decoded. The points of the point clouds are
+/// culled by chunks of [`RAY_CAST_CHUNK_SIZE`] points.
+///
+/// Needs the [`PointCloudRayCastPlugin`], added by the [`PointCloudPlugin`](crate::PointCloudPlugin).
+#[derive(SystemParam)]
+pub struct PointCloudRayCast<'w, 's> {
+    point_clouds: Res<'w, Assets<PointCloud>>,
+    octrees: Option<Res<'w, Assets<PointCloudOctree>>>,
+    point_cloud_entities: Query<
+        'w,
+        's,
+        (
+            Entity,
+            &'static PointCloud3d,
+            &'static GlobalTransform,
+            &'static InheritedVisibility,
+            Option<&'static Aabb>,
+        ),
+    >,
+    octree_entities: Query<
+        'w,
+        's,
+        (
+            Entity,
+            &'static PointCloudOctree3d,
+            &'static GlobalTransform,
+            &'static InheritedVisibility,
+        ),
+    >,
+    cache: Res<'w, PointCloudRayCastCache>,
+    clip_volumes: Option<Res<'w, ClipVolumes>>,
+}
+
+impl PointCloudRayCast<'_, '_> {
+    /// Returns the point closest to the ray origin among the points within the pick radius of the
+    /// ray.
+    pub fn cast_ray(
+        &self,
+        ray: Ray3d,
+        settings: &PointCloudRayCastSettings,
+    ) -> Option<PointCloudRayHit> {
+        #[cfg(feature = "trace")]
+        let _span = bevy_log::info_span!("point_cloud_ray_cast").entered();
+
+        let clip_volumes = self
+            .clip_volumes
+            .as_deref()
+            .filter(|_| settings.respect_clip_volumes);
+
+        let mut closest: Option<PointCloudRayHit> = None;
+        let max_distance = |closest: &Option<PointCloudRayHit>| {
+            closest.as_ref().map_or(f32::MAX, |hit| hit.distance)
+        };
+
+        for (entity, point_cloud_3d, global_transform, inherited_visibility, aabb) in
+            &self.point_cloud_entities
+        {
+            if !inherited_visibility.get() || !(settings.filter)(entity) {
+                continue;
+            }
+            let Some(point_cloud) = self.point_clouds.get(&point_cloud_3d.0) else {
+                continue;
+            };
+
+            let point_ray = PointRay::new(
+                entity,
+                ray,
+                settings.pick_radius,
+                global_transform.affine(),
+                clip_volumes,
+            );
+            if aabb.is_some_and(|aabb| point_ray.enter_distance(aabb).is_none()) {
+                continue;
+            }
+
+            // the chunks are outdated until the cache is updated
+            let chunks = self
+                .cache
+                .chunks
+                .get(&point_cloud_3d.id())
+                .filter(|chunks| {
+                    chunks.len() == point_cloud.points.len().div_ceil(RAY_CAST_CHUNK_SIZE)
+                });
+            for start in (0..point_cloud.points.len()).step_by(RAY_CAST_CHUNK_SIZE) {
+                if let Some(chunks) = chunks
+                    && chunks[start / RAY_CAST_CHUNK_SIZE].is_none_or(|chunk_aabb| {
+                        point_ray
+                            .enter_distance(&chunk_aabb)
+                            .is_none_or(|distance| distance >= max_distance(&closest))
+                    })
+                {
+                    continue;
+                }
+
+                let end = point_cloud.points.len().min(start + RAY_CAST_CHUNK_SIZE);
+                let points = (start..end).map(|index| {
+                    let point = &point_cloud.points[index];
+                    (index, point.into(), point_cloud.normal(index))
+                });
+                if let Some(hit) = point_ray.closest_point(None, points, max_distance(&closest)) {
+                    closest = Some(hit);
+                }
+            }
+        }
+
+        let view = match settings.camera {
+            Some(camera) => self.cache.visible_nodes.get(&camera),
+            None => None,
+        };
+
+        for (entity, octree_3d, global_transform, inherited_visibility) in &self.octree_entities {
+            if !inherited_visibility.get() || !(settings.filter)(entity) {
+                continue;
+            }
+            let Some(octree) = self
+                .octrees
+                .as_ref()
+                .and_then(|octrees| octrees.get(&octree_3d.0))
+            else {
+                continue;
+            };
+
+            // the nodes visible by the camera, at its level of detail
+            let visible_nodes = match (settings.camera, view) {
+                (None, _) => None,
+                (Some(_), Some(view)) => {
+                    let Some(nodes) = view.get(&entity) else {
+                        continue;
+                    };
+                    Some(nodes)
+                }
+                (Some(_), None) => continue,
+            };
+
+            let point_ray = PointRay::new(
+                entity,
+                ray,
+                settings.pick_radius,
+                global_transform.affine(),
+                clip_volumes,
+            );
+            if let Some(hit) = point_ray.cast_octree(octree, visible_nodes, max_distance(&closest))
+            {
+                closest = Some(hit);
+            }
+        }
+
+        closest
+    }
+}
+
+impl From<&PointCloudData> for PointData {
+    fn from(point: &PointCloudData) -> Self {
+        Self {
+            position: point.position.extend(point.point_size),
+            color: Vec4::from_array(point.color),
+        }
+    }
+}
+
+/// A ray cast against the points of an entity.
+struct PointRay<'a> {
+    entity: Entity,
+    origin: Vec3A,
+    direction: Vec3A,
+    /// origin and direction in the local space of the entity, the distances along the ray being
+    /// the same as in world space
+    local_origin: Vec3A,
+    local_direction: Vec3A,
+    pick_radius: f32,
+    /// pick radius in the local space of the entity, large enough for any direction
+    local_pick_radius: f32,
+    world_from_local: Affine3A,
+    /// inverse transpose of the linear part of `world_from_local`, transforming the normals
+    world_from_local_normal: Mat3A,
+    clip_volumes: Option<&'a ClipVolumes>,
+}
+
+impl<'a> PointRay<'a> {
+    fn new(
+        entity: Entity,
+        ray: Ray3d,
+        pick_radius: f32,
+        world_from_local: Affine3A,
+        clip_volumes: Option<&'a ClipVolumes>,
+    ) -> Self {
+        let local_from_world = world_from_local.inverse();
+        let matrix = world_from_local.matrix3;
+        let min_scale = matrix
+            .x_axis
+            .length()
+            .min(matrix.y_axis.length())
+            .min(matrix.z_axis.length());
+
+        Self {
+            entity,
+            origin: ray.origin.into(),
+            direction: Vec3A::from(*ray.direction),
+            local_origin: local_from_world.transform_point3a(ray.origin.into()),
+            local_direction: local_from_world.transform_vector3a(Vec3A::from(*ray.direction)),
+            pick_radius,
+            local_pick_radius: pick_radius / min_scale.max(f32::EPSILON),
+            world_from_local,
+            world_from_local_normal: matrix.inverse().transpose(),
+            clip_volumes,
+        }
+    }
+
+    /// Distance along the ray at which it enters a bounding box enlarged by the pick radius.
+    fn enter_distance(&self, aabb: &Aabb) -> Option<f32> {
+        let min = aabb.min() - self.local_pick_radius;
+        let max = aabb.max() + self.local_pick_radius;
+        let inverse_direction = self.local_direction.recip();
+        let t0 = (min - self.local_origin) * inverse_direction;
+        let t1 = (max - self.local_origin) * inverse_direction;
+        let enter = t0.min(t1).max_element().max(0.0);
+        let exit = t0.max(t1).min_element();
+        (enter <= exit).then_some(enter)
+    }
+
+    fn cast_octree(
+        &self,
+        octree: &PointCloudOctree,
+        visible_nodes: Option<&HashSet<NodeId>>,
+        mut max_distance: f32,
+    ) -> Option<PointCloudRayHit> {
+        let root = octree.node_root()?;
+
+        let mut closest = None;
+        let mut stack = BinaryHeap::new();
+        if let Some(distance) = self.enter_distance(&root.hierarchy.bounding_box) {
+            stack.push(StackedNode {
+                node_id: root.hierarchy.id,
+                distance,
+            });
+        }
+
+        while let Some(StackedNode { node_id, distance }) = stack.pop() {
+            // the remaining nodes are all farther than the closest hit
+            if distance > max_distance {
+                break;
+            }
+            let Some(node) = octree.node(node_id) else {
+                continue;
+            };
+            let bounding_box = &node.hierarchy.bounding_box;
+            if let Some(clip_volumes) = self.clip_volumes
+                && clip_volumes.clips_obb(bounding_box, &self.world_from_local)
+            {
+                continue;
+            }
+
+            if let Some(data) = &node.data
+                && visible_nodes.is_none_or(|nodes| nodes.contains(&node_id))
+                && let Some(hit) = self.closest_point(
+                    Some(node_id),
+                    data.points
+                        .decode(bounding_box)
+                        .iter()
+                        .enumerate()
+                        .map(|(index, point)| (index, *point, data.normal(index))),
+                    max_distance,
+                )
+            {
+                max_distance = hit.distance;
+                closest = Some(hit);
+            }
+
+            for child_index in iter_one_bits(node.hierarchy.children_mask) {
+                let child_id = node.hierarchy.children[child_index as usize];
+                if let Some(child) = octree.node(child_id)
+                    && let Some(distance) = self.enter_distance(&child.hierarchy.bounding_box)
+                {
+                    stack.push(StackedNode {
+                        node_id: child_id,
+                        distance,
+                    });
+                }
+            }
+        }
+
+        closest
+    }
+
+    /// The point closest to the ray origin within the pick radius, and nearer than `max_distance`.
+    fn closest_point(
+        &self,
+        node_id: Option<NodeId>,
+        points: impl Iterator<Item = (usize, PointData, Vec3)>,
+        mut max_distance: f32,
+    ) -> Option<PointCloudRayHit> {
+        let pick_radius_squared = self.pick_radius * self.pick_radius;
+
+        let mut closest = None;
+        for (point_index, point, normal) in points {
+            let position = self
+                .world_from_local
+                .transform_point3a(point.position.truncate().into());
+            let to_point = position - self.origin;
+            let distance = to_point.dot(self.direction);
+            if distance < 0.0 || distance >= max_distance {
+                continue;
+            }
+            let ray_distance_squared = to_point.length_squared() - distance * distance;
+            if ray_distance_squared > pick_radius_squared {
+                continue;
+            }
+            if let Some(clip_volumes) = self.clip_volumes
+                && clip_volumes.clips_point(position.into())
+            {
+                continue;
+            }
+
+            max_distance = distance;
+            closest = Some(PointCloudRayHit {
+                entity: self.entity,
+                node_id,
+                point_index,
+                position: position.into(),
+                normal: (self.world_from_local_normal * Vec3A::from(normal))
+                    .try_normalize()
+                    .map(Vec3::from),
+                point,
+                distance,
+                ray_distance: ray_distance_squared.max(0.0).sqrt(),
+            });
+        }
+
+        closest
+    }
+}
+
+/// A node to visit, the nearest one first.
+struct StackedNode {
+    node_id: NodeId,
+    distance: f32,
+}
+
+impl PartialEq for StackedNode {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for StackedNode {}
+
+impl PartialOrd for StackedNode {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for StackedNode {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.distance.total_cmp(&self.distance)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normals_stay_perpendicular_under_non_uniform_scale() {
+        let world_from_local = Affine3A::from_scale(Vec3::new(2.0, 1.0, 1.0));
+        let ray = PointRay::new(
+            Entity::PLACEHOLDER,
+            Ray3d::new(Vec3::new(2.0, 0.0, -5.0), Dir3::Z),
+            0.1,
+            world_from_local,
+            None,
+        );
+        let point = PointData {
+            position: Vec4::new(1.0, 0.0, 0.0, 1.0),
+            color: Vec4::ONE,
+        };
+        // a surface along (1, -1, 0) in local space
+        let normal = Vec3::new(1.0, 1.0, 0.0).normalize();
+        let hit = ray
+            .closest_point(None, [(0, point, normal)].into_iter(), f32::MAX)
+            .unwrap();
+        assert!((hit.distance - 5.0).abs() < 1e-5);
+
+        let normal = hit.normal.unwrap();
+        let tangent = world_from_local.transform_vector3(Vec3::new(1.0, -1.0, 0.0));
+        assert!(normal.dot(tangent).abs() < 1e-5);
+        assert!((normal.length() - 1.0).abs() < 1e-5);
+    }
+}
@@ -1,13 +1,14 @@
-use bevy_asset::Assets;
+use bevy_asset::{AssetEvent, Assets};
 use bevy_camera::{primitives::Aabb, visibility::NoFrustumCulling};
 use bevy_ecs::prelude::*;
+use bevy_platform::collections::HashSet;
 
 use crate::{point_cloud::PointCloud, render::PointCloud3d};
 
 #[derive(Component)]
 pub struct AabbComputed;
 
-/// Compute AABB for point clouds
+/// Compute AABB for point clouds, and recompute it when the point cloud is modified
 ///
 /// # Arguments
 ///
@@ -23,18 +24,27 @@ pub struct AabbComputed;
 /// ```
 #[allow(clippy::type_complexity)]
 pub fn compute_point_cloud_aabb(
-    point_clouds_without_aabb: Query<
-        (Entity, &PointCloud3d),
-        (
-            With<PointCloud3d>,
-            Without<NoFrustumCulling>,
-            Without<AabbComputed>,
-        ),
+    point_clouds_3d: Query<
+        (Entity, &PointCloud3d, Has<AabbComputed>),
+        (With<PointCloud3d>, Without<NoFrustumCulling>),
     >,
     point_clouds: Res<Assets<PointCloud>>,
+    mut events: MessageReader<AssetEvent<PointCloud>>,
     mut commands: Commands,
 ) {
-    for (entity, point_cloud_3d) in point_clouds_without_aabb.iter() {
+    let modified = events
+        .read()
+        .filter_map(|event| match event {
+            AssetEvent::Modified { id } => Some(*id),
+            _ => None,
+        })
+        .collect::<HashSet<_>>();
+
+    for (entity, point_cloud_3d, aabb_computed) in point_clouds_3d.iter() {
+        if aabb_computed && !modified.contains(&point_cloud_3d.id()) {
+            continue;
+        }
+
         let Some(point_cloud) = point_clouds.get(point_cloud_3d) else {
             continue;
         };
@@ -7,12 +7,14 @@ use bevy_app::prelude::*;
 use bevy_camera::{Camera, Camera3d};
 use bevy_core_pipeline::core_3d::graph::Core3d;
 use bevy_ecs::{change_detection::Tick, prelude::*};
+use bevy_light::ShadowFilteringMethod;
 use bevy_log::prelude::*;
 use bevy_pbr::{MeshPipelineKey, SetMeshViewBindGroup};
 use bevy_platform::collections::HashSet;
 use bevy_render::{
     batching::gpu_preprocessing::{GpuPreprocessingMode, GpuPreprocessingSupport},
     prelude::*,
+    render_asset::RenderAssets,
     render_graph::{RenderGraphExt, ViewNodeRunner},
     render_phase::{
         AddRenderCommand, BinnedRenderPhaseType, DrawFunctions, InputUniformIndex, SetItemPipeline,
@@ -29,6 +31,8 @@ use texture::prepare_attribute_pass_textures;
 
 use crate::{
     point_cloud::PointCloud3d,
+    point_cloud_material::PointCloudMaterial3d,
+    pointcloud_octree::asset::data::PointFormat,
     render::{
         attribute_pass::{
             pipeline::{AttributePassPipeline, AttributePipelineKey},
@@ -36,9 +40,10 @@ use crate::{
         },
         depth_pass::node::DepthPassLabel,
         draw::DrawPointCloud,
-        material::SetPointCloudMaterialGroup,
+        material::{RenderPointCloudMaterial, SetPointCloudMaterialGroup},
         phase::{PointCloud3dBatchSetKey, PointCloud3dBinKey},
         point_cloud_uniform::SetPointCloudUniformGroup,
+        shadow_pass::shadow_filter_method_key,
     },
 };
 
@@ -134,30 +139,29 @@ fn queue_attribute_pass(
     mut pipelines: ResMut<SpecializedRenderPipelines<AttributePassPipeline>>,
     pipeline_cache: Res<PipelineCache>,
     custom_draw_pipeline: Res<AttributePassPipeline>,
-    point_clouds_3d: Query<&PointCloud3d>,
+    point_clouds_3d: Query<(&PointCloud3d, &PointCloudMaterial3d)>,
+    render_materials: Res<RenderAssets<RenderPointCloudMaterial>>,
     mut custom_render_phases: ResMut<ViewBinnedRenderPhases<PointCloud3dAttributePhase>>,
-    mut views: Query<(&ExtractedView, &RenderVisibleEntities, &Msaa)>,
+    mut views: Query<(
+        &ExtractedView,
+        &RenderVisibleEntities,
+        &Msaa,
+        Option<&ShadowFilteringMethod>,
+    )>,
     main_entities: Query<&MainEntity>,
     mut next_tick: Local<Tick>,
 ) {
-    for (view, visible_entities, msaa) in &mut views {
+    for (view, visible_entities, msaa, shadow_filter_method) in &mut views {
         let Some(custom_phase) = custom_render_phases.get_mut(&view.retained_view_entity) else {
             continue;
         };
         let draw_custom = custom_draw_functions.read().id::<DrawAttributePass>();
 
         // Create the key based on the view.
-        // In this case we only care about MSAA and HDR
+        // In this case we care about MSAA, HDR and the shadow filtering method
         let view_key = MeshPipelineKey::from_msaa_samples(msaa.samples())
-            | MeshPipelineKey::from_hdr(view.hdr);
-
-        let attribute_key = AttributePipelineKey {
-            mesh_key: view_key,
-            is_octree: false,
-        };
-
-        let pipeline_id =
-            pipelines.specialize(&pipeline_cache, &custom_draw_pipeline, attribute_key);
+            | MeshPipelineKey::from_hdr(view.hdr)
+            | shadow_filter_method_key(shadow_filter_method);
 
         // Since our phase can work on any 3d mesh we can reuse the default mesh 3d filter
         for (render_entity, _visible_entity) in visible_entities.iter::<PointCloud3d>() {
@@ -165,10 +169,28 @@ fn queue_attribute_pass(
                 warn!("Render entity not found, skipping.");
                 continue;
             };
-            let Ok(point_cloud_3d) = point_clouds_3d.get(*render_entity) else {
+            let Ok((point_cloud_3d, point_cloud_material_3d)) = point_clouds_3d.get(*render_entity)
+            else {
                 warn!("point_cloud_3d missing");
                 continue;
             };
+            let Some(material) = render_materials.get(point_cloud_material_3d) else {
+                continue;
+            };
+
+            let attribute_key = AttributePipelineKey {
+                mesh_key: view_key,
+                is_octree: false,
+                shape: material.shape,
+                splatting: material.splatting,
+                geometry: material.geometry,
+                point_format: PointFormat::Full,
+                receive_shadows: material.receive_shadows,
+                lighting: material.lighting,
+            };
+
+            let pipeline_id =
+                pipelines.specialize(&pipeline_cache, &custom_draw_pipeline, attribute_key);
 
             // Bump the change tick in order to force Bevy to rebuild the bin.
             let this_tick = next_tick.get() + 1;
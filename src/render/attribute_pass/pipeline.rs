@@ -19,10 +19,17 @@ use bevy_shader::Shader;
 use bevy_utils::default;
 
 use crate::{
-    point_cloud::PointCloudData,
-    point_cloud_material::PointCloudMaterial,
-    pointcloud_octree::extract::{PointCloudNodeDataUniform, PointCloudOctreeUniform},
-    render::{point_cloud_uniform::PointCloudUniform, POINTCLOUD_SHADER_HANDLE},
+    point_cloud_material::{PointGeometry, PointLighting, PointShape, PointSplatting},
+    pointcloud_octree::{
+        asset::data::PointFormat,
+        extract::{PointCloudNodeDataUniform, PointCloudOctreeUniform},
+        render::prepare::visible_nodes_layout_entries,
+    },
+    render::{
+        clip_volume::clip_volumes_shader_defs, material::point_cloud_material_layout_entries,
+        point_cloud::PointCloudInstance, point_cloud_uniform::PointCloudUniform,
+        POINTCLOUD_SHADER_HANDLE,
+    },
 };
 
 #[derive(Resource)]
@@ -66,19 +73,11 @@ impl FromWorld for AttributePassPipeline {
             point_cloud_layout: PointCloudUniform::bind_group_layout_descriptor(render_device),
             point_cloud_material_layout: BindGroupLayoutDescriptor {
                 label: "pcl_material".into(),
-                entries: BindGroupLayoutEntries::single(
-                    ShaderStages::VERTEX,
-                    uniform_buffer::<PointCloudMaterial>(false),
-                )
-                .to_vec(),
+                entries: point_cloud_material_layout_entries(),
             },
             point_cloud_octree_visible_nodes_layout: BindGroupLayoutDescriptor {
                 label: "pcl_octree_visible_nodes_layout".into(),
-                entries: BindGroupLayoutEntries::single(
-                    ShaderStages::VERTEX,
-                    texture_2d(TextureSampleType::Uint),
-                )
-                .to_vec(),
+                entries: visible_nodes_layout_entries(),
             },
             point_cloud_octree_node_data_layout: BindGroupLayoutDescriptor {
                 label: "pcl_octree_node_data".into(),
@@ -104,6 +103,14 @@ impl FromWorld for AttributePassPipeline {
 pub struct AttributePipelineKey {
     pub mesh_key: MeshPipelineKey,
     pub is_octree: bool,
+    pub shape: PointShape,
+    pub splatting: PointSplatting,
+    pub geometry: PointGeometry,
+    /// Layout of the octree points
+    pub point_format: PointFormat,
+    /// Darken the points in the shadows of the directional lights
+    pub receive_shadows: bool,
+    pub lighting: PointLighting,
 }
 
 impl SpecializedRenderPipeline for AttributePassPipeline {
@@ -120,29 +127,82 @@ impl SpecializedRenderPipeline for AttributePassPipeline {
             }],
         };
 
-        let instance_buffer_layout = VertexBufferLayout {
-            array_stride: size_of::<PointCloudData>() as u64,
-            step_mode: VertexStepMode::Instance,
-            attributes: vec![
-                // Point position
-                VertexAttribute {
-                    format: VertexFormat::Float32x4,
-                    offset: 0,
-                    shader_location: 1,
-                },
-                // Point color
-                VertexAttribute {
-                    format: VertexFormat::Float32x4,
-                    offset: VertexFormat::Float32x4.size(),
-                    shader_location: 2,
-                },
-            ],
+        let instance_buffer_layout = if key.is_octree {
+            key.point_format.instance_buffer_layout()
+        } else {
+            VertexBufferLayout {
+                array_stride: size_of::<PointCloudInstance>() as u64,
+                step_mode: VertexStepMode::Instance,
+                attributes: vec![
+                    // Point position
+                    VertexAttribute {
+                        format: VertexFormat::Float32x4,
+                        offset: 0,
+                        shader_location: 1,
+                    },
+                    // Point color
+                    VertexAttribute {
+                        format: VertexFormat::Float32x4,
+                        offset: VertexFormat::Float32x4.size(),
+                        shader_location: 2,
+                    },
+                    // Point normal
+                    VertexAttribute {
+                        format: VertexFormat::Float32x3,
+                        offset: VertexFormat::Float32x4.size() * 2,
+                        shader_location: 3,
+                    },
+                ],
+            }
         };
 
-        let mut shader_defs = vec!["ATTRIBUTE_PASS".into(), "WEIGHTED_SPLATS".into()];
+        let mut shader_defs = vec!["ATTRIBUTE_PASS".into()];
+        // the clip volumes are bound with the material
+        shader_defs.extend(clip_volumes_shader_defs(2, 1));
+
+        if key.splatting == PointSplatting::Weighted {
+            shader_defs.push("WEIGHTED_SPLATS".into());
+        }
+        match key.shape {
+            PointShape::Square => shader_defs.push("SQUARE_POINT_SHAPE".into()),
+            PointShape::Circle => {}
+            // The paraboloid offset is made for points facing the camera
+            PointShape::Paraboloid if key.geometry == PointGeometry::ScreenAligned => {
+                shader_defs.push("PARABOLOID_POINT_SHAPE".into())
+            }
+            PointShape::Paraboloid => {}
+        }
+        if key.geometry == PointGeometry::Surfel {
+            shader_defs.push("SURFEL_GEOMETRY".into());
+        }
+
+        if key.lighting == PointLighting::Lit {
+            shader_defs.push("LIT_POINTS".into());
+        }
+        if key.receive_shadows {
+            shader_defs.push("RECEIVE_SHADOWS".into());
+            let shadow_filter_method = key
+                .mesh_key
+                .intersection(MeshPipelineKey::SHADOW_FILTER_METHOD_RESERVED_BITS);
+            if shadow_filter_method == MeshPipelineKey::SHADOW_FILTER_METHOD_HARDWARE_2X2 {
+                shader_defs.push("SHADOW_FILTER_METHOD_HARDWARE_2X2".into());
+            } else if shadow_filter_method == MeshPipelineKey::SHADOW_FILTER_METHOD_GAUSSIAN {
+                shader_defs.push("SHADOW_FILTER_METHOD_GAUSSIAN".into());
+            } else if shadow_filter_method == MeshPipelineKey::SHADOW_FILTER_METHOD_TEMPORAL {
+                shader_defs.push("SHADOW_FILTER_METHOD_TEMPORAL".into());
+            }
+            #[cfg(all(feature = "webgl", target_arch = "wasm32"))]
+            shader_defs.push("WEBGL2".into());
+        }
 
         if key.is_octree {
             shader_defs.push("IS_OCTREE".into());
+            #[cfg(feature = "webgl")]
+            shader_defs.push("VISIBLE_NODES_TEXTURE".into());
+            if let Some(shader_def) = key.point_format.shader_def() {
+                shader_defs.push("QUANTIZED_POSITIONS".into());
+                shader_defs.push(shader_def.into());
+            }
         }
 
         let mut layout = vec![
@@ -163,6 +223,13 @@ impl SpecializedRenderPipeline for AttributePassPipeline {
             layout.push(self.point_cloud_octree_data_layout.clone());
         }
 
+        let mut buffers = vec![vertex_buffer_layout, instance_buffer_layout];
+        if key.is_octree
+            && let Some(node_bounds_buffer_layout) = key.point_format.node_bounds_buffer_layout()
+        {
+            buffers.push(node_bounds_buffer_layout);
+        }
+
         RenderPipelineDescriptor {
             label: Some("pcl_attribute_pass_pipeline".into()),
             // We want to reuse the data from bevy so we use the same bind groups as the default
@@ -173,7 +240,7 @@ impl SpecializedRenderPipeline for AttributePassPipeline {
                 shader: self.shader_handle.clone(),
                 shader_defs: shader_defs.clone(),
                 entry_point: Some("vertex".into()),
-                buffers: vec![vertex_buffer_layout, instance_buffer_layout],
+                buffers,
             },
             fragment: Some(FragmentState {
                 shader: self.shader_handle.clone(),
@@ -0,0 +1,153 @@
+use bevy_color::{ColorToComponents, Srgba};
+use bevy_ecs::prelude::*;
+use bevy_math::{Mat4, Vec4};
+use bevy_render::{
+    render_resource::{ShaderType, UniformBuffer},
+    renderer::{RenderDevice, RenderQueue},
+};
+use bevy_shader::ShaderDefVal;
+
+use crate::clip_volume::{
+    ClipMode, ClipShape, ClipVolumes, GlobalClipVolume, MAX_CLIP_POLYGON_VERTICES, MAX_CLIP_VOLUMES,
+};
+
+/// The GPU representation of a [`GlobalClipVolume`].
+#[derive(ShaderType, Debug, Clone, Copy, Default)]
+pub struct ClipVolumeUniform {
+    pub volume_from_world: Mat4,
+    /// half size of a box, radius of a sphere, normal of a half-space or heights of a polygon
+    pub params: Vec4,
+    /// sRGB highlight color, like the colors of the points
+    pub highlight_color: Vec4,
+    /// 0: box, 1: sphere, 2: half-space, 3: polygon
+    pub shape: u32,
+    /// 0: inside, 1: outside, 2: highlight
+    pub mode: u32,
+    /// index of the first vertex of a polygon in [`ClipVolumesUniform::polygon_vertices`]
+    pub first_vertex: u32,
+    pub vertex_count: u32,
+}
+
+/// The GPU representation of the [`ClipVolumes`].
+#[derive(ShaderType, Debug, Clone, Copy)]
+pub struct ClipVolumesUniform {
+    pub volumes: [ClipVolumeUniform; MAX_CLIP_VOLUMES],
+    /// vertices of the polygons, two per element
+    pub polygon_vertices: [Vec4; MAX_CLIP_POLYGON_VERTICES / 2],
+    pub count: u32,
+}
+
+impl Default for ClipVolumesUniform {
+    fn default() -> Self {
+        Self {
+            volumes: Default::default(),
+            polygon_vertices: [Vec4::ZERO; MAX_CLIP_POLYGON_VERTICES / 2],
+            count: 0,
+        }
+    }
+}
+
+impl ClipVolumesUniform {
+    /// Appends a volume, which is ignored if there is no room left for it.
+    fn push(&mut self, volume: &GlobalClipVolume, polygon_vertex_count: &mut usize) {
+        let Some(uniform) = self.volumes.get_mut(self.count as usize) else {
+            return;
+        };
+
+        let mut first_vertex = 0;
+        let mut vertex_count = 0;
+        let (shape, params) = match &volume.shape {
+            ClipShape::Box { half_size } => (0, half_size.extend(0.0)),
+            ClipShape::Sphere { radius } => (1, Vec4::new(*radius, 0.0, 0.0, 0.0)),
+            ClipShape::HalfSpace { normal } => (2, normal.extend(0.0)),
+            ClipShape::Polygon {
+                vertices,
+                min_height,
+                max_height,
+            } => {
+                if *polygon_vertex_count + vertices.len() > MAX_CLIP_POLYGON_VERTICES {
+                    return;
+                }
+                first_vertex = *polygon_vertex_count as u32;
+                vertex_count = vertices.len() as u32;
+                for vertex in vertices {
+                    let packed = &mut self.polygon_vertices[*polygon_vertex_count / 2];
+                    if polygon_vertex_count.is_multiple_of(2) {
+                        packed.x = vertex.x;
+                        packed.y = vertex.y;
+                    } else {
+                        packed.z = vertex.x;
+                        packed.w = vertex.y;
+                    }
+                    *polygon_vertex_count += 1;
+                }
+                (3, Vec4::new(*min_height, *max_height, 0.0, 0.0))
+            }
+        };
+
+        *uniform = ClipVolumeUniform {
+            volume_from_world: volume.volume_from_world.into(),
+            params,
+            highlight_color: Vec4::from_array(Srgba::from(volume.highlight_color).to_f32_array()),
+            shape,
+            mode: match volume.mode {
+                ClipMode::Inside => 0,
+                ClipMode::Outside => 1,
+                ClipMode::Highlight => 2,
+            },
+            first_vertex,
+            vertex_count,
+        };
+        self.count += 1;
+    }
+}
+
+/// Shader defs placing the clip volumes binding of the `bevy_pointcloud::clip_volume` shader
+/// module.
+pub fn clip_volumes_shader_defs(bind_group: u32, binding: u32) -> [ShaderDefVal; 2] {
+    [
+        ShaderDefVal::UInt("CLIP_VOLUMES_BIND_GROUP".into(), bind_group),
+        ShaderDefVal::UInt("CLIP_VOLUMES_BINDING".into(), binding),
+    ]
+}
+
+/// The clip volumes bound by all the point cloud passes.
+///
+/// The buffer is only written to, so that the bind groups using it stay valid.
+#[derive(Resource)]
+pub struct RenderClipVolumes {
+    pub buffer: UniformBuffer<ClipVolumesUniform>,
+}
+
+impl FromWorld for RenderClipVolumes {
+    fn from_world(world: &mut World) -> Self {
+        let render_device = world.resource::<RenderDevice>();
+        let render_queue = world.resource::<RenderQueue>();
+
+        let mut buffer = UniformBuffer::<ClipVolumesUniform>::default();
+        buffer.set_label(Some("pcl_clip_volumes"));
+        buffer.write_buffer(render_device, render_queue);
+
+        Self { buffer }
+    }
+}
+
+pub fn prepare_clip_volumes(
+    clip_volumes: Option<Res<ClipVolumes>>,
+    mut render_clip_volumes: ResMut<RenderClipVolumes>,
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+) {
+    let mut uniform = ClipVolumesUniform::default();
+    if let Some(clip_volumes) = clip_volumes {
+        let mut polygon_vertex_count = 0;
+        for volume in &clip_volumes.volumes {
+            uniform.push(volume, &mut polygon_vertex_count);
+        }
+    }
+
+    render_clip_volumes.buffer.set(uniform);
+    render_clip_volumes
+        .buffer
+        .write_buffer(&render_device, &render_queue);
+}
@@ -0,0 +1,110 @@
+#define_import_path bevy_pointcloud::clip_volume
+
+// Must match the MAX_CLIP_VOLUMES and MAX_CLIP_POLYGON_VERTICES constants
+const MAX_CLIP_VOLUMES: u32 = 8u;
+const MAX_CLIP_POLYGON_VERTICES: u32 = 512u;
+
+const CLIP_SHAPE_BOX: u32 = 0u;
+const CLIP_SHAPE_SPHERE: u32 = 1u;
+const CLIP_SHAPE_HALF_SPACE: u32 = 2u;
+const CLIP_SHAPE_POLYGON: u32 = 3u;
+
+const CLIP_MODE_INSIDE: u32 = 0u;
+const CLIP_MODE_OUTSIDE: u32 = 1u;
+const CLIP_MODE_HIGHLIGHT: u32 = 2u;
+
+struct ClipVolume {
+    volume_from_world: mat4x4<f32>,
+    // half size of a box, radius of a sphere, normal of a half-space or min and max heights of
+    // a polygon
+    params: vec4<f32>,
+    // sRGB, blended by its alpha
+    highlight_color: vec4<f32>,
+    shape: u32,
+    mode: u32,
+    first_vertex: u32,
+    vertex_count: u32,
+};
+
+struct ClipVolumes {
+    volumes: array<ClipVolume, MAX_CLIP_VOLUMES>,
+    // two vertices per element
+    polygon_vertices: array<vec4<f32>, MAX_CLIP_POLYGON_VERTICES / 2u>,
+    count: u32,
+};
+
+// The binding depends on the pipeline importing this module
+@group(#{CLIP_VOLUMES_BIND_GROUP}) @binding(#{CLIP_VOLUMES_BINDING})
+var<uniform> clip_volumes: ClipVolumes;
+
+// The clip volumes containing a point
+struct ClipState {
+    has_inside_volume: bool,
+    is_inside: bool,
+    is_outside: bool,
+    // zero alpha when not highlighted
+    highlight_color: vec4<f32>,
+};
+
+fn clip_polygon_vertex(index: u32) -> vec2<f32> {
+    let vertices = clip_volumes.polygon_vertices[index >> 1u];
+    return select(vertices.xy, vertices.zw, (index & 1u) == 1u);
+}
+
+// Whether a position of the XZ plane is inside a polygon, with the even-odd rule
+fn clip_polygon_contains(volume: ClipVolume, point: vec2<f32>) -> bool {
+    if volume.vertex_count == 0u {
+        return false;
+    }
+    var inside = false;
+    var previous = clip_polygon_vertex(volume.first_vertex + volume.vertex_count - 1u);
+    for (var i = 0u; i < volume.vertex_count; i++) {
+        let vertex = clip_polygon_vertex(volume.first_vertex + i);
+        if (vertex.y > point.y) != (previous.y > point.y)
+            && point.x < vertex.x + (point.y - vertex.y) * (previous.x - vertex.x) / (previous.y - vertex.y) {
+            inside = !inside;
+        }
+        previous = vertex;
+    }
+    return inside;
+}
+
+fn clip_volume_contains(volume: ClipVolume, world_position: vec3<f32>) -> bool {
+    let position = (volume.volume_from_world * vec4<f32>(world_position, 1.0)).xyz;
+    if volume.shape == CLIP_SHAPE_BOX {
+        return all(abs(position) <= volume.params.xyz);
+    } else if volume.shape == CLIP_SHAPE_SPHERE {
+        return dot(position, position) <= volume.params.x * volume.params.x;
+    } else if volume.shape == CLIP_SHAPE_HALF_SPACE {
+        return dot(position, volume.params.xyz) <= 0.0;
+    }
+    return position.y >= volume.params.x && position.y <= volume.params.y
+        && clip_polygon_contains(volume, position.xz);
+}
+
+fn clip_point(world_position: vec3<f32>) -> ClipState {
+    var state = ClipState(false, false, false, vec4<f32>(0.0));
+    for (var i = 0u; i < clip_volumes.count; i++) {
+        let volume = clip_volumes.volumes[i];
+        let contains = clip_volume_contains(volume, world_position);
+        if volume.mode == CLIP_MODE_INSIDE {
+            state.has_inside_volume = true;
+            state.is_inside = state.is_inside || contains;
+        } else if volume.mode == CLIP_MODE_OUTSIDE {
+            state.is_outside = state.is_outside || contains;
+        } else if contains {
+            state.highlight_color = volume.highlight_color;
+        }
+    }
+    return state;
+}
+
+// Whether the point is discarded by the volumes
+fn clip_state_is_clipped(state: ClipState) -> bool {
+    return state.is_outside || (state.has_inside_volume && !state.is_inside);
+}
+
+// Blends the highlight color of the volumes with a sRGB color
+fn clip_state_highlight(state: ClipState, color: vec3<f32>) -> vec3<f32> {
+    return mix(color, state.highlight_color.rgb, state.highlight_color.a);
+}
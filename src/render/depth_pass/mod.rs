@@ -13,6 +13,7 @@ use bevy_platform::collections::HashSet;
 use bevy_render::{
     batching::gpu_preprocessing::{GpuPreprocessingMode, GpuPreprocessingSupport},
     prelude::*,
+    render_asset::RenderAssets,
     render_graph::{RenderGraphExt, ViewNodeRunner},
     render_phase::{
         AddRenderCommand, BinnedRenderPhaseType, DrawFunctions, InputUniformIndex, SetItemPipeline,
@@ -26,6 +27,8 @@ use phase::PointCloud3dDepthPhase;
 
 use crate::{
     point_cloud::PointCloud3d,
+    point_cloud_material::PointCloudMaterial3d,
+    pointcloud_octree::asset::data::PointFormat,
     render::{
         depth_pass::{
             node::{DepthPassLabel, DepthPassNode},
@@ -33,7 +36,7 @@ use crate::{
             texture::{prepare_depth_pass_textures, DepthPassLayout},
         },
         draw::DrawPointCloud,
-        material::SetPointCloudMaterialGroup,
+        material::{RenderPointCloudMaterial, SetPointCloudMaterialGroup},
         phase::{PointCloud3dBatchSetKey, PointCloud3dBinKey},
         point_cloud_uniform::SetPointCloudUniformGroup,
         PointCloudRenderMode, PointCloudRenderModeOpt,
@@ -67,8 +70,8 @@ impl Plugin for DepthPassPlugin {
 
         render_app
             .add_render_graph_node::<ViewNodeRunner<DepthPassNode>>(Core3d, DepthPassLabel)
-            // Tell the node to run before the main transparent pass
-            .add_render_graph_edges(Core3d, (DepthPassLabel, Node3d::MainTransparentPass));
+            // Run after the opaque meshes so that the points are tested against their depth
+            .add_render_graph_edges(Core3d, (Node3d::MainOpaquePass, DepthPassLabel));
     }
 
     fn finish(&self, app: &mut App) {
@@ -138,7 +141,8 @@ fn queue_depth_pass(
     mut pipelines: ResMut<SpecializedRenderPipelines<DepthPipeline>>,
     pipeline_cache: Res<PipelineCache>,
     custom_draw_pipeline: Res<DepthPipeline>,
-    point_clouds_3d: Query<&PointCloud3d>,
+    point_clouds_3d: Query<(&PointCloud3d, &PointCloudMaterial3d)>,
+    render_materials: Res<RenderAssets<RenderPointCloudMaterial>>,
     mut custom_render_phases: ResMut<ViewBinnedRenderPhases<PointCloud3dDepthPhase>>,
     mut views: Query<(
         &ExtractedView,
@@ -159,20 +163,30 @@ fn queue_depth_pass(
         let view_key = MeshPipelineKey::from_msaa_samples(msaa.samples())
             | MeshPipelineKey::from_hdr(view.hdr);
 
-        let depth_key = DepthPipelineKey {
-            mesh_key: view_key,
-            use_edl: point_cloud_render_mode.use_edl(),
-            is_octree: false,
-        };
-
-        let pipeline_id = pipelines.specialize(&pipeline_cache, &custom_draw_pipeline, depth_key);
-
         // Since our phase can work on any 3d mesh we can reuse the default mesh 3d filter
         for (render_entity, main_entity) in visible_entities.iter::<PointCloud3d>() {
-            let Ok(point_cloud_3d) = point_clouds_3d.get(*render_entity) else {
+            let Ok((point_cloud_3d, point_cloud_material_3d)) = point_clouds_3d.get(*render_entity)
+            else {
                 warn!("point_cloud_3d missing");
                 continue;
             };
+            let Some(material) = render_materials.get(point_cloud_material_3d) else {
+                continue;
+            };
+
+            let depth_key = DepthPipelineKey {
+                mesh_key: view_key,
+                use_edl: point_cloud_render_mode.use_edl(),
+                is_octree: false,
+                shape: material.shape,
+                splatting: material.splatting,
+                geometry: material.geometry,
+                point_format: PointFormat::Full,
+                picking: false,
+            };
+
+            let pipeline_id =
+                pipelines.specialize(&pipeline_cache, &custom_draw_pipeline, depth_key);
 
             // Bump the change tick in order to force Bevy to rebuild the bin.
             let this_tick = next_tick.get() + 1;
@@ -5,23 +5,32 @@ use bevy_mesh::{PrimitiveTopology, VertexBufferLayout, VertexFormat};
 use bevy_pbr::{MeshPipeline, MeshPipelineKey, MeshPipelineViewLayoutKey};
 use bevy_render::{
     render_resource::{
-        binding_types::{texture_2d, uniform_buffer},
-        AsBindGroup, BindGroupLayoutDescriptor, BindGroupLayoutEntries, ColorTargetState,
-        ColorWrites, CompareFunction, DepthBiasState, DepthStencilState, Face, FragmentState,
-        FrontFace, MultisampleState, PolygonMode, PrimitiveState, RenderPipelineDescriptor,
-        ShaderStages, SpecializedRenderPipeline, StencilState, TextureFormat, TextureSampleType,
-        VertexAttribute, VertexState, VertexStepMode,
+        binding_types::uniform_buffer, AsBindGroup, BindGroupLayoutDescriptor,
+        BindGroupLayoutEntries, ColorTargetState, ColorWrites, CompareFunction, DepthBiasState,
+        DepthStencilState, Face, FragmentState, FrontFace, MultisampleState, PolygonMode,
+        PrimitiveState, RenderPipelineDescriptor, ShaderStages, SpecializedRenderPipeline,
+        StencilState, TextureFormat, VertexAttribute, VertexState, VertexStepMode,
     },
     renderer::RenderDevice,
 };
-use bevy_shader::Shader;
+use bevy_shader::{Shader, ShaderDefVal};
 use bevy_utils::default;
 
 use crate::{
-    point_cloud::PointCloudData,
-    point_cloud_material::PointCloudMaterial,
-    pointcloud_octree::extract::{PointCloudNodeDataUniform, PointCloudOctreeUniform},
-    render::{point_cloud_uniform::PointCloudUniform, POINTCLOUD_SHADER_HANDLE},
+    point_cloud_material::{PointGeometry, PointShape, PointSplatting},
+    pointcloud_octree::{
+        asset::data::PointFormat,
+        extract::{PointCloudNodeDataUniform, PointCloudOctreeUniform},
+        render::prepare::visible_nodes_layout_entries,
+    },
+    render::{
+        clip_volume::clip_volumes_shader_defs,
+        material::point_cloud_material_layout_entries,
+        picking_pass::{PickingDrawUniform, PickingViewUniform, PICKING_TEXTURE_FORMAT},
+        point_cloud::PointCloudInstance,
+        point_cloud_uniform::PointCloudUniform,
+        POINTCLOUD_SHADER_HANDLE,
+    },
 };
 
 #[derive(Resource)]
@@ -33,6 +42,7 @@ pub struct DepthPipeline {
     point_cloud_octree_visible_nodes_layout: BindGroupLayoutDescriptor,
     point_cloud_octree_node_data_layout: BindGroupLayoutDescriptor,
     point_cloud_octree_data_layout: BindGroupLayoutDescriptor,
+    pub picking_draw_layout: BindGroupLayoutDescriptor,
 }
 impl FromWorld for DepthPipeline {
     fn from_world(world: &mut World) -> Self {
@@ -45,19 +55,11 @@ impl FromWorld for DepthPipeline {
             point_cloud_layout: PointCloudUniform::bind_group_layout_descriptor(render_device),
             point_cloud_material_layout: BindGroupLayoutDescriptor {
                 label: "pcl_material".into(),
-                entries: BindGroupLayoutEntries::single(
-                    ShaderStages::VERTEX,
-                    uniform_buffer::<PointCloudMaterial>(false),
-                )
-                .to_vec(),
+                entries: point_cloud_material_layout_entries(),
             },
             point_cloud_octree_visible_nodes_layout: BindGroupLayoutDescriptor {
                 label: "pcl_octree_visible_nodes_layout".into(),
-                entries: BindGroupLayoutEntries::single(
-                    ShaderStages::VERTEX,
-                    texture_2d(TextureSampleType::Uint),
-                )
-                .to_vec(),
+                entries: visible_nodes_layout_entries(),
             },
             point_cloud_octree_node_data_layout: BindGroupLayoutDescriptor {
                 label: "pcl_octree_node_data".into(),
@@ -75,6 +77,17 @@ impl FromWorld for DepthPipeline {
                 )
                 .to_vec(),
             },
+            picking_draw_layout: BindGroupLayoutDescriptor {
+                label: "pcl_picking_draw_layout".into(),
+                entries: BindGroupLayoutEntries::sequential(
+                    ShaderStages::VERTEX,
+                    (
+                        uniform_buffer::<PickingDrawUniform>(true),
+                        uniform_buffer::<PickingViewUniform>(true),
+                    ),
+                )
+                .to_vec(),
+            },
         }
     }
 }
@@ -84,6 +97,13 @@ pub struct DepthPipelineKey {
     pub mesh_key: MeshPipelineKey,
     pub use_edl: bool,
     pub is_octree: bool,
+    pub shape: PointShape,
+    pub splatting: PointSplatting,
+    pub geometry: PointGeometry,
+    /// Layout of the octree points
+    pub point_format: PointFormat,
+    /// Write the picking ids of the points instead of their depth, without multisampling
+    pub picking: bool,
 }
 
 impl SpecializedRenderPipeline for DepthPipeline {
@@ -100,31 +120,72 @@ impl SpecializedRenderPipeline for DepthPipeline {
             }],
         };
 
-        let instance_buffer_layout = VertexBufferLayout {
-            array_stride: size_of::<PointCloudData>() as u64,
-            step_mode: VertexStepMode::Instance,
-            attributes: vec![
-                // Point position
-                VertexAttribute {
-                    format: VertexFormat::Float32x4,
-                    offset: 0,
-                    shader_location: 1,
-                },
-                // Point color
-                VertexAttribute {
-                    format: VertexFormat::Float32x4,
-                    offset: VertexFormat::Float32x4.size(),
-                    shader_location: 2,
-                },
-            ],
+        let instance_buffer_layout = if key.is_octree {
+            key.point_format.instance_buffer_layout()
+        } else {
+            VertexBufferLayout {
+                array_stride: size_of::<PointCloudInstance>() as u64,
+                step_mode: VertexStepMode::Instance,
+                attributes: vec![
+                    // Point position
+                    VertexAttribute {
+                        format: VertexFormat::Float32x4,
+                        offset: 0,
+                        shader_location: 1,
+                    },
+                    // Point color
+                    VertexAttribute {
+                        format: VertexFormat::Float32x4,
+                        offset: VertexFormat::Float32x4.size(),
+                        shader_location: 2,
+                    },
+                    // Point normal
+                    VertexAttribute {
+                        format: VertexFormat::Float32x3,
+                        offset: VertexFormat::Float32x4.size() * 2,
+                        shader_location: 3,
+                    },
+                ],
+            }
         };
-        let mut shader_defs = vec!["DEPTH_PASS".into(), "HQ_DEPTH_PASS".into()];
+        let mut shader_defs = vec!["DEPTH_PASS".into()];
+        // the clip volumes are bound with the material
+        shader_defs.extend(clip_volumes_shader_defs(2, 1));
+
+        // Push the depth back so that the points close to the nearest surface are blended
+        if key.splatting == PointSplatting::Weighted && !key.picking {
+            shader_defs.push("HQ_DEPTH_PASS".into());
+        }
+        match key.shape {
+            PointShape::Square => shader_defs.push("SQUARE_POINT_SHAPE".into()),
+            PointShape::Circle => {}
+            // The paraboloid offset is made for points facing the camera
+            PointShape::Paraboloid if key.geometry == PointGeometry::ScreenAligned => {
+                shader_defs.push("PARABOLOID_POINT_SHAPE".into())
+            }
+            PointShape::Paraboloid => {}
+        }
+        if key.geometry == PointGeometry::Surfel {
+            shader_defs.push("SURFEL_GEOMETRY".into());
+        }
 
         if key.use_edl {
             shader_defs.push("USE_EDL".into());
         }
+        if key.picking {
+            shader_defs.push("PICKING_PASS".into());
+            // the picking group follows the other groups
+            let bind_group = if key.is_octree { 6 } else { 3 };
+            shader_defs.push(ShaderDefVal::UInt("PICKING_BIND_GROUP".into(), bind_group));
+        }
         if key.is_octree {
             shader_defs.push("IS_OCTREE".into());
+            #[cfg(feature = "webgl")]
+            shader_defs.push("VISIBLE_NODES_TEXTURE".into());
+            if let Some(shader_def) = key.point_format.shader_def() {
+                shader_defs.push("QUANTIZED_POSITIONS".into());
+                shader_defs.push(shader_def.into());
+            }
         }
 
         let mut layout = vec![
@@ -144,6 +205,24 @@ impl SpecializedRenderPipeline for DepthPipeline {
             layout.push(self.point_cloud_octree_node_data_layout.clone());
             layout.push(self.point_cloud_octree_data_layout.clone());
         }
+        if key.picking {
+            layout.push(self.picking_draw_layout.clone());
+        }
+
+        let target_format = if key.picking {
+            PICKING_TEXTURE_FORMAT
+        } else if key.use_edl {
+            TextureFormat::Rg32Float
+        } else {
+            TextureFormat::R32Float
+        };
+
+        let mut buffers = vec![vertex_buffer_layout, instance_buffer_layout];
+        if key.is_octree
+            && let Some(node_bounds_buffer_layout) = key.point_format.node_bounds_buffer_layout()
+        {
+            buffers.push(node_bounds_buffer_layout);
+        }
 
         RenderPipelineDescriptor {
             label: Some("pcl_depth_pass_pipeline".into()),
@@ -155,7 +234,7 @@ impl SpecializedRenderPipeline for DepthPipeline {
                 shader: self.shader_handle.clone(),
                 shader_defs: shader_defs.clone(),
                 entry_point: Some("vertex".into()),
-                buffers: vec![vertex_buffer_layout, instance_buffer_layout],
+                buffers,
             },
             fragment: Some(FragmentState {
                 shader: self.shader_handle.clone(),
@@ -164,11 +243,7 @@ impl SpecializedRenderPipeline for DepthPipeline {
                 // The target will store a mask to discard outside pixels in normalize pass
                 // Because we can't bind the depth buffer in WASM/WebGL
                 targets: vec![Some(ColorTargetState {
-                    format: if key.use_edl {
-                        TextureFormat::Rg32Float
-                    } else {
-                        TextureFormat::R32Float
-                    },
+                    format: target_format,
                     blend: None,
                     write_mask: ColorWrites::ALL,
                 })],
@@ -189,7 +264,12 @@ impl SpecializedRenderPipeline for DepthPipeline {
                 bias: DepthBiasState::default(),
             }),
             multisample: MultisampleState {
-                count: key.mesh_key.msaa_samples(),
+                // the integer picking ids can't be resolved
+                count: if key.picking {
+                    1
+                } else {
+                    key.mesh_key.msaa_samples()
+                },
                 mask: !0,
                 alpha_to_coverage_enabled: false,
             },
@@ -1,7 +1,13 @@
-use bevy_ecs::system::{
-    lifetimeless::{Read, SRes},
-    SystemParamItem,
+use bevy_camera::primitives::Frustum;
+use bevy_ecs::{
+    query::Has,
+    system::{
+        lifetimeless::{Read, SRes},
+        SystemParamItem,
+    },
 };
+use bevy_math::Affine3A;
+use bevy_pbr::LightEntity;
 use bevy_render::{
     render_asset::RenderAssets,
     render_phase::{PhaseItem, RenderCommand, RenderCommandResult, TrackedRenderPass},
@@ -10,21 +16,23 @@ use bevy_render::{
 
 use crate::{
     point_cloud::PointCloud3d,
-    render::{mesh::PointCloudMesh, point_cloud::RenderPointCloud},
+    render::{
+        mesh::PointCloudMesh, point_cloud::RenderPointCloud, point_cloud_uniform::PointCloudUniform,
+    },
 };
 
 pub struct DrawPointCloud;
 
 impl<P: PhaseItem> RenderCommand<P> for DrawPointCloud {
     type Param = (SRes<PointCloudMesh>, SRes<RenderAssets<RenderPointCloud>>);
-    type ViewQuery = ();
-    type ItemQuery = Read<PointCloud3d>;
+    type ViewQuery = (Option<Read<Frustum>>, Has<LightEntity>);
+    type ItemQuery = (Read<PointCloud3d>, Read<PointCloudUniform>);
 
     #[inline]
     fn render<'w>(
         _item: &P,
-        _view: (),
-        point_cloud_3d: Option<&'w PointCloud3d>,
+        (frustum, is_light_view): (Option<&'w Frustum>, bool),
+        item: Option<(&'w PointCloud3d, &'w PointCloudUniform)>,
         (point_cloud_mesh, render_point_clouds): SystemParamItem<'w, '_, Self::Param>,
         pass: &mut TrackedRenderPass<'w>,
     ) -> RenderCommandResult {
@@ -32,7 +40,7 @@ impl<P: PhaseItem> RenderCommand<P> for DrawPointCloud {
         let point_cloud_mesh = point_cloud_mesh.into_inner();
         let render_point_clouds = render_point_clouds.into_inner();
 
-        let Some(point_cloud_3d) = point_cloud_3d else {
+        let Some((point_cloud_3d, point_cloud_uniform)) = item else {
             return RenderCommandResult::Skip;
         };
         let Some(render_point_cloud) = render_point_clouds.get(point_cloud_3d) else {
@@ -42,13 +50,22 @@ impl<P: PhaseItem> RenderCommand<P> for DrawPointCloud {
         pass.set_vertex_buffer(0, point_cloud_mesh.vertex_buffer.slice(..));
         pass.set_index_buffer(point_cloud_mesh.index_buffer.slice(..), IndexFormat::Uint32);
 
-        pass.set_vertex_buffer(1, render_point_cloud.buffer.slice(..));
+        let world_from_local = Affine3A::from_mat4(point_cloud_uniform.world_from_local);
 
-        pass.draw_indexed(
-            0..point_cloud_mesh.index_count,
-            0,
-            0..render_point_cloud.length as u32,
-        );
+        for chunk in &render_point_cloud.chunks {
+            // cull chunks outside of the view frustum, the casters in front of the near plane of
+            // a light view still cast shadows
+            if let Some(frustum) = frustum
+                && let Some(aabb) = &chunk.aabb
+                && !frustum.intersects_obb(aabb, &world_from_local, !is_light_view, true)
+            {
+                continue;
+            }
+
+            pass.set_vertex_buffer(1, chunk.buffer.slice(..));
+
+            pass.draw_indexed(0..point_cloud_mesh.index_count, 0, 0..chunk.length as u32);
+        }
 
         RenderCommandResult::Success
     }
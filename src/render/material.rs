@@ -14,17 +14,68 @@ use bevy_render::{
     render_phase::{PhaseItem, RenderCommand, RenderCommandResult, TrackedRenderPass},
     render_resource::{
         binding_types::uniform_buffer, BindGroup, BindGroupEntries, BindGroupLayout,
-        BindGroupLayoutEntries, ShaderStages, UniformBuffer,
+        BindGroupLayoutEntries, BindGroupLayoutEntry, ShaderStages, ShaderType, UniformBuffer,
     },
     renderer::{RenderDevice, RenderQueue},
 };
+use bytemuck::{Pod, Zeroable};
 
-use crate::point_cloud_material::{PointCloudMaterial, PointCloudMaterial3d};
+use crate::{
+    point_cloud_material::{
+        PointCloudMaterial, PointCloudMaterial3d, PointGeometry, PointLighting, PointShape,
+        PointSizing, PointSplatting,
+    },
+    render::clip_volume::{ClipVolumesUniform, RenderClipVolumes},
+};
 
 /// The render world representation of a [`PointCloudMaterial`].
 pub struct RenderPointCloudMaterial {
     pub uniform: BindGroup,
-    pub uniform_buffer: UniformBuffer<PointCloudMaterial>,
+    pub uniform_buffer: UniformBuffer<PointCloudMaterialUniform>,
+    pub shape: PointShape,
+    pub splatting: PointSplatting,
+    pub cast_shadows: bool,
+    pub receive_shadows: bool,
+    pub lighting: PointLighting,
+    pub geometry: PointGeometry,
+}
+
+/// The GPU representation of a [`PointCloudMaterial`].
+#[derive(ShaderType, Debug, Clone, Default, Copy, Pod, Zeroable)]
+#[repr(C)]
+pub struct PointCloudMaterialUniform {
+    pub point_size: f32,
+    pub min_point_size: f32,
+    pub max_point_size: f32,
+    pub sizing: u32,
+}
+
+impl From<&PointCloudMaterial> for PointCloudMaterialUniform {
+    fn from(material: &PointCloudMaterial) -> Self {
+        Self {
+            point_size: material.point_size,
+            min_point_size: material.min_point_size,
+            max_point_size: material.max_point_size,
+            sizing: match material.sizing {
+                PointSizing::Viewport => 0,
+                PointSizing::FixedPixels => 1,
+                PointSizing::WorldSpace => 2,
+                PointSizing::Adaptive => 3,
+            },
+        }
+    }
+}
+
+/// Entries of the material bind group: the material and the clip volumes.
+pub fn point_cloud_material_layout_entries() -> Vec<BindGroupLayoutEntry> {
+    BindGroupLayoutEntries::sequential(
+        ShaderStages::VERTEX,
+        (
+            uniform_buffer::<PointCloudMaterialUniform>(false),
+            uniform_buffer::<ClipVolumesUniform>(false),
+        ),
+    )
+    .to_vec()
 }
 
 #[derive(Resource)]
@@ -35,13 +86,8 @@ pub struct RenderPointCloudMaterialLayout {
 impl FromWorld for RenderPointCloudMaterialLayout {
     fn from_world(world: &mut World) -> Self {
         let render_device = world.resource::<RenderDevice>();
-        let layout = render_device.create_bind_group_layout(
-            "pcl_material",
-            &BindGroupLayoutEntries::single(
-                ShaderStages::VERTEX,
-                uniform_buffer::<PointCloudMaterial>(false),
-            ),
-        );
+        let layout = render_device
+            .create_bind_group_layout("pcl_material", &point_cloud_material_layout_entries());
         RenderPointCloudMaterialLayout { layout }
     }
 }
@@ -52,28 +98,42 @@ impl RenderAsset for RenderPointCloudMaterial {
         SRes<RenderDevice>,
         SRes<RenderQueue>,
         SRes<RenderPointCloudMaterialLayout>,
+        SRes<RenderClipVolumes>,
     );
 
     fn prepare_asset(
         source_asset: Self::SourceAsset,
         _asset_id: AssetId<Self::SourceAsset>,
-        (render_device, render_queue, prepared_point_cloud_material_layout): &mut SystemParamItem<
-            Self::Param,
-        >,
+        (
+            render_device,
+            render_queue,
+            prepared_point_cloud_material_layout,
+            render_clip_volumes,
+        ): &mut SystemParamItem<Self::Param>,
         _: Option<&Self>,
     ) -> Result<Self, PrepareAssetError<Self::SourceAsset>> {
-        let mut uniform_buffer = UniformBuffer::from(source_asset);
+        let mut uniform_buffer =
+            UniformBuffer::from(PointCloudMaterialUniform::from(&source_asset));
         uniform_buffer.write_buffer(render_device, render_queue);
 
         let uniform = render_device.create_bind_group(
             "pcl_material",
             &prepared_point_cloud_material_layout.layout,
-            &BindGroupEntries::single(uniform_buffer.binding().unwrap()),
+            &BindGroupEntries::sequential((
+                uniform_buffer.binding().unwrap(),
+                render_clip_volumes.buffer.binding().unwrap(),
+            )),
         );
 
         Ok(RenderPointCloudMaterial {
             uniform,
             uniform_buffer,
+            shape: source_asset.shape,
+            splatting: source_asset.splatting,
+            cast_shadows: source_asset.cast_shadows,
+            receive_shadows: source_asset.receive_shadows,
+            lighting: source_asset.lighting,
+            geometry: source_asset.geometry,
         })
     }
 }
@@ -1,5 +1,6 @@
 mod aabb;
 pub mod attribute_pass;
+pub mod clip_volume;
 pub mod depth_pass;
 mod draw;
 mod extract;
@@ -8,13 +9,15 @@ pub mod material;
 pub mod mesh;
 pub mod normalize_pass;
 pub mod phase;
+pub mod picking_pass;
 pub mod point_cloud;
 pub mod point_cloud_uniform;
+pub mod shadow_pass;
 
 use aabb::compute_point_cloud_aabb;
 use attribute_pass::AttributePassPlugin;
 use bevy_app::prelude::*;
-use bevy_asset::{load_internal_asset, prelude::*, uuid_handle};
+use bevy_asset::{load_internal_asset, prelude::*, uuid_handle, AssetEventSystems};
 use bevy_camera::visibility::calculate_bounds;
 use bevy_ecs::prelude::*;
 use bevy_render::{
@@ -25,9 +28,11 @@ use bevy_render::{
     Render, RenderApp, RenderSystems,
 };
 use bevy_shader::Shader;
+use clip_volume::{prepare_clip_volumes, RenderClipVolumes};
 use depth_pass::DepthPassPlugin;
 use normalize_pass::NormalizePassPlugin;
 use point_cloud_uniform::{prepare_point_cloud_uniform, PointCloudUniformLayout};
+use shadow_pass::ShadowPassPlugin;
 
 use crate::{
     point_cloud::PointCloud3d,
@@ -35,7 +40,7 @@ use crate::{
         eye_dome_lighting::{extract_cameras_render_mode, EyeDomeLightingUniform, NeighboursCache},
         material::{RenderPointCloudMaterial, RenderPointCloudMaterialLayout},
         mesh::PointCloudMesh,
-        point_cloud::RenderPointCloud,
+        point_cloud::{clear_point_cloud_changes, RenderPointCloud},
     },
 };
 
@@ -45,6 +50,9 @@ const POINTCLOUD_SHADER_HANDLE: Handle<Shader> =
 const NORMALIZE_SHADER_HANDLE: Handle<Shader> =
     uuid_handle!("0e5fffec-7e0b-4b44-8c32-b92d9b99fd58");
 
+const CLIP_VOLUME_SHADER_HANDLE: Handle<Shader> =
+    uuid_handle!("7f3a9c21-5e84-4b0d-a6c2-d19e83f4b527");
+
 pub struct RenderPipelinePlugin;
 
 impl Plugin for RenderPipelinePlugin {
@@ -63,6 +71,13 @@ impl Plugin for RenderPipelinePlugin {
             Shader::from_wgsl
         );
 
+        load_internal_asset!(
+            app,
+            CLIP_VOLUME_SHADER_HANDLE,
+            "clip_volume.wgsl",
+            Shader::from_wgsl
+        );
+
         // Automatically create uniform from these settings
         app.add_plugins(RenderAssetPlugin::<RenderPointCloud>::default())
             .add_plugins(RenderAssetPlugin::<RenderPointCloudMaterial>::default())
@@ -71,12 +86,18 @@ impl Plugin for RenderPipelinePlugin {
             // compute point cloud aabb **before** [`bevy_render::view::calculate_bounds`] to prevent using mesh's aabb.
             .add_systems(
                 PostUpdate,
-                compute_point_cloud_aabb.before(calculate_bounds),
+                compute_point_cloud_aabb
+                    .after(AssetEventSystems)
+                    .before(calculate_bounds),
             )
+            .add_systems(First, clear_point_cloud_changes)
             .sub_app_mut(RenderApp)
             .add_systems(
                 Render,
-                prepare_point_cloud_uniform.in_set(RenderSystems::PrepareResources),
+                (
+                    prepare_point_cloud_uniform.in_set(RenderSystems::PrepareResources),
+                    prepare_clip_volumes.in_set(RenderSystems::PrepareResources),
+                ),
             );
 
         let render_app = app.sub_app_mut(RenderApp);
@@ -87,11 +108,17 @@ impl Plugin for RenderPipelinePlugin {
                 extract_cameras_render_mode.after(extract_cameras),
             );
 
-        app.add_plugins((DepthPassPlugin, AttributePassPlugin, NormalizePassPlugin));
+        app.add_plugins((
+            DepthPassPlugin,
+            AttributePassPlugin,
+            NormalizePassPlugin,
+            ShadowPassPlugin,
+        ));
     }
 
     fn finish(&self, app: &mut App) {
         app.sub_app_mut(RenderApp)
+            .init_resource::<RenderClipVolumes>()
             .init_resource::<RenderPointCloudMaterialLayout>()
             .init_resource::<PointCloudUniformLayout>()
             .init_resource::<PointCloudMesh>();
@@ -104,6 +131,22 @@ pub struct PointCloudRenderMode {
     pub edl_neighbour_count: u32,
     pub edl_strength: f32,
     pub edl_radius: f32,
+    /// How the point cloud octrees are rasterized by this camera
+    pub rasterization: PointCloudRasterization,
+}
+
+/// The rasterization path used to draw point cloud octrees.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum PointCloudRasterization {
+    /// Points are drawn as instanced quads, honouring the material size, shape and splatting.
+    #[default]
+    Hardware,
+    /// Points are rasterized by a compute shader with 64 bits atomics, one pixel per point.
+    ///
+    /// This is much faster for very dense clouds, but requires the `SHADER_INT64` and
+    /// `SHADER_INT64_ATOMIC_MIN_MAX` features and is not available with WebGL.
+    /// Falls back to [`PointCloudRasterization::Hardware`] when they are missing.
+    Compute,
 }
 pub trait PointCloudRenderModeOpt {
     fn use_edl(&self) -> bool;
@@ -117,6 +160,7 @@ impl Default for PointCloudRenderMode {
             edl_neighbour_count: 4,
             edl_strength: 0.4,
             edl_radius: 1.4,
+            rasterization: PointCloudRasterization::default(),
         }
     }
 }
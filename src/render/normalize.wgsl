@@ -39,6 +39,9 @@
 
 struct FragmentOutput {
     @location(0) color: vec4<f32>,
+    // the depth of the points, tested against and written to the view depth so that the meshes
+    // rendered afterwards are occluded by the points
+    @builtin(frag_depth) depth: f32,
 }
 
 
@@ -120,6 +123,7 @@ fn fragment(in: FullscreenVertexOutput) -> FragmentOutput {
     let stored_color = textureLoad(attribute_texture, vec2<i32>(in.position.xy), 0);
 
     var output: FragmentOutput;
+    output.depth = depth;
     output.color = stored_color / stored_color.a;
 
 #ifdef USE_EDL
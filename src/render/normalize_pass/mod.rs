@@ -52,11 +52,12 @@ impl Plugin for NormalizePassPlugin {
             )
             .add_render_graph_edges(
                 Core3d,
-                // Specify the node ordering.
+                // Composite the points before the transmissive and transparent meshes, which are
+                // then tested against their depth.
                 (
                     AttributePassLabel,
                     NormalizePassLabel,
-                    Node3d::MainTransparentPass,
+                    Node3d::MainTransmissivePass,
                 ),
             );
     }
@@ -1,3 +1,4 @@
+use bevy_core_pipeline::prepass::ViewPrepassTextures;
 use bevy_ecs::{prelude::*, query::QueryItem};
 use bevy_render::{
     render_graph::{NodeRunError, RenderGraphContext, RenderLabel, ViewNode},
@@ -25,15 +26,21 @@ impl ViewNode for NormalizePassNode {
         &'static NormalizePassBindGroup,
         Option<&'static NormalizePassEdlBindgroup>,
         &'static NormalizePassPipelineId,
+        Option<&'static ViewPrepassTextures>,
     );
 
     fn run(
         &self,
         _graph: &mut RenderGraphContext,
         render_context: &mut RenderContext,
-        (view_target, depth, textures_bind_group, edl_bind_group, pipeline_id): QueryItem<
-            Self::ViewQuery,
-        >,
+        (
+            view_target,
+            depth,
+            textures_bind_group,
+            edl_bind_group,
+            pipeline_id,
+            prepass_textures,
+        ): QueryItem<Self::ViewQuery>,
         world: &World,
     ) -> Result<(), NodeRunError> {
         let pipeline_cache = world.resource::<PipelineCache>();
@@ -59,6 +66,18 @@ impl ViewNode for NormalizePassNode {
             render_pass.set_bind_group(1, &edl_bind_group.value, &[]);
         }
         render_pass.draw(0..3, 0..1);
+        drop(render_pass);
+
+        // Update the depth prepass texture, so that the effects reading it see the points
+        if let Some(prepass_textures) = prepass_textures
+            && let Some(prepass_depth_texture) = &prepass_textures.depth
+        {
+            render_context.command_encoder().copy_texture_to_texture(
+                depth.texture.as_image_copy(),
+                prepass_depth_texture.texture.texture.as_image_copy(),
+                prepass_textures.size,
+            );
+        }
 
         Ok(())
     }
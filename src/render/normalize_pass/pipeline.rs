@@ -114,9 +114,10 @@ impl SpecializedRenderPipeline for NormalizePassPipeline {
             primitive: PrimitiveState::default(),
             depth_stencil: Some(DepthStencilState {
                 format: TextureFormat::Depth32Float,
-                // Do not write the depth, because it has already been written in the depth pass, and we don't have access to it
-                depth_write_enabled: false,
-                depth_compare: CompareFunction::Always,
+                // The depth pass may have pushed the points back, write their actual depth where they
+                // are in front of the meshes
+                depth_write_enabled: true,
+                depth_compare: CompareFunction::GreaterEqual,
                 stencil: Default::default(),
                 bias: Default::default(),
             }),
@@ -0,0 +1,1112 @@
+//! GPU picking of the drawn points.
+//!
+//! The point clouds and octrees seen by a camera with [`PointCloudGpuPicking`] are drawn again
+//! with the depth pass pipeline, around the picked position only, into an integer texture storing
+//! the draw and the instance of each point. The texture only covers the picked region, the
+//! clip positions being scaled and offset so that the region is drawn at its origin. The pixels are copied to a
+//! staging buffer and read back asynchronously, and the point drawn closest to the position is sent as a
+//! [`PointCloudGpuPick`] message a few frames later.
+//!
+//! The picks are consistent with the level of detail, the clip volumes and the point shapes
+//! drawn by the camera. The meshes are not drawn into the picking texture and don't hide the
+//! points.
+pub mod node;
+
+use std::{
+    ops::Range,
+    sync::{
+        atomic::{AtomicU8, Ordering},
+        Arc,
+    },
+};
+
+use bevy_app::prelude::*;
+use bevy_asset::Assets;
+use bevy_camera::{Camera, Camera3d};
+use bevy_core_pipeline::core_3d::{
+    graph::{Core3d, Node3d},
+    CORE_3D_DEPTH_FORMAT,
+};
+use bevy_ecs::{
+    prelude::*,
+    query::ROQueryItem,
+    system::{lifetimeless::*, SystemParamItem},
+};
+use bevy_log::prelude::*;
+use bevy_math::{prelude::*, URect, Vec3A};
+use bevy_pbr::{MeshPipelineKey, SetMeshViewBindGroup};
+use bevy_platform::collections::HashSet;
+use bevy_render::{
+    camera::extract_cameras,
+    prelude::*,
+    render_asset::RenderAssets,
+    render_graph::{RenderGraphExt, ViewNodeRunner},
+    render_phase::{
+        AddRenderCommand, DrawFunctions, PhaseItem, RenderCommand, RenderCommandResult,
+        SetItemPipeline, TrackedRenderPass,
+    },
+    render_resource::{
+        BindGroup, BindGroupEntries, Buffer, BufferDescriptor, BufferUsages, DynamicUniformBuffer,
+        Extent3d, IndexFormat, MapMode, PipelineCache, ShaderType, SpecializedRenderPipelines,
+        TextureDescriptor, TextureDimension, TextureFormat, TextureUsages,
+    },
+    renderer::{render_system, RenderDevice, RenderQueue},
+    sync_world::{MainEntity, RenderEntity},
+    texture::{CachedTexture, TextureCache},
+    view::{ExtractedView, RenderVisibleEntities, RetainedViewEntity},
+    Extract, ExtractSchedule, Render, RenderApp, RenderSystems,
+};
+use bevy_transform::prelude::*;
+use crossbeam::channel::{Receiver, Sender};
+use node::{PickingPassLabel, PickingPassNode};
+
+use crate::{
+    octree::{
+        extract::{
+            render::{components::RenderVisibleOctreeNodes, resources::RenderOctrees},
+            resources::OctreeBufferSettings,
+        },
+        storage::NodeId,
+    },
+    point_cloud::{PointCloud, PointCloud3d},
+    point_cloud_material::PointCloudMaterial3d,
+    pointcloud_octree::{
+        asset::{
+            data::{PointCloudNodeData, PointData, PointFormat},
+            extract::PointCloudOctreeExtraction,
+            PointCloudOctree,
+        },
+        component::PointCloudOctree3d,
+        extract::RenderPointCloudNodeData,
+        render::{
+            data::SetPointCloudOctree3dUniformGroup,
+            draw::{
+                DrawPointCloudOctree, SetPointCloudOctreeNodeUniformGroup,
+                SetRenderOctreeUniformGroup,
+            },
+            phase::{
+                PointCloudOctree3dBinKey, PointCloudOctree3dNodePhase, ViewOctreeNodesRenderPhases,
+            },
+            prepare::SetVisibleNodes,
+        },
+    },
+    render::{
+        depth_pass::{
+            phase::PointCloud3dDepthPhase,
+            pipeline::{DepthPipeline, DepthPipelineKey},
+        },
+        material::{RenderPointCloudMaterial, SetPointCloudMaterialGroup},
+        mesh::PointCloudMesh,
+        phase::{PointCloud3dBatchSetKey, PointCloud3dBinKey},
+        point_cloud::RenderPointCloud,
+        point_cloud_uniform::SetPointCloudUniformGroup,
+    },
+};
+
+/// Format of the picking texture, storing the picking id of the draw plus one, zero where no
+/// point is drawn, and the instance index of the point.
+pub const PICKING_TEXTURE_FORMAT: TextureFormat = TextureFormat::Rg32Uint;
+/// Number of picking readbacks a view can have in flight.
+const MAX_PICKING_READBACKS: usize = 3;
+
+const READBACK_FREE: u8 = 0;
+/// waiting for the node to copy the picking texture
+const READBACK_PREPARED: u8 = 1;
+/// copied, waiting to be mapped after the submission
+const READBACK_COPIED: u8 = 2;
+const READBACK_MAPPING: u8 = 3;
+
+/// Picks the point drawn by a camera at a position of its viewport, on the GPU.
+///
+/// The result is sent as a [`PointCloudGpuPick`] message. Requires the
+/// [`PointCloudGpuPickingPlugin`].
+#[derive(Component, Clone, Debug)]
+pub struct PointCloudGpuPicking {
+    /// Position in the viewport, in logical pixels, `None` to disable the picking pass
+    pub position: Option<Vec2>,
+    /// Radius in physical pixels of the square around the position where points are picked,
+    /// the closest one to the position being kept
+    pub radius: u32,
+}
+
+impl Default for PointCloudGpuPicking {
+    fn default() -> Self {
+        Self {
+            position: None,
+            radius: 2,
+        }
+    }
+}
+
+/// A point drawn by a camera with [`PointCloudGpuPicking`].
+#[derive(Debug, Clone)]
+pub struct PointCloudGpuHit {
+    /// The [`PointCloud3d`] or [`PointCloudOctree3d`] entity
+    pub entity: Entity,
+    /// The octree node of the point, `None` for a [`PointCloud3d`]
+    pub node_id: Option<NodeId>,
+    /// Index of the point in its point cloud or octree node
+    pub point_index: usize,
+    /// Position of the point in world space
+    pub position: Vec3,
+    /// Unit normal of the point in world space, if known
+    pub normal: Option<Vec3>,
+    /// Attributes of the point, in the local space of the entity
+    pub point: PointData,
+}
+
+/// The result of a GPU pick, sent once the picking texture has been read back.
+#[derive(Message, Debug, Clone)]
+pub struct PointCloudGpuPick {
+    pub camera: Entity,
+    /// The picked position of [`PointCloudGpuPicking`]
+    pub position: Vec2,
+    /// The picked point, `None` if there is no point around the position or if its octree node
+    /// has been unloaded in the meantime
+    pub hit: Option<PointCloudGpuHit>,
+}
+
+/// Adds the picking pass to the cameras with [`PointCloudGpuPicking`].
+pub struct PointCloudGpuPickingPlugin;
+
+impl Plugin for PointCloudGpuPickingPlugin {
+    fn build(&self, app: &mut App) {
+        let (sender, receiver) = crossbeam::channel::unbounded();
+
+        app.add_message::<PointCloudGpuPick>()
+            .insert_resource(GpuPickingReceiver(receiver))
+            .add_systems(PreUpdate, receive_gpu_picks);
+
+        let Some(render_app) = app.get_sub_app_mut(RenderApp) else {
+            return;
+        };
+
+        render_app
+            .insert_resource(GpuPickingSender(sender))
+            .init_resource::<DrawFunctions<PointCloud3dDepthPhase>>()
+            .init_resource::<DrawFunctions<PointCloudOctree3dNodePhase>>()
+            .init_resource::<SpecializedRenderPipelines<DepthPipeline>>()
+            .init_resource::<ViewPickingPhases>()
+            .init_resource::<PickingDraws>()
+            .add_render_command::<PointCloud3dDepthPhase, DrawPickingPass>()
+            .add_render_command::<PointCloudOctree3dNodePhase, DrawOctreePickingPass>()
+            .add_systems(
+                ExtractSchedule,
+                extract_picking_views.after(extract_cameras),
+            )
+            .add_systems(
+                Render,
+                (
+                    prepare_picking_draws.in_set(RenderSystems::PrepareResources),
+                    prepare_picking_buffers.in_set(RenderSystems::PrepareResources),
+                    queue_picking_pass.in_set(RenderSystems::QueueMeshes),
+                    map_picking_readbacks
+                        .after(render_system)
+                        .in_set(RenderSystems::Render),
+                ),
+            )
+            .add_render_graph_node::<ViewNodeRunner<PickingPassNode>>(Core3d, PickingPassLabel)
+            .add_render_graph_edges(Core3d, (Node3d::MainOpaquePass, PickingPassLabel));
+    }
+}
+
+type DrawPickingPass = (
+    SetItemPipeline,
+    SetMeshViewBindGroup<0>,
+    SetPointCloudUniformGroup<1>,
+    SetPointCloudMaterialGroup<2>,
+    DrawPointCloudPicking<3>,
+);
+
+// the nodes are drawn directly, the indirect draws being culled for the whole view
+type DrawOctreePickingPass = (
+    SetItemPipeline,
+    SetMeshViewBindGroup<0>,
+    SetPointCloudOctree3dUniformGroup<1>,
+    SetPointCloudMaterialGroup<2>,
+    SetVisibleNodes<3>,
+    SetPointCloudOctreeNodeUniformGroup<4>,
+    SetRenderOctreeUniformGroup<5>,
+    SetPickingDrawGroup<6>,
+    DrawPointCloudOctree,
+);
+
+/// Receives the picks read back by the render world, in the main world.
+#[derive(Resource)]
+pub struct GpuPickingReceiver(Receiver<GpuPickingReadbackResult>);
+
+/// Sends the picks read back to the main world.
+#[derive(Resource)]
+pub struct GpuPickingSender(Sender<GpuPickingReadbackResult>);
+
+/// A pick resolved by the render world, before the point is fetched from its asset.
+pub struct GpuPickingReadbackResult {
+    camera: Entity,
+    position: Vec2,
+    /// entity, octree node and index of the point
+    target: Option<(Entity, Option<NodeId>, usize)>,
+}
+
+/// Fetch the picked points from their assets and send the [`PointCloudGpuPick`]s.
+fn receive_gpu_picks(
+    receiver: Res<GpuPickingReceiver>,
+    point_clouds: Res<Assets<PointCloud>>,
+    octrees: Option<Res<Assets<PointCloudOctree>>>,
+    point_cloud_entities: Query<(&PointCloud3d, &GlobalTransform)>,
+    octree_entities: Query<(&PointCloudOctree3d, &GlobalTransform)>,
+    mut picks: MessageWriter<PointCloudGpuPick>,
+) {
+    for GpuPickingReadbackResult {
+        camera,
+        position,
+        target,
+    } in receiver.0.try_iter()
+    {
+        let hit = target.and_then(|(entity, node_id, point_index)| {
+            let (point, normal, global_transform) = match node_id {
+                None => {
+                    let (point_cloud_3d, global_transform) =
+                        point_cloud_entities.get(entity).ok()?;
+                    let point_cloud = point_clouds.get(&point_cloud_3d.0)?;
+                    let point = point_cloud.points.get(point_index)?;
+                    (
+                        PointData::from(point),
+                        point_cloud.normal(point_index),
+                        global_transform,
+                    )
+                }
+                Some(node_id) => {
+                    let (octree_3d, global_transform) = octree_entities.get(entity).ok()?;
+                    let node = octrees.as_ref()?.get(&octree_3d.0)?.node(node_id)?;
+                    let data = node.data.as_ref()?;
+                    let points = data.points.decode(&node.hierarchy.bounding_box);
+                    (
+                        *points.get(point_index)?,
+                        data.normal(point_index),
+                        global_transform,
+                    )
+                }
+            };
+
+            let world_from_local = global_transform.affine();
+            Some(PointCloudGpuHit {
+                entity,
+                node_id,
+                point_index,
+                position: world_from_local.transform_point3(point.position.truncate()),
+                normal: (world_from_local.matrix3.inverse().transpose() * Vec3A::from(normal))
+                    .try_normalize()
+                    .map(Vec3::from),
+                point,
+            })
+        });
+
+        picks.write(PointCloudGpuPick {
+            camera,
+            position,
+            hit,
+        });
+    }
+}
+
+/// The picked region of a view, in physical pixels of its render target.
+#[derive(Component, Clone, Debug)]
+pub struct ExtractedGpuPicking {
+    pub position: Vec2,
+    /// pixel of the position
+    pub pixel: UVec2,
+    /// pixels drawn and read back around the position
+    pub region: URect,
+    /// size of the region without clamping to the viewport
+    pub max_size: u32,
+    /// viewport of the camera, in physical pixels of its render target
+    pub viewport: URect,
+}
+
+/// The picking phases of each view.
+#[derive(Resource, Default)]
+pub struct ViewPickingPhases {
+    pub point_clouds: ViewOctreeNodesRenderPhases<PointCloud3dDepthPhase>,
+    pub octrees: ViewOctreeNodesRenderPhases<PointCloudOctree3dNodePhase>,
+}
+
+#[allow(clippy::type_complexity)]
+fn extract_picking_views(
+    mut commands: Commands,
+    mut phases: ResMut<ViewPickingPhases>,
+    cameras: Extract<
+        Query<
+            (
+                Entity,
+                &RenderEntity,
+                &Camera,
+                Option<&PointCloudGpuPicking>,
+            ),
+            With<Camera3d>,
+        >,
+    >,
+    mut live_entities: Local<HashSet<RetainedViewEntity>>,
+) {
+    live_entities.clear();
+    for (main_entity, render_entity, camera, picking) in &cameras {
+        let picking = picking
+            .filter(|_| camera.is_active)
+            .and_then(|picking| Some((picking, picking.position?)));
+        let extracted = picking.and_then(|(picking, position)| {
+            let viewport = camera.physical_viewport_rect()?;
+            let scale_factor = camera.target_scaling_factor()?;
+            let pixel = viewport.min.as_vec2() + position * scale_factor;
+            if !viewport.as_rect().contains(pixel) {
+                return None;
+            }
+
+            let pixel = pixel.as_uvec2();
+            let region = URect::from_corners(
+                pixel.saturating_sub(UVec2::splat(picking.radius)),
+                pixel + picking.radius + 1,
+            )
+            .intersect(viewport);
+            Some(ExtractedGpuPicking {
+                position,
+                pixel,
+                region,
+                max_size: picking.radius * 2 + 1,
+                viewport,
+            })
+        });
+
+        let mut entity = commands.entity(render_entity.id());
+        let Some(extracted) = extracted else {
+            entity.remove::<ExtractedGpuPicking>();
+            continue;
+        };
+        entity.insert(extracted);
+
+        // This is the main camera, so we use the first subview index (0)
+        let retained_view_entity = RetainedViewEntity::new(main_entity.into(), None, 0);
+        phases
+            .point_clouds
+            .prepare_for_new_frame(retained_view_entity);
+        phases.octrees.prepare_for_new_frame(retained_view_entity);
+        live_entities.insert(retained_view_entity);
+    }
+
+    // Clear out all dead views.
+    phases
+        .point_clouds
+        .retain(|camera_entity, _| live_entities.contains(camera_entity));
+    phases
+        .octrees
+        .retain(|camera_entity, _| live_entities.contains(camera_entity));
+}
+
+/// The picking id of a draw.
+#[derive(ShaderType, Clone, Copy, Debug, Default)]
+pub struct PickingDrawUniform {
+    pub id: u32,
+    // WebGL2 structs must be 16 byte aligned.
+    #[cfg(all(feature = "webgl", target_arch = "wasm32", not(feature = "webgpu")))]
+    pub _webgl2_padding: Vec3,
+}
+
+/// The transform of the clip positions of a view, drawing its picked region at the origin of the
+/// picking texture.
+#[derive(ShaderType, Clone, Copy, Debug, Default)]
+pub struct PickingViewUniform {
+    /// scale of the clip XY coordinates, and their offset multiplied by W
+    pub clip_transform: Vec4,
+}
+
+impl PickingViewUniform {
+    /// Maps the NDC of a viewport to the NDC of a viewport covering only a region of its render
+    /// target, placed at the origin.
+    fn new(viewport: URect, region: URect) -> Self {
+        let viewport_size = viewport.size().as_vec2();
+        let region_size = region.size().as_vec2();
+        let scale = viewport_size / region_size;
+        // NDC of the viewport corner opposite to its origin, along X and Y
+        let offset = (2.0 * (viewport.min.as_vec2() - region.min.as_vec2()) + viewport_size)
+            / region_size
+            - 1.0;
+        Self {
+            clip_transform: Vec4::new(scale.x, scale.y, offset.x, -offset.y),
+        }
+    }
+}
+
+/// Dynamic offset of the [`PickingViewUniform`] of a view.
+#[derive(Component)]
+pub struct PickingViewOffset(u32);
+
+/// The entity drawn with a picking id.
+enum PickingTarget {
+    PointCloud {
+        entity: Entity,
+        /// index of the first point of the drawn chunk
+        first_point: usize,
+    },
+    Octree {
+        entity: Entity,
+        /// instances of the allocated nodes, sorted by first instance
+        nodes: Vec<(Range<u32>, NodeId)>,
+    },
+}
+
+impl PickingTarget {
+    /// The targets of the chunks of a point cloud, each one drawing its consecutive points.
+    fn point_cloud_chunks(
+        entity: Entity,
+        chunk_count: usize,
+        chunk_size: usize,
+    ) -> impl Iterator<Item = Self> {
+        (0..chunk_count).map(move |index| PickingTarget::PointCloud {
+            entity,
+            first_point: index * chunk_size,
+        })
+    }
+
+    /// The entity, octree node and index of an instance drawn with this target.
+    fn resolve(&self, instance_index: u32) -> Option<(Entity, Option<NodeId>, usize)> {
+        match self {
+            PickingTarget::PointCloud {
+                entity,
+                first_point,
+            } => Some((*entity, None, first_point + instance_index as usize)),
+            PickingTarget::Octree { entity, nodes } => {
+                let index = nodes
+                    .partition_point(|(instances, _)| instances.start <= instance_index)
+                    .checked_sub(1)?;
+                let (instances, node_id) = &nodes[index];
+                instances.contains(&instance_index).then(|| {
+                    (
+                        *entity,
+                        Some(*node_id),
+                        (instance_index - instances.start) as usize,
+                    )
+                })
+            }
+        }
+    }
+}
+
+/// The picking ids of the draws of this frame, one per point cloud chunk or octree.
+#[derive(Resource)]
+pub struct PickingDraws {
+    uniforms: DynamicUniformBuffer<PickingDrawUniform>,
+    views: DynamicUniformBuffer<PickingViewUniform>,
+    bind_group: Option<BindGroup>,
+    /// drawn entity of each picking id, kept by the readbacks of this frame
+    targets: Arc<Vec<PickingTarget>>,
+}
+
+impl Default for PickingDraws {
+    fn default() -> Self {
+        let mut uniforms = DynamicUniformBuffer::default();
+        uniforms.set_label(Some("pcl_picking_draws"));
+        let mut views = DynamicUniformBuffer::default();
+        views.set_label(Some("pcl_picking_views"));
+
+        Self {
+            uniforms,
+            views,
+            bind_group: None,
+            targets: Default::default(),
+        }
+    }
+}
+
+/// Dynamic offsets of the picking ids of an entity, one per point cloud chunk.
+#[derive(Component)]
+pub struct PickingDrawOffsets(Vec<u32>);
+
+#[allow(clippy::too_many_arguments)]
+fn prepare_picking_draws(
+    mut commands: Commands,
+    mut draws: ResMut<PickingDraws>,
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+    pipeline_cache: Res<PipelineCache>,
+    depth_pipeline: Res<DepthPipeline>,
+    render_point_clouds: Res<RenderAssets<RenderPointCloud>>,
+    render_octrees: Option<Res<RenderOctrees<RenderPointCloudNodeData>>>,
+    picking_views: Query<(Entity, &ExtractedGpuPicking)>,
+    point_clouds: Query<(Entity, &MainEntity, &PointCloud3d)>,
+    octrees: Query<(Entity, &MainEntity, &PointCloudOctree3d)>,
+) {
+    if picking_views.is_empty() {
+        return;
+    }
+
+    let PickingDraws {
+        uniforms,
+        views,
+        bind_group,
+        targets,
+    } = draws.as_mut();
+    uniforms.clear();
+    views.clear();
+    for (entity, picking) in &picking_views {
+        if picking.region.is_empty() {
+            continue;
+        }
+        let offset = views.push(&PickingViewUniform::new(picking.viewport, picking.region));
+        commands.entity(entity).insert(PickingViewOffset(offset));
+    }
+
+    let mut new_targets = Vec::new();
+    let mut push = |target: PickingTarget| {
+        let offset = uniforms.push(&PickingDrawUniform {
+            id: new_targets.len() as u32,
+            #[cfg(all(feature = "webgl", target_arch = "wasm32", not(feature = "webgpu")))]
+            _webgl2_padding: Default::default(),
+        });
+        new_targets.push(target);
+        offset
+    };
+
+    for (entity, main_entity, point_cloud_3d) in &point_clouds {
+        let Some(render_point_cloud) = render_point_clouds.get(point_cloud_3d) else {
+            continue;
+        };
+        let offsets = PickingTarget::point_cloud_chunks(
+            main_entity.id(),
+            render_point_cloud.chunks.len(),
+            render_point_cloud.chunk_size,
+        )
+        .map(&mut push)
+        .collect();
+        commands.entity(entity).insert(PickingDrawOffsets(offsets));
+    }
+
+    if let Some(render_octrees) = &render_octrees {
+        for (entity, main_entity, octree_3d) in &octrees {
+            let Some(render_octree) = render_octrees.get(octree_3d) else {
+                continue;
+            };
+            let mut nodes = render_octree
+                .nodes
+                .values()
+                .map(|node| {
+                    let start = node.allocation.start;
+                    (start..start + node.allocation.count, node.id)
+                })
+                .collect::<Vec<_>>();
+            nodes.sort_unstable_by_key(|(instances, _)| instances.start);
+
+            let offset = push(PickingTarget::Octree {
+                entity: main_entity.id(),
+                nodes,
+            });
+            commands
+                .entity(entity)
+                .insert(PickingDrawOffsets(vec![offset]));
+        }
+    }
+
+    *targets = Arc::new(new_targets);
+    uniforms.write_buffer(&render_device, &render_queue);
+    views.write_buffer(&render_device, &render_queue);
+
+    *bind_group = uniforms.binding().zip(views.binding()).map(|bindings| {
+        render_device.create_bind_group(
+            "pcl_picking_draws_bind_group",
+            &pipeline_cache.get_bind_group_layout(&depth_pipeline.picking_draw_layout),
+            &BindGroupEntries::sequential(bindings),
+        )
+    });
+}
+
+#[allow(clippy::too_many_arguments)]
+#[allow(clippy::type_complexity)]
+fn queue_picking_pass(
+    draw_functions: Res<DrawFunctions<PointCloud3dDepthPhase>>,
+    octree_draw_functions: Res<DrawFunctions<PointCloudOctree3dNodePhase>>,
+    mut pipelines: ResMut<SpecializedRenderPipelines<DepthPipeline>>,
+    pipeline_cache: Res<PipelineCache>,
+    depth_pipeline: Res<DepthPipeline>,
+    point_clouds_3d: Query<(&PointCloud3d, &PointCloudMaterial3d)>,
+    point_cloud_octrees_3d: Query<(&MainEntity, &PointCloudOctree3d, &PointCloudMaterial3d)>,
+    render_materials: Res<RenderAssets<RenderPointCloudMaterial>>,
+    octree_buffer_settings: Option<Res<OctreeBufferSettings<PointCloudOctreeExtraction>>>,
+    mut phases: ResMut<ViewPickingPhases>,
+    views: Query<
+        (
+            &ExtractedView,
+            &Msaa,
+            &RenderVisibleEntities,
+            Option<&RenderVisibleOctreeNodes<PointCloudNodeData, PointCloudOctree3d>>,
+        ),
+        With<ExtractedGpuPicking>,
+    >,
+) {
+    let draw_point_cloud = draw_functions.read().id::<DrawPickingPass>();
+    let draw_octree = octree_draw_functions.read().id::<DrawOctreePickingPass>();
+
+    for (view, msaa, visible_entities, visible_octree_nodes) in &views {
+        let ViewPickingPhases {
+            point_clouds,
+            octrees,
+        } = phases.as_mut();
+        let (Some(point_cloud_phase), Some(octree_phase)) = (
+            point_clouds.get_mut(&view.retained_view_entity),
+            octrees.get_mut(&view.retained_view_entity),
+        ) else {
+            continue;
+        };
+
+        // the view bind group layout depends on the view, even if the picking pass is not
+        // multisampled
+        let view_key = MeshPipelineKey::from_msaa_samples(msaa.samples())
+            | MeshPipelineKey::from_hdr(view.hdr);
+
+        let mut specialize = |material: &RenderPointCloudMaterial,
+                              point_format: Option<PointFormat>| {
+            let key = DepthPipelineKey {
+                mesh_key: view_key,
+                use_edl: false,
+                is_octree: point_format.is_some(),
+                shape: material.shape,
+                splatting: material.splatting,
+                geometry: material.geometry,
+                point_format: point_format.unwrap_or(PointFormat::Full),
+                picking: true,
+            };
+            pipelines.specialize(&pipeline_cache, &depth_pipeline, key)
+        };
+
+        for (render_entity, main_entity) in visible_entities.iter::<PointCloud3d>() {
+            let Ok((point_cloud_3d, point_cloud_material_3d)) = point_clouds_3d.get(*render_entity)
+            else {
+                continue;
+            };
+            let Some(material) = render_materials.get(point_cloud_material_3d) else {
+                continue;
+            };
+
+            point_cloud_phase.add(
+                PointCloud3dBatchSetKey {
+                    pipeline: specialize(material, None),
+                    draw_function: draw_point_cloud,
+                },
+                PointCloud3dBinKey {
+                    asset_id: point_cloud_3d.0.id(),
+                },
+                (*render_entity, *main_entity),
+            );
+        }
+
+        let (Some(visible_octree_nodes), Some(octree_buffer_settings)) =
+            (visible_octree_nodes, &octree_buffer_settings)
+        else {
+            continue;
+        };
+        for render_entity in visible_octree_nodes.octrees.keys() {
+            let Ok((main_entity, point_cloud_octree_3d, point_cloud_material_3d)) =
+                point_cloud_octrees_3d.get(*render_entity)
+            else {
+                continue;
+            };
+            let Some(material) = render_materials.get(point_cloud_material_3d) else {
+                continue;
+            };
+
+            octree_phase.add(
+                PointCloud3dBatchSetKey {
+                    pipeline: specialize(material, Some(*octree_buffer_settings.instance_format())),
+                    draw_function: draw_octree,
+                },
+                PointCloudOctree3dBinKey {
+                    asset_id: point_cloud_octree_3d.0.id(),
+                },
+                (*render_entity, *main_entity),
+            );
+        }
+    }
+}
+
+/// A staging buffer the picked region of a frame is copied to, and mapped once the frame is
+/// submitted.
+struct PickingReadback {
+    buffer: Buffer,
+    /// size of the region the buffer can hold
+    max_size: u32,
+    bytes_per_row: u32,
+    region: URect,
+    pixel: UVec2,
+    position: Vec2,
+    targets: Arc<Vec<PickingTarget>>,
+    state: Arc<AtomicU8>,
+}
+
+/// Textures and buffers used to pick the points of a view.
+#[derive(Component, Default)]
+pub struct PickingBuffers {
+    ids: Option<CachedTexture>,
+    depth: Option<CachedTexture>,
+    readbacks: Vec<PickingReadback>,
+    /// index of the readback written this frame
+    current: Option<usize>,
+}
+
+impl PickingBuffers {
+    /// The staging buffer the picking texture must be copied to this frame.
+    fn current_readback(&self) -> Option<&PickingReadback> {
+        self.readbacks.get(self.current?)
+    }
+}
+
+fn prepare_picking_buffers(
+    mut commands: Commands,
+    mut texture_cache: ResMut<TextureCache>,
+    render_device: Res<RenderDevice>,
+    draws: Res<PickingDraws>,
+    mut views: Query<(Entity, &ExtractedGpuPicking, Option<&mut PickingBuffers>)>,
+) {
+    for (entity, picking, buffers) in &mut views {
+        let Some(buffers) = buffers else {
+            commands.entity(entity).insert(PickingBuffers::default());
+            continue;
+        };
+        let PickingBuffers {
+            ids,
+            depth,
+            readbacks,
+            current,
+        } = buffers.into_inner();
+
+        *current = None;
+
+        if picking.region.is_empty() {
+            continue;
+        }
+
+        // large enough for the region, whatever its clamping to the viewport
+        let size = Extent3d {
+            width: picking.max_size,
+            height: picking.max_size,
+            depth_or_array_layers: 1,
+        };
+        let mut texture = |label, format, usage| {
+            texture_cache.get(
+                &render_device,
+                TextureDescriptor {
+                    label: Some(label),
+                    size,
+                    mip_level_count: 1,
+                    sample_count: 1,
+                    dimension: TextureDimension::D2,
+                    format,
+                    usage,
+                    view_formats: &[],
+                },
+            )
+        };
+        *ids = Some(texture(
+            "pcl_picking_texture",
+            PICKING_TEXTURE_FORMAT,
+            TextureUsages::RENDER_ATTACHMENT | TextureUsages::COPY_SRC,
+        ));
+        *depth = Some(texture(
+            "pcl_picking_depth_texture",
+            CORE_3D_DEPTH_FORMAT,
+            TextureUsages::RENDER_ATTACHMENT,
+        ));
+
+        // drop the idle readbacks of another radius
+        readbacks.retain(|readback| {
+            readback.max_size == picking.max_size
+                || readback.state.load(Ordering::Acquire) != READBACK_FREE
+        });
+
+        let free = readbacks.iter().position(|readback| {
+            readback.max_size == picking.max_size
+                && readback.state.load(Ordering::Acquire) == READBACK_FREE
+        });
+        let index = match free {
+            Some(index) => index,
+            None if readbacks.len() < MAX_PICKING_READBACKS => {
+                let bytes_per_row = RenderDevice::align_copy_bytes_per_row(
+                    picking.max_size as usize * size_of::<[u32; 2]>(),
+                ) as u32;
+                readbacks.push(PickingReadback {
+                    buffer: render_device.create_buffer(&BufferDescriptor {
+                        label: Some("pcl_picking_readback_buffer"),
+                        size: bytes_per_row as u64 * picking.max_size as u64,
+                        usage: BufferUsages::MAP_READ | BufferUsages::COPY_DST,
+                        mapped_at_creation: false,
+                    }),
+                    max_size: picking.max_size,
+                    bytes_per_row,
+                    region: URect::EMPTY,
+                    pixel: UVec2::ZERO,
+                    position: Vec2::ZERO,
+                    targets: Default::default(),
+                    state: Arc::new(AtomicU8::new(READBACK_FREE)),
+                });
+                readbacks.len() - 1
+            }
+            // all the readbacks are in flight, skip this frame
+            None => continue,
+        };
+
+        let readback = &mut readbacks[index];
+        readback.region = picking.region;
+        readback.pixel = picking.pixel;
+        readback.position = picking.position;
+        readback.targets = draws.targets.clone();
+        readback.state.store(READBACK_PREPARED, Ordering::Release);
+        *current = Some(index);
+    }
+}
+
+/// Map the readbacks copied this frame once the commands are submitted, and send their pick to
+/// the main world.
+fn map_picking_readbacks(
+    sender: Res<GpuPickingSender>,
+    mut views: Query<(&MainEntity, &mut PickingBuffers)>,
+) {
+    for (main_entity, mut buffers) in &mut views {
+        let Some(readback) = buffers
+            .current
+            .take()
+            .and_then(|index| buffers.readbacks.get(index))
+        else {
+            continue;
+        };
+
+        // the node didn't run this frame
+        if readback.state.load(Ordering::Acquire) != READBACK_COPIED {
+            readback.state.store(READBACK_FREE, Ordering::Release);
+            continue;
+        }
+        readback.state.store(READBACK_MAPPING, Ordering::Release);
+
+        let camera = main_entity.id();
+        let buffer = readback.buffer.clone();
+        let state = readback.state.clone();
+        let targets = readback.targets.clone();
+        let (region, pixel, position, bytes_per_row) = (
+            readback.region,
+            readback.pixel,
+            readback.position,
+            readback.bytes_per_row,
+        );
+        let sender = sender.0.clone();
+
+        readback
+            .buffer
+            .slice(..)
+            .map_async(MapMode::Read, move |result| {
+                if let Err(error) = result {
+                    warn!("Unable to read back the picking texture: {}", error);
+                    state.store(READBACK_FREE, Ordering::Release);
+                    return;
+                }
+
+                let closest = closest_texel(
+                    &buffer.slice(..).get_mapped_range(),
+                    region,
+                    pixel,
+                    bytes_per_row,
+                );
+                buffer.unmap();
+                state.store(READBACK_FREE, Ordering::Release);
+
+                let target = closest.and_then(|(id, instance_index)| {
+                    targets.get(id as usize)?.resolve(instance_index)
+                });
+                // the main world may have been dropped on exit
+                let _ = sender.send(GpuPickingReadbackResult {
+                    camera,
+                    position,
+                    target,
+                });
+            });
+    }
+}
+
+/// The picking id and instance index of the drawn texel closest to the picked pixel, in the
+/// picking texture read back for a region.
+fn closest_texel(
+    data: &[u8],
+    region: URect,
+    pixel: UVec2,
+    bytes_per_row: u32,
+) -> Option<(u32, u32)> {
+    let size = region.size();
+    (0..size.y)
+        .flat_map(|y| (0..size.x).map(move |x| UVec2::new(x, y)))
+        .filter_map(|texel| {
+            let offset =
+                (texel.y * bytes_per_row) as usize + texel.x as usize * size_of::<[u32; 2]>();
+            let [id, instance_index]: [u32; 2] =
+                bytemuck::pod_read_unaligned(&data[offset..offset + size_of::<[u32; 2]>()]);
+            let distance = (region.min + texel).as_ivec2() - pixel.as_ivec2();
+            (id != 0).then(|| (distance.length_squared(), id - 1, instance_index))
+        })
+        .min_by_key(|(distance, _, _)| *distance)
+        .map(|(_, id, instance_index)| (id, instance_index))
+}
+
+pub struct SetPickingDrawGroup<const I: usize>;
+impl<P: PhaseItem, const I: usize> RenderCommand<P> for SetPickingDrawGroup<I> {
+    type Param = SRes<PickingDraws>;
+    type ViewQuery = Read<PickingViewOffset>;
+    type ItemQuery = Read<PickingDrawOffsets>;
+
+    fn render<'w>(
+        _item: &P,
+        view_offset: ROQueryItem<'w, '_, Self::ViewQuery>,
+        offsets: Option<ROQueryItem<'w, '_, Self::ItemQuery>>,
+        draws: SystemParamItem<'w, '_, Self::Param>,
+        pass: &mut TrackedRenderPass<'w>,
+    ) -> RenderCommandResult {
+        let (Some(bind_group), Some(offset)) = (
+            draws.into_inner().bind_group.as_ref(),
+            offsets.and_then(|offsets| offsets.0.first()),
+        ) else {
+            return RenderCommandResult::Skip;
+        };
+
+        pass.set_bind_group(I, bind_group, &[*offset, view_offset.0]);
+
+        RenderCommandResult::Success
+    }
+}
+
+/// Draws the chunks of a point cloud, each one with its own picking id.
+pub struct DrawPointCloudPicking<const I: usize>;
+impl<P: PhaseItem, const I: usize> RenderCommand<P> for DrawPointCloudPicking<I> {
+    type Param = (
+        SRes<PointCloudMesh>,
+        SRes<RenderAssets<RenderPointCloud>>,
+        SRes<PickingDraws>,
+    );
+    type ViewQuery = Read<PickingViewOffset>;
+    type ItemQuery = (Read<PointCloud3d>, Read<PickingDrawOffsets>);
+
+    #[inline]
+    fn render<'w>(
+        _item: &P,
+        view_offset: ROQueryItem<'w, '_, Self::ViewQuery>,
+        item: Option<(&'w PointCloud3d, &'w PickingDrawOffsets)>,
+        (point_cloud_mesh, render_point_clouds, draws): SystemParamItem<'w, '_, Self::Param>,
+        pass: &mut TrackedRenderPass<'w>,
+    ) -> RenderCommandResult {
+        // A borrow check workaround.
+        let point_cloud_mesh = point_cloud_mesh.into_inner();
+        let render_point_clouds = render_point_clouds.into_inner();
+
+        let Some((point_cloud_3d, offsets)) = item else {
+            return RenderCommandResult::Skip;
+        };
+        let (Some(render_point_cloud), Some(bind_group)) = (
+            render_point_clouds.get(point_cloud_3d),
+            draws.into_inner().bind_group.as_ref(),
+        ) else {
+            return RenderCommandResult::Skip;
+        };
+
+        pass.set_vertex_buffer(0, point_cloud_mesh.vertex_buffer.slice(..));
+        pass.set_index_buffer(point_cloud_mesh.index_buffer.slice(..), IndexFormat::Uint32);
+
+        for (chunk, offset) in render_point_cloud.chunks.iter().zip(&offsets.0) {
+            pass.set_bind_group(I, bind_group, &[*offset, view_offset.0]);
+            pass.set_vertex_buffer(1, chunk.buffer.slice(..));
+            pass.draw_indexed(0..point_cloud_mesh.index_count, 0, 0..chunk.length as u32);
+        }
+
+        RenderCommandResult::Success
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node_id(index: usize) -> NodeId {
+        NodeId {
+            index,
+            generation: 0,
+        }
+    }
+
+    #[test]
+    fn point_cloud_chunks_resolve_to_global_indices() {
+        let entity = Entity::PLACEHOLDER;
+        let targets = PickingTarget::point_cloud_chunks(entity, 3, 100).collect::<Vec<_>>();
+        assert_eq!(targets.len(), 3);
+        assert_eq!(targets[0].resolve(7), Some((entity, None, 7)));
+        assert_eq!(targets[2].resolve(5), Some((entity, None, 205)));
+    }
+
+    #[test]
+    fn octree_targets_resolve_to_node_indices() {
+        let entity = Entity::PLACEHOLDER;
+        let target = PickingTarget::Octree {
+            entity,
+            nodes: vec![
+                (0..10, node_id(0)),
+                (10..15, node_id(1)),
+                (20..30, node_id(2)),
+            ],
+        };
+        assert_eq!(target.resolve(0), Some((entity, Some(node_id(0)), 0)));
+        assert_eq!(target.resolve(12), Some((entity, Some(node_id(1)), 2)));
+        assert_eq!(target.resolve(25), Some((entity, Some(node_id(2)), 5)));
+        // between the allocations and after the last one
+        assert_eq!(target.resolve(17), None);
+        assert_eq!(target.resolve(30), None);
+    }
+
+    #[test]
+    fn closest_texel_is_nearest_to_the_pixel() {
+        let region = URect::new(10, 10, 15, 15);
+        let bytes_per_row = 256;
+        let mut data = vec![0u8; bytes_per_row as usize * 5];
+        let write = |data: &mut [u8], texel: UVec2, texel_data: [u32; 2]| {
+            let offset = (texel.y * bytes_per_row) as usize + texel.x as usize * 8;
+            data[offset..offset + 8].copy_from_slice(bytemuck::bytes_of(&texel_data));
+        };
+
+        let pixel = UVec2::new(12, 12);
+        assert_eq!(closest_texel(&data, region, pixel, bytes_per_row), None);
+
+        // ids are offset by one, zero being empty
+        write(&mut data, UVec2::new(0, 0), [1, 7]);
+        write(&mut data, UVec2::new(4, 4), [2, 8]);
+        assert_eq!(
+            closest_texel(&data, region, pixel, bytes_per_row),
+            Some((0, 7))
+        );
+
+        write(&mut data, UVec2::new(3, 2), [3, 9]);
+        assert_eq!(
+            closest_texel(&data, region, pixel, bytes_per_row),
+            Some((2, 9))
+        );
+    }
+
+    #[test]
+    fn clip_transform_draws_the_region_at_the_origin() {
+        let viewport = URect::new(100, 50, 900, 650);
+        let region = URect::new(400, 300, 405, 305);
+        let transform = PickingViewUniform::new(viewport, region).clip_transform;
+
+        let ndc = |rect: URect, pixel: Vec2| {
+            let uv = (pixel - rect.min.as_vec2()) / rect.size().as_vec2();
+            Vec2::new(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0)
+        };
+        for pixel in [
+            Vec2::new(400.0, 300.0),
+            Vec2::new(402.5, 301.5),
+            Vec2::new(405.0, 305.0),
+        ] {
+            // with a W other than one
+            let w = 3.0;
+            let clip = ndc(viewport, pixel) * w;
+            let transformed = (clip * transform.xy() + transform.zw() * w) / w;
+            assert!(transformed.abs_diff_eq(ndc(region, pixel), 1e-4));
+        }
+    }
+}
@@ -0,0 +1,141 @@
+use std::sync::atomic::Ordering;
+
+use bevy_color::LinearRgba;
+use bevy_ecs::{prelude::*, query::QueryItem};
+use bevy_log::prelude::*;
+use bevy_render::{
+    camera::ExtractedCamera,
+    render_graph::{NodeRunError, RenderGraphContext, RenderLabel, ViewNode},
+    render_resource::{
+        Extent3d, LoadOp, Operations, Origin3d, RenderPassColorAttachment,
+        RenderPassDepthStencilAttachment, RenderPassDescriptor, StoreOp, TexelCopyBufferInfo,
+        TexelCopyBufferLayout, TexelCopyTextureInfo, TextureAspect,
+    },
+    renderer::RenderContext,
+    view::ExtractedView,
+};
+
+use super::{ExtractedGpuPicking, PickingBuffers, ViewPickingPhases, READBACK_COPIED};
+
+#[derive(RenderLabel, Debug, Clone, Hash, PartialEq, Eq)]
+pub struct PickingPassLabel;
+
+/// Draws the picked region of the view into the picking texture and copies it to a readback
+/// buffer.
+#[derive(Default)]
+pub struct PickingPassNode;
+
+impl ViewNode for PickingPassNode {
+    type ViewQuery = (
+        &'static ExtractedCamera,
+        &'static ExtractedView,
+        &'static ExtractedGpuPicking,
+        &'static PickingBuffers,
+    );
+
+    fn run<'w>(
+        &self,
+        graph: &mut RenderGraphContext,
+        render_context: &mut RenderContext<'w>,
+        (camera, view, _picking, buffers): QueryItem<'w, '_, Self::ViewQuery>,
+        world: &'w World,
+    ) -> Result<(), NodeRunError> {
+        #[cfg(feature = "trace")]
+        let _span = info_span!("picking_pass_node").entered();
+
+        let (Some(ids), Some(depth), Some(readback)) = (
+            buffers.ids.as_ref(),
+            buffers.depth.as_ref(),
+            buffers.current_readback(),
+        ) else {
+            return Ok(());
+        };
+
+        let phases = world.resource::<ViewPickingPhases>();
+        let (Some(point_cloud_phase), Some(octree_phase)) = (
+            phases.point_clouds.get(&view.retained_view_entity),
+            phases.octrees.get(&view.retained_view_entity),
+        ) else {
+            return Ok(());
+        };
+
+        let view_entity = graph.view_entity();
+        let region = readback.region;
+
+        {
+            let mut render_pass = render_context.begin_tracked_render_pass(RenderPassDescriptor {
+                label: Some("pcl_picking_pass"),
+                color_attachments: &[Some(RenderPassColorAttachment {
+                    view: &ids.default_view,
+                    depth_slice: None,
+                    resolve_target: None,
+                    ops: Operations {
+                        // no point drawn
+                        load: LoadOp::Clear(LinearRgba::NONE.into()),
+                        store: StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: Some(RenderPassDepthStencilAttachment {
+                    view: &depth.default_view,
+                    // reverse Z
+                    depth_ops: Some(Operations {
+                        load: LoadOp::Clear(0.0),
+                        store: StoreOp::Discard,
+                    }),
+                    stencil_ops: None,
+                }),
+                timestamp_writes: None,
+                occlusion_query_set: None,
+            });
+
+            // the clip positions are offset so that the region is drawn at the origin of the
+            // textures
+            let depth_range = camera
+                .viewport
+                .as_ref()
+                .map_or(0.0..1.0, |viewport| viewport.depth.clone());
+            let size = region.size();
+            render_pass.set_viewport(
+                0.0,
+                0.0,
+                size.x as f32,
+                size.y as f32,
+                depth_range.start,
+                depth_range.end,
+            );
+
+            if let Err(err) = point_cloud_phase.render(&mut render_pass, world, view_entity) {
+                error!("Error encountered while rendering the point cloud picking phase {err:?}");
+            }
+            if let Err(err) = octree_phase.render(&mut render_pass, world, view_entity) {
+                error!("Error encountered while rendering the octree picking phase {err:?}");
+            }
+        }
+
+        let size = region.size();
+        render_context.command_encoder().copy_texture_to_buffer(
+            TexelCopyTextureInfo {
+                texture: &ids.texture,
+                mip_level: 0,
+                origin: Origin3d::ZERO,
+                aspect: TextureAspect::All,
+            },
+            TexelCopyBufferInfo {
+                buffer: &readback.buffer,
+                layout: TexelCopyBufferLayout {
+                    offset: 0,
+                    bytes_per_row: Some(readback.bytes_per_row),
+                    rows_per_image: None,
+                },
+            },
+            Extent3d {
+                width: size.x,
+                height: size.y,
+                depth_or_array_layers: 1,
+            },
+        );
+        readback.state.store(READBACK_COPIED, Ordering::Release);
+
+        Ok(())
+    }
+}
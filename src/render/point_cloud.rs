@@ -1,42 +1,205 @@
-use bevy_asset::AssetId;
-use bevy_ecs::system::{lifetimeless::SRes, SystemParamItem};
+use std::ops::Range;
+
+use bevy_asset::{AssetEvent, AssetId, Assets};
+use bevy_camera::primitives::Aabb;
+use bevy_ecs::{
+    prelude::*,
+    system::{lifetimeless::SRes, SystemParamItem},
+};
+use bevy_math::Vec3;
 use bevy_render::{
     render_asset::{PrepareAssetError, RenderAsset},
-    render_resource::{Buffer, BufferInitDescriptor, BufferUsages},
-    renderer::RenderDevice,
+    render_resource::{Buffer, BufferDescriptor, BufferUsages},
+    renderer::{RenderDevice, RenderQueue},
 };
+use bytemuck::{Pod, Zeroable};
 
 use crate::point_cloud::{PointCloud, PointCloudData};
 
+/// Maximum number of points in a chunk.
+pub const MAX_CHUNK_SIZE: usize = 1 << 20;
+
+/// A point of a [`PointCloud`] in the GPU buffer, with its normal.
+#[derive(Debug, Clone, Copy, Pod, Zeroable)]
+#[repr(C)]
+pub struct PointCloudInstance {
+    pub point: PointCloudData,
+    /// Unit normal, zero when unknown
+    pub normal: Vec3,
+}
+
 /// The render world representation of a [`PointCloud`].
+///
+/// Points are split in chunks of consecutive points, each one in its own buffer and with its own
+/// bounding box, so that large point clouds fit the buffer size limits and can be partially culled.
 pub struct RenderPointCloud {
+    pub chunks: Vec<RenderPointCloudChunk>,
+    pub length: usize,
+    /// Maximum number of points per chunk.
+    pub chunk_size: usize,
+}
+
+#[derive(Clone)]
+pub struct RenderPointCloudChunk {
     pub buffer: Buffer,
+    /// Bounding box of the chunk points, in local space.
+    pub aabb: Option<Aabb>,
     pub length: usize,
+    /// Number of points the buffer can hold.
+    pub capacity: usize,
 }
 
 impl RenderAsset for RenderPointCloud {
     type SourceAsset = PointCloud;
-    type Param = SRes<RenderDevice>;
+    type Param = (SRes<RenderDevice>, SRes<RenderQueue>);
 
     fn byte_len(source_asset: &Self::SourceAsset) -> Option<usize> {
-        Some(source_asset.points.len() * size_of::<PointCloudData>())
+        let changes = source_asset.changes();
+        let len = if changes.is_tracked() {
+            changes.dirty_len()
+        } else {
+            source_asset.points.len()
+        };
+        Some(len * size_of::<PointCloudInstance>())
     }
 
     fn prepare_asset(
         source_asset: Self::SourceAsset,
         _asset_id: AssetId<Self::SourceAsset>,
-        render_device: &mut SystemParamItem<Self::Param>,
-        _previous_asset: Option<&Self>,
+        (render_device, render_queue): &mut SystemParamItem<Self::Param>,
+        previous_asset: Option<&Self>,
     ) -> Result<Self, PrepareAssetError<Self::SourceAsset>> {
-        let buffer = render_device.create_buffer_with_data(&BufferInitDescriptor {
-            label: Some("PointCloud data buffer"),
-            contents: bytemuck::cast_slice(source_asset.points.as_slice()),
-            usage: BufferUsages::VERTEX, // | BufferUsages::COPY_DST,
-        });
+        let length = source_asset.points.len();
+        let changes = source_asset.changes();
+
+        // only upload dirty ranges if the changes are tracked
+        let previous_asset = previous_asset.filter(|_| changes.is_tracked());
+
+        let chunk_size = previous_asset
+            .map(|previous_asset| previous_asset.chunk_size)
+            .unwrap_or_else(|| {
+                let max_points = render_device.limits().max_buffer_size as usize
+                    / size_of::<PointCloudInstance>();
+                max_points.min(MAX_CHUNK_SIZE)
+            });
+
+        let chunks = (0..length.div_ceil(chunk_size))
+            .map(|index| {
+                let range = index * chunk_size..length.min((index + 1) * chunk_size);
+                let points = &source_asset.points[range.clone()];
+                let previous_chunk =
+                    previous_asset.and_then(|previous_asset| previous_asset.chunks.get(index));
+
+                match previous_chunk {
+                    Some(previous_chunk) if points.len() <= previous_chunk.capacity => {
+                        let mut dirty = points.len() != previous_chunk.length;
+                        for dirty_range in changes.dirty_ranges() {
+                            let start = dirty_range.start.max(range.start);
+                            let end = dirty_range.end.min(range.end);
+                            if start >= end {
+                                continue;
+                            }
+
+                            render_queue.write_buffer(
+                                &previous_chunk.buffer,
+                                ((start - range.start) * size_of::<PointCloudInstance>()) as u64,
+                                bytemuck::cast_slice(&instances(&source_asset, start..end)),
+                            );
+                            dirty = true;
+                        }
+
+                        // points may have moved away or been removed, so the bounding box is
+                        // recomputed instead of grown
+                        let aabb = if dirty {
+                            enclosing(points)
+                        } else {
+                            previous_chunk.aabb
+                        };
+
+                        RenderPointCloudChunk {
+                            buffer: previous_chunk.buffer.clone(),
+                            aabb,
+                            length: points.len(),
+                            capacity: previous_chunk.capacity,
+                        }
+                    }
+                    _ => {
+                        // grow geometrically when the point cloud is being updated to amortize reallocations
+                        let capacity = match previous_chunk {
+                            Some(previous_chunk) => points
+                                .len()
+                                .max(previous_chunk.capacity * 2)
+                                .min(chunk_size),
+                            None if previous_asset.is_some() => {
+                                points.len().next_power_of_two().min(chunk_size)
+                            }
+                            None => points.len(),
+                        };
+
+                        let buffer = render_device.create_buffer(&BufferDescriptor {
+                            label: Some("PointCloud data buffer"),
+                            size: (capacity * size_of::<PointCloudInstance>()) as u64,
+                            usage: BufferUsages::VERTEX | BufferUsages::COPY_DST,
+                            mapped_at_creation: false,
+                        });
+                        render_queue.write_buffer(
+                            &buffer,
+                            0,
+                            bytemuck::cast_slice(&instances(&source_asset, range)),
+                        );
+
+                        RenderPointCloudChunk {
+                            buffer,
+                            aabb: enclosing(points),
+                            length: points.len(),
+                            capacity,
+                        }
+                    }
+                }
+            })
+            .collect();
 
         Ok(RenderPointCloud {
-            buffer,
-            length: source_asset.points.len(),
+            chunks,
+            length,
+            chunk_size,
+        })
+    }
+}
+
+/// Returns the points in `range` along with their normals, as laid out in the GPU buffer.
+fn instances(point_cloud: &PointCloud, range: Range<usize>) -> Vec<PointCloudInstance> {
+    let normals = point_cloud
+        .normals
+        .as_ref()
+        .and_then(|normals| normals.get(range.clone()));
+    point_cloud.points[range]
+        .iter()
+        .enumerate()
+        .map(|(index, point)| PointCloudInstance {
+            point: *point,
+            normal: normals.map_or(Vec3::ZERO, |normals| normals[index]),
         })
+        .collect()
+}
+
+/// Returns the bounding box enclosing the points.
+fn enclosing(points: &[PointCloudData]) -> Option<Aabb> {
+    Aabb::enclosing(points.iter().map(|point| point.position))
+}
+
+/// Forget the changes of the [`PointCloud`]s extracted during the previous frame,
+/// without triggering a new [`AssetEvent::Modified`].
+pub fn clear_point_cloud_changes(
+    mut events: MessageReader<AssetEvent<PointCloud>>,
+    mut point_clouds: ResMut<Assets<PointCloud>>,
+) {
+    for event in events.read() {
+        // the added point clouds are uploaded entirely
+        if let AssetEvent::Added { id } | AssetEvent::Modified { id } = event
+            && let Some(point_cloud) = point_clouds.get_mut_untracked(*id)
+        {
+            point_cloud.clear_changes();
+        }
     }
 }
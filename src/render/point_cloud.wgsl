@@ -10,6 +10,21 @@
 #import bevy_pbr::view_transformations::position_view_to_clip
 #import bevy_pbr::view_transformations::position_view_to_ndc
 
+#import bevy_pointcloud::clip_volume::{clip_point, clip_state_is_clipped, clip_state_highlight}
+
+#ifdef RECEIVE_SHADOWS
+#import bevy_pbr::mesh_view_types::DIRECTIONAL_LIGHT_FLAGS_SHADOWS_ENABLED_BIT
+#import bevy_pbr::shadows::fetch_directional_shadow
+#endif
+
+#ifdef LIT_POINTS
+#import bevy_pbr::mesh_view_types::POINT_LIGHT_FLAGS_SPOT_LIGHT_Y_NEGATIVE
+#import bevy_pbr::clustered_forward::{
+    fragment_cluster_index, unpack_clusterable_object_index_ranges, get_clusterable_object_id,
+}
+#import bevy_pbr::lighting::getDistanceAttenuation
+#endif
+
 struct Vertex {
     // This is needed if you are using batching and/or gpu preprocessing
     // It's a built in so you don't need to define it in the vertex layout
@@ -18,8 +33,28 @@ struct Vertex {
     // position is at location 0
     @location(0) position: vec3<f32>,
 
+#ifdef QUANTIZED_POSITIONS_16
+    // position quantized relative to the node bounding box + octahedral normal
+    @location(1) i_quantized_position: vec4<u32>,
+#else ifdef QUANTIZED_POSITIONS_32
+    // position quantized relative to the node bounding box
+    @location(1) i_quantized_position: vec3<u32>,
+#else
     @location(1) i_pos_size: vec4<f32>,
+#endif
     @location(2) i_color: vec4<f32>,
+#ifdef IS_OCTREE
+#ifndef QUANTIZED_POSITIONS_16
+    @location(3) i_octahedral_normal: vec2<u32>,
+#endif
+#else
+    @location(3) i_normal: vec3<f32>,
+#endif
+#ifdef QUANTIZED_POSITIONS
+    // bounds of the drawn node, the same for all the instances of a draw
+    @location(4) node_center: vec3<f32>,
+    @location(5) node_half_extents: vec3<f32>,
+#endif
 };
 
 // This is the output of the vertex shader and we also use it as the input for the fragment shader
@@ -30,6 +65,21 @@ struct VertexOutput {
     @location(2) color: vec4<f32>,
     @location(3) log_depth: f32,
     @location(4) radius: f32,
+    // depth of the point before being pushed back by the HQ depth pass
+    @location(5) depth: f32,
+#ifdef RECEIVE_SHADOWS
+    @location(6) world_position: vec3<f32>,
+#else ifdef LIT_POINTS
+    @location(6) world_position: vec3<f32>,
+#endif
+#ifdef LIT_POINTS
+    // zero when unknown
+    @location(7) world_normal: vec3<f32>,
+#endif
+#ifdef PICKING_PASS
+    // picking id of the draw, plus one, and instance index of the point
+    @location(8) @interpolate(flat) picking: vec2<u32>,
+#endif
 };
 
 @group(1) @binding(0)
@@ -39,14 +89,39 @@ struct PointCloudMaterial {
     point_size: f32,
     min_point_size: f32,
     max_point_size: f32,
+    // 0: viewport, 1: fixed pixels, 2: world space, 3: adaptive
+    sizing: u32,
+};
+
+const SIZING_VIEWPORT: u32 = 0u;
+const SIZING_FIXED_PIXELS: u32 = 1u;
+const SIZING_WORLD_SPACE: u32 = 2u;
+const SIZING_ADAPTIVE: u32 = 3u;
+
+@group(2) @binding(0)
+var<uniform> material: PointCloudMaterial;
+
+#ifdef PICKING_PASS
+struct PickingDraw {
+    id: u32,
 #ifdef SIXTEEN_BYTE_ALIGNMENT
     // WebGL2 structs must be 16 byte aligned.
-    _webgl2_padding: f32,
+    _webgl2_padding: vec3<f32>,
 #endif
 };
 
-@group(2) @binding(0)
-var<uniform> material: PointCloudMaterial;
+// The group follows the groups of the depth pass, which depend on the drawn entity
+@group(#{PICKING_BIND_GROUP}) @binding(0)
+var<uniform> picking_draw: PickingDraw;
+
+struct PickingView {
+    // scale of the clip XY coordinates, and their offset multiplied by W
+    clip_transform: vec4<f32>,
+};
+
+@group(#{PICKING_BIND_GROUP}) @binding(1)
+var<uniform> picking_view: PickingView;
+#endif
 
 #ifdef IS_OCTREE
 
@@ -65,8 +140,23 @@ struct OctreeEntity {
 #endif
 };
 
+#ifdef VISIBLE_NODES_TEXTURE
 @group(3) @binding(0)
 var visible_nodes: texture_2d<u32>;
+#else
+struct VisibleNode {
+    children_mask: u32,
+    offset: u32,
+    first_child_index: u32,
+};
+
+@group(3) @binding(0)
+var<storage, read> visible_nodes: array<VisibleNode>;
+
+// Index of the first visible node and number of visible nodes of each octree
+@group(3) @binding(1)
+var<storage, read> visible_nodes_offsets: array<vec2<u32>>;
+#endif
 
 @group(4) @binding(0)
 var<uniform> octree_node: OctreeNode;
@@ -93,6 +183,36 @@ fn extract_max_scale(matrix: mat4x4<f32>) -> f32 {
     return max(scale_x, max(scale_y, scale_z));
 }
 
+fn is_orthographic_view() -> bool {
+    return view_bindings::view.clip_from_view[3].w == 1.0;
+}
+
+// Decode a normal stored with an octahedral encoding on 8 bits per component, [0, 0] being unknown
+fn decode_octahedral_normal(encoded: vec2<u32>) -> vec3<f32> {
+    if all(encoded == vec2<u32>(0u)) {
+        return vec3<f32>(0.0);
+    }
+
+    let octahedral = vec2<f32>(encoded) / 255.0 * 2.0 - 1.0;
+    var normal = vec3<f32>(octahedral, 1.0 - abs(octahedral.x) - abs(octahedral.y));
+    let fold = max(-normal.z, 0.0);
+    normal.x += select(fold, -fold, normal.x >= 0.0);
+    normal.y += select(fold, -fold, normal.y >= 0.0);
+    return normalize(normal);
+}
+
+// Transform a normal to world space, the inverse transpose of a rotation and scale being the
+// matrix divided by the squared scales
+fn normal_local_to_world(normal: vec3<f32>) -> vec3<f32> {
+    if all(normal == vec3<f32>(0.0)) {
+        return normal;
+    }
+
+    let matrix = mat3x3<f32>(world_from_local[0].xyz, world_from_local[1].xyz, world_from_local[2].xyz);
+    let squared_scale = vec3<f32>(dot(matrix[0], matrix[0]), dot(matrix[1], matrix[1]), dot(matrix[2], matrix[2]));
+    return normalize(matrix * (normal / squared_scale));
+}
+
 
 #ifdef IS_OCTREE
 
@@ -119,6 +239,18 @@ fn count_bits_before(mask: u32, index: u32) -> u32 {
 }
 
 
+// Load a visible node of the current octree as (children mask, offset, first child index)
+fn load_visible_node(index: u32) -> vec3<u32> {
+#ifdef VISIBLE_NODES_TEXTURE
+    let texel = textureLoad(visible_nodes, vec2<u32>(index, octree_entity.octree_index), 0);
+    // u16 reconstruit à partir de B et A
+    return vec3<u32>(texel.r, texel.g, texel.b | (texel.a << 8u));
+#else
+    let node = visible_nodes[visible_nodes_offsets[octree_entity.octree_index].x + index];
+    return vec3<u32>(node.children_mask, node.offset, node.first_child_index);
+#endif
+}
+
 fn get_max_relative_depth(position: vec3<f32>) -> f32 {
 
     // var current_index = visible_node.node_index;
@@ -129,12 +261,12 @@ fn get_max_relative_depth(position: vec3<f32>) -> f32 {
     var half_extents = octree_node.half_extents;
 
     for (var i = 0; i <= 30; i ++) {
-        let current_node = textureLoad(visible_nodes, vec2<u32>(current_index, octree_entity.octree_index), 0);
+        let current_node = load_visible_node(current_index);
 
         // Extract data
-        let children_mask = current_node.r;  // u8 dans le canal R
+        let children_mask = current_node.x;
 
-        let first_child_index = current_node.b | (current_node.a << 8u);  // u16 reconstruit à partir de B et A
+        let first_child_index = current_node.z;
 
         // Determiner in which octant is the position
         let relative_position = position - center;
@@ -163,7 +295,7 @@ fn get_max_relative_depth(position: vec3<f32>) -> f32 {
             let offset = (index3d * 2.0 - 1.0) * half_extents;
             center = center + offset;
         } else {
-            let offset = f32(current_node.g) / 10.0 - 10.0;
+            let offset = f32(current_node.y) / 10.0 - 10.0;
             return f32(relative_depth) + offset;
         }
 
@@ -174,18 +306,74 @@ fn get_max_relative_depth(position: vec3<f32>) -> f32 {
 
 #endif
 
+#ifdef QUANTIZED_POSITIONS_16
+const QUANTIZATION_MAX: f32 = 65535.0;
+#else ifdef QUANTIZED_POSITIONS_32
+const QUANTIZATION_MAX: f32 = 4294967295.0;
+#endif
+
+// Local position of the point instance
+fn instance_position(vertex: Vertex) -> vec3<f32> {
+#ifdef QUANTIZED_POSITIONS
+    // positions are quantized relative to the bounding box of their node
+    let normalized = vec3<f32>(vertex.i_quantized_position.xyz) / QUANTIZATION_MAX;
+    return vertex.node_center + vertex.node_half_extents * (normalized * 2.0 - 1.0);
+#else
+    return vertex.i_pos_size.xyz;
+#endif
+}
+
+// Local normal of the point instance, zero when unknown
+fn instance_normal(vertex: Vertex) -> vec3<f32> {
+#ifdef QUANTIZED_POSITIONS_16
+    let packed = vertex.i_quantized_position.w;
+    return decode_octahedral_normal(vec2<u32>(packed & 0xffu, packed >> 8u));
+#else ifdef IS_OCTREE
+    return decode_octahedral_normal(vertex.i_octahedral_normal);
+#else
+    return vertex.i_normal;
+#endif
+}
+
+#ifdef SURFEL_GEOMETRY
+// View space offset of a corner of a point lying on the surface given by its normal, the points
+// without a normal facing the camera
+fn surfel_offset(world_normal: vec3<f32>, view_position: vec3<f32>, corner: vec2<f32>) -> vec3<f32> {
+    if all(world_normal == vec3<f32>(0.0)) {
+        return vec3<f32>(corner, 0.0);
+    }
+
+    var normal = normalize((view_bindings::view.view_from_world * vec4<f32>(world_normal, 0.0)).xyz);
+
+    // the culled back face is the one turned away from the camera
+    let to_camera = select(normalize(-view_position), vec3<f32>(0.0, 0.0, 1.0), is_orthographic_view());
+    if dot(normal, to_camera) < 0.0 {
+        normal = -normal;
+    }
+
+    // the tangents are the screen axes for a normal facing the camera
+    let up = select(vec3<f32>(1.0, 0.0, 0.0), vec3<f32>(0.0, 1.0, 0.0), abs(normal.y) < 0.99);
+    let tangent = normalize(cross(up, normal));
+    let bitangent = cross(normal, tangent);
+
+    return tangent * corner.x + bitangent * corner.y;
+}
+#endif
 
 @vertex
 fn vertex(vertex: Vertex) -> VertexOutput {
-    let center = vertex.i_pos_size.xyz;
+    let center = instance_position(vertex);
 
     let viewport = view_bindings::view.viewport;
 
     let transform_scale = extract_max_scale(world_from_local);
 
     // Compute world & view position of the point instance (applying the world_from_local matrix)
-    let world_position = mesh_position_local_to_world(world_from_local, vec4<f32>(vertex.i_pos_size.xyz, 1.0));
+    let world_position = mesh_position_local_to_world(world_from_local, vec4<f32>(center, 1.0));
     var view_position = position_world_to_view(world_position.xyz);
+    let world_normal = normal_local_to_world(instance_normal(vertex));
+
+    let clip_state = clip_point(world_position.xyz);
 
 #ifdef IS_OCTREE
     // Get the fov from projection matrix
@@ -202,7 +390,7 @@ fn vertex(vertex: Vertex) -> VertexOutput {
     //   model_view * vec4(octree_node.spacing, 0, 0, 1)
     // ) / octree_node.spacing;
 
-    let max_relative_depth = get_max_relative_depth(vertex.i_pos_size.xyz);
+    let max_relative_depth = get_max_relative_depth(center);
     let attenuation = exp2(max_relative_depth);
     //let attenuation = pow(2.0, max_relative_depth);
 
@@ -214,19 +402,58 @@ fn vertex(vertex: Vertex) -> VertexOutput {
     radius_screen = min(material.max_point_size, radius_screen);
 
 
+#ifdef SHADOW_PASS
+    // the light views have no meaningful pixel size, so the points keep their size in world units
+    let radius = octree_node.spacing * 1.7 / attenuation * transform_scale;
+#else
     let radius = radius_screen / proj_factor;
+#endif
 #else
-    let point_size = select(material.point_size, vertex.i_pos_size.w, material.point_size <= 0.0) * transform_scale;
+    var radius: f32;
+    if material.sizing == SIZING_VIEWPORT {
+        let point_size = select(material.point_size, vertex.i_pos_size.w, material.point_size <= 0.0) * transform_scale;
+
+        // Compute radius to size the point correctly with viewport size
+        radius = point_size / min(viewport[2], viewport[3]);
+    } else {
+        // Number of pixels covered by one view space unit at the point depth, works for both projections
+        let clip_w = position_view_to_clip(view_position).w;
+        let pixels_per_unit = 0.5 * viewport[3] * view_bindings::view.clip_from_view[1][1] / clip_w;
+
+        var radius_screen: f32;
+        if material.sizing == SIZING_FIXED_PIXELS {
+            radius_screen = material.point_size;
+        } else if material.sizing == SIZING_WORLD_SPACE {
+            radius_screen = material.point_size * transform_scale * pixels_per_unit;
+        } else {
+            radius_screen = vertex.i_pos_size.w * material.point_size * transform_scale * pixels_per_unit;
+        }
+
+        radius_screen = max(material.min_point_size, radius_screen);
+        if material.max_point_size > 0.0 {
+            radius_screen = min(material.max_point_size, radius_screen);
+        }
+
+        radius = radius_screen / pixels_per_unit;
+    }
+#endif
 
-    // Compute radius to size the point correctly with viewport size
-    let radius = point_size / min(viewport[2], viewport[3]);
+#ifdef SQUARE_POINT_SHAPE
+    // Enlarge the triangle so that it contains the whole square
+    let corner = vertex.position.xy * 1.2247449;
+#else
+    let corner = vertex.position.xy;
 #endif
 
     // Compute the offset to apply for creating a quad
-    let offset = vertex.position.xy * radius;
+#ifdef SURFEL_GEOMETRY
+    let offset = surfel_offset(world_normal, view_position, corner * radius);
+#else
+    let offset = vec3<f32>(corner * radius, 0.0);
+#endif
 
     // Apply the offset to the view position and compute clip position
-    let clip_position = position_view_to_clip(view_position + vec3<f32>(offset, 0.0));
+    let clip_position = position_view_to_clip(view_position + offset);
 
     var out: VertexOutput;
 
@@ -255,9 +482,28 @@ fn vertex(vertex: Vertex) -> VertexOutput {
 #endif // DEBUG_COLOR
 #endif // IS_OCTREE
 
-    out.uv = vertex.position.xy + vec2(0.5);
+    out.color = vec4<f32>(clip_state_highlight(clip_state, out.color.rgb), out.color.a);
+
+    out.uv = corner + vec2(0.5);
     out.log_depth = log2(-view_position.z);
     out.radius = radius;
+    out.depth = clip_position.z / clip_position.w;
+#ifdef RECEIVE_SHADOWS
+    out.world_position = world_position.xyz;
+#else ifdef LIT_POINTS
+    out.world_position = world_position.xyz;
+#endif
+#ifdef LIT_POINTS
+    out.world_normal = world_normal;
+#endif
+#ifdef PICKING_PASS
+    out.picking = vec2<u32>(picking_draw.id + 1u, vertex.instance_index);
+#endif
+
+#ifdef DEPTH_CLAMP_ORTHO
+    // keep the casters between the light and the near plane of the cascade, with reverse Z
+    out.clip_position.z = min(out.clip_position.z, 1.0);
+#endif
 
 	#ifdef HQ_DEPTH_PASS
 		let original_depth = clip_position.w;
@@ -265,16 +511,124 @@ fn vertex(vertex: Vertex) -> VertexOutput {
 		let adjust = adjusted_depth / original_depth;
 
         view_position *= adjust;
-        view_position += vec3<f32>(offset, 0.0);
+        view_position += offset;
 
         out.clip_position = position_view_to_clip(view_position);
 	#endif
 
+#ifdef PICKING_PASS
+    // draw the picked region at the origin of the picking texture
+    out.clip_position = vec4<f32>(
+        out.clip_position.xy * picking_view.clip_transform.xy
+            + picking_view.clip_transform.zw * out.clip_position.w,
+        out.clip_position.zw,
+    );
+#endif
+
+    // the triangles of the clipped points are collapsed outside of the view
+    if clip_state_is_clipped(clip_state) {
+        out.clip_position = vec4<f32>(2.0, 2.0, 2.0, 1.0);
+    }
+
     return out;
 }
 
+#ifdef RECEIVE_SHADOWS
+// Points keeping this fraction of their color when they are completely in shadow
+const SHADOW_AMBIENT: f32 = 0.4;
+
+// Fraction of the light of a directional light received at a position
+fn directional_light_shadow(light_index: u32, world_position: vec3<f32>, normal: vec3<f32>, view_z: f32) -> f32 {
+    let light = &view_bindings::lights.directional_lights[light_index];
+    if ((*light).flags & DIRECTIONAL_LIGHT_FLAGS_SHADOWS_ENABLED_BIT) == 0u {
+        return 1.0;
+    }
+    return fetch_directional_shadow(light_index, vec4(world_position, 1.0), normal, view_z);
+}
+
+// Fraction of the light of the directional lights received at a position
+fn directional_shadow(world_position: vec3<f32>, view_z: f32) -> f32 {
+    var shadow = 1.0;
+    for (var i = 0u; i < view_bindings::lights.n_directional_lights; i = i + 1u) {
+        // points have no normal, the normal bias pushes them towards the light instead
+        let direction_to_light = view_bindings::lights.directional_lights[i].direction_to_light;
+        shadow = min(shadow, directional_light_shadow(i, world_position, direction_to_light, view_z));
+    }
+    return shadow;
+}
+#endif
+
+#ifdef LIT_POINTS
+// Lambert diffuse lighting of a point by the ambient light and the directional, point and spot
+// lights of the view
+fn lit_color(albedo: vec3<f32>, in: VertexOutput) -> vec3<f32> {
+    let world_position = in.world_position;
+    let view_z = in.view_position.z;
+    let is_orthographic = is_orthographic_view();
+
+    // both sides of the points are lit, as seen from the camera
+    var normal = normalize(in.world_normal);
+    let to_camera = select(
+        view_bindings::view.world_position - world_position,
+        view_bindings::view.world_from_view[2].xyz,
+        is_orthographic,
+    );
+    if dot(normal, to_camera) < 0.0 {
+        normal = -normal;
+    }
+
+    var light = vec3<f32>(0.0);
+
+    for (var i = 0u; i < view_bindings::lights.n_directional_lights; i = i + 1u) {
+        let directional_light = &view_bindings::lights.directional_lights[i];
+        var contribution = (*directional_light).color.rgb
+            * saturate(dot(normal, (*directional_light).direction_to_light));
+#ifdef RECEIVE_SHADOWS
+        contribution *= directional_light_shadow(i, world_position, normal, view_z);
+#endif
+        light += contribution;
+    }
+
+    let cluster_index = fragment_cluster_index(in.clip_position.xy, view_z, is_orthographic);
+    let ranges = unpack_clusterable_object_index_ranges(cluster_index);
+
+    // point lights are followed by the spot lights in the cluster
+    for (var i = ranges.first_point_light_index_offset; i < ranges.first_reflection_probe_index_offset; i = i + 1u) {
+        let point_light = &view_bindings::clusterable_objects.data[get_clusterable_object_id(i)];
+        let light_to_point = (*point_light).position_radius.xyz - world_position;
+        let distance_attenuation = getDistanceAttenuation(
+            dot(light_to_point, light_to_point),
+            (*point_light).color_inverse_square_range.w,
+        );
+        var contribution = (*point_light).color_inverse_square_range.rgb
+            * saturate(dot(normal, normalize(light_to_point)))
+            * distance_attenuation;
+
+        if i >= ranges.first_spot_light_index_offset {
+            // reconstruct the spot direction from x/z and the y-direction flag
+            var spot_direction = vec3<f32>((*point_light).light_custom_data.x, 0.0, (*point_light).light_custom_data.y);
+            spot_direction.y = sqrt(max(0.0, 1.0 - spot_direction.x * spot_direction.x - spot_direction.z * spot_direction.z));
+            if ((*point_light).flags & POINT_LIGHT_FLAGS_SPOT_LIGHT_Y_NEGATIVE) != 0u {
+                spot_direction.y = -spot_direction.y;
+            }
+
+            let cos_angle = dot(-spot_direction, normalize(light_to_point));
+            let spot_attenuation = saturate(cos_angle * (*point_light).light_custom_data.z + (*point_light).light_custom_data.w);
+            contribution *= spot_attenuation * spot_attenuation;
+        }
+
+        light += contribution;
+    }
+
+    let diffuse = albedo / PI * light + albedo * view_bindings::lights.ambient_color.rgb;
+    return diffuse * view_bindings::view.exposure;
+}
+#endif
+
 struct FragmentOutput {
-#ifdef DEPTH_PASS
+#ifdef PICKING_PASS
+    @location(0) picking: vec2<u32>,
+#else ifdef DEPTH_PASS
     #ifdef USE_EDL
     @location(0) depth_texture: vec2<f32>,
     #else // USE EDL
@@ -290,25 +644,57 @@ struct FragmentOutput {
 fn fragment(in: VertexOutput) -> FragmentOutput {
     let u = 2.0 * in.uv.x - 1.0;
     let v = 2.0 * in.uv.y - 1.0;
+#ifdef SQUARE_POINT_SHAPE
+    let cc = max(u*u, v*v);
+#else
     let cc = u*u + v*v;
+#endif
     if(cc > 1.0){
         discard;
     }
 
     // convert the color to linear RGB
-    let color = srgb_to_rgb_simple(in.color.xyz);
+    var color = srgb_to_rgb_simple(in.color.xyz);
+
+#ifdef LIT_POINTS
+    let is_lit = any(in.world_normal != vec3<f32>(0.0));
+    if is_lit {
+        color = lit_color(color, in);
+    }
+#else
+    let is_lit = false;
+#endif
+
+#ifdef RECEIVE_SHADOWS
+    // the lit points are already shadowed by each light
+    if !is_lit {
+        color *= mix(SHADOW_AMBIENT, 1.0, directional_shadow(in.world_position, in.view_position.z));
+    }
+#endif
 
     var output: FragmentOutput;
 
     output.depth = in.clip_position.z;
 
 #ifdef DEPTH_PASS
+    #ifdef PICKING_PASS
+    output.picking = in.picking;
+    #else // PICKING_PASS
+    // the depth texture keeps the actual depth of the points, which is written to the view depth
+    // by the normalize pass
+    #ifdef HQ_DEPTH_PASS
+    let depth = in.depth;
+    #else // HQ_DEPTH_PASS
+    let depth = in.clip_position.z;
+    #endif // HQ_DEPTH_PASS
+
     #ifdef USE_EDL
-        output.depth_texture.r = in.clip_position.z;
+        output.depth_texture.r = depth;
         output.depth_texture.g = in.log_depth;
     #else // USE_EDL
-        output.depth_texture = in.clip_position.z;
+        output.depth_texture = depth;
     #endif // USE_EDL
+    #endif // PICKING_PASS
 
     #ifdef PARABOLOID_POINT_SHAPE
     let radius = in.radius;
@@ -350,3 +736,20 @@ fn fragment(in: VertexOutput) -> FragmentOutput {
 
     return output;
 }
+
+#ifdef SHADOW_PASS
+// Only discards the fragments outside of the point shape, the depth is written by the rasterizer
+@fragment
+fn fragment_shadow(in: VertexOutput) {
+    let u = 2.0 * in.uv.x - 1.0;
+    let v = 2.0 * in.uv.y - 1.0;
+#ifdef SQUARE_POINT_SHAPE
+    let cc = max(u*u, v*v);
+#else
+    let cc = u*u + v*v;
+#endif
+    if(cc > 1.0){
+        discard;
+    }
+}
+#endif
@@ -0,0 +1,223 @@
+//! Draws the point clouds into the shadow maps of the directional lights.
+//!
+//! The points are queued in the bevy [`Shadow`] phase of every cascade of the directional lights
+//! seen by a camera, only when their material has
+//! [`cast_shadows`](crate::point_cloud_material::PointCloudMaterial::cast_shadows) enabled.
+//! Point and spot lights are not supported.
+
+pub mod pipeline;
+
+use bevy_app::prelude::*;
+use bevy_ecs::{
+    change_detection::Tick,
+    prelude::*,
+    query::ROQueryItem,
+    system::{lifetimeless::Read, SystemParamItem},
+};
+use bevy_light::ShadowFilteringMethod;
+use bevy_pbr::{
+    LightEntity, MeshPipelineKey, Shadow, ShadowBatchSetKey, ShadowBinKey, ViewLightEntities,
+};
+use bevy_render::{
+    mesh::allocator::SlabId,
+    render_asset::RenderAssets,
+    render_phase::{
+        AddRenderCommand, BinnedRenderPhaseType, DrawFunctions, InputUniformIndex, PhaseItem,
+        RenderCommand, RenderCommandResult, SetItemPipeline, TrackedRenderPass,
+        ViewBinnedRenderPhases,
+    },
+    render_resource::{BindGroup, BindGroupEntries, PipelineCache, SpecializedRenderPipelines},
+    renderer::RenderDevice,
+    sync_world::MainEntity,
+    view::{ExtractedView, ViewUniformOffset, ViewUniforms},
+    Render, RenderApp, RenderSystems,
+};
+
+use crate::{
+    point_cloud::PointCloud3d,
+    point_cloud_material::PointCloudMaterial3d,
+    pointcloud_octree::asset::data::PointFormat,
+    render::{
+        draw::DrawPointCloud,
+        material::{RenderPointCloudMaterial, SetPointCloudMaterialGroup},
+        point_cloud_uniform::SetPointCloudUniformGroup,
+        shadow_pass::pipeline::{ShadowPipeline, ShadowPipelineKey},
+    },
+};
+
+pub struct ShadowPassPlugin;
+impl Plugin for ShadowPassPlugin {
+    fn build(&self, app: &mut App) {
+        let Some(render_app) = app.get_sub_app_mut(RenderApp) else {
+            return;
+        };
+        render_app
+            .add_render_command::<Shadow, DrawShadowPass>()
+            .init_resource::<SpecializedRenderPipelines<ShadowPipeline>>()
+            .init_resource::<ShadowViewBindGroup>()
+            .add_systems(
+                Render,
+                (
+                    queue_shadow_pass.in_set(RenderSystems::QueueMeshes),
+                    prepare_shadow_view_bind_group.in_set(RenderSystems::PrepareBindGroups),
+                ),
+            );
+    }
+
+    fn finish(&self, app: &mut App) {
+        let Some(render_app) = app.get_sub_app_mut(RenderApp) else {
+            return;
+        };
+        render_app.init_resource::<ShadowPipeline>();
+    }
+}
+
+type DrawShadowPass = (
+    SetItemPipeline,
+    SetShadowViewBindGroup<0>,
+    SetPointCloudUniformGroup<1>,
+    SetPointCloudMaterialGroup<2>,
+    DrawPointCloud,
+);
+
+/// The view uniforms bound for the light views, shared by all of them with a dynamic offset.
+#[derive(Resource, Default)]
+pub struct ShadowViewBindGroup {
+    pub bind_group: Option<BindGroup>,
+}
+
+fn prepare_shadow_view_bind_group(
+    render_device: Res<RenderDevice>,
+    pipeline_cache: Res<PipelineCache>,
+    shadow_pipeline: Res<ShadowPipeline>,
+    view_uniforms: Res<ViewUniforms>,
+    mut shadow_view_bind_group: ResMut<ShadowViewBindGroup>,
+) {
+    shadow_view_bind_group.bind_group = view_uniforms.uniforms.binding().map(|binding| {
+        render_device.create_bind_group(
+            "pcl_shadow_view_bind_group",
+            &pipeline_cache.get_bind_group_layout(&shadow_pipeline.view_layout),
+            &BindGroupEntries::single(binding),
+        )
+    });
+}
+
+pub struct SetShadowViewBindGroup<const I: usize>;
+impl<P: PhaseItem, const I: usize> RenderCommand<P> for SetShadowViewBindGroup<I> {
+    type Param = Res<'static, ShadowViewBindGroup>;
+    type ViewQuery = Read<ViewUniformOffset>;
+    type ItemQuery = ();
+
+    fn render<'w>(
+        _item: &P,
+        view_uniform_offset: ROQueryItem<'w, '_, Self::ViewQuery>,
+        _entity: Option<ROQueryItem<'w, '_, Self::ItemQuery>>,
+        shadow_view_bind_group: SystemParamItem<'w, '_, Self::Param>,
+        pass: &mut TrackedRenderPass<'w>,
+    ) -> RenderCommandResult {
+        let Some(bind_group) = &shadow_view_bind_group.into_inner().bind_group else {
+            return RenderCommandResult::Skip;
+        };
+
+        pass.set_bind_group(I, bind_group, &[view_uniform_offset.offset]);
+
+        RenderCommandResult::Success
+    }
+}
+
+/// The mesh pipeline key bits of the shadow filtering method of a camera, used by the pipelines
+/// of the points receiving shadows.
+pub(crate) fn shadow_filter_method_key(
+    shadow_filter_method: Option<&ShadowFilteringMethod>,
+) -> MeshPipelineKey {
+    match shadow_filter_method.unwrap_or(&ShadowFilteringMethod::default()) {
+        ShadowFilteringMethod::Hardware2x2 => MeshPipelineKey::SHADOW_FILTER_METHOD_HARDWARE_2X2,
+        ShadowFilteringMethod::Gaussian => MeshPipelineKey::SHADOW_FILTER_METHOD_GAUSSIAN,
+        ShadowFilteringMethod::Temporal => MeshPipelineKey::SHADOW_FILTER_METHOD_TEMPORAL,
+    }
+}
+
+/// Returns the light views of the directional light cascades seen by a camera.
+pub(crate) fn directional_light_views<'a>(
+    view_light_entities: &'a ViewLightEntities,
+    light_views: &'a Query<(&LightEntity, &ExtractedView)>,
+) -> impl Iterator<Item = (Entity, &'a ExtractedView)> + 'a {
+    view_light_entities
+        .lights
+        .iter()
+        .filter_map(|&light_view_entity| {
+            let (light_entity, extracted_view) = light_views.get(light_view_entity).ok()?;
+            matches!(light_entity, LightEntity::Directional { .. })
+                .then_some((light_view_entity, extracted_view))
+        })
+}
+
+#[allow(clippy::too_many_arguments)]
+fn queue_shadow_pass(
+    shadow_draw_functions: Res<DrawFunctions<Shadow>>,
+    mut pipelines: ResMut<SpecializedRenderPipelines<ShadowPipeline>>,
+    pipeline_cache: Res<PipelineCache>,
+    shadow_pipeline: Res<ShadowPipeline>,
+    point_clouds_3d: Query<(Entity, &MainEntity, &PointCloud3d, &PointCloudMaterial3d)>,
+    render_materials: Res<RenderAssets<RenderPointCloudMaterial>>,
+    mut shadow_render_phases: ResMut<ViewBinnedRenderPhases<Shadow>>,
+    cameras: Query<&ViewLightEntities>,
+    light_views: Query<(&LightEntity, &ExtractedView)>,
+    mut next_tick: Local<Tick>,
+) {
+    let draw_shadow = shadow_draw_functions.read().id::<DrawShadowPass>();
+
+    for view_light_entities in &cameras {
+        for (_, light_view) in directional_light_views(view_light_entities, &light_views) {
+            let Some(shadow_phase) = shadow_render_phases.get_mut(&light_view.retained_view_entity)
+            else {
+                continue;
+            };
+
+            // The point clouds outside of the camera view can still cast shadows inside of it,
+            // the chunks are culled against the light frustum when drawn
+            for (render_entity, main_entity, point_cloud_3d, point_cloud_material_3d) in
+                &point_clouds_3d
+            {
+                let Some(material) = render_materials.get(point_cloud_material_3d) else {
+                    continue;
+                };
+                if !material.cast_shadows {
+                    continue;
+                }
+
+                let shadow_key = ShadowPipelineKey {
+                    is_octree: false,
+                    shape: material.shape,
+                    geometry: material.geometry,
+                    point_format: PointFormat::Full,
+                    depth_clamp_ortho: true,
+                };
+
+                let pipeline_id =
+                    pipelines.specialize(&pipeline_cache, &shadow_pipeline, shadow_key);
+
+                // Bump the change tick in order to force Bevy to rebuild the bin.
+                let this_tick = next_tick.get() + 1;
+                next_tick.set(this_tick);
+
+                shadow_phase.add(
+                    ShadowBatchSetKey {
+                        pipeline: pipeline_id,
+                        draw_function: draw_shadow,
+                        material_bind_group_index: None,
+                        vertex_slab: SlabId::default(),
+                        index_slab: None,
+                    },
+                    ShadowBinKey {
+                        asset_id: point_cloud_3d.0.id().untyped(),
+                    },
+                    (render_entity, *main_entity),
+                    InputUniformIndex::default(),
+                    BinnedRenderPhaseType::NonMesh,
+                    *next_tick,
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,225 @@
+use bevy_asset::prelude::*;
+use bevy_core_pipeline::core_3d::CORE_3D_DEPTH_FORMAT;
+use bevy_ecs::prelude::*;
+use bevy_mesh::{PrimitiveTopology, VertexBufferLayout, VertexFormat};
+use bevy_render::{
+    render_resource::{
+        binding_types::uniform_buffer, AsBindGroup, BindGroupLayoutDescriptor,
+        BindGroupLayoutEntries, CompareFunction, DepthBiasState, DepthStencilState, Face,
+        FragmentState, FrontFace, MultisampleState, PolygonMode, PrimitiveState,
+        RenderPipelineDescriptor, ShaderStages, SpecializedRenderPipeline, StencilState,
+        VertexAttribute, VertexState, VertexStepMode,
+    },
+    renderer::RenderDevice,
+    view::ViewUniform,
+};
+use bevy_shader::Shader;
+use bevy_utils::default;
+
+use crate::{
+    point_cloud_material::{PointGeometry, PointShape},
+    pointcloud_octree::{
+        asset::data::PointFormat,
+        extract::{PointCloudNodeDataUniform, PointCloudOctreeUniform},
+        render::prepare::visible_nodes_layout_entries,
+    },
+    render::{
+        clip_volume::clip_volumes_shader_defs, material::point_cloud_material_layout_entries,
+        point_cloud::PointCloudInstance, point_cloud_uniform::PointCloudUniform,
+        POINTCLOUD_SHADER_HANDLE,
+    },
+};
+
+/// Draws the points into the shadow maps of the lights.
+#[derive(Resource)]
+pub struct ShadowPipeline {
+    shader_handle: Handle<Shader>,
+    /// The light views have no mesh view bind group, only the view uniform is bound
+    pub(crate) view_layout: BindGroupLayoutDescriptor,
+    point_cloud_layout: BindGroupLayoutDescriptor,
+    point_cloud_material_layout: BindGroupLayoutDescriptor,
+    point_cloud_octree_visible_nodes_layout: BindGroupLayoutDescriptor,
+    point_cloud_octree_node_data_layout: BindGroupLayoutDescriptor,
+    point_cloud_octree_data_layout: BindGroupLayoutDescriptor,
+}
+
+impl FromWorld for ShadowPipeline {
+    fn from_world(world: &mut World) -> Self {
+        let render_device = world.resource::<RenderDevice>();
+
+        Self {
+            shader_handle: POINTCLOUD_SHADER_HANDLE,
+            view_layout: BindGroupLayoutDescriptor {
+                label: "pcl_shadow_view_layout".into(),
+                entries: BindGroupLayoutEntries::single(
+                    ShaderStages::VERTEX_FRAGMENT,
+                    uniform_buffer::<ViewUniform>(true),
+                )
+                .to_vec(),
+            },
+            point_cloud_layout: PointCloudUniform::bind_group_layout_descriptor(render_device),
+            point_cloud_material_layout: BindGroupLayoutDescriptor {
+                label: "pcl_material".into(),
+                entries: point_cloud_material_layout_entries(),
+            },
+            point_cloud_octree_visible_nodes_layout: BindGroupLayoutDescriptor {
+                label: "pcl_octree_visible_nodes_layout".into(),
+                entries: visible_nodes_layout_entries(),
+            },
+            point_cloud_octree_node_data_layout: BindGroupLayoutDescriptor {
+                label: "pcl_octree_node_data".into(),
+                entries: BindGroupLayoutEntries::single(
+                    ShaderStages::VERTEX,
+                    uniform_buffer::<PointCloudNodeDataUniform>(false),
+                )
+                .to_vec(),
+            },
+            point_cloud_octree_data_layout: BindGroupLayoutDescriptor {
+                label: "layout_pcl_octree_layout".into(),
+                entries: BindGroupLayoutEntries::single(
+                    ShaderStages::VERTEX,
+                    uniform_buffer::<PointCloudOctreeUniform>(false),
+                )
+                .to_vec(),
+            },
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Hash, Clone)]
+pub struct ShadowPipelineKey {
+    pub is_octree: bool,
+    pub shape: PointShape,
+    pub geometry: PointGeometry,
+    /// Layout of the octree points
+    pub point_format: PointFormat,
+    /// Clamp the depth of the casters in front of the near plane of an orthographic light view
+    pub depth_clamp_ortho: bool,
+}
+
+impl SpecializedRenderPipeline for ShadowPipeline {
+    type Key = ShadowPipelineKey;
+
+    fn specialize(&self, key: Self::Key) -> RenderPipelineDescriptor {
+        let vertex_buffer_layout = VertexBufferLayout {
+            array_stride: VertexFormat::Float32x4.size(),
+            step_mode: VertexStepMode::Vertex,
+            attributes: vec![VertexAttribute {
+                format: VertexFormat::Float32x3,
+                offset: 0,
+                shader_location: 0,
+            }],
+        };
+
+        let instance_buffer_layout = if key.is_octree {
+            key.point_format.instance_buffer_layout()
+        } else {
+            VertexBufferLayout {
+                array_stride: size_of::<PointCloudInstance>() as u64,
+                step_mode: VertexStepMode::Instance,
+                attributes: vec![
+                    // Point position
+                    VertexAttribute {
+                        format: VertexFormat::Float32x4,
+                        offset: 0,
+                        shader_location: 1,
+                    },
+                    // Point color
+                    VertexAttribute {
+                        format: VertexFormat::Float32x4,
+                        offset: VertexFormat::Float32x4.size(),
+                        shader_location: 2,
+                    },
+                    // Point normal
+                    VertexAttribute {
+                        format: VertexFormat::Float32x3,
+                        offset: VertexFormat::Float32x4.size() * 2,
+                        shader_location: 3,
+                    },
+                ],
+            }
+        };
+
+        let mut shader_defs = vec!["SHADOW_PASS".into()];
+        // the clip volumes are bound with the material
+        shader_defs.extend(clip_volumes_shader_defs(2, 1));
+
+        // the paraboloid offset is not worth it in the shadow maps
+        if key.shape == PointShape::Square {
+            shader_defs.push("SQUARE_POINT_SHAPE".into());
+        }
+        if key.geometry == PointGeometry::Surfel {
+            shader_defs.push("SURFEL_GEOMETRY".into());
+        }
+        if key.depth_clamp_ortho {
+            shader_defs.push("DEPTH_CLAMP_ORTHO".into());
+        }
+        if key.is_octree {
+            shader_defs.push("IS_OCTREE".into());
+            #[cfg(feature = "webgl")]
+            shader_defs.push("VISIBLE_NODES_TEXTURE".into());
+            if let Some(shader_def) = key.point_format.shader_def() {
+                shader_defs.push("QUANTIZED_POSITIONS".into());
+                shader_defs.push(shader_def.into());
+            }
+        }
+
+        let mut layout = vec![
+            // Bind group 0 is the view uniform
+            self.view_layout.clone(),
+            // Bind group 1 is our point cloud uniform
+            self.point_cloud_layout.clone(),
+            // Bind group 2 is the point cloud material
+            self.point_cloud_material_layout.clone(),
+        ];
+
+        if key.is_octree {
+            layout.push(self.point_cloud_octree_visible_nodes_layout.clone());
+            layout.push(self.point_cloud_octree_node_data_layout.clone());
+            layout.push(self.point_cloud_octree_data_layout.clone());
+        }
+
+        let mut buffers = vec![vertex_buffer_layout, instance_buffer_layout];
+        if key.is_octree
+            && let Some(node_bounds_buffer_layout) = key.point_format.node_bounds_buffer_layout()
+        {
+            buffers.push(node_bounds_buffer_layout);
+        }
+
+        RenderPipelineDescriptor {
+            label: Some("pcl_shadow_pass_pipeline".into()),
+            layout,
+            push_constant_ranges: vec![],
+            vertex: VertexState {
+                shader: self.shader_handle.clone(),
+                shader_defs: shader_defs.clone(),
+                entry_point: Some("vertex".into()),
+                buffers,
+            },
+            fragment: Some(FragmentState {
+                shader: self.shader_handle.clone(),
+                shader_defs,
+                entry_point: Some("fragment_shadow".into()),
+                // Only the depth is written to the shadow maps
+                targets: vec![],
+            }),
+            primitive: PrimitiveState {
+                topology: PrimitiveTopology::TriangleList,
+                front_face: FrontFace::Ccw,
+                cull_mode: Some(Face::Back),
+                polygon_mode: PolygonMode::Fill,
+                ..default()
+            },
+            // The shadow maps use the same format as the view depth
+            depth_stencil: Some(DepthStencilState {
+                format: CORE_3D_DEPTH_FORMAT,
+                depth_write_enabled: true,
+                depth_compare: CompareFunction::GreaterEqual,
+                stencil: StencilState::default(),
+                bias: DepthBiasState::default(),
+            }),
+            multisample: MultisampleState::default(),
+            zero_initialize_workgroup_memory: false,
+        }
+    }
+}
@@ -0,0 +1,748 @@
+//! Cut and fill volumes between the surface of the point clouds and a reference.
+//!
+//! A [`PointCloudVolume`] rasterizes the points inside a polygon into a [`HeightGrid`], keeping
+//! the minimum, maximum and mean height of each cell, and integrates the heights of the cells
+//! against a plane or the surface measured by another volume. The points are queried like the
+//! [`PointCloudClipVolume`] polygons select them, the missing octree nodes of the polygon being
+//! loaded.
+//!
+//! The heights of the empty cells are estimated from their neighbours, the spread of the
+//! neighbour heights bounding the error reported in [`PointCloudVolumeResult::uncertainty`].
+
+use std::{cell::RefCell, sync::Arc};
+
+use bevy_app::prelude::*;
+use bevy_ecs::{entity::EntityHashMap, prelude::*};
+use bevy_log::prelude::*;
+use bevy_math::{prelude::*, Affine3A};
+use bevy_platform::collections::HashSet;
+use bevy_reflect::{std_traits::ReflectDefault, Reflect};
+use bevy_transform::prelude::*;
+
+use crate::{
+    clip_volume::{polygon_contains, ClipShape, GlobalClipVolume, PointCloudClipVolume},
+    octree::{
+        server::process::process_octree_load_tasks, storage::NodeId,
+        visibility::OctreeVisibilitySystems,
+    },
+    pointcloud_octree::asset::data::PointCloudNodeData,
+    query::{PointCloudRegionQuery, RegionQuerySettings},
+};
+
+/// Maximum number of cells of a [`HeightGrid`] along each axis, the cell size being increased
+/// beyond.
+pub const MAX_HEIGHT_GRID_SIZE: u32 = 4096;
+
+/// Computes the [`PointCloudVolume`]s.
+pub struct PointCloudVolumePlugin;
+
+impl Plugin for PointCloudVolumePlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<PointCloudVolume>().add_systems(
+            PostUpdate,
+            // the load requests are cleared by the visibility check
+            update_volumes
+                .after(TransformSystems::Propagate)
+                .after(OctreeVisibilitySystems::CheckOctreeNodesVisibility)
+                .before(process_octree_load_tasks::<PointCloudNodeData>),
+        );
+    }
+}
+
+/// The height of a cell integrated by a [`PointCloudVolume`].
+#[derive(Reflect, Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum HeightStatistic {
+    Min,
+    Max,
+    #[default]
+    Mean,
+}
+
+/// The surface the heights of a [`PointCloudVolume`] are compared to.
+#[derive(Reflect, Debug, Clone, PartialEq)]
+pub enum VolumeReference {
+    /// Horizontal plane at a height along the Y axis of the volume.
+    Height(f32),
+    /// Plane going through a point, in the space of the volume.
+    Plane { point: Vec3, normal: Dir3 },
+    /// The surface measured by another [`PointCloudVolume`] entity, for instance an earlier
+    /// survey selected with [`PointCloudVolume::entities`].
+    ///
+    /// Both volumes must have the same vertical axis.
+    Surface(Entity),
+}
+
+impl Default for VolumeReference {
+    fn default() -> Self {
+        VolumeReference::Height(0.0)
+    }
+}
+
+/// Measures the volume between the points inside a polygon and a reference surface.
+///
+/// The polygon lies in the XZ plane of the volume, which is placed by its transform, and the
+/// heights are measured along its Y axis, like a [`ClipShape::Polygon`].
+#[derive(Component, Reflect, Debug, Clone)]
+#[reflect(Component, Default, Clone)]
+#[require(Transform, PointCloudVolumeResult)]
+pub struct PointCloudVolume {
+    /// X and Z coordinates of a simple polygon, in either winding order
+    pub vertices: Vec<Vec2>,
+    /// The points below are ignored
+    pub min_height: f32,
+    /// The points above are ignored
+    pub max_height: f32,
+    /// Size of the cells of the [`HeightGrid`], in the units of the volume
+    pub cell_size: f32,
+    pub statistic: HeightStatistic,
+    pub reference: VolumeReference,
+    /// Deepest level of the octree nodes whose points are rasterized, `None` for all the levels
+    pub max_depth: Option<u32>,
+    /// Whether to load the missing octree nodes of the polygon, up to `max_depth`
+    pub load_missing_nodes: bool,
+    /// Whether to ignore the points discarded by the [`PointCloudClipVolume`]s
+    pub respect_clip_volumes: bool,
+    /// The point cloud and octree entities measured, all of them if empty
+    pub entities: Vec<Entity>,
+}
+
+impl Default for PointCloudVolume {
+    fn default() -> Self {
+        Self {
+            vertices: Vec::new(),
+            min_height: f32::NEG_INFINITY,
+            max_height: f32::INFINITY,
+            cell_size: 0.5,
+            statistic: HeightStatistic::default(),
+            reference: VolumeReference::default(),
+            max_depth: None,
+            load_missing_nodes: true,
+            respect_clip_volumes: true,
+            entities: Vec::new(),
+        }
+    }
+}
+
+impl PointCloudVolume {
+    pub fn new(vertices: impl IntoIterator<Item = Vec2>, reference: VolumeReference) -> Self {
+        Self {
+            vertices: vertices.into_iter().collect(),
+            reference,
+            ..Default::default()
+        }
+    }
+
+    pub fn with_cell_size(mut self, cell_size: f32) -> Self {
+        self.cell_size = cell_size;
+        self
+    }
+
+    pub fn with_entities(mut self, entities: impl IntoIterator<Item = Entity>) -> Self {
+        self.entities = entities.into_iter().collect();
+        self
+    }
+}
+
+/// The heights of the points of a [`HeightGrid`] cell.
+#[derive(Debug, Clone, Copy)]
+pub struct HeightCell {
+    pub min: f32,
+    pub max: f32,
+    pub sum: f64,
+    pub count: u32,
+}
+
+impl Default for HeightCell {
+    fn default() -> Self {
+        Self {
+            min: f32::INFINITY,
+            max: f32::NEG_INFINITY,
+            sum: 0.0,
+            count: 0,
+        }
+    }
+}
+
+impl HeightCell {
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    pub fn mean(&self) -> Option<f32> {
+        (!self.is_empty()).then(|| (self.sum / self.count as f64) as f32)
+    }
+
+    pub fn height(&self, statistic: HeightStatistic) -> Option<f32> {
+        if self.is_empty() {
+            return None;
+        }
+        match statistic {
+            HeightStatistic::Min => Some(self.min),
+            HeightStatistic::Max => Some(self.max),
+            HeightStatistic::Mean => self.mean(),
+        }
+    }
+
+    fn insert(&mut self, height: f32) {
+        self.min = self.min.min(height);
+        self.max = self.max.max(height);
+        self.sum += height as f64;
+        self.count += 1;
+    }
+}
+
+/// A regular grid of the XZ plane, storing the heights of the points of each cell.
+#[derive(Debug, Clone, Default)]
+pub struct HeightGrid {
+    /// Corner of the first cell
+    pub origin: Vec2,
+    pub cell_size: f32,
+    /// Number of cells along X and Z
+    pub size: UVec2,
+    /// The cells, row by row along X
+    pub cells: Vec<HeightCell>,
+}
+
+impl HeightGrid {
+    /// An empty grid covering a rectangle, with at most [`MAX_HEIGHT_GRID_SIZE`] cells along each
+    /// axis.
+    pub fn new(min: Vec2, max: Vec2, cell_size: f32) -> Self {
+        let extent = (max - min).max(Vec2::ZERO);
+        let cell_size = cell_size
+            .max(extent.max_element() / MAX_HEIGHT_GRID_SIZE as f32)
+            .max(f32::EPSILON);
+        let size = (extent / cell_size).ceil().as_uvec2().max(UVec2::ONE);
+
+        Self {
+            origin: min,
+            cell_size,
+            size,
+            cells: vec![HeightCell::default(); (size.x * size.y) as usize],
+        }
+    }
+
+    /// The cell containing a position.
+    pub fn cell(&self, position: Vec2) -> Option<UVec2> {
+        let cell = ((position - self.origin) / self.cell_size).floor();
+        (cell.cmpge(Vec2::ZERO).all() && cell.cmplt(self.size.as_vec2()).all())
+            .then(|| cell.as_uvec2())
+    }
+
+    pub fn cell_center(&self, cell: UVec2) -> Vec2 {
+        self.origin + (cell.as_vec2() + 0.5) * self.cell_size
+    }
+
+    pub fn get(&self, cell: UVec2) -> &HeightCell {
+        &self.cells[(cell.y * self.size.x + cell.x) as usize]
+    }
+
+    /// The height of the cell containing a position.
+    pub fn sample(&self, position: Vec2, statistic: HeightStatistic) -> Option<f32> {
+        self.get(self.cell(position)?).height(statistic)
+    }
+
+    pub fn insert(&mut self, position: Vec2, height: f32) {
+        if let Some(cell) = self.cell(position) {
+            let index = (cell.y * self.size.x + cell.x) as usize;
+            self.cells[index].insert(height);
+        }
+    }
+
+    /// Range of the heights of all the cells.
+    pub fn height_range(&self, statistic: HeightStatistic) -> Option<(f32, f32)> {
+        self.cells
+            .iter()
+            .filter_map(|cell| cell.height(statistic))
+            .fold(None, |range, height| {
+                let (min, max) = range.unwrap_or((height, height));
+                Some((min.min(height), max.max(height)))
+            })
+    }
+
+    /// The height of a cell, estimated from the mean of its closest non-empty neighbours if it is
+    /// empty, and the spread of the heights it was estimated from.
+    pub fn estimate(&self, cell: UVec2, statistic: HeightStatistic) -> Option<(f32, f32)> {
+        const MAX_RING: i32 = 2;
+
+        if let Some(height) = self.get(cell).height(statistic) {
+            return Some((height, 0.0));
+        }
+        for ring in 1..=MAX_RING {
+            let (mut sum, mut count, mut min, mut max) = (0.0, 0, f32::INFINITY, f32::NEG_INFINITY);
+            for y in -ring..=ring {
+                for x in -ring..=ring {
+                    if x.abs() != ring && y.abs() != ring {
+                        continue;
+                    }
+                    let neighbour = cell.as_ivec2() + IVec2::new(x, y);
+                    if neighbour.cmplt(IVec2::ZERO).any()
+                        || neighbour.cmpge(self.size.as_ivec2()).any()
+                    {
+                        continue;
+                    }
+                    if let Some(height) = self.get(neighbour.as_uvec2()).height(statistic) {
+                        sum += height;
+                        count += 1;
+                        min = min.min(height);
+                        max = max.max(height);
+                    }
+                }
+            }
+            if count > 0 {
+                return Some((sum / count as f32, max - min));
+            }
+        }
+        None
+    }
+}
+
+/// The volume between the points of a [`PointCloudVolume`] and its reference, in the units of
+/// the volume.
+#[derive(Component, Debug, Clone, Default)]
+pub struct PointCloudVolumeResult {
+    pub grid: Arc<HeightGrid>,
+    /// Volume of the surface above the reference
+    pub cut: f64,
+    /// Volume of the surface below the reference
+    pub fill: f64,
+    /// Area of the cells whose center is inside the polygon
+    pub area: f64,
+    /// Number of cells whose center is inside the polygon
+    pub cell_count: usize,
+    /// Number of these cells without points, or without reference height
+    pub empty_cell_count: usize,
+    /// Number of the empty cells without neighbours to estimate their height from, which are
+    /// ignored
+    pub unresolved_cell_count: usize,
+    /// Bound of the error of the cut and fill volumes due to the empty cells
+    pub uncertainty: f64,
+    /// Whether all the octree nodes of the polygon are loaded, up to the maximum depth
+    pub complete: bool,
+}
+
+impl PointCloudVolumeResult {
+    /// Cut minus fill.
+    pub fn net(&self) -> f64 {
+        self.cut - self.fill
+    }
+}
+
+/// The reference heights of a volume, in its space.
+enum ReferenceHeights {
+    Plane {
+        point: Vec3,
+        normal: Vec3,
+    },
+    Surface {
+        grid: Arc<HeightGrid>,
+        reference_from_volume: Affine3A,
+    },
+}
+
+impl ReferenceHeights {
+    /// The reference height above a position of the XZ plane of the volume, and its spread.
+    fn estimate(&self, position: Vec2, statistic: HeightStatistic) -> Option<(f32, f32)> {
+        match self {
+            ReferenceHeights::Plane { point, normal } => {
+                if normal.y.abs() <= f32::EPSILON {
+                    return None;
+                }
+                let offset = position - point.xz();
+                Some((point.y - offset.dot(normal.xz()) / normal.y, 0.0))
+            }
+            ReferenceHeights::Surface {
+                grid,
+                reference_from_volume,
+            } => {
+                let reference_position =
+                    reference_from_volume.transform_point3(Vec3::new(position.x, 0.0, position.y));
+                let (height, spread) =
+                    grid.estimate(grid.cell(reference_position.xz())?, statistic)?;
+                let volume_position = reference_from_volume
+                    .inverse()
+                    .transform_point3(reference_position.with_y(height));
+                Some((volume_position.y, spread))
+            }
+        }
+    }
+}
+
+/// Integrates the heights of a grid against the reference, inside the polygon.
+fn integrate(
+    volume: &PointCloudVolume,
+    result: &mut PointCloudVolumeResult,
+    reference: &ReferenceHeights,
+) {
+    let grid = result.grid.clone();
+    let cell_area = grid.cell_size as f64 * grid.cell_size as f64;
+    let (min, max) = grid.height_range(volume.statistic).unwrap_or_default();
+
+    *result = PointCloudVolumeResult {
+        grid: grid.clone(),
+        complete: result.complete,
+        ..Default::default()
+    };
+    for y in 0..grid.size.y {
+        for x in 0..grid.size.x {
+            let cell = UVec2::new(x, y);
+            let center = grid.cell_center(cell);
+            if !polygon_contains(&volume.vertices, center) {
+                continue;
+            }
+            result.cell_count += 1;
+
+            let surface = grid.estimate(cell, volume.statistic);
+            let reference = reference.estimate(center, volume.statistic);
+            let (Some((surface, surface_spread)), Some((reference, reference_spread))) =
+                (surface, reference)
+            else {
+                result.empty_cell_count += 1;
+                result.unresolved_cell_count += 1;
+                result.uncertainty += cell_area * (max - min) as f64;
+                continue;
+            };
+            if surface_spread > 0.0 || reference_spread > 0.0 || grid.get(cell).is_empty() {
+                result.empty_cell_count += 1;
+                result.uncertainty += cell_area * (surface_spread + reference_spread) as f64;
+            }
+
+            let difference = (surface - reference) as f64;
+            if difference > 0.0 {
+                result.cut += difference * cell_area;
+            } else {
+                result.fill -= difference * cell_area;
+            }
+        }
+    }
+    result.area = result.cell_count as f64 * cell_area;
+}
+
+#[allow(clippy::type_complexity)]
+fn update_volumes(
+    mut volumes: Query<(
+        Entity,
+        Ref<PointCloudVolume>,
+        Ref<GlobalTransform>,
+        &mut PointCloudVolumeResult,
+    )>,
+    mut query: PointCloudRegionQuery,
+    mut updated: Local<HashSet<Entity>>,
+    mut rasterized: Local<EntityHashMap<HashSet<(Entity, Option<NodeId>)>>>,
+) {
+    #[cfg(feature = "trace")]
+    let _span = info_span!("update_volumes").entered();
+
+    // new nodes may have been loaded
+    let octrees_changed = query.octrees_changed();
+
+    updated.clear();
+    rasterized.retain(|entity, _| volumes.contains(*entity));
+    for (entity, volume, global_transform, mut result) in &mut volumes {
+        // the points of the newly loaded nodes are added to the grid, which is only rebuilt when
+        // the volume changes
+        let rebuild = volume.is_changed() || global_transform.is_changed();
+        let rasterize = rebuild || (!result.complete && octrees_changed);
+        if !rasterize && result.complete {
+            continue;
+        }
+        if volume.vertices.len() < 3 {
+            *result = PointCloudVolumeResult::default();
+            rasterized.remove(&entity);
+            continue;
+        }
+        let rasterized = rasterized.entry(entity).or_default();
+        if rebuild {
+            rasterized.clear();
+        }
+
+        let region = GlobalClipVolume::new(
+            &PointCloudClipVolume {
+                shape: ClipShape::polygon(
+                    volume.vertices.iter().copied(),
+                    volume.min_height,
+                    volume.max_height,
+                ),
+                ..Default::default()
+            },
+            &global_transform,
+        );
+        let visited = RefCell::new(Vec::new());
+        let settings = RegionQuerySettings {
+            max_depth: volume.max_depth,
+            min_spacing: None,
+            load_missing_nodes: volume.load_missing_nodes,
+            respect_clip_volumes: volume.respect_clip_volumes,
+            visit_points: rasterize,
+            filter: &|entity| volume.entities.is_empty() || volume.entities.contains(&entity),
+            skip_points: &|entity, node_id| {
+                let skipped = rasterized.contains(&(entity, node_id));
+                if !skipped {
+                    visited.borrow_mut().push((entity, node_id));
+                }
+                skipped
+            },
+        };
+
+        let mut grid = if rebuild {
+            let (min, max) = volume
+                .vertices
+                .iter()
+                .fold((Vec2::MAX, Vec2::MIN), |(min, max), vertex| {
+                    (min.min(*vertex), max.max(*vertex))
+                });
+            let grid = HeightGrid::new(min, max, volume.cell_size);
+            if grid.cell_size > volume.cell_size {
+                warn_once!(
+                    "The cells of point cloud volumes are enlarged to at most {} cells per axis",
+                    MAX_HEIGHT_GRID_SIZE
+                );
+            }
+            Some(grid)
+        } else {
+            None
+        };
+
+        let status = query.query(&region, &settings, |point| {
+            if !region.contains_point(point.world_position) {
+                return;
+            }
+            let position = region
+                .volume_from_world
+                .transform_point3(point.world_position);
+            // the grid of the result is only copied if it is shared, for instance as a reference
+            grid.get_or_insert_with(|| Arc::unwrap_or_clone(std::mem::take(&mut result.grid)))
+                .insert(position.xz(), position.y);
+        });
+        result.complete = status.complete();
+
+        let visited = visited.into_inner();
+        if rebuild || !visited.is_empty() {
+            rasterized.extend(visited);
+            if let Some(grid) = grid {
+                result.grid = Arc::new(grid);
+            }
+            updated.insert(entity);
+        }
+    }
+
+    // integrated after all the grids are rasterized, as they may be the reference of others
+    let mut integrated = Vec::new();
+    for (entity, volume, global_transform, result) in &volumes {
+        let reference = match &volume.reference {
+            VolumeReference::Height(height) => ReferenceHeights::Plane {
+                point: Vec3::Y * *height,
+                normal: Vec3::Y,
+            },
+            VolumeReference::Plane { point, normal } => ReferenceHeights::Plane {
+                point: *point,
+                normal: **normal,
+            },
+            VolumeReference::Surface(reference) => {
+                let Ok((_, _, reference_transform, reference_result)) = volumes.get(*reference)
+                else {
+                    continue;
+                };
+                if !updated.contains(reference) && !updated.contains(&entity) {
+                    continue;
+                }
+                ReferenceHeights::Surface {
+                    grid: reference_result.grid.clone(),
+                    reference_from_volume: reference_transform.affine().inverse()
+                        * global_transform.affine(),
+                }
+            }
+        };
+        if !updated.contains(&entity) && !matches!(volume.reference, VolumeReference::Surface(_)) {
+            continue;
+        }
+
+        let mut result = result.clone();
+        integrate(&volume, &mut result, &reference);
+        integrated.push((entity, result));
+    }
+
+    for (entity, result) in integrated {
+        if let Ok((_, _, _, mut volume_result)) = volumes.get_mut(entity) {
+            *volume_result = result;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SQUARE: [Vec2; 4] = [
+        Vec2::new(0.0, 0.0),
+        Vec2::new(4.0, 0.0),
+        Vec2::new(4.0, 4.0),
+        Vec2::new(0.0, 4.0),
+    ];
+
+    /// A 4×4 grid of unit cells, the cells of the left half at `left` and the others at `right`.
+    fn step_grid(left: f32, right: f32) -> HeightGrid {
+        let mut grid = HeightGrid::new(Vec2::ZERO, Vec2::splat(4.0), 1.0);
+        for y in 0..4 {
+            for x in 0..4 {
+                let center = grid.cell_center(UVec2::new(x, y));
+                grid.insert(center, if x < 2 { left } else { right });
+            }
+        }
+        grid
+    }
+
+    fn integrated(grid: HeightGrid, reference: &ReferenceHeights) -> PointCloudVolumeResult {
+        let volume = PointCloudVolume::new(SQUARE, VolumeReference::default());
+        let mut result = PointCloudVolumeResult {
+            grid: Arc::new(grid),
+            ..Default::default()
+        };
+        integrate(&volume, &mut result, reference);
+        result
+    }
+
+    #[test]
+    fn estimate_empty_cells_from_neighbours() {
+        let mut grid = HeightGrid::new(Vec2::ZERO, Vec2::splat(6.0), 1.0);
+        grid.insert(Vec2::new(1.5, 2.5), 1.0);
+        grid.insert(Vec2::new(3.5, 2.5), 3.0);
+
+        // the cell between both points is estimated from the closest ring
+        assert_eq!(
+            grid.estimate(UVec2::new(2, 2), HeightStatistic::Mean),
+            Some((2.0, 2.0))
+        );
+        // the filled cells are exact
+        assert_eq!(
+            grid.estimate(UVec2::new(1, 2), HeightStatistic::Mean),
+            Some((1.0, 0.0))
+        );
+        // the second ring reaches the point on the left
+        assert_eq!(
+            grid.estimate(UVec2::new(0, 0), HeightStatistic::Mean),
+            Some((1.0, 0.0))
+        );
+        // beyond two rings, the height is unknown
+        assert_eq!(grid.estimate(UVec2::new(5, 5), HeightStatistic::Mean), None);
+        assert_eq!(
+            HeightGrid::new(Vec2::ZERO, Vec2::ONE, 1.0).estimate(UVec2::ZERO, HeightStatistic::Min),
+            None
+        );
+    }
+
+    #[test]
+    fn estimate_statistics() {
+        let mut grid = HeightGrid::new(Vec2::ZERO, Vec2::ONE, 1.0);
+        for height in [1.0, 2.0, 6.0] {
+            grid.insert(Vec2::splat(0.5), height);
+        }
+        assert_eq!(
+            grid.estimate(UVec2::ZERO, HeightStatistic::Min),
+            Some((1.0, 0.0))
+        );
+        assert_eq!(
+            grid.estimate(UVec2::ZERO, HeightStatistic::Max),
+            Some((6.0, 0.0))
+        );
+        assert_eq!(
+            grid.estimate(UVec2::ZERO, HeightStatistic::Mean),
+            Some((3.0, 0.0))
+        );
+    }
+
+    #[test]
+    fn integrate_against_height() {
+        let result = integrated(
+            step_grid(2.0, -1.0),
+            &ReferenceHeights::Plane {
+                point: Vec3::ZERO,
+                normal: Vec3::Y,
+            },
+        );
+        assert_eq!(result.cell_count, 16);
+        assert_eq!(result.area, 16.0);
+        assert_eq!(result.cut, 16.0);
+        assert_eq!(result.fill, 8.0);
+        assert_eq!(result.net(), 8.0);
+        assert_eq!(result.empty_cell_count, 0);
+        assert_eq!(result.uncertainty, 0.0);
+    }
+
+    #[test]
+    fn integrate_against_tilted_plane() {
+        // the plane rises by 1 per unit along X, from 0 at the left border to 4 at the right one
+        let result = integrated(
+            step_grid(2.0, 2.0),
+            &ReferenceHeights::Plane {
+                point: Vec3::ZERO,
+                normal: Vec3::new(-1.0, 1.0, 0.0).normalize(),
+            },
+        );
+        // columns at 0.5 and 1.5 are below the surface, 2.5 and 3.5 above
+        assert!((result.cut - 8.0).abs() < 1e-5, "{}", result.cut);
+        assert!((result.fill - 8.0).abs() < 1e-5, "{}", result.fill);
+    }
+
+    #[test]
+    fn integrate_against_surface() {
+        let mut grid = step_grid(2.0, 2.0);
+        // an empty cell surrounded by cells at the same height is estimated exactly
+        grid.cells[5] = HeightCell::default();
+        let result = integrated(
+            grid,
+            &ReferenceHeights::Surface {
+                grid: Arc::new(step_grid(1.0, 3.0)),
+                reference_from_volume: Affine3A::IDENTITY,
+            },
+        );
+        assert_eq!(result.cut, 8.0);
+        assert_eq!(result.fill, 8.0);
+        assert_eq!(result.empty_cell_count, 1);
+        assert_eq!(result.unresolved_cell_count, 0);
+        assert_eq!(result.uncertainty, 0.0);
+    }
+
+    #[test]
+    fn integrate_inside_polygon() {
+        // the triangle contains the centers of the 6 cells below the diagonal, excluded
+        let volume = PointCloudVolume::new(
+            [Vec2::ZERO, Vec2::new(4.0, 0.0), Vec2::new(0.0, 4.0)],
+            VolumeReference::default(),
+        );
+        let mut result = PointCloudVolumeResult {
+            grid: Arc::new(step_grid(1.0, 1.0)),
+            ..Default::default()
+        };
+        integrate(
+            &volume,
+            &mut result,
+            &ReferenceHeights::Plane {
+                point: Vec3::ZERO,
+                normal: Vec3::Y,
+            },
+        );
+        assert_eq!(result.cell_count, 6);
+        assert_eq!(result.cut, 6.0);
+        assert_eq!(result.fill, 0.0);
+    }
+
+    #[test]
+    fn integrate_unresolved_cells() {
+        let mut grid = HeightGrid::new(Vec2::ZERO, Vec2::splat(4.0), 1.0);
+        grid.insert(Vec2::new(0.5, 0.5), 1.0);
+        grid.insert(Vec2::new(0.5, 1.5), 3.0);
+        let result = integrated(
+            grid,
+            &ReferenceHeights::Plane {
+                point: Vec3::ZERO,
+                normal: Vec3::Y,
+            },
+        );
+        // the last column is farther than two rings from the points
+        assert_eq!(result.cell_count, 16);
+        assert_eq!(result.empty_cell_count, 14);
+        assert_eq!(result.unresolved_cell_count, 4);
+        // bounded by the range of the heights
+        assert!(result.uncertainty >= 8.0);
+    }
+}